@@ -0,0 +1,62 @@
+/*
+ * A minimal C API for web-transport-iroh.
+ *
+ * Build the library with the `ffi` feature, for example:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * All functions block the calling thread. Functions returning a pointer return NULL
+ * on failure and functions returning an integer return a negative value; call
+ * wt_last_error() for a description of the error.
+ */
+
+#ifndef WEB_TRANSPORT_IROH_H
+#define WEB_TRANSPORT_IROH_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct WtEndpoint WtEndpoint;
+typedef struct WtSession WtSession;
+typedef struct WtSendStream WtSendStream;
+typedef struct WtRecvStream WtRecvStream;
+
+/* Errors */
+const char *wt_last_error(void);
+void wt_string_free(char *s);
+
+/* Endpoints */
+WtEndpoint *wt_endpoint_bind(void);
+char *wt_endpoint_id(const WtEndpoint *endpoint);
+WtSession *wt_connect(const WtEndpoint *endpoint, const char *endpoint_id, const char *path);
+WtSession *wt_accept(const WtEndpoint *endpoint);
+void wt_endpoint_free(WtEndpoint *endpoint);
+
+/* Sessions */
+WtSendStream *wt_session_open_uni(const WtSession *session);
+int wt_session_open_bi(const WtSession *session, WtSendStream **send, WtRecvStream **recv);
+WtRecvStream *wt_session_accept_uni(const WtSession *session);
+int wt_session_accept_bi(const WtSession *session, WtSendStream **send, WtRecvStream **recv);
+int wt_session_send_datagram(const WtSession *session, const uint8_t *data, size_t len);
+ssize_t wt_session_recv_datagram(const WtSession *session, uint8_t *buf, size_t len);
+size_t wt_session_max_datagram_size(const WtSession *session);
+void wt_session_close(const WtSession *session, uint32_t code, const char *reason);
+void wt_session_free(WtSession *session);
+
+/* Streams */
+int wt_send_write(WtSendStream *send, const uint8_t *data, size_t len);
+int wt_send_finish(WtSendStream *send);
+void wt_send_free(WtSendStream *send);
+ssize_t wt_recv_read(WtRecvStream *recv, uint8_t *buf, size_t len);
+void wt_recv_free(WtRecvStream *recv);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* WEB_TRANSPORT_IROH_H */
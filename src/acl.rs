@@ -0,0 +1,75 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use iroh::EndpointId;
+
+/// An allowlist and denylist of peers, checked by a [`Server`](crate::Server) right after the
+/// QUIC handshake, see [`Server::with_acl`](crate::Server::with_acl).
+///
+/// Denied peers are always rejected. If there is an allowlist, only the peers on it are
+/// accepted, otherwise everyone else is. Clones share the lists, so they can be updated at
+/// runtime while the server is running, and the changes apply to the next connection.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAcl {
+    lists: Arc<RwLock<Lists>>,
+}
+
+#[derive(Debug, Default)]
+struct Lists {
+    allow: Option<HashSet<EndpointId>>,
+    deny: HashSet<EndpointId>,
+}
+
+impl PeerAcl {
+    /// Returns an ACL accepting every peer that isn't denied.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an ACL only accepting the given peers.
+    pub fn allowlist(peers: impl IntoIterator<Item = EndpointId>) -> Self {
+        let lists = Lists {
+            allow: Some(peers.into_iter().collect()),
+            deny: HashSet::new(),
+        };
+        Self {
+            lists: Arc::new(RwLock::new(lists)),
+        }
+    }
+
+    /// Allows the peer, removing it from the denylist and adding it to the allowlist, if any.
+    pub fn allow(&self, peer: EndpointId) {
+        let mut lists = self.lists.write().unwrap();
+        lists.deny.remove(&peer);
+        if let Some(allow) = &mut lists.allow {
+            allow.insert(peer);
+        }
+    }
+
+    /// Denies the peer, removing it from the allowlist, if any.
+    pub fn deny(&self, peer: EndpointId) {
+        let mut lists = self.lists.write().unwrap();
+        if let Some(allow) = &mut lists.allow {
+            allow.remove(&peer);
+        }
+        lists.deny.insert(peer);
+    }
+
+    /// Replaces the allowlist, or removes it with None to accept every peer that isn't denied.
+    pub fn set_allowlist(&self, peers: Option<impl IntoIterator<Item = EndpointId>>) {
+        self.lists.write().unwrap().allow = peers.map(|peers| peers.into_iter().collect());
+    }
+
+    /// Replaces the denylist.
+    pub fn set_denylist(&self, peers: impl IntoIterator<Item = EndpointId>) {
+        self.lists.write().unwrap().deny = peers.into_iter().collect();
+    }
+
+    /// Returns true if a connection from the peer is accepted.
+    pub fn is_allowed(&self, peer: &EndpointId) -> bool {
+        let lists = self.lists.read().unwrap();
+        !lists.deny.contains(peer) && lists.allow.as_ref().is_none_or(|a| a.contains(peer))
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use iroh::endpoint::Incoming;
+
+use crate::H3Request;
+
+/// The status used by [`RateLimit`] to reject sessions by default.
+pub const ADMISSION_REJECTED: http::StatusCode = http::StatusCode::SERVICE_UNAVAILABLE;
+
+/// Decides whether a connection is handshaked and whether its session request is passed on to
+/// the application, see [`Server::with_admission_control`](crate::Server::with_admission_control).
+///
+/// [`Self::admit_connection`] runs in the accept loop before any handshake work, so refusing
+/// connections there is cheap. [`Self::admit`] runs in the handshake task as soon as the CONNECT
+/// request was received, so rejected requests never reach [`Server::accept`](crate::Server::accept).
+pub trait AdmissionControl: Send + Sync + 'static {
+    /// Returns false to refuse the incoming connection before its QUIC handshake.
+    ///
+    /// `active` is the number of sessions accepted through the server that are still open.
+    /// The default admits every connection.
+    fn admit_connection(&self, incoming: &Incoming, active: usize) -> bool {
+        let _ = (incoming, active);
+        true
+    }
+
+    /// Returns the status to reject the request with, or Ok to admit it.
+    ///
+    /// `active` is the number of sessions accepted through the server that are still open.
+    fn admit(&self, request: &H3Request, active: usize) -> Result<(), http::StatusCode>;
+}
+
+impl<F> AdmissionControl for F
+where
+    F: Fn(&H3Request, usize) -> Result<(), http::StatusCode> + Send + Sync + 'static,
+{
+    fn admit(&self, request: &H3Request, active: usize) -> Result<(), http::StatusCode> {
+        self(request, active)
+    }
+}
+
+/// Admission control with a token bucket for new sessions and a cap on concurrent ones.
+///
+/// The bucket holds up to `burst` tokens and is refilled at `per_second` tokens per second.
+/// Each incoming connection takes one, and connections that find the bucket empty are refused
+/// before their handshake starts. Requests that arrive while the session cap is reached are
+/// rejected with [`ADMISSION_REJECTED`], unless another status is set with [`Self::with_status`].
+/// Clones share the bucket, so one limit can be applied to several servers.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    bucket: Arc<Mutex<Bucket>>,
+    per_second: f64,
+    burst: f64,
+    max_sessions: Option<usize>,
+    status: http::StatusCode,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// Admits up to `per_second` sessions per second on average, and bursts of up to `burst`.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            })),
+            per_second: f64::from(per_second),
+            burst,
+            max_sessions: None,
+            status: ADMISSION_REJECTED,
+        }
+    }
+
+    /// Also rejects requests while this many sessions are open.
+    pub fn with_max_sessions(mut self, max: usize) -> Self {
+        self.max_sessions = Some(max);
+        self
+    }
+
+    /// Sets the status to reject requests with, e.g. 429 Too Many Requests.
+    pub fn with_status(mut self, status: http::StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+impl AdmissionControl for RateLimit {
+    fn admit_connection(&self, _incoming: &Incoming, _active: usize) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn admit(&self, _request: &H3Request, active: usize) -> Result<(), http::StatusCode> {
+        match self.max_sessions.is_some_and(|max| active >= max) {
+            true => Err(self.status),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+use iroh::endpoint::PathStats;
+
+// Every QUIC path carries 1200 byte UDP payloads. Leave room for the short header with the
+// largest connection ID, the packet number, the AEAD tag and the DATAGRAM frame header.
+const GUARANTEED_DATAGRAM_SIZE: usize = 1200 - 1 - 20 - 4 - 16 - 9;
+
+// Shrink the recommendation when more than 1 in this many packets were lost.
+const LOSS_RATIO: u64 = 20;
+
+// Tracks which datagram sizes survive the path, see `Session::recommended_datagram_size`.
+#[derive(Debug, Default)]
+pub(crate) struct DatagramAdvisor {
+    // Datagrams sent above the guaranteed size since the last recommendation.
+    large_sent: AtomicU64,
+    state: Mutex<AdvisorState>,
+}
+
+#[derive(Debug, Default)]
+struct AdvisorState {
+    // The current recommendation including the session header, once made.
+    size: Option<usize>,
+    // The path counters at the last recommendation.
+    sent_packets: u64,
+    lost_packets: u64,
+    black_holes: u64,
+}
+
+impl DatagramAdvisor {
+    /// Records a datagram of the given size, including the session header.
+    pub(crate) fn sent(&self, size: usize) {
+        if size > GUARANTEED_DATAGRAM_SIZE {
+            self.large_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the recommended datagram size including the session header, at most `max`.
+    ///
+    /// Shrinks when large datagrams were sent while packets were lost or a black hole was
+    /// detected, and grows back while there is no loss.
+    pub(crate) fn recommend(&self, max: usize, stats: Option<PathStats>) -> usize {
+        let floor = GUARANTEED_DATAGRAM_SIZE.min(max);
+        let mut state = self.state.lock().unwrap();
+        let mut size = state.size.unwrap_or(max);
+
+        if let Some(stats) = stats {
+            // Counters restart when another path is selected.
+            let sent = stats.udp_tx.datagrams.saturating_sub(state.sent_packets);
+            let lost = stats.lost_packets.saturating_sub(state.lost_packets);
+            let black_holes = stats.black_holes_detected.saturating_sub(state.black_holes);
+            state.sent_packets = stats.udp_tx.datagrams;
+            state.lost_packets = stats.lost_packets;
+            state.black_holes = stats.black_holes_detected;
+            let large = self.large_sent.swap(0, Ordering::Relaxed);
+
+            if black_holes > 0 {
+                size = floor;
+            } else if large > 0 && lost * LOSS_RATIO > sent {
+                size -= size / 8;
+            } else if lost == 0 && sent > 0 {
+                size += size / 16;
+            }
+        }
+
+        let size = size.clamp(floor, max);
+        state.size = Some(size);
+        size
+    }
+}
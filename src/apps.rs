@@ -0,0 +1,212 @@
+//! Small applications built on [`Session`], enabled with the `apps` feature.
+//!
+//! They back the `wt-iroh` command-line tool and are meant to be embedded directly, or
+//! used as a starting point for your own protocol. Each works on both raw QUIC and HTTP/3
+//! sessions, and runs until the session is closed.
+
+use bytes::Bytes;
+use n0_error::stack_error;
+use tokio::{io::AsyncRead, sync::broadcast};
+use web_transport_trait::{RecvStream as _, SendStream as _};
+
+use crate::{
+    ClosedStream, MessageError, ReadExactError, RecvStream, Session, SessionError, WriteError,
+};
+
+/// The largest chat message relayed by a [`ChatRoom`].
+pub const MAX_CHAT_MESSAGE: usize = 64 * 1024;
+
+/// Echoes every bidirectional stream and datagram back to the peer.
+///
+/// Works on any [`web_transport_trait::Session`], e.g. a [`Session`] wrapped in
+/// [`Instrumented`](crate::Instrumented). Returns the error the session was closed with.
+pub async fn echo<S: web_transport_trait::Session>(session: S) -> S::Error
+where
+    S::SendStream: Send + 'static,
+    S::RecvStream: Send + 'static,
+{
+    let datagrams = async {
+        while let Ok(datagram) = session.recv_datagram().await {
+            session.send_datagram(datagram).ok();
+        }
+    };
+    let streams = async {
+        while let Ok((mut send, mut recv)) = session.accept_bi().await {
+            tokio::spawn(async move {
+                while let Ok(Some(chunk)) = recv.read_chunk(usize::MAX).await {
+                    if send.write_chunk(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                send.finish().ok();
+            });
+        }
+    };
+    tokio::join!(datagrams, streams);
+    session.closed().await
+}
+
+/// A chat room relaying each message to all other members.
+///
+/// Members send a message per unidirectional stream with [`send_chat`], and receive the
+/// messages of the others the same way with [`recv_chat`]. Clones share the same room.
+#[derive(Debug, Clone)]
+pub struct ChatRoom {
+    tx: broadcast::Sender<Relayed>,
+}
+
+#[derive(Debug, Clone)]
+struct Relayed {
+    // The connection the message was received on, so it isn't echoed back.
+    sender: usize,
+    text: Bytes,
+}
+
+impl ChatRoom {
+    /// Creates an empty room, buffering up to `capacity` messages for slow members.
+    ///
+    /// Members that fall further behind miss messages.
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Returns the number of members in the room.
+    pub fn members(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// Adds the session to the room, relaying messages until it is closed.
+    ///
+    /// Returns the error the session was closed with.
+    pub async fn join(&self, session: Session) -> SessionError {
+        let sender = session.conn().stable_id();
+        let mut rx = self.tx.subscribe();
+
+        let receive = async {
+            loop {
+                match session.accept_uni_message(MAX_CHAT_MESSAGE).await {
+                    Ok(text) => {
+                        self.tx.send(Relayed { sender, text }).ok();
+                    }
+                    Err(MessageError::SessionError(err)) => return err,
+                    Err(err) => tracing::debug!("dropping chat message: {err:#}"),
+                }
+            }
+        };
+        let relay = async {
+            loop {
+                match rx.recv().await {
+                    Ok(message) if message.sender != sender => {
+                        if let Err(err) = send_chat(&session, message.text).await {
+                            tracing::debug!("failed to relay chat message: {err:#}");
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::debug!(missed, "chat member fell behind");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        };
+
+        tokio::select! {
+            err = receive => err,
+            () = relay => session.closed().await,
+        }
+    }
+}
+
+impl Default for ChatRoom {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+/// Sends a chat message on a new unidirectional stream, see [`ChatRoom`].
+pub async fn send_chat(session: &Session, text: Bytes) -> Result<(), WriteError> {
+    let mut send = session.open_uni().await?;
+    send.write_chunk(text).await?;
+    send.finish().map_err(|_| WriteError::ClosedStream)
+}
+
+/// Receives the next chat message relayed by a [`ChatRoom`].
+pub async fn recv_chat(session: &Session) -> Result<Bytes, MessageError> {
+    session.accept_uni_message(MAX_CHAT_MESSAGE).await
+}
+
+/// An error sending or receiving a file with [`send_file`] and [`accept_file`].
+#[stack_error(derive, from_sources)]
+pub enum FileDropError {
+    #[error("session error")]
+    SessionError(#[error(source, from)] SessionError),
+
+    #[error("write error")]
+    WriteError(#[error(source, from)] WriteError),
+
+    #[error("read error")]
+    ReadError(#[error(source, from)] ReadExactError),
+
+    #[error("failed to read the file")]
+    Io(#[error(source, from, std_err)] std::io::Error),
+
+    #[error("stream closed")]
+    ClosedStream(#[error(source, from)] ClosedStream),
+
+    /// The file name is empty, too long, or could escape the target directory.
+    #[error("invalid file name")]
+    InvalidName,
+}
+
+/// A file sent by the peer, see [`accept_file`].
+#[derive(Debug)]
+pub struct IncomingFile {
+    /// The name of the file, without any path components.
+    pub name: String,
+    /// The contents of the file, e.g. to copy into a file or read with [`RecvStream::read_to_end`].
+    pub stream: RecvStream,
+}
+
+/// Sends a file on a new unidirectional stream, returning the number of bytes sent.
+///
+/// The name is sent first, prefixed by its length, followed by the contents.
+pub async fn send_file(
+    session: &Session,
+    name: &str,
+    mut data: impl AsyncRead + Unpin,
+) -> Result<u64, FileDropError> {
+    if !is_valid_name(name) {
+        return Err(FileDropError::InvalidName);
+    }
+    let mut send = session.open_uni().await?;
+    send.write_all(&(name.len() as u16).to_be_bytes()).await?;
+    send.write_all(name.as_bytes()).await?;
+    let size = tokio::io::copy(&mut data, &mut send).await?;
+    send.finish()?;
+    Ok(size)
+}
+
+/// Accepts the next file sent with [`send_file`].
+///
+/// The name is checked to be a plain file name, so it can be joined to a directory.
+pub async fn accept_file(session: &Session) -> Result<IncomingFile, FileDropError> {
+    let mut stream = session.accept_uni().await?;
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut name = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut name).await?;
+    let name = String::from_utf8(name).map_err(|_| FileDropError::InvalidName)?;
+    if !is_valid_name(&name) {
+        return Err(FileDropError::InvalidName);
+    }
+    Ok(IncomingFile { name, stream })
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= u16::MAX as usize
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+}
@@ -0,0 +1,65 @@
+use std::{borrow::Cow, future::Future, pin::Pin, sync::Arc};
+
+use http::HeaderMap;
+use iroh::EndpointId;
+use url::Url;
+
+/// The query parameter read by [`AuthRequest::bearer_token`], for clients that can't set headers.
+pub const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+type AuthFuture = Pin<Box<dyn Future<Output = Result<http::Extensions, http::StatusCode>> + Send>>;
+
+// Runs the hook and returns the extensions holding the identity.
+pub(crate) type AuthCallback = Arc<dyn Fn(AuthRequest) -> AuthFuture + Send + Sync>;
+
+/// The CONNECT request passed to an authentication hook, see [`Server::with_auth`](crate::Server::with_auth).
+#[derive(Debug, Clone)]
+pub struct AuthRequest {
+    /// The peer, as authenticated by the QUIC handshake.
+    pub remote: EndpointId,
+    /// The URL of the CONNECT request.
+    pub url: Url,
+    /// The headers of the CONNECT request.
+    pub headers: HeaderMap,
+}
+
+impl AuthRequest {
+    /// Returns the bearer token from the `Authorization` header, or else the [`ACCESS_TOKEN_PARAM`] query parameter.
+    ///
+    /// Browsers can't set headers on WebTransport sessions, so they pass the token in the URL.
+    pub fn bearer_token(&self) -> Option<Cow<'_, str>> {
+        let header = self
+            .headers
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| Cow::Borrowed(token.trim()));
+        header.or_else(|| self.query(ACCESS_TOKEN_PARAM))
+    }
+
+    /// Returns the first value of the query parameter, percent-decoded.
+    pub fn query(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.url
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+// Erases the type of the identity returned by the hook.
+pub(crate) fn callback<F, Fut, I>(f: F) -> AuthCallback
+where
+    F: Fn(AuthRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<I, http::StatusCode>> + Send + 'static,
+    I: Clone + Send + Sync + 'static,
+{
+    Arc::new(move |request| {
+        let identity = f(request);
+        Box::pin(async move {
+            let mut extensions = http::Extensions::new();
+            extensions.insert(identity.await?);
+            Ok(extensions)
+        })
+    })
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{ClosedStream, SendStream, WriteError};
+
+/// How small writes are aggregated by a [`BatchedSendStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// How long written data may be buffered before it is sent.
+    pub max_delay: Duration,
+    /// How much data may be buffered before it is sent.
+    pub max_bytes: usize,
+}
+
+impl Batching {
+    /// Buffer writes for at most `max_delay` or until `max_bytes` are buffered, whichever is first.
+    pub fn new(max_delay: Duration, max_bytes: usize) -> Self {
+        Self {
+            max_delay,
+            max_bytes,
+        }
+    }
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(5), 16 * 1024)
+    }
+}
+
+/// A [`SendStream`] that aggregates small writes, like Nagle's algorithm.
+///
+/// Created by [`SendStream::batched`]. Writes are buffered and sent once [`Batching::max_bytes`]
+/// are buffered, or by the first write at least [`Batching::max_delay`] after the first buffered
+/// write. There is no background task, so use [`Self::flush`] to send buffered data when no more
+/// writes follow. When this is dropped, buffered data is only sent if the stream accepts it right
+/// away, otherwise the stream is reset with code 0.
+///
+/// Once sending fails, later writes and flushes return the same error.
+#[derive(Debug)]
+pub struct BatchedSendStream {
+    stream: SendStream,
+    buf: BytesMut,
+    // When the buffer was last empty, so the delay counts from the first buffered write.
+    since: Option<Instant>,
+    error: Option<WriteError>,
+    batching: Batching,
+}
+
+impl BatchedSendStream {
+    pub(crate) fn new(stream: SendStream, batching: Batching) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::new(),
+            since: None,
+            error: None,
+            batching,
+        }
+    }
+
+    /// Buffer all of the data, sending it once the batch is full or the delay elapsed.
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), WriteError> {
+        self.check()?;
+        self.buf.extend_from_slice(buf);
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if self.buf.len() >= self.batching.max_bytes || since.elapsed() >= self.batching.max_delay {
+            self.flush().await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Buffer a chunk of data, sending it once the batch is full or the delay elapsed.
+    ///
+    /// Chunks of at least [`Batching::max_bytes`] are sent right away without copying, after
+    /// any buffered data.
+    pub async fn write_chunk(&mut self, buf: Bytes) -> Result<(), WriteError> {
+        if buf.len() < self.batching.max_bytes {
+            return self.write_all(&buf).await;
+        }
+        self.check()?;
+        let buffered = self.take().freeze();
+        let res = self.stream.write_all_chunks(&mut [buffered, buf]).await;
+        self.record(res)
+    }
+
+    /// Buffer all of the chunks, emptying them, like [`SendStream::write_all_chunks`].
+    ///
+    /// Each chunk is written like [`Self::write_chunk`], so large chunks aren't copied.
+    pub async fn write_all_chunks(&mut self, bufs: &mut [Bytes]) -> Result<(), WriteError> {
+        for buf in bufs {
+            self.write_chunk(std::mem::take(buf)).await?;
+        }
+        Ok(())
+    }
+
+    /// Send any buffered data right away.
+    pub async fn flush(&mut self) -> Result<(), WriteError> {
+        self.check()?;
+        let buf = self.take();
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let res = self.stream.write_chunk(buf.freeze()).await;
+        self.record(res)
+    }
+
+    /// Send any buffered data and mark the stream as finished. See [`SendStream::finish`].
+    pub async fn finish(&mut self) -> Result<(), WriteError> {
+        self.flush().await?;
+        self.stream.finish().map_err(|_| WriteError::ClosedStream)
+    }
+
+    /// Discard any buffered data and abruptly reset the stream. See [`SendStream::reset`].
+    pub async fn reset(&mut self, code: u32) -> Result<(), ClosedStream> {
+        self.take();
+        self.stream.reset(code)
+    }
+
+    fn take(&mut self) -> BytesMut {
+        self.since = None;
+        std::mem::take(&mut self.buf)
+    }
+
+    fn check(&self) -> Result<(), WriteError> {
+        match &self.error {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, res: Result<(), WriteError>) -> Result<(), WriteError> {
+        if let Err(err) = &res {
+            self.error = Some(err.clone());
+        }
+        res
+    }
+}
+
+impl Drop for BatchedSendStream {
+    fn drop(&mut self) {
+        if self.buf.is_empty() || self.error.is_some() {
+            return;
+        }
+        let buf = self.take().freeze();
+        // Only write what the stream accepts without waiting, there is nothing left to wait on.
+        match n0_future::future::now_or_never(self.stream.write_chunk(buf)) {
+            Some(Ok(())) => {}
+            Some(Err(err)) => tracing::debug!("failed to send buffered data: {err}"),
+            None => {
+                // Don't let the peer mistake the truncated data for a finished stream.
+                tracing::debug!("resetting a batched stream whose buffered data didn't fit");
+                self.stream.reset(0).ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Command-line demo and diagnostic tool for web-transport-iroh.
+//!
+//! Run `wt-iroh serve-echo` on one machine and use the printed endpoint id with
+//! `wt-iroh connect`, `wt-iroh bench` or `wt-iroh probe` on another.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+use iroh::{Endpoint, EndpointId, Watcher, endpoint::Connection};
+use url::Url;
+use web_transport_iroh::{ALPN_H3, Client, H3Request, Instrumented, QuicRequest, Session, apps};
+
+/// ALPN used by `serve-echo` for raw QUIC sessions.
+const ALPN_ECHO: &[u8] = b"wt-iroh/echo/0";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "wt-iroh",
+    about = "WebTransport over iroh demo and diagnostics"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Accept sessions and echo back every stream and datagram.
+    ServeEcho,
+    /// Connect to an echo server, send a message and print the reply.
+    Connect {
+        #[command(flatten)]
+        target: Target,
+        /// The message to send.
+        #[arg(default_value = "hello")]
+        message: String,
+    },
+    /// Measure round-trip throughput against an echo server.
+    Bench {
+        #[command(flatten)]
+        target: Target,
+        /// Total number of bytes to send.
+        #[arg(long, default_value_t = 16 * 1024 * 1024)]
+        bytes: usize,
+        /// Size of each write.
+        #[arg(long, default_value_t = 64 * 1024)]
+        chunk_size: usize,
+    },
+    /// Print connection and path information for a remote endpoint.
+    Probe {
+        #[command(flatten)]
+        target: Target,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct Target {
+    /// The endpoint id of the remote.
+    endpoint_id: EndpointId,
+    /// Use raw QUIC instead of the HTTP/3 handshake.
+    #[arg(long)]
+    raw: bool,
+    /// The URL path to request when using HTTP/3.
+    #[arg(long, default_value = "/")]
+    path: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::ServeEcho => serve_echo().await,
+        Command::Connect { target, message } => connect(target, message).await,
+        Command::Bench {
+            target,
+            bytes,
+            chunk_size,
+        } => bench(target, bytes, chunk_size).await,
+        Command::Probe { target } => probe(target).await,
+    }
+}
+
+async fn serve_echo() -> Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec(), ALPN_ECHO.to_vec()])
+        .bind()
+        .await?;
+    println!("endpoint id: {}", endpoint.id());
+    println!("waiting for connections, press ctrl-c to exit");
+
+    loop {
+        tokio::select! {
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else {
+                    break;
+                };
+                tokio::spawn(async move {
+                    if let Err(err) = handle_echo(incoming).await {
+                        eprintln!("session failed: {err:#}");
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    endpoint.close().await;
+    Ok(())
+}
+
+async fn handle_echo(incoming: iroh::endpoint::Incoming) -> Result<()> {
+    let conn = incoming.accept()?.await?;
+    let remote = conn.remote_id();
+    let session = if conn.alpn() == ALPN_H3.as_bytes() {
+        let request = H3Request::accept(conn).await?;
+        println!("{remote}: accepted h3 session for {}", request.url);
+        request.ok().await?
+    } else {
+        println!("{remote}: accepted raw session");
+        QuicRequest::accept(conn).ok()
+    };
+    let session = Instrumented::with_span(session, tracing::info_span!("echo", %remote));
+    let closed = apps::echo(session.clone()).await;
+    println!("{remote}: session closed: {closed}");
+    let metrics = session.metrics();
+    println!(
+        "{remote}: echoed {} streams and {} datagrams ({} bytes)",
+        metrics.bi_accepted(),
+        metrics.datagrams_received(),
+        metrics.datagram_bytes_received()
+    );
+    Ok(())
+}
+
+async fn open(target: &Target) -> Result<(Client, Session)> {
+    let endpoint = Endpoint::bind().await?;
+    let client = Client::new(endpoint);
+    let session = if target.raw {
+        client.connect_quic(target.endpoint_id, ALPN_ECHO).await?
+    } else {
+        let url: Url = format!("https://{}{}", target.endpoint_id, target.path)
+            .parse()
+            .context("invalid path")?;
+        client.connect_h3(target.endpoint_id, url).await?
+    };
+    Ok((client, session))
+}
+
+async fn connect(target: Target, message: String) -> Result<()> {
+    let (client, session) = open(&target).await?;
+    let start = Instant::now();
+    let (mut send, mut recv) = session.open_bi().await?;
+    send.write_all(message.as_bytes()).await?;
+    send.finish()?;
+    let reply = recv.read_to_end(message.len()).await?;
+    println!(
+        "reply after {:?}: {}",
+        start.elapsed(),
+        String::from_utf8_lossy(&reply)
+    );
+    session.close(0, b"done");
+    client.close().await;
+    Ok(())
+}
+
+async fn bench(target: Target, bytes: usize, chunk_size: usize) -> Result<()> {
+    let (client, session) = open(&target).await?;
+    let (mut send, mut recv) = session.open_bi().await?;
+    let chunk = Bytes::from(vec![0u8; chunk_size.max(1)]);
+
+    let start = Instant::now();
+    let writer = async move {
+        let mut remaining = bytes;
+        while remaining > 0 {
+            let len = remaining.min(chunk.len());
+            send.write_chunk(chunk.slice(..len)).await?;
+            remaining -= len;
+        }
+        send.finish()?;
+        anyhow::Ok(())
+    };
+    let reader = async move {
+        let mut received = 0;
+        while let Some(chunk) = recv.read_chunk(usize::MAX).await? {
+            received += chunk.bytes.len();
+        }
+        anyhow::Ok(received)
+    };
+    let ((), received) = tokio::try_join!(writer, reader)?;
+    let elapsed = start.elapsed();
+
+    println!(
+        "echoed {received} bytes in {elapsed:?} ({:.2} MiB/s)",
+        throughput(received, elapsed)
+    );
+    session.close(0, b"done");
+    client.close().await;
+    Ok(())
+}
+
+async fn probe(target: Target) -> Result<()> {
+    let start = Instant::now();
+    let (client, session) = open(&target).await?;
+    println!("session established in {:?}", start.elapsed());
+    print_conn(session.conn());
+    println!("max datagram size: {}", session.max_datagram_size());
+    if let Some(response) = session.response() {
+        println!("CONNECT response: {}", response.status);
+    }
+    session.close(0, b"done");
+    client.close().await;
+    Ok(())
+}
+
+fn print_conn(conn: &Connection) {
+    println!("remote: {}", conn.remote_id());
+    println!("alpn: {}", String::from_utf8_lossy(conn.alpn()));
+    for path in conn.paths().get().iter() {
+        println!(
+            "path: {:?} selected={} relay={} rtt={:?}",
+            path.remote_addr(),
+            path.is_selected(),
+            path.is_relay(),
+            path.rtt()
+        );
+    }
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
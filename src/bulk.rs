@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::time::Instant;
+
+use crate::{ClosedStream, SendStream, SessionError, WriteError};
+
+/// How a stream opened with [`Session::open_uni_bulk_with_config`](crate::Session::open_uni_bulk_with_config)
+/// is tuned for throughput.
+///
+/// QUIC flow control windows are chosen by the receiver, so there is no way to ask the peer for
+/// a larger stream receive window. Raise it on the receiving endpoint's transport config instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkConfig {
+    /// The priority of the stream, see [`SendStream::set_priority`].
+    ///
+    /// Defaults to the lowest priority, so bulk transfers don't delay other streams.
+    pub priority: i32,
+    /// Limits the average send rate in bytes per second, or None to send as fast as possible.
+    pub pacing: Option<u64>,
+}
+
+impl BulkConfig {
+    /// Returns the default config, sending at the lowest priority without pacing.
+    pub fn new() -> Self {
+        Self {
+            priority: i32::MIN,
+            pacing: None,
+        }
+    }
+
+    /// Sets the priority of the stream.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Limits the average send rate in bytes per second.
+    pub fn with_pacing(mut self, bytes_per_second: u64) -> Self {
+        self.pacing = Some(bytes_per_second);
+        self
+    }
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`SendStream`] for bulk transfers, created by [`Session::open_uni_bulk`](crate::Session::open_uni_bulk).
+///
+/// Writes are sent at the [`BulkConfig::priority`] and, if configured, paced to the
+/// [`BulkConfig::pacing`] rate. Writes are not coalesced, so callers should pass large buffers.
+#[derive(Debug)]
+pub struct BulkSendStream {
+    stream: SendStream,
+    pacer: Option<Pacer>,
+}
+
+// How far the pacer may fall behind while idle, bounding the burst that follows.
+const MAX_BURST: Duration = Duration::from_millis(100);
+
+// Spreads writes over time so the average rate stays below the limit.
+#[derive(Debug)]
+struct Pacer {
+    rate: u64,
+    // When the data written so far is due at the configured rate.
+    due: Instant,
+}
+
+impl Pacer {
+    async fn wait(&mut self, size: usize) {
+        let now = Instant::now();
+        let earliest = now.checked_sub(MAX_BURST).unwrap_or(now);
+        let cost = Duration::from_secs_f64(size as f64 / self.rate.max(1) as f64);
+        self.due = self.due.max(earliest) + cost;
+        tokio::time::sleep_until(self.due).await;
+    }
+}
+
+impl BulkSendStream {
+    pub(crate) fn new(stream: SendStream, config: BulkConfig) -> Self {
+        stream.set_priority(config.priority).ok();
+        let pacer = config.pacing.map(|rate| Pacer {
+            rate,
+            due: Instant::now(),
+        });
+        Self { stream, pacer }
+    }
+
+    /// Write all of the data to the stream, waiting for the pacing afterwards.
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), WriteError> {
+        self.stream.write_all(buf).await?;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(buf.len()).await;
+        }
+        Ok(())
+    }
+
+    /// Write a chunk of data to the stream without copying, waiting for the pacing afterwards.
+    pub async fn write_chunk(&mut self, buf: Bytes) -> Result<(), WriteError> {
+        let size = buf.len();
+        self.stream.write_chunk(buf).await?;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(size).await;
+        }
+        Ok(())
+    }
+
+    /// Write all of the chunks to the stream without copying, waiting for the pacing afterwards.
+    ///
+    /// See [`SendStream::write_all_chunks`].
+    pub async fn write_all_chunks(&mut self, bufs: &mut [Bytes]) -> Result<(), WriteError> {
+        let size = bufs.iter().map(Bytes::len).sum();
+        self.stream.write_all_chunks(bufs).await?;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(size).await;
+        }
+        Ok(())
+    }
+
+    /// Mark the stream as finished. See [`SendStream::finish`].
+    pub fn finish(&mut self) -> Result<(), ClosedStream> {
+        self.stream.finish()
+    }
+
+    /// Abruptly reset the stream. See [`SendStream::reset`].
+    pub fn reset(&mut self, code: u32) -> Result<(), ClosedStream> {
+        self.stream.reset(code)
+    }
+
+    /// Wait until the stream has been stopped. See [`SendStream::stopped`].
+    pub async fn stopped(&mut self) -> Result<Option<u32>, SessionError> {
+        self.stream.stopped().await
+    }
+
+    /// Returns the underlying stream, discarding the pacing.
+    pub fn into_inner(self) -> SendStream {
+        self.stream
+    }
+}
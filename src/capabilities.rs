@@ -0,0 +1,158 @@
+use std::{fmt, ops::BitOr};
+
+use web_transport_proto::{Setting, VarInt};
+
+/// The SETTINGS identifier carrying the crate version, see [`Capabilities::version`].
+///
+/// Other HTTP/3 stacks ignore unknown settings, so this is safe to send to any peer.
+pub const SETTINGS_IROH_VERSION: Setting = Setting(VarInt::from_u32(0x4957_0001));
+
+/// The SETTINGS identifier carrying the supported [`Features`].
+pub const SETTINGS_IROH_FEATURES: Setting = Setting(VarInt::from_u32(0x4957_0002));
+
+/// A set of optional protocol features, advertised to the peer in SETTINGS.
+///
+/// The lower 32 bits are reserved for this crate, the upper 30 bits can be used by applications
+/// to advertise their own extensions, see [`Features::application`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Features(u64);
+
+impl Features {
+    /// Understands the `DRAIN_WEBTRANSPORT_SESSION` capsule, see [`Session::drain`](crate::Session::drain).
+    pub const DRAIN: Self = Self(1 << 0);
+    /// Reconnects elsewhere after a GOAWAY, see [`Session::handoff`](crate::Session::handoff).
+    pub const HANDOFF: Self = Self(1 << 1);
+    /// Reads length-prefixed messages, see [`Session::accept_uni_message`](crate::Session::accept_uni_message).
+    pub const MESSAGES: Self = Self(1 << 2);
+    /// Routes sessions by the [`AFFINITY_KEY`](crate::AFFINITY_KEY) header.
+    pub const AFFINITY: Self = Self(1 << 3);
+
+    /// Returns an empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns the features supported by this version of the crate.
+    pub const fn builtin() -> Self {
+        Self(Self::DRAIN.0 | Self::HANDOFF.0 | Self::MESSAGES.0 | Self::AFFINITY.0)
+    }
+
+    /// Returns an application-defined feature, where `bit` must be less than 30.
+    pub const fn application(bit: u32) -> Self {
+        assert!(bit < 30, "application feature bit out of range");
+        Self(1 << (32 + bit))
+    }
+
+    /// Returns the raw bits, as sent in [`SETTINGS_IROH_FEATURES`].
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns a set from the raw bits, ignoring those that don't fit in a varint.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & VarInt::MAX.into_inner())
+    }
+
+    /// Returns true if all features in `other` are in this set.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features in both sets, i.e. those that can be used with a peer.
+    pub const fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Features({:#x})", self.0)
+    }
+}
+
+/// A crate version, as advertised in [`SETTINGS_IROH_VERSION`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version.
+    pub major: u16,
+    /// The minor version.
+    pub minor: u16,
+    /// The patch version.
+    pub patch: u16,
+}
+
+impl Version {
+    /// Returns the version of this crate.
+    pub fn current() -> Self {
+        Self {
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+            patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0),
+        }
+    }
+
+    fn encode(&self) -> VarInt {
+        let value = (self.major as u64) << 32 | (self.minor as u64) << 16 | self.patch as u64;
+        VarInt::try_from(value).expect("version fits in a varint")
+    }
+
+    fn decode(value: VarInt) -> Self {
+        let value = value.into_inner();
+        Self {
+            major: (value >> 32) as u16,
+            minor: (value >> 16) as u16,
+            patch: value as u16,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The version and features a peer advertised, see [`Session::peer_capabilities`](crate::Session::peer_capabilities).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// The crate version of the peer, if it sent one.
+    pub version: Option<Version>,
+    /// The features supported by the peer.
+    pub features: Features,
+}
+
+impl Capabilities {
+    /// Returns true if the peer supports all the given features.
+    pub fn supports(&self, features: Features) -> bool {
+        self.features.contains(features)
+    }
+
+    /// Adds our version and features to the SETTINGS we send.
+    pub(crate) fn apply(features: Features, settings: &mut web_transport_proto::Settings) {
+        settings.insert(SETTINGS_IROH_VERSION, Version::current().encode());
+        settings.insert(
+            SETTINGS_IROH_FEATURES,
+            VarInt::try_from(features.bits()).expect("features fit in a varint"),
+        );
+    }
+
+    /// Reads the peer's version and features, if it advertised any.
+    pub(crate) fn read(settings: &web_transport_proto::Settings) -> Option<Self> {
+        let features = settings.get(&SETTINGS_IROH_FEATURES)?;
+        Some(Self {
+            version: settings
+                .get(&SETTINGS_IROH_VERSION)
+                .copied()
+                .map(Version::decode),
+            features: Features::from_bits(features.into_inner()),
+        })
+    }
+}
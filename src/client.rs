@@ -1,29 +1,246 @@
-use std::sync::Arc;
+use std::{fmt, future::Future, net::SocketAddr, sync::Arc, time::Duration};
 
+use http::{HeaderMap, HeaderName, HeaderValue};
 use iroh::{
-    Endpoint, EndpointAddr,
-    endpoint::{ConnectOptions, QuicTransportConfig},
+    Endpoint, EndpointAddr, EndpointId, RelayUrl,
+    endpoint::{
+        self, ConnectOptions, Connecting, QuicTransportConfig, QuicTransportConfigBuilder,
+        ZeroRttStatus,
+    },
 };
+#[cfg(feature = "tickets")]
+use iroh_tickets::endpoint::EndpointTicket;
+use n0_error::AnyError;
+use n0_future::{FuturesUnordered, StreamExt};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use url::Url;
+use web_transport_proto::ConnectRequest;
 
-use crate::{ALPN_H3, ClientError, Session};
+use crate::{
+    AFFINITY_KEY, ALPN_H3, ClientError, Connected, ErrorKind, HandshakeOptions, PathMode,
+    PoolConfig, Resolve, RetryPolicy, Session, Settings, SettingsProfile, Spawner, Strictness,
+    TransportTuning, path, pool::Pool, transport::idle_timeout,
+};
+
+// The HTTP/3 error code for a request that was cancelled by the client.
+const H3_REQUEST_CANCELLED: u32 = 0x10c;
+
+// Type alias just so clippy doesn't complain about the complexity.
+type RequestHook =
+    Arc<dyn Fn(&EndpointAddr, &mut ConnectRequest) -> Result<(), String> + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct RequestHooks(Vec<RequestHook>);
+
+#[derive(Clone, Default)]
+struct Resolvers(Vec<Arc<dyn Resolve>>);
+
+impl fmt::Debug for Resolvers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolvers")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+impl fmt::Debug for RequestHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestHooks")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
 
 /// A client for connecting to an iroh WebTransport endpoint.
 #[derive(Debug)]
 pub struct Client {
     endpoint: Endpoint,
     config: QuicTransportConfig,
+    // The strictness, header size limit and SETTINGS of HTTP/3 handshakes.
+    options: HandshakeOptions,
+    handshake_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    alpns: Vec<Vec<u8>>,
+    headers: HeaderMap,
+    hooks: RequestHooks,
+    resolvers: Resolvers,
+    zero_rtt: bool,
+    pool: Option<Pool>,
+    retry: Option<RetryPolicy>,
+    stagger: Duration,
+    path_mode: PathMode,
+    // Starts the drivers and other background tasks of sessions.
+    spawner: Spawner,
 }
 
 impl Client {
-    /// Creates a client from an endpoint with the default transport config.
+    /// Returns a builder to configure a client, see [`ClientBuilder`].
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Creates a client from an endpoint with the default [`TransportTuning`].
     pub fn new(endpoint: Endpoint) -> Self {
-        Self::with_transport_config(endpoint, Default::default())
+        Self::with_tuning(endpoint, TransportTuning::default())
+    }
+
+    /// Creates a client from an endpoint with the given handshake timings.
+    pub fn with_tuning(endpoint: Endpoint, tuning: TransportTuning) -> Self {
+        Self::with_transport_config(endpoint, tuning.transport_config())
+            .with_handshake_timeout(tuning.handshake_timeout)
     }
 
     /// Creates a client from an endpoint and a transport config.
+    ///
+    /// The handshake timeout of the default [`TransportTuning`] is used.
     pub fn with_transport_config(endpoint: Endpoint, config: QuicTransportConfig) -> Self {
-        Self { endpoint, config }
+        Self {
+            endpoint,
+            config,
+            options: HandshakeOptions::default(),
+            handshake_timeout: TransportTuning::default().handshake_timeout,
+            connect_timeout: None,
+            alpns: Vec::new(),
+            headers: HeaderMap::new(),
+            hooks: RequestHooks::default(),
+            resolvers: Resolvers::default(),
+            zero_rtt: false,
+            pool: None,
+            retry: None,
+            stagger: Duration::ZERO,
+            path_mode: PathMode::Any,
+            spawner: Spawner::default(),
+        }
+    }
+
+    /// Sets how long to wait for the handshake, or None to only rely on the idle timeout.
+    ///
+    /// For HTTP/3 sessions, this includes the SETTINGS and CONNECT exchange.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Bounds each connect call as a whole, or None to only rely on the handshake timeout.
+    ///
+    /// Unlike the handshake timeout, this includes address lookup, holepunching and all
+    /// attempts of the [`RetryPolicy`]. Fails with [`ClientError::HandshakeTimeout`].
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how strictly the protocol is enforced for HTTP/3 sessions. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.options.strictness = strictness;
+        self
+    }
+
+    /// Limits the size of the response headers for HTTP/3 sessions, advertised in SETTINGS.
+    ///
+    /// Larger responses fail with [`ConnectError::FieldSectionTooLarge`](crate::ConnectError::FieldSectionTooLarge).
+    pub fn with_max_field_section_size(mut self, size: u64) -> Self {
+        self.options.max_field_section_size = Some(size);
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent and which omissions by the server are tolerated.
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    /// Sets all options of HTTP/3 handshakes at once, see [`HandshakeOptions`].
+    pub fn with_handshake_options(mut self, options: HandshakeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Starts the background tasks of sessions with the [`Spawner`], including the
+    /// [`SessionDriver`](crate::SessionDriver) of each HTTP/3 session.
+    ///
+    /// Defaults to the current tokio runtime.
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Runs a hook on every CONNECT request before it is sent, e.g. to inject credentials.
+    ///
+    /// The hook may modify the request, or veto it by returning an error, which fails the
+    /// connect with [`ClientError::Vetoed`]. Hooks run in the order they were added, after the
+    /// default headers were merged in.
+    pub fn with_request_hook(
+        mut self,
+        hook: impl Fn(&EndpointAddr, &mut ConnectRequest) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.0.push(Arc::new(hook));
+        self
+    }
+
+    /// Resolves domain names in [`Self::connect_url`], before falling back to DNS.
+    ///
+    /// Resolvers are tried in the order they were added.
+    pub fn with_resolver(mut self, resolver: impl Resolve) -> Self {
+        self.resolvers.0.push(Arc::new(resolver));
+        self
+    }
+
+    /// Sends the handshake in 0-RTT data when resuming a connection to a known server.
+    ///
+    /// For HTTP/3 sessions the SETTINGS and CONNECT request are sent before the TLS handshake
+    /// completes, saving a round trip. If the server rejects the 0-RTT data, the handshake is
+    /// transparently retried. See [`Session::is_0rtt`] for the outcome.
+    ///
+    /// 0-RTT data can be replayed by an attacker, so only enable this if the CONNECT request
+    /// is safe to process more than once.
+    pub fn with_0rtt(mut self, enabled: bool) -> Self {
+        self.zero_rtt = enabled;
+        self
+    }
+
+    /// Requires a relayed or direct path before returning a session, see [`PathMode`].
+    pub fn with_path_mode(mut self, mode: PathMode) -> Self {
+        self.path_mode = mode;
+        self
+    }
+
+    /// Retries failed connects with exponential backoff, see [`RetryPolicy`].
+    ///
+    /// Each attempt gets its own handshake timeout.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets how long [`Self::connect_quic_any`] and [`Self::connect_h3_any`] wait for an
+    /// attempt before starting the next one in parallel.
+    ///
+    /// Defaults to zero, dialing all candidates at once. A delay like the 250ms recommended by
+    /// RFC 8305 suits candidates that are different paths to the same service, where trying
+    /// them in order saves connections. [`Self::connect_any`] is never staggered.
+    pub fn with_stagger(mut self, delay: Duration) -> Self {
+        self.stagger = delay;
+        self
+    }
+
+    /// Keeps released sessions around, so connecting to the same peer again reuses them.
+    ///
+    /// See [`Self::release`].
+    pub fn with_pool(mut self, config: PoolConfig) -> Self {
+        self.pool = Some(Pool::new(config));
+        self
+    }
+
+    /// Hands a session back for reuse by the next connect to the same peer.
+    ///
+    /// Raw QUIC sessions are reused for the same ALPN, HTTP/3 sessions for the same CONNECT
+    /// request. Without a pool, see [`Self::with_pool`], the session is just dropped.
+    pub fn release(&self, session: Session) {
+        if let Some(pool) = &self.pool {
+            pool.release(session);
+        }
     }
 
     /// Connect to an iroh endpoint without HTTP/3.
@@ -32,43 +249,977 @@ impl Client {
         addr: impl Into<EndpointAddr>,
         alpn: &[u8],
     ) -> Result<Session, ClientError> {
-        let conn = self.connect(addr, alpn).await?;
-        Ok(Session::raw(conn))
+        self.bounded(None, self.dial_quic(addr.into(), self.offered(alpn)))
+            .await
+    }
+
+    /// Connect to an iroh endpoint without HTTP/3, until the token is cancelled.
+    ///
+    /// Cancelling aborts the attempt and fails with [`ClientError::Cancelled`].
+    pub async fn connect_quic_with_cancel(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        alpn: &[u8],
+        cancel: &CancellationToken,
+    ) -> Result<Session, ClientError> {
+        self.bounded(
+            Some(cancel),
+            self.dial_quic(addr.into(), self.offered(alpn)),
+        )
+        .await
+    }
+
+    /// Connect to an iroh endpoint without HTTP/3, offering the ALPNs in preference order.
+    ///
+    /// The server picks one it supports, which [`Session::protocol`] returns afterwards. Note that
+    /// iroh servers pick by their own preference order, the client's order is only a hint.
+    /// The ALPNs set with [`ClientBuilder::with_alpns`] aren't offered. A pooled session is
+    /// reused if it was established with any of the ALPNs, preferring earlier ones.
+    pub async fn connect_quic_preferred(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        alpns: impl IntoIterator<Item = impl AsRef<[u8]>>,
+    ) -> Result<Session, ClientError> {
+        let alpns: Vec<Vec<u8>> = alpns.into_iter().map(|a| a.as_ref().to_vec()).collect();
+        if alpns.is_empty() {
+            return Err(ClientError::NoAlpns);
+        }
+        self.bounded(None, self.dial_quic(addr.into(), alpns)).await
+    }
+
+    // The ALPNs offered by `connect_quic`, the given one first.
+    fn offered(&self, alpn: &[u8]) -> Vec<Vec<u8>> {
+        let mut alpns = vec![alpn.to_vec()];
+        // Additional ALPNs only make sense for raw QUIC, HTTP/3 is negotiated on its own.
+        if alpn != ALPN_H3.as_bytes() {
+            alpns.extend(self.alpns.iter().cloned());
+        }
+        alpns
+    }
+
+    async fn dial_quic(
+        &self,
+        addr: EndpointAddr,
+        alpns: Vec<Vec<u8>>,
+    ) -> Result<Session, ClientError> {
+        let pooled = self
+            .pool
+            .as_ref()
+            .and_then(|p| alpns.iter().find_map(|alpn| p.take_quic(addr.id, alpn)));
+        if let Some(session) = pooled {
+            tracing::debug!(remote = %addr.id.fmt_short(), "reusing pooled session");
+            return Ok(session);
+        }
+        self.retry(|| self.require_path(self.connect_quic_once(addr.clone(), &alpns)))
+            .await
+    }
+
+    async fn connect_quic_once(
+        &self,
+        addr: EndpointAddr,
+        alpns: &[Vec<u8>],
+    ) -> Result<Session, ClientError> {
+        let (alpn, additional) = alpns.split_first().expect("at least one ALPN");
+        let deadline = self.deadline();
+        let mut connecting = with_deadline(
+            deadline,
+            self.connecting_with_alpns(addr, alpn, additional.to_vec()),
+        )
+        .await??;
+        if self.zero_rtt {
+            match connecting.into_0rtt() {
+                // There is no handshake to send early, but report whether 0-RTT was accepted.
+                Ok(conn) => {
+                    let status = with_deadline(deadline, conn.handshake_completed())
+                        .await?
+                        .map_err(|err| ClientError::Connect(Arc::new(err.into())))?;
+                    return Ok(match status {
+                        ZeroRttStatus::Accepted(conn) => Session::raw(conn).with_0rtt(),
+                        ZeroRttStatus::Rejected(conn) => Session::raw(conn),
+                    }
+                    .with_spawner(self.spawner.clone()));
+                }
+                Err(fallback) => connecting = fallback,
+            }
+        }
+        let conn = with_deadline(deadline, connecting)
+            .await?
+            .map_err(|err| ClientError::Connect(Arc::new(err.into())))?;
+        Ok(Session::raw(conn).with_spawner(self.spawner.clone()))
     }
 
     /// Connect with a full HTTP/3 handshake and WebTransport semantics.
     ///
     /// Note that the url needs to have a `https:` scheme, otherwise the accepting side will
     /// fail to accept the connection.
+    ///
+    /// Pass a [`ConnectRequestBuilder`](crate::ConnectRequestBuilder) instead of a URL to offer
+    /// subprotocols, see [`Session::protocol`], or to send headers such as `authorization`.
     pub async fn connect_h3(
         &self,
         addr: impl Into<EndpointAddr>,
-        url: Url,
+        request: impl Into<ConnectRequest>,
     ) -> Result<Session, ClientError> {
-        let conn = self.connect(addr, ALPN_H3.as_bytes()).await?;
-        // Connect with the connection we established.
-        Session::connect_h3(conn, url).await
+        self.bounded(None, self.dial_h3(addr.into(), request.into()))
+            .await
     }
 
-    async fn connect(
+    /// Connect with a full HTTP/3 handshake, until the token is cancelled.
+    ///
+    /// Cancelling aborts the attempt and fails with [`ClientError::Cancelled`].
+    pub async fn connect_h3_with_cancel(
         &self,
         addr: impl Into<EndpointAddr>,
-        alpn: &[u8],
-    ) -> Result<iroh::endpoint::Connection, ClientError> {
-        let opts = ConnectOptions::new().with_transport_config(self.config.clone());
-        let conn = self
-            .endpoint
-            .connect_with_opts(addr, alpn, opts)
+        request: impl Into<ConnectRequest>,
+        cancel: &CancellationToken,
+    ) -> Result<Session, ClientError> {
+        self.bounded(Some(cancel), self.dial_h3(addr.into(), request.into()))
             .await
+    }
+
+    async fn dial_h3(
+        &self,
+        addr: EndpointAddr,
+        request: ConnectRequest,
+    ) -> Result<Session, ClientError> {
+        let request = self.prepare(&addr, request)?;
+        if let Some(session) = self
+            .pool
+            .as_ref()
+            .and_then(|p| p.take_h3(addr.id, &request))
+        {
+            tracing::debug!(remote = %addr.id.fmt_short(), "reusing pooled session");
+            return Ok(session);
+        }
+        self.retry(|| self.require_path(self.connect_h3_once(addr.clone(), request.clone())))
+            .await
+    }
+
+    /// Establishes the QUIC connection for an HTTP/3 session, without sending the CONNECT request.
+    ///
+    /// This lets callers inspect the connection, e.g. its path type or RTT, before deciding to
+    /// continue with [`Dialed::handshake`] or to [`Dialed::abort`]. Each stage is bounded by the
+    /// handshake timeout. Pooled sessions, 0-RTT, retries and the connect timeout aren't used.
+    pub async fn connect_h3_staged(
+        &self,
+        addr: impl Into<EndpointAddr>,
+    ) -> Result<Dialed<'_>, ClientError> {
+        let addr = addr.into();
+        let deadline = self.deadline();
+        let connecting =
+            with_deadline(deadline, self.connecting(addr.clone(), ALPN_H3.as_bytes())).await??;
+        let conn = with_deadline(deadline, connecting)
+            .await?
             .map_err(|err| ClientError::Connect(Arc::new(err.into())))?;
-        let conn = conn
+        Ok(Dialed {
+            client: self,
+            addr,
+            conn,
+        })
+    }
+
+    /// Merges the default headers into the request and runs the request hooks.
+    fn prepare(
+        &self,
+        addr: &EndpointAddr,
+        mut request: ConnectRequest,
+    ) -> Result<ConnectRequest, ClientError> {
+        for (name, value) in &self.headers {
+            if !request.headers.contains_key(name) {
+                request.headers.insert(name, value.clone());
+            }
+        }
+        for hook in &self.hooks.0 {
+            hook(addr, &mut request).map_err(ClientError::Vetoed)?;
+        }
+        Ok(request)
+    }
+
+    /// Connects with HTTP/3 if the server supports it, otherwise with the raw QUIC ALPN.
+    ///
+    /// Both ALPNs are offered in the same QUIC handshake, so falling back doesn't take another
+    /// round trip. If the server supports both, its preference decides. Check
+    /// [`Session::request`] to tell which one was used. This eases migrating servers between
+    /// the two modes. 0-RTT isn't used for these connects.
+    pub async fn connect_auto(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        request: impl Into<ConnectRequest>,
+        raw_alpn: &[u8],
+    ) -> Result<Session, ClientError> {
+        self.bounded(None, self.dial_auto(addr.into(), request.into(), raw_alpn))
             .await
+    }
+
+    async fn dial_auto(
+        &self,
+        addr: EndpointAddr,
+        request: ConnectRequest,
+        raw_alpn: &[u8],
+    ) -> Result<Session, ClientError> {
+        let request = self.prepare(&addr, request)?;
+        let pooled = self.pool.as_ref().and_then(|p| {
+            p.take_h3(addr.id, &request)
+                .or_else(|| p.take_quic(addr.id, raw_alpn))
+        });
+        if let Some(session) = pooled {
+            tracing::debug!(remote = %addr.id.fmt_short(), "reusing pooled session");
+            return Ok(session);
+        }
+        self.retry(|| {
+            self.require_path(self.connect_auto_once(addr.clone(), request.clone(), raw_alpn))
+        })
+        .await
+    }
+
+    async fn connect_auto_once(
+        &self,
+        addr: EndpointAddr,
+        request: ConnectRequest,
+        raw_alpn: &[u8],
+    ) -> Result<Session, ClientError> {
+        let deadline = self.deadline();
+        let alpns = vec![raw_alpn.to_vec()];
+        let connecting = with_deadline(
+            deadline,
+            self.connecting_with_alpns(addr, ALPN_H3.as_bytes(), alpns),
+        )
+        .await??;
+        let conn = with_deadline(deadline, connecting)
+            .await?
             .map_err(|err| ClientError::Connect(Arc::new(err.into())))?;
-        Ok(conn)
+        if conn.alpn() == ALPN_H3.as_bytes() {
+            self.handshake_h3(conn, request, deadline).await
+        } else {
+            tracing::debug!(remote = %conn.remote_id().fmt_short(), "server only supports raw QUIC");
+            Ok(Session::raw(conn).with_spawner(self.spawner.clone()))
+        }
     }
 
-    /// Close the client endpoint.
+    /// Connects to the endpoint named by the host of an `https:` URL, with a full HTTP/3 handshake.
+    ///
+    /// The host is either an endpoint id or a domain name, which is resolved to an endpoint
+    /// by the resolvers added with [`Self::with_resolver`], falling back to the `_iroh` TXT
+    /// record published by iroh's DNS address lookup. The CONNECT request is sent to the
+    /// original URL.
+    ///
+    /// The query parameters `relay` and `addr` are used as the relay URL and direct socket
+    /// addresses of the endpoint, so no discovery is needed, e.g.
+    /// `https://<endpoint-id>/path?relay=https://relay.example&addr=192.0.2.1:4433`.
+    /// Both can be repeated. They are removed from the URL sent in the CONNECT request.
+    pub async fn connect_url(&self, mut url: Url) -> Result<Session, ClientError> {
+        let host = url.host_str().ok_or(ClientError::InvalidUrl)?;
+        let addr = match host.parse::<EndpointId>() {
+            Ok(id) => EndpointAddr::new(id),
+            Err(_) => self.resolve(host).await?,
+        };
+        let addr = take_hints(&mut url, addr)?;
+        self.connect_h3(addr, url).await
+    }
+
+    /// Looks up the endpoint for a domain name, trying each resolver in turn before DNS.
+    async fn resolve(&self, host: &str) -> Result<EndpointAddr, ClientError> {
+        let dns = self.endpoint.dns_resolver();
+        let resolvers = self
+            .resolvers
+            .0
+            .iter()
+            .map(|resolver| resolver.as_ref())
+            .chain([dns as &dyn Resolve]);
+        for resolver in resolvers {
+            let addr = resolver
+                .resolve(host)
+                .await
+                .map_err(|err| ClientError::Resolve(Arc::new(err)))?;
+            if let Some(addr) = addr {
+                tracing::debug!(%host, remote = %addr.id.fmt_short(), "resolved endpoint");
+                return Ok(addr);
+            }
+        }
+        Err(ClientError::Resolve(Arc::new(AnyError::from_display(
+            format!("no endpoint found for {host}"),
+        ))))
+    }
+
+    /// Connects to the endpoint of an iroh [`EndpointTicket`], with a full HTTP/3 handshake.
+    ///
+    /// The ticket may be followed by the path of the CONNECT request and a subprotocol to offer,
+    /// e.g. `<ticket>/chat#v1`. The path defaults to `/`. Requires the `tickets` feature.
+    #[cfg(feature = "tickets")]
+    pub async fn connect_ticket(&self, ticket: &str) -> Result<Session, ClientError> {
+        let (ticket, protocol) = match ticket.split_once('#') {
+            Some((ticket, protocol)) => (ticket, Some(protocol)),
+            None => (ticket, None),
+        };
+        let (ticket, path) = match ticket.split_once('/') {
+            Some((ticket, path)) => (ticket, path),
+            None => (ticket, ""),
+        };
+        let ticket: EndpointTicket = ticket
+            .parse()
+            .map_err(|err| ClientError::InvalidTicket(Arc::new(err)))?;
+        let addr = EndpointAddr::from(ticket);
+        let url: Url = format!("https://{}/{path}", addr.id)
+            .parse()
+            .map_err(|_| ClientError::InvalidUrl)?;
+        let mut request = crate::ConnectRequestBuilder::new(url);
+        if let Some(protocol) = protocol {
+            request = request.with_protocol(protocol);
+        }
+        self.connect_h3(addr, request).await
+    }
+
+    /// Dials all of the candidates at once with a full HTTP/3 handshake, returning the first
+    /// session established.
+    ///
+    /// The candidates are distinct servers of a load-balanced service. Candidates with the
+    /// same [`EndpointId`] are dialed once, with their addresses merged. Sessions established
+    /// after the first are closed and the other attempts are aborted. If all attempts fail,
+    /// the last error is returned. Unlike [`Self::connect_h3_any`], this ignores
+    /// [`Self::with_stagger`].
+    pub async fn connect_any(
+        &self,
+        addrs: impl IntoIterator<Item = impl Into<EndpointAddr>>,
+        request: impl Into<ConnectRequest>,
+    ) -> Result<Session, ClientError> {
+        let request = request.into();
+        let attempts = candidates(addrs)
+            .into_iter()
+            .map(|addr| self.connect_h3(addr, request.clone()));
+        self.race(attempts, Duration::ZERO).await
+    }
+
+    /// Connects to whichever of the candidates answers first, without HTTP/3.
+    ///
+    /// Candidates with the same [`EndpointId`] are dialed once, with their addresses merged.
+    /// All of them are dialed at once, unless a delay is set with [`Self::with_stagger`]: then
+    /// attempts are started in order, each after the previous one failed or didn't succeed
+    /// within the delay. The first session established wins and the other attempts are
+    /// aborted. If all attempts fail, the last error is returned.
+    pub async fn connect_quic_any(
+        &self,
+        addrs: impl IntoIterator<Item = impl Into<EndpointAddr>>,
+        alpn: &[u8],
+    ) -> Result<Session, ClientError> {
+        let attempts = candidates(addrs)
+            .into_iter()
+            .map(|addr| self.connect_quic(addr, alpn));
+        self.race(attempts, self.stagger).await
+    }
+
+    /// Connects to whichever of the candidates answers first, with a full HTTP/3 handshake.
+    ///
+    /// See [`Self::connect_quic_any`] for how the attempts are raced.
+    pub async fn connect_h3_any(
+        &self,
+        addrs: impl IntoIterator<Item = impl Into<EndpointAddr>>,
+        request: impl Into<ConnectRequest>,
+    ) -> Result<Session, ClientError> {
+        let request = request.into();
+        let attempts = candidates(addrs)
+            .into_iter()
+            .map(|addr| self.connect_h3(addr, request.clone()));
+        self.race(attempts, self.stagger).await
+    }
+
+    async fn connect_h3_once(
+        &self,
+        addr: EndpointAddr,
+        request: ConnectRequest,
+    ) -> Result<Session, ClientError> {
+        let deadline = self.deadline();
+        let mut connecting =
+            with_deadline(deadline, self.connecting(addr, ALPN_H3.as_bytes())).await??;
+        if self.zero_rtt {
+            match connecting.into_0rtt() {
+                Ok(conn) => return self.connect_h3_0rtt(conn, request, deadline).await,
+                Err(fallback) => connecting = fallback,
+            }
+        }
+        let conn = with_deadline(deadline, connecting)
+            .await?
+            .map_err(|err| ClientError::Connect(Arc::new(err.into())))?;
+        self.handshake_h3(conn, request, deadline).await
+    }
+
+    /// Bounds a connect by the connect timeout and the cancellation token, if any.
+    async fn bounded(
+        &self,
+        cancel: Option<&CancellationToken>,
+        connect: impl Future<Output = Result<Session, ClientError>>,
+    ) -> Result<Session, ClientError> {
+        let deadline = self.connect_timeout.map(|timeout| Instant::now() + timeout);
+        let connect = with_deadline(deadline, connect);
+        match cancel {
+            Some(cancel) => cancel
+                .run_until_cancelled(connect)
+                .await
+                .ok_or(ClientError::Cancelled)??,
+            None => connect.await?,
+        }
+    }
+
+    /// Fails the connect attempt if the session doesn't use the path required by the [`PathMode`].
+    async fn require_path(
+        &self,
+        connect: impl Future<Output = Result<Session, ClientError>>,
+    ) -> Result<Session, ClientError> {
+        let mode = self.path_mode;
+        if mode == PathMode::Relay && self.endpoint.addr().relay_urls().next().is_none() {
+            return Err(ClientError::PathUnavailable(mode));
+        }
+        let session = connect.await?;
+        if !mode.wait(session.conn()).await {
+            session.close(0, b"path unavailable");
+            return Err(ClientError::PathUnavailable(mode));
+        }
+        Ok(session)
+    }
+
+    /// Runs the connect attempt until it succeeds or the [`RetryPolicy`] gives up.
+    async fn retry<F: Future<Output = Result<Session, ClientError>>>(
+        &self,
+        connect: impl Fn() -> F,
+    ) -> Result<Session, ClientError> {
+        let mut attempt = 1;
+        loop {
+            let err = match connect().await {
+                Ok(session) => return Ok(session),
+                Err(err) => err,
+            };
+            let Some(policy) = &self.retry else {
+                return Err(err);
+            };
+            if attempt >= policy.max_attempts || !policy.retry_on.contains(&self.diagnose(&err)) {
+                return Err(err);
+            }
+            let backoff = policy.backoff(attempt);
+            tracing::debug!(attempt, ?backoff, "failed to connect, retrying: {err:#}");
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    /// Runs the attempts with a staggered start and returns the first session established.
+    ///
+    /// With a zero stagger, all attempts are started at once.
+    async fn race<F: Future<Output = Result<Session, ClientError>>>(
+        &self,
+        attempts: impl Iterator<Item = F>,
+        stagger: Duration,
+    ) -> Result<Session, ClientError> {
+        let mut attempts = attempts.peekable();
+        let mut running = FuturesUnordered::new();
+        let mut last_err = None;
+        loop {
+            if stagger.is_zero() {
+                for attempt in attempts.by_ref() {
+                    running.push(attempt);
+                }
+            }
+            if running.is_empty() {
+                match attempts.next() {
+                    Some(attempt) => running.push(attempt),
+                    None => return Err(last_err.unwrap_or(ClientError::NoAddresses)),
+                }
+            }
+            let more = attempts.peek().is_some();
+            tokio::select! {
+                Some(result) = running.next() => match result {
+                    Ok(session) => {
+                        // Close the attempts that succeeded at the same time, the others are aborted when dropped.
+                        while let Some(Some(result)) =
+                            n0_future::future::poll_once(running.next()).await
+                        {
+                            if let Ok(other) = result {
+                                other.close(0, b"lost race");
+                            }
+                        }
+                        return Ok(session);
+                    }
+                    Err(err) => {
+                        tracing::debug!("connect attempt failed: {err:#}");
+                        last_err = Some(err);
+                        if let Some(attempt) = attempts.next() {
+                            running.push(attempt);
+                        }
+                    }
+                },
+                _ = tokio::time::sleep(stagger), if more => {
+                    if let Some(attempt) = attempts.next() {
+                        running.push(attempt);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the HTTP/3 handshake in 0-RTT data, retrying it if the server rejects the data.
+    async fn connect_h3_0rtt(
+        &self,
+        conn: endpoint::OutgoingZeroRttConnection,
+        request: ConnectRequest,
+        deadline: Option<Instant>,
+    ) -> Result<Session, ClientError> {
+        // Don't wait for the server's SETTINGS before sending the CONNECT request,
+        // the server already advertised WebTransport support on the previous connection.
+        let options = &self.options;
+        let early = async {
+            let settings = async {
+                Ok::<_, ClientError>(Settings::connect_with_options(&conn, options).await?)
+            };
+            let connect = async {
+                Ok::<_, ClientError>(
+                    Connected::open_with_options(&conn, request.clone(), options).await?,
+                )
+            };
+            tokio::try_join!(settings, connect)
+        };
+        let mut early = Box::pin(early);
+
+        // Keep driving the early handshake until we know whether the server accepted it.
+        let mut result = None;
+        let status = with_deadline(deadline, async {
+            loop {
+                tokio::select! {
+                    status = conn.handshake_completed() => break status,
+                    res = &mut early, if result.is_none() => result = Some(res),
+                }
+            }
+        })
+        .await;
+        let status = match status {
+            Ok(status) => status.map_err(|err| ClientError::Connect(Arc::new(err.into())))?,
+            Err(err) => {
+                conn.close(H3_REQUEST_CANCELLED.into(), b"handshake timeout");
+                return Err(err);
+            }
+        };
+
+        match status {
+            ZeroRttStatus::Accepted(conn) => {
+                let early = match result {
+                    Some(result) => result,
+                    None => match with_deadline(deadline, early).await {
+                        Ok(result) => result,
+                        Err(err) => {
+                            conn.close(H3_REQUEST_CANCELLED.into(), b"handshake timeout");
+                            return Err(err);
+                        }
+                    },
+                };
+                let (settings, connect) = early?;
+                let spawner = self.spawner.clone();
+                Ok(Session::new_h3_spawned(conn, settings, connect, None, spawner).with_0rtt())
+            }
+            ZeroRttStatus::Rejected(conn) => {
+                // The streams opened in 0-RTT data were reset, so start over.
+                drop(early);
+                tracing::debug!("0-RTT rejected, retrying the handshake");
+                self.handshake_h3(conn, request, deadline).await
+            }
+        }
+    }
+
+    async fn handshake_h3(
+        &self,
+        conn: endpoint::Connection,
+        request: ConnectRequest,
+        deadline: Option<Instant>,
+    ) -> Result<Session, ClientError> {
+        // Connect with the connection we established.
+        let handshake =
+            Session::connect_h3_spawned(conn.clone(), request, &self.options, &self.spawner);
+        let Some(deadline) = deadline else {
+            return handshake.await;
+        };
+        match tokio::time::timeout_at(deadline, handshake).await {
+            Ok(result) => result,
+            Err(_) => {
+                conn.close(H3_REQUEST_CANCELLED.into(), b"handshake timeout");
+                Err(ClientError::HandshakeTimeout)
+            }
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.handshake_timeout
+            .map(|timeout| Instant::now() + timeout)
+    }
+
+    async fn connecting(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        alpn: &[u8],
+    ) -> Result<Connecting, ClientError> {
+        self.connecting_with_alpns(addr, alpn, Vec::new()).await
+    }
+
+    async fn connecting_with_alpns(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        alpn: &[u8],
+        alpns: Vec<Vec<u8>>,
+    ) -> Result<Connecting, ClientError> {
+        let addr = self.path_mode.filter(addr.into());
+        let mut opts = ConnectOptions::new().with_transport_config(self.config.clone());
+        if !alpns.is_empty() {
+            opts = opts.with_additional_alpns(alpns);
+        }
+        self.endpoint
+            .connect_with_opts(addr, alpn, opts)
+            .await
+            .map_err(|err| ClientError::Connect(Arc::new(err.into())))
+    }
+
+    /// Classifies an error returned by this client, like [`ClientError::kind`].
+    ///
+    /// Timeouts are reported as [`ErrorKind::RelayUnreachable`] if the endpoint isn't
+    /// connected to a relay, since peers behind a NAT can usually only be reached through one.
+    pub fn diagnose(&self, err: &ClientError) -> ErrorKind {
+        let kind = err.kind();
+        if kind == ErrorKind::TimedOut && self.endpoint.addr().relay_urls().next().is_none() {
+            return ErrorKind::RelayUnreachable;
+        }
+        kind
+    }
+
+    /// Close the client endpoint, including any pooled sessions.
     pub async fn close(&self) {
+        if let Some(pool) = &self.pool {
+            pool.clear();
+        }
         self.endpoint.close().await;
     }
 }
+
+/// A QUIC connection established by [`Client::connect_h3_staged`], before the HTTP/3 handshake.
+///
+/// Dropping it closes the connection.
+#[derive(Debug)]
+pub struct Dialed<'a> {
+    client: &'a Client,
+    addr: EndpointAddr,
+    conn: endpoint::Connection,
+}
+
+impl Dialed<'_> {
+    /// Returns the QUIC connection, e.g. to check [`endpoint::Connection::remote_id`] or its paths.
+    pub fn conn(&self) -> &endpoint::Connection {
+        &self.conn
+    }
+
+    /// Returns the round-trip time of the selected path, if known yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        path::rtt(&self.conn)
+    }
+
+    /// Returns true if the connection currently goes through a relay.
+    pub fn is_relay(&self) -> bool {
+        path::is_relay(&self.conn)
+    }
+
+    /// Sends the CONNECT request and waits for the response, like [`Client::connect_h3`].
+    ///
+    /// Default headers and request hooks are applied to the request.
+    pub async fn handshake(
+        self,
+        request: impl Into<ConnectRequest>,
+    ) -> Result<Session, ClientError> {
+        let request = match self.client.prepare(&self.addr, request.into()) {
+            Ok(request) => request,
+            Err(err) => {
+                self.abort(b"vetoed");
+                return Err(err);
+            }
+        };
+        let deadline = self.client.deadline();
+        self.client.handshake_h3(self.conn, request, deadline).await
+    }
+
+    /// Closes the connection without sending the CONNECT request.
+    pub fn abort(self, reason: &[u8]) {
+        self.conn.close(H3_REQUEST_CANCELLED.into(), reason);
+    }
+}
+
+/// Builds a [`Client`] with a custom configuration, see [`Client::builder`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    tuning: TransportTuning,
+    transport: Option<QuicTransportConfigBuilder>,
+    keep_alive_interval: Option<Duration>,
+    // None keeps the timeout of the tuning or transport config.
+    max_idle_timeout: Option<Option<Duration>>,
+    handshake_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    options: HandshakeOptions,
+    alpns: Vec<Vec<u8>>,
+    headers: HeaderMap,
+    hooks: RequestHooks,
+    resolvers: Resolvers,
+    zero_rtt: bool,
+    pool: Option<PoolConfig>,
+    retry: Option<RetryPolicy>,
+    stagger: Duration,
+    path_mode: PathMode,
+    // Starts the drivers and other background tasks of sessions.
+    spawner: Spawner,
+}
+
+impl ClientBuilder {
+    /// Returns a builder with the default [`TransportTuning`].
+    pub fn new() -> Self {
+        let tuning = TransportTuning::default();
+        Self {
+            tuning,
+            transport: None,
+            keep_alive_interval: None,
+            max_idle_timeout: None,
+            handshake_timeout: tuning.handshake_timeout,
+            connect_timeout: None,
+            options: HandshakeOptions::default(),
+            alpns: Vec::new(),
+            headers: HeaderMap::new(),
+            hooks: RequestHooks::default(),
+            resolvers: Resolvers::default(),
+            zero_rtt: false,
+            pool: None,
+            retry: None,
+            stagger: Duration::ZERO,
+            path_mode: PathMode::Any,
+            spawner: Spawner::default(),
+        }
+    }
+
+    /// Sets the handshake timings, including the handshake timeout.
+    ///
+    /// They are applied to the transport config unless one is set with [`Self::with_transport_config`].
+    pub fn with_tuning(mut self, tuning: TransportTuning) -> Self {
+        self.tuning = tuning;
+        self.handshake_timeout = tuning.handshake_timeout;
+        self
+    }
+
+    /// Sets the transport config, replacing the defaults of the [`TransportTuning`].
+    pub fn with_transport_config(mut self, config: QuicTransportConfigBuilder) -> Self {
+        self.transport = Some(config);
+        self
+    }
+
+    /// Sends keep-alive packets at the given interval, so idle connections aren't closed.
+    ///
+    /// Defaults to [`TransportTuning::keep_alive_interval`], and overrides the transport config.
+    pub fn with_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long a connection may be idle before it is closed, or None to never close it.
+    ///
+    /// Defaults to [`TransportTuning::max_idle_timeout`], and overrides the transport config.
+    pub fn with_max_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.max_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for the handshake, or None to only rely on the idle timeout.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Bounds each connect call as a whole, see [`Client::with_connect_timeout`].
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how strictly the protocol is enforced for HTTP/3 sessions. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.options.strictness = strictness;
+        self
+    }
+
+    /// Limits the size of the response headers for HTTP/3 sessions, see [`Client::with_max_field_section_size`].
+    pub fn with_max_field_section_size(mut self, size: u64) -> Self {
+        self.options.max_field_section_size = Some(size);
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent, see [`Client::with_settings_profile`].
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    /// Sets all options of HTTP/3 handshakes at once, see [`HandshakeOptions`].
+    pub fn with_handshake_options(mut self, options: HandshakeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Starts the background tasks of sessions with the spawner, see [`Client::with_spawner`].
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Offers additional ALPNs in [`Client::connect_quic`], after the one passed to it.
+    ///
+    /// The server picks the first one it supports, see [`iroh::endpoint::Connection::alpn`].
+    /// Use [`Client::connect_quic_preferred`] to pick the ALPNs per connect instead.
+    pub fn with_alpns(mut self, alpns: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        self.alpns = alpns.into_iter().collect();
+        self
+    }
+
+    /// Sends a header with every CONNECT request, unless the request already sets it.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sends the headers with every CONNECT request, unless the request already sets them.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Sends an affinity key with every CONNECT request, see [`ConnectRequestBuilder::with_affinity_key`](crate::ConnectRequestBuilder::with_affinity_key).
+    pub fn with_affinity_key(self, key: HeaderValue) -> Self {
+        self.with_header(AFFINITY_KEY, key)
+    }
+
+    /// Runs a hook on every CONNECT request before it is sent, see [`Client::with_request_hook`].
+    pub fn with_request_hook(
+        mut self,
+        hook: impl Fn(&EndpointAddr, &mut ConnectRequest) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.0.push(Arc::new(hook));
+        self
+    }
+
+    /// Resolves domain names before falling back to DNS, see [`Client::with_resolver`].
+    pub fn with_resolver(mut self, resolver: impl Resolve) -> Self {
+        self.resolvers.0.push(Arc::new(resolver));
+        self
+    }
+
+    /// Sends the handshake in 0-RTT data when possible, see [`Client::with_0rtt`].
+    pub fn with_0rtt(mut self, enabled: bool) -> Self {
+        self.zero_rtt = enabled;
+        self
+    }
+
+    /// Keeps released sessions around for reuse, see [`Client::with_pool`].
+    pub fn with_pool(mut self, config: PoolConfig) -> Self {
+        self.pool = Some(config);
+        self
+    }
+
+    /// Requires a relayed or direct path, see [`Client::with_path_mode`].
+    pub fn with_path_mode(mut self, mode: PathMode) -> Self {
+        self.path_mode = mode;
+        self
+    }
+
+    /// Retries failed connects, see [`Client::with_retry`].
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets the delay between racing connection attempts, see [`Client::with_stagger`].
+    pub fn with_stagger(mut self, delay: Duration) -> Self {
+        self.stagger = delay;
+        self
+    }
+
+    /// Creates the client from an endpoint.
+    pub fn build(self, endpoint: Endpoint) -> Client {
+        let mut transport = self
+            .transport
+            .unwrap_or_else(|| self.tuning.apply(QuicTransportConfig::builder()));
+        if let Some(interval) = self.keep_alive_interval {
+            transport = transport.keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.max_idle_timeout {
+            transport = transport.max_idle_timeout(idle_timeout(timeout));
+        }
+
+        Client {
+            endpoint,
+            config: transport.build(),
+            options: self.options,
+            handshake_timeout: self.handshake_timeout,
+            connect_timeout: self.connect_timeout,
+            alpns: self.alpns,
+            headers: self.headers,
+            hooks: self.hooks,
+            resolvers: self.resolvers,
+            zero_rtt: self.zero_rtt,
+            pool: self.pool.map(Pool::new),
+            retry: self.retry,
+            stagger: self.stagger,
+            path_mode: self.path_mode,
+            spawner: self.spawner,
+        }
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the candidates in order, merging the addresses of candidates with the same id.
+fn candidates(addrs: impl IntoIterator<Item = impl Into<EndpointAddr>>) -> Vec<EndpointAddr> {
+    let mut candidates: Vec<EndpointAddr> = Vec::new();
+    for addr in addrs {
+        let addr = addr.into();
+        match candidates.iter_mut().find(|c| c.id == addr.id) {
+            Some(existing) => existing.addrs.extend(addr.addrs),
+            None => candidates.push(addr),
+        }
+    }
+    candidates
+}
+
+/// Removes the relay and address hints from the URL, adding them to the endpoint address.
+fn take_hints(url: &mut Url, mut addr: EndpointAddr) -> Result<EndpointAddr, ClientError> {
+    let mut query = Vec::new();
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "relay" => {
+                let relay: RelayUrl = value.parse().map_err(|_| ClientError::InvalidUrl)?;
+                addr = addr.with_relay_url(relay);
+            }
+            "addr" => {
+                let ip: SocketAddr = value.parse().map_err(|_| ClientError::InvalidUrl)?;
+                addr = addr.with_ip_addr(ip);
+            }
+            _ => query.push((key.into_owned(), value.into_owned())),
+        }
+    }
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    Ok(addr)
+}
+
+/// Runs the future until the deadline, if any.
+async fn with_deadline<F: Future>(
+    deadline: Option<Instant>,
+    future: F,
+) -> Result<F::Output, ClientError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| ClientError::HandshakeTimeout),
+        None => Ok(future.await),
+    }
+}
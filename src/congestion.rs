@@ -0,0 +1,91 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use iroh::endpoint::{Connection, ConnectionInfo};
+use tokio::sync::watch;
+
+use crate::Spawner;
+
+// Bounds for how often the path is sampled, which is once per round trip.
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+// Tracks whether the connection recently hit congestion, once anyone asks.
+#[derive(Debug)]
+pub(crate) struct Congestion {
+    started: AtomicBool,
+    state: watch::Sender<bool>,
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            state: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Congestion {
+    pub(crate) fn is_congested(self: &Arc<Self>, conn: &Connection, spawner: &Spawner) -> bool {
+        self.start(conn, spawner);
+        *self.state.borrow()
+    }
+
+    pub(crate) fn subscribe(
+        self: &Arc<Self>,
+        conn: &Connection,
+        spawner: &Spawner,
+    ) -> watch::Receiver<bool> {
+        self.start(conn, spawner);
+        self.state.subscribe()
+    }
+
+    fn start(self: &Arc<Self>, conn: &Connection, spawner: &Spawner) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let this = self.clone();
+        // Only hold a weak handle, so sampling doesn't keep the connection alive.
+        let info = conn.to_info();
+        spawner.spawn(async move { this.run(info).await });
+    }
+
+    async fn run(&self, conn: ConnectionInfo) {
+        let mut last = congestion_events(&conn).unwrap_or_default();
+        loop {
+            let interval = conn
+                .selected_path()
+                .map(|path| path.rtt())
+                .unwrap_or(MAX_INTERVAL)
+                .clamp(MIN_INTERVAL, MAX_INTERVAL);
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = conn.closed() => break,
+            }
+
+            // Congested while the congestion controller reacted within the last round trip.
+            // The counter restarts when another path is selected.
+            let Some(events) = congestion_events(&conn) else {
+                continue;
+            };
+            let congested = events > last;
+            last = events;
+            self.state.send_if_modified(|state| {
+                let changed = *state != congested;
+                *state = congested;
+                changed
+            });
+        }
+        self.state.send_replace(false);
+    }
+}
+
+fn congestion_events(conn: &ConnectionInfo) -> Option<u64> {
+    Some(conn.selected_path()?.stats().congestion_events)
+}
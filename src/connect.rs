@@ -1,8 +1,26 @@
-use std::ops::Deref;
+use std::{ops::Deref, sync::Arc, time::Duration};
 
+use bytes::{Bytes, BytesMut};
+use http::HeaderMap;
 use iroh::endpoint::{self, Connection, RecvStream, SendStream};
 use n0_error::stack_error;
-use web_transport_proto::{ConnectRequest, ConnectResponse, VarInt};
+use web_transport_proto::{ConnectRequest, ConnectResponse, Frame, VarInt};
+
+use crate::{
+    HandshakeOptions, Strictness,
+    headers::{
+        AVAILABLE_PROTOCOLS, SELECTED_PROTOCOL, encode_protocol, encode_protocols, encode_response,
+        read_body, read_request, read_response,
+    },
+    panic_policy::unexpected,
+    request::request_uri,
+};
+
+/// How long a rejection waits for the client to close the CONNECT stream or the connection.
+///
+/// Closing the connection right after sending the response could discard it before the client
+/// read it, so [`Connecting::reject`] keeps it open until then, but no longer than this.
+pub const REJECT_LINGER: Duration = Duration::from_secs(2);
 
 /// An error during the HTTP/3 CONNECT handshake.
 #[derive(Clone)]
@@ -26,8 +44,20 @@ pub enum ConnectError {
     #[error("http error status: {_0}")]
     ErrorStatus(http::StatusCode),
 
+    #[error("rejected with status: {}", _0.status())]
+    Rejected(Arc<http::Response<Bytes>>),
+
     #[error("server returned protocol not in request: {_0}")]
     ProtocolMismatch(String),
+
+    #[error("field section of {size} bytes exceeds the limit of {max} bytes")]
+    FieldSectionTooLarge { size: u64, max: u64 },
+
+    #[error("request target is not a valid uri: {_0}")]
+    InvalidUri(String),
+
+    #[error("invalid QPACK field section")]
+    InvalidFieldSection,
 }
 
 /// An in-progress HTTP/3 CONNECT handshake, awaiting a response.
@@ -36,51 +66,126 @@ pub struct Connecting {
     // The request that was sent by the client.
     request: ConnectRequest,
 
+    // The request target, checked when the request was received.
+    uri: http::Uri,
+
     // A reference to the send/recv stream, so we don't close it until dropped.
     send: SendStream,
 
     #[allow(dead_code)]
     recv: RecvStream,
+
+    // How strictly the response is validated.
+    strictness: Strictness,
 }
 
 impl Connecting {
     /// Accepts an incoming HTTP/3 CONNECT request from the client.
     pub async fn accept(conn: &Connection) -> Result<Self, ConnectError> {
+        Self::accept_with_options(conn, &HandshakeOptions::default()).await
+    }
+
+    /// Accepts an incoming HTTP/3 CONNECT request, with the strictness and header size limit
+    /// of the [`HandshakeOptions`].
+    ///
+    /// Requests larger than [`HandshakeOptions::max_field_section_size`] are rejected with a
+    /// 431 status and [`ConnectError::FieldSectionTooLarge`].
+    pub async fn accept_with_options(
+        conn: &Connection,
+        options: &HandshakeOptions,
+    ) -> Result<Self, ConnectError> {
+        let max_field_section_size = options.max_field_section_size;
+
         // Accept the stream that will be used to send the HTTP CONNECT request.
         // If they try to send any other type of HTTP request, we will error out.
-        let (send, mut recv) = conn.accept_bi().await?;
-
-        let request = web_transport_proto::ConnectRequest::read(&mut recv).await?;
-        tracing::debug!("received CONNECT request: {request:?}");
+        let (mut send, mut recv) = conn.accept_bi().await?;
+
+        let request = match read_request(&mut recv, max_field_section_size).await {
+            Ok(request) => request,
+            Err(err @ ConnectError::FieldSectionTooLarge { .. }) => {
+                tracing::debug!("rejecting CONNECT request: {err:#}");
+                let status = http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+                let mut buf = BytesMut::new();
+                encode_response(&ConnectResponse::new(status), &HeaderMap::new(), &mut buf)?;
+                send.write_all(&buf).await.ok();
+                send.finish().ok();
+                recv.stop(0u32.into()).ok();
+                linger(&mut send).await;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        tracing::debug!(url = %request.url, protocols = ?request.protocols, "received CONNECT request");
+        let uri = request_uri(&request.url)?;
 
         // The request was successfully decoded, so we can send a response.
         Ok(Self {
             request,
+            uri,
             send,
             recv,
+            strictness: options.strictness,
         })
     }
 
+    /// Returns the request headers sent by the client, excluding pseudo-headers.
+    pub fn headers(&self) -> &http::HeaderMap {
+        &self.request.headers
+    }
+
+    /// Returns the request headers mutably, e.g. to normalize them before handling the request.
+    pub fn headers_mut(&mut self) -> &mut http::HeaderMap {
+        &mut self.request.headers
+    }
+
+    /// Returns the request target from the `:scheme`, `:authority` and `:path` pseudo-headers.
+    ///
+    /// The method is always `CONNECT`, which is checked when decoding the request.
+    pub fn uri(&self) -> http::Uri {
+        self.uri.clone()
+    }
+
+    /// Sets how strictly the response is validated. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
     /// Sends a response to the client and establishes the session.
     pub async fn respond(
+        self,
+        response: impl Into<ConnectResponse>,
+    ) -> Result<Connected, ConnectError> {
+        self.respond_with_headers(response, HeaderMap::new()).await
+    }
+
+    /// Sends a response with additional headers to the client and establishes the session.
+    ///
+    /// The client can read them with [`crate::Session::response_headers`].
+    pub async fn respond_with_headers(
         mut self,
         response: impl Into<ConnectResponse>,
+        headers: HeaderMap,
     ) -> Result<Connected, ConnectError> {
         let response = response.into();
 
         // Validate that our protocol was in the client's request.
         if let Some(protocol) = &response.protocol
             && !self.request.protocols.contains(protocol)
+            && !self.strictness.is_lenient()
         {
             return Err(ConnectError::ProtocolMismatch(protocol.clone()));
         }
 
         tracing::debug!(?response, "sending CONNECT response");
-        response.write(&mut self.send).await?;
+        let mut buf = BytesMut::new();
+        encode_response(&response, &headers, &mut buf)?;
+        self.send.write_all(&buf).await?;
 
         Ok(Connected {
             request: self.request,
             response,
+            response_headers: headers,
             send: self.send,
             recv: self.recv,
         })
@@ -88,12 +193,53 @@ impl Connecting {
 
     /// Rejects the CONNECT request with the given status code.
     pub async fn reject(self, status: http::StatusCode) -> Result<(), ConnectError> {
-        let mut connect = self.respond(status).await?;
-        connect.send.finish().ok();
+        let mut response = http::Response::new(Bytes::new());
+        *response.status_mut() = status;
+        self.reject_with(response).await
+    }
+
+    /// Rejects the CONNECT request with a full response, usually with a 4xx or 5xx status.
+    ///
+    /// The headers and body, if not empty, are sent on the CONNECT stream, which is then finished.
+    /// Clients receive them as [`ConnectError::Rejected`]. Returns once the client closed the
+    /// stream or the connection, or after [`REJECT_LINGER`], so the connection can be dropped.
+    pub async fn reject_with(
+        mut self,
+        response: http::Response<Bytes>,
+    ) -> Result<(), ConnectError> {
+        let (parts, body) = response.into_parts();
+        tracing::debug!(status = %parts.status, "rejecting CONNECT request");
+
+        let mut buf = BytesMut::new();
+        encode_response(
+            &ConnectResponse::new(parts.status),
+            &parts.headers,
+            &mut buf,
+        )?;
+        if !body.is_empty() {
+            Frame::DATA.encode(&mut buf);
+            VarInt::try_from(body.len() as u64)
+                .map_err(|_| web_transport_proto::ConnectError::FrameTooLarge)?
+                .encode(&mut buf);
+            buf.extend_from_slice(&body);
+        }
+        self.send.write_all(&buf).await?;
+        self.send.finish().ok();
+        self.recv.stop(0u32.into()).ok();
+        linger(&mut self.send).await;
         Ok(())
     }
 }
 
+// Waits until the client stopped the finished stream or closed the connection, up to REJECT_LINGER.
+//
+// The stream keeps the connection open in the meantime.
+async fn linger(send: &mut SendStream) {
+    tokio::time::timeout(REJECT_LINGER, send.stopped())
+        .await
+        .ok();
+}
+
 impl Deref for Connecting {
     type Target = ConnectRequest;
 
@@ -102,6 +248,28 @@ impl Deref for Connecting {
     }
 }
 
+/// Convert the received request into an [`http::Request`], e.g. to validate it with HTTP middleware.
+///
+/// Subprotocols are in the `wt-available-protocols` header.
+impl From<&Connecting> for http::Request<()> {
+    fn from(connecting: &Connecting) -> Self {
+        let mut http = http::Request::new(());
+        *http.method_mut() = http::Method::CONNECT;
+        *http.uri_mut() = connecting.uri();
+        *http.headers_mut() = connecting.request.headers.clone();
+        if !connecting.request.protocols.is_empty() {
+            // The subprotocols were decoded from the header, so they can be encoded again.
+            match encode_protocols(&connecting.request.protocols) {
+                Ok(value) => {
+                    http.headers_mut().insert(AVAILABLE_PROTOCOLS, value);
+                }
+                Err(err) => unexpected(&format!("received subprotocols can't be encoded: {err:#}")),
+            }
+        }
+        http
+    }
+}
+
 /// An established HTTP/3 CONNECT session with both request and response.
 #[derive(Debug)]
 pub struct Connected {
@@ -111,6 +279,9 @@ pub struct Connected {
     /// The response sent by the server.
     pub response: ConnectResponse,
 
+    /// The additional headers of the response, excluding pseudo-headers.
+    pub response_headers: HeaderMap,
+
     // A reference to the send/recv stream, so we don't close it until dropped.
     pub(crate) send: SendStream,
     pub(crate) recv: RecvStream,
@@ -121,21 +292,50 @@ impl Connected {
     ///
     /// You may add any number of subprotocols allowing the server to select from.
     /// If the list is empty the field will be omitted in the request header.
+    /// Use a [`ConnectRequestBuilder`](crate::ConnectRequestBuilder) to send additional headers.
     pub async fn open(
         conn: &Connection,
         request: impl Into<ConnectRequest>,
+    ) -> Result<Self, ConnectError> {
+        Self::open_with_options(conn, request, &HandshakeOptions::default()).await
+    }
+
+    /// Open a new WebTransport session, validating the response with the strictness and header
+    /// size limit of the [`HandshakeOptions`].
+    ///
+    /// Responses larger than [`HandshakeOptions::max_field_section_size`] fail with
+    /// [`ConnectError::FieldSectionTooLarge`].
+    pub async fn open_with_options<T: endpoint::ConnectionState>(
+        conn: &Connection<T>,
+        request: impl Into<ConnectRequest>,
+        options: &HandshakeOptions,
     ) -> Result<Self, ConnectError> {
         let request = request.into();
+        let HandshakeOptions {
+            strictness,
+            max_field_section_size,
+            ..
+        } = *options;
 
         // Create a new stream that will be used to send the CONNECT frame.
         let (mut send, mut recv) = conn.open_bi().await?;
 
-        tracing::debug!(?request, "sending CONNECT request");
+        // Don't log the headers, they may contain credentials.
+        tracing::debug!(url = %request.url, protocols = ?request.protocols, "sending CONNECT request");
         request.write(&mut send).await?;
 
-        let response = web_transport_proto::ConnectResponse::read(&mut recv).await?;
+        let (response, response_headers) = read_response(&mut recv, max_field_section_size).await?;
         tracing::debug!(?response, "received CONNECT response");
 
+        // Read the body of a rejection, which may explain the error.
+        if !response.status.is_success() {
+            let body = read_body(&mut recv).await?;
+            let mut rejection = http::Response::new(body);
+            *rejection.status_mut() = response.status;
+            *rejection.headers_mut() = response_headers;
+            return Err(ConnectError::Rejected(Arc::new(rejection)));
+        }
+
         // Throw an error if we didn't get a 200 OK.
         if response.status != http::StatusCode::OK {
             return Err(ConnectError::ErrorStatus(response.status));
@@ -145,12 +345,16 @@ impl Connected {
         if let Some(protocol) = &response.protocol
             && !request.protocols.contains(protocol)
         {
-            return Err(ConnectError::ProtocolMismatch(protocol.clone()));
+            if !strictness.is_lenient() {
+                return Err(ConnectError::ProtocolMismatch(protocol.clone()));
+            }
+            tracing::debug!(%protocol, "server selected a protocol that wasn't offered");
         }
 
         Ok(Self {
             request,
             response,
+            response_headers,
             send,
             recv,
         })
@@ -163,28 +367,23 @@ impl Connected {
         let stream_id = endpoint::VarInt::from(self.send.id());
         VarInt::try_from(stream_id.into_inner()).unwrap()
     }
+}
 
-    // Keep reading from the control stream until it's closed.
-    pub(crate) async fn run_closed(&mut self) -> (u32, String) {
-        loop {
-            match web_transport_proto::Capsule::read(&mut self.recv).await {
-                Ok(Some(web_transport_proto::Capsule::CloseWebTransportSession {
-                    code,
-                    reason,
-                })) => {
-                    return (code, reason);
-                }
-                Ok(Some(web_transport_proto::Capsule::Grease { .. })) => {}
-                Ok(Some(web_transport_proto::Capsule::Unknown { typ, payload })) => {
-                    tracing::warn!(%typ, size = payload.len(), "unknown capsule");
-                }
-                Ok(None) => {
-                    return (0, "stream closed".to_string());
-                }
-                Err(_) => {
-                    return (1, "capsule error".to_string());
+/// Convert the response into an [`http::Response`], with the selected subprotocol in the `wt-protocol` header.
+impl From<&Connected> for http::Response<()> {
+    fn from(connected: &Connected) -> Self {
+        let mut http = http::Response::new(());
+        *http.status_mut() = connected.response.status;
+        *http.headers_mut() = connected.response_headers.clone();
+        if let Some(protocol) = &connected.response.protocol {
+            // The protocol was already encoded or decoded as a header, so it is valid.
+            match encode_protocol(protocol) {
+                Ok(value) => {
+                    http.headers_mut().insert(SELECTED_PROTOCOL, value);
                 }
+                Err(err) => unexpected(&format!("selected protocol can't be encoded: {err:#}")),
             }
         }
+        http
     }
 }
@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use bytes::{Bytes, BytesMut};
+use iroh::endpoint;
+use tokio::sync::{Mutex, mpsc, watch};
+use web_transport_proto::{Capsule, VarInt};
+
+use crate::{SessionError, WebTransportError};
+
+// The capsule type of DRAIN_WEBTRANSPORT_SESSION.
+pub(crate) const DRAIN_WEBTRANSPORT_SESSION: VarInt = VarInt::from_u32(0x78ae);
+
+// How many received capsules are buffered until the application reads them.
+const CAPSULE_BUFFER: usize = 32;
+
+/// A callback invoked with the payload of each received capsule of a registered type.
+pub(crate) type CapsuleHandler = Arc<dyn Fn(Bytes) + Send + Sync>;
+
+/// The shared state of the CONNECT stream, used to exchange capsules with the peer.
+pub(crate) struct Control {
+    // The send half of the CONNECT stream. Capsules are written whole, so guard it with a lock.
+    send: Mutex<endpoint::SendStream>,
+
+    // Set once the peer sent a DRAIN_WEBTRANSPORT_SESSION capsule.
+    draining: watch::Sender<bool>,
+
+    // Set once either side closed the session, with the code and reason.
+    closed: watch::Sender<Option<(u32, String)>>,
+
+    // Capsules not handled by the session itself, for the application to read.
+    capsules_tx: mpsc::Sender<(VarInt, Bytes)>,
+    capsules_rx: Mutex<mpsc::Receiver<(VarInt, Bytes)>>,
+
+    // Handlers registered by the application, taking precedence over the capsule queue.
+    handlers: StdMutex<HashMap<VarInt, CapsuleHandler>>,
+}
+
+impl std::fmt::Debug for Control {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Control")
+            .field("send", &self.send)
+            .field("draining", &self.draining)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Control {
+    pub(crate) fn new(send: endpoint::SendStream) -> Self {
+        let (capsules_tx, capsules_rx) = mpsc::channel(CAPSULE_BUFFER);
+        Self {
+            send: Mutex::new(send),
+            draining: watch::Sender::new(false),
+            closed: watch::Sender::new(None),
+            capsules_tx,
+            capsules_rx: Mutex::new(capsules_rx),
+            handlers: Default::default(),
+        }
+    }
+
+    /// Registers a handler for a capsule type, returning true if it replaced another.
+    pub(crate) fn set_handler(&self, typ: VarInt, handler: CapsuleHandler) -> bool {
+        self.handlers
+            .lock()
+            .expect("poisoned")
+            .insert(typ, handler)
+            .is_some()
+    }
+
+    /// Removes the handler for a capsule type, returning true if there was one.
+    pub(crate) fn remove_handler(&self, typ: VarInt) -> bool {
+        self.handlers
+            .lock()
+            .expect("poisoned")
+            .remove(&typ)
+            .is_some()
+    }
+
+    /// Waits for the next capsule not handled by the session itself.
+    ///
+    /// Returns None once the session is closed.
+    pub(crate) async fn recv(&self) -> Option<(VarInt, Bytes)> {
+        let mut capsules = self.capsules_rx.lock().await;
+        tokio::select! {
+            capsule = capsules.recv() => capsule,
+            _ = self.closed() => None,
+        }
+    }
+
+    /// Writes a capsule to the CONNECT stream.
+    pub(crate) async fn write(&self, capsule: &Capsule) -> Result<(), SessionError> {
+        let mut buf = BytesMut::new();
+        capsule.encode(&mut buf);
+
+        let mut send = self.send.lock().await;
+        match send.write_all(&buf).await {
+            Ok(()) => Ok(()),
+            Err(endpoint::WriteError::ConnectionLost(err)) => Err(err.into()),
+            Err(err) => Err(WebTransportError::WriteError(err).into()),
+        }
+    }
+
+    /// Sends a CLOSE_WEBTRANSPORT_SESSION capsule and finishes the CONNECT stream.
+    pub(crate) async fn close(&self, code: u32, reason: &str) -> Result<(), SessionError> {
+        if self.set_closed(code, reason.to_string()) {
+            let capsule = Capsule::CloseWebTransportSession {
+                code,
+                reason: reason.to_string(),
+            };
+            self.write(&capsule).await?;
+            // The stream can only be finished already if the connection is gone.
+            self.send.lock().await.finish().ok();
+        }
+        Ok(())
+    }
+
+    /// Records that the session was closed, returning false if it was already closed.
+    pub(crate) fn set_closed(&self, code: u32, reason: String) -> bool {
+        self.closed.send_if_modified(|closed| {
+            if closed.is_some() {
+                return false;
+            }
+            *closed = Some((code, reason));
+            true
+        })
+    }
+
+    /// Returns the code and reason if the session was closed.
+    pub(crate) fn close_reason(&self) -> Option<(u32, String)> {
+        self.closed.borrow().clone()
+    }
+
+    /// Waits until the session is closed, returning the code and reason.
+    pub(crate) async fn closed(&self) -> (u32, String) {
+        let mut closed = self.closed.subscribe();
+        // The sender lives as long as self, so this can't fail.
+        let closed = match closed.wait_for(|closed| closed.is_some()).await {
+            Ok(closed) => closed.clone(),
+            Err(_) => None,
+        };
+        closed.expect("closed is set before the sender is dropped")
+    }
+
+    /// Returns true if the peer asked to drain the session.
+    pub(crate) fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Waits until the peer asks to drain the session.
+    pub(crate) async fn draining(&self) {
+        let mut draining = self.draining.subscribe();
+        // The sender lives as long as self, so this can't fail.
+        draining.wait_for(|draining| *draining).await.ok();
+    }
+
+    /// Keeps reading capsules from the CONNECT stream until the session is closed.
+    pub(crate) async fn run(&self, recv: &mut endpoint::RecvStream) -> (u32, String) {
+        loop {
+            match Capsule::read(recv).await {
+                Ok(Some(Capsule::CloseWebTransportSession { code, reason })) => {
+                    return (code, reason);
+                }
+                Ok(Some(Capsule::Grease { .. })) => {}
+                Ok(Some(Capsule::Unknown { typ, .. })) if typ == DRAIN_WEBTRANSPORT_SESSION => {
+                    tracing::debug!("received DRAIN_WEBTRANSPORT_SESSION");
+                    self.draining.send_replace(true);
+                }
+                Ok(Some(Capsule::Unknown { typ, payload })) => {
+                    // Clone the handler so it can (de)register handlers itself.
+                    let handler = self.handlers.lock().expect("poisoned").get(&typ).cloned();
+                    if let Some(handler) = handler {
+                        handler(payload);
+                        continue;
+                    }
+
+                    // Don't block the control stream if the application doesn't read capsules.
+                    if let Err(err) = self.capsules_tx.try_send((typ, payload)) {
+                        let (typ, payload) = err.into_inner();
+                        tracing::warn!(%typ, size = payload.len(), "dropping unread capsule");
+                    }
+                }
+                Ok(None) => {
+                    return (0, "stream closed".to_string());
+                }
+                Err(_) => {
+                    return (1, "capsule error".to_string());
+                }
+            }
+        }
+    }
+}
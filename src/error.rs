@@ -1,9 +1,9 @@
 use std::sync::Arc;
 
-use iroh::endpoint;
+use iroh::{EndpointId, endpoint};
 use n0_error::stack_error;
 
-use crate::{ConnectError, SettingsError};
+use crate::{ConnectError, PathMode, SettingsError};
 
 /// An error returned when connecting to a WebTransport endpoint.
 #[stack_error(derive, from_sources)]
@@ -35,6 +35,31 @@ pub enum ClientError {
 
     #[error("endpoint failed to bind")]
     Bind(#[error(source)] Arc<endpoint::BindError>),
+
+    #[error("timed out during the handshake")]
+    HandshakeTimeout,
+
+    #[error("no addresses to connect to")]
+    NoAddresses,
+
+    #[error("no ALPNs to offer")]
+    NoAlpns,
+
+    #[error("connect was cancelled")]
+    Cancelled,
+
+    #[error("request vetoed: {_0}")]
+    Vetoed(String),
+
+    #[error("required path unavailable: {_0:?}")]
+    PathUnavailable(PathMode),
+
+    #[error("failed to resolve the host")]
+    Resolve(#[error(source)] Arc<n0_error::AnyError>),
+
+    #[cfg(feature = "tickets")]
+    #[error("invalid ticket")]
+    InvalidTicket(#[error(source)] Arc<iroh_tickets::ParseError>),
 }
 
 /// An error returned by [`crate::Session`], split between underlying QUIC errors and WebTransport errors.
@@ -44,11 +69,21 @@ pub enum SessionError {
     #[error("connection error")]
     ConnectionError(#[error(source, from, std_err)] endpoint::ConnectionError),
 
+    /// The peer closed the connection with a WebTransport error code.
+    ///
+    /// For HTTP/3 sessions the code is already translated from the HTTP/3 error space.
+    #[error("closed by peer: code={code} reason={reason}")]
+    ApplicationClosed { code: u32, reason: String },
+
     #[error("webtransport error")]
     WebTransportError(#[error(source, from, std_err)] WebTransportError),
 
     #[error("send datagram error")]
     SendDatagramError(#[error(source, from, std_err)] endpoint::SendDatagramError),
+
+    /// The server rejected the 0-RTT data the stream was opened with.
+    #[error("0-RTT data was rejected")]
+    ZeroRttRejected,
 }
 
 /// An error that can occur when reading/writing the WebTransport stream header.
@@ -61,6 +96,16 @@ pub enum WebTransportError {
     #[error("unknown session")]
     UnknownSession,
 
+    #[error("capsules require an HTTP/3 session")]
+    CapsulesUnsupported,
+
+    #[error("GOAWAY requires an HTTP/3 session")]
+    GoAwayUnsupported,
+
+    /// The peer reset a stream with a code outside the WebTransport range, e.g. H3_NO_ERROR.
+    #[error("invalid RESET_STREAM: {_0}")]
+    InvalidReset(endpoint::VarInt),
+
     #[error("read error")]
     ReadError(#[error(source, from, std_err)] endpoint::ReadExactError),
 
@@ -96,11 +141,33 @@ impl From<endpoint::WriteError> for WriteError {
             }
             endpoint::WriteError::ClosedStream => WriteError::ClosedStream,
             endpoint::WriteError::ConnectionLost(e) => WriteError::SessionError(e.into()),
-            endpoint::WriteError::ZeroRttRejected => unreachable!("0-RTT not supported"),
+            endpoint::WriteError::ZeroRttRejected => {
+                WriteError::SessionError(SessionError::ZeroRttRejected)
+            }
         }
     }
 }
 
+/// Used by the [`tokio::io::AsyncWrite`] impl of [`crate::SendStream`], with the same kinds as Quinn.
+///
+/// The [`WriteError`] can be recovered with [`std::io::Error::downcast`], e.g. to read the
+/// WebTransport code of a STOP_SENDING.
+impl From<WriteError> for std::io::Error {
+    fn from(err: WriteError) -> Self {
+        let kind = match &err {
+            WriteError::Stopped(_)
+            | WriteError::InvalidStopped(_)
+            | WriteError::SessionError(SessionError::ZeroRttRejected) => {
+                std::io::ErrorKind::ConnectionReset
+            }
+            WriteError::SessionError(_) | WriteError::ClosedStream => {
+                std::io::ErrorKind::NotConnected
+            }
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
 /// An error when reading from [`crate::RecvStream`]. Similar to [`iroh::endpoint::ReadError`].
 #[stack_error(derive, from_sources)]
 #[derive(Clone)]
@@ -129,7 +196,9 @@ impl From<endpoint::ReadError> for ReadError {
             }
             endpoint::ReadError::ConnectionLost(e) => Self::SessionError(e.into()),
             endpoint::ReadError::ClosedStream => Self::ClosedStream,
-            endpoint::ReadError::ZeroRttRejected => unreachable!("0-RTT not supported"),
+            endpoint::ReadError::ZeroRttRejected => {
+                Self::SessionError(SessionError::ZeroRttRejected)
+            }
         }
     }
 }
@@ -174,6 +243,25 @@ impl From<endpoint::ReadToEndError> for ReadToEndError {
     }
 }
 
+/// An error returned by [`crate::Session::accept_uni_message`] and [`crate::Session::accept_bi_request`].
+#[stack_error(derive, from_sources)]
+#[derive(Clone)]
+pub enum MessageError {
+    #[error("session error")]
+    SessionError(#[error(source, from)] SessionError),
+
+    /// The message exceeded the size limit and the stream was stopped.
+    #[error("message too long")]
+    TooLong,
+
+    /// The message wasn't received before its deadline, see [`crate::PartialPolicy`].
+    #[error("message timed out")]
+    TimedOut,
+
+    #[error("read error")]
+    ReadError(#[error(source, from)] ReadError),
+}
+
 /// An error indicating the stream was already closed.
 #[stack_error(derive)]
 #[derive(Clone)]
@@ -216,15 +304,239 @@ pub enum ServerError {
 
     #[error("failed to exchange h3 settings")]
     SettingsError(#[error(source, from, std_err)] SettingsError),
+
+    #[error("origin not allowed: {origin:?}")]
+    OriginRejected { origin: Option<String> },
+
+    #[error("timed out during the handshake")]
+    HandshakeTimeout,
+
+    #[error("session not admitted, rejected with {status}")]
+    NotAdmitted { status: http::StatusCode },
+
+    #[error("peer denied by the ACL")]
+    PeerDenied,
+
+    #[error("connection dropped by the connection filter")]
+    Filtered,
+
+    #[error("authentication failed, rejected with {status}")]
+    Unauthenticated { status: http::StatusCode },
+}
+
+/// A failed handshake, returned by [`crate::Server::accept_with_errors`].
+#[stack_error(derive)]
+#[derive(Clone)]
+#[error("handshake with {remote:?} failed")]
+pub struct HandshakeError {
+    /// The peer, if the QUIC handshake got far enough to authenticate it.
+    pub remote: Option<EndpointId>,
+    /// Why the handshake failed.
+    pub source: ServerError,
+}
+
+impl HandshakeError {
+    /// Returns a machine-readable classification of the error, see [`ServerError::kind`].
+    pub fn kind(&self) -> ErrorKind {
+        self.source.kind()
+    }
+}
+
+/// A machine-readable classification of a [`ClientError`] or [`ServerError`].
+///
+/// Use [`Self::hint`] to show an actionable message to users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The local port is already in use by another socket.
+    PortInUse,
+    /// Not allowed to bind the socket, e.g. to a privileged port.
+    PermissionDenied,
+    /// There is no network route to the peer.
+    NoRoute,
+    /// The peer couldn't be reached and we aren't connected to a relay.
+    ///
+    /// Only reported by [`crate::Client::diagnose`], which knows the relay state.
+    RelayUnreachable,
+    /// The peer's addresses are unknown and couldn't be looked up.
+    DiscoveryNotConfigured,
+    /// The peer didn't respond in time.
+    TimedOut,
+    /// The peer refused or closed the connection or session.
+    Refused,
+    /// The local endpoint or connection was closed.
+    Closed,
+    /// The peer violated the QUIC, HTTP/3 or WebTransport protocol.
+    Protocol,
+    /// Any other error.
+    Other,
+}
+
+impl ErrorKind {
+    /// Returns an actionable description of the error, suitable for users.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::PortInUse => {
+                "the port is already in use, pick another port or stop the other process"
+            }
+            Self::PermissionDenied => "not allowed to bind the socket, use an unprivileged port",
+            Self::NoRoute => "no route to the peer, check the network connection",
+            Self::RelayUnreachable => {
+                "no relay server is reachable, check that outgoing HTTPS connections are allowed"
+            }
+            Self::DiscoveryNotConfigured => {
+                "the peer's address is unknown, pass its relay URL or direct addresses, or enable address lookup"
+            }
+            Self::TimedOut => "the peer didn't respond, check that it is online",
+            Self::Refused => "the peer refused the connection",
+            Self::Closed => "the endpoint or connection was closed locally",
+            Self::Protocol => "the peer doesn't speak a compatible protocol",
+            Self::Other => "an unexpected error occurred",
+        }
+    }
+}
+
+impl ClientError {
+    /// Returns a machine-readable classification of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Connect(source) => connect_kind(source),
+            Self::Connection(source) => connection_kind(source),
+            Self::Bind(source) => bind_kind(source),
+            Self::HandshakeTimeout => ErrorKind::TimedOut,
+            Self::SettingsError(source) => settings_kind(source),
+            Self::HttpError(source) => http_kind(source),
+            Self::Resolve(_) => ErrorKind::NoRoute,
+            Self::PathUnavailable(PathMode::Relay) => ErrorKind::RelayUnreachable,
+            Self::PathUnavailable(_) => ErrorKind::NoRoute,
+            Self::UnexpectedEnd
+            | Self::WriteError(_)
+            | Self::ReadError(_)
+            | Self::InvalidUrl
+            | Self::NoAddresses
+            | Self::NoAlpns
+            | Self::Cancelled
+            | Self::Vetoed(_) => ErrorKind::Other,
+            #[cfg(feature = "tickets")]
+            Self::InvalidTicket(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl ServerError {
+    /// Returns a machine-readable classification of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Connection(source) => connection_kind(source),
+            Self::Connecting(source) => connecting_kind(source),
+            Self::IoError(source) => io_kind(source),
+            Self::Bind(source) => bind_kind(source),
+            Self::HttpError(source) => http_kind(source),
+            Self::SettingsError(source) => settings_kind(source),
+            Self::OriginRejected { .. }
+            | Self::NotAdmitted { .. }
+            | Self::PeerDenied
+            | Self::Filtered
+            | Self::Unauthenticated { .. } => ErrorKind::Refused,
+            Self::HandshakeTimeout => ErrorKind::TimedOut,
+            Self::UnexpectedEnd | Self::WriteError(_) | Self::ReadError(_) => ErrorKind::Other,
+        }
+    }
+}
+
+fn bind_kind(err: &endpoint::BindError) -> ErrorKind {
+    match err {
+        endpoint::BindError::Sockets { source, .. }
+        | endpoint::BindError::CreateQuicEndpoint { source, .. } => io_kind(source),
+        endpoint::BindError::AddressLookup { .. } => ErrorKind::DiscoveryNotConfigured,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn io_kind(err: &std::io::Error) -> ErrorKind {
+    match err.kind() {
+        std::io::ErrorKind::AddrInUse => ErrorKind::PortInUse,
+        std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+        std::io::ErrorKind::NetworkUnreachable
+        | std::io::ErrorKind::HostUnreachable
+        | std::io::ErrorKind::AddrNotAvailable => ErrorKind::NoRoute,
+        std::io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+        std::io::ErrorKind::ConnectionRefused => ErrorKind::Refused,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn connect_kind(err: &endpoint::ConnectError) -> ErrorKind {
+    match err {
+        endpoint::ConnectError::Connect { source, .. } => match source {
+            endpoint::ConnectWithOptsError::NoAddress { .. } => ErrorKind::DiscoveryNotConfigured,
+            endpoint::ConnectWithOptsError::Quinn { source, .. } => match source {
+                endpoint::QuicConnectError::EndpointStopping => ErrorKind::Closed,
+                endpoint::QuicConnectError::InvalidRemoteAddress(_) => ErrorKind::NoRoute,
+                _ => ErrorKind::Other,
+            },
+            endpoint::ConnectWithOptsError::LocallyRejected { .. } => ErrorKind::Closed,
+            _ => ErrorKind::Other,
+        },
+        endpoint::ConnectError::Connecting { source, .. } => connecting_kind(source),
+        endpoint::ConnectError::Connection { source, .. } => connection_kind(source),
+        _ => ErrorKind::Other,
+    }
+}
+
+fn connecting_kind(err: &endpoint::ConnectingError) -> ErrorKind {
+    match err {
+        endpoint::ConnectingError::ConnectionError { source, .. } => connection_kind(source),
+        endpoint::ConnectingError::HandshakeFailure { .. } => ErrorKind::Protocol,
+        endpoint::ConnectingError::LocallyRejected { .. } => ErrorKind::Closed,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn connection_kind(err: &endpoint::ConnectionError) -> ErrorKind {
+    match err {
+        endpoint::ConnectionError::TimedOut => ErrorKind::TimedOut,
+        endpoint::ConnectionError::ConnectionClosed(_)
+        | endpoint::ConnectionError::ApplicationClosed(_)
+        | endpoint::ConnectionError::Reset => ErrorKind::Refused,
+        endpoint::ConnectionError::LocallyClosed => ErrorKind::Closed,
+        endpoint::ConnectionError::VersionMismatch
+        | endpoint::ConnectionError::TransportError(_) => ErrorKind::Protocol,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn settings_kind(err: &SettingsError) -> ErrorKind {
+    match err {
+        SettingsError::ConnectionError(source) => connection_kind(source),
+        SettingsError::MaxSessionsExceeded => ErrorKind::Refused,
+        SettingsError::ProtoError(_) | SettingsError::WebTransportUnsupported => {
+            ErrorKind::Protocol
+        }
+        _ => ErrorKind::Other,
+    }
+}
+
+fn http_kind(err: &ConnectError) -> ErrorKind {
+    match err {
+        ConnectError::ConnectionError(source) => connection_kind(source),
+        ConnectError::ErrorStatus(_) | ConnectError::Rejected(_) => ErrorKind::Refused,
+        ConnectError::ProtoError(_)
+        | ConnectError::ProtocolMismatch(_)
+        | ConnectError::FieldSectionTooLarge { .. }
+        | ConnectError::InvalidUri(_)
+        | ConnectError::InvalidFieldSection => ErrorKind::Protocol,
+        _ => ErrorKind::Other,
+    }
 }
 
 impl web_transport_trait::Error for SessionError {
     fn session_error(&self) -> Option<(u32, String)> {
-        if let SessionError::WebTransportError(WebTransportError::Closed { code, reason }) = self {
-            return Some((*code, reason.to_string()));
+        match self {
+            SessionError::WebTransportError(WebTransportError::Closed { code, reason })
+            | SessionError::ApplicationClosed { code, reason } => Some((*code, reason.to_string())),
+            _ => None,
         }
-
-        None
     }
 }
 
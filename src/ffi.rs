@@ -0,0 +1,428 @@
+//! A minimal C-compatible API, enabled with the `ffi` feature.
+//!
+//! All functions block the calling thread on a shared multi-threaded tokio runtime.
+//! Objects are passed as opaque pointers and must be released with the matching `*_free` function.
+//! Functions returning a pointer return null on failure and functions returning an integer return
+//! a negative value; the error message is available through [`wt_last_error`].
+//!
+//! The declarations are in `include/web_transport_iroh.h`.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int},
+    fmt::Display,
+    ptr,
+    sync::OnceLock,
+};
+
+use bytes::Bytes;
+use iroh::{Endpoint, EndpointId};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::{ALPN_H3, Client, H3Request, RecvStream, SendStream, Session};
+
+/// An iroh endpoint, able to both connect and accept HTTP/3 WebTransport sessions.
+pub struct WtEndpoint {
+    endpoint: Endpoint,
+    client: Client,
+}
+
+/// An established WebTransport session.
+pub struct WtSession(Session);
+
+/// The sending half of a stream.
+pub struct WtSendStream(SendStream);
+
+/// The receiving half of a stream.
+pub struct WtRecvStream(RecvStream);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the tokio runtime")
+    })
+}
+
+fn set_error(err: impl Display) {
+    let msg = CString::new(format!("{err:#}").replace('\0', "")).expect("nul bytes were removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+}
+
+// Records the error, if any, and converts the result into an option.
+fn check<T, E: Display>(res: Result<T, E>) -> Option<T> {
+    res.map_err(set_error).ok()
+}
+
+fn into_ptr<T>(value: Option<T>) -> *mut T {
+    value.map_or(ptr::null_mut(), |value| Box::into_raw(Box::new(value)))
+}
+
+fn into_status(res: Option<()>) -> c_int {
+    res.map_or(-1, |()| 0)
+}
+
+// Reads a UTF-8 string argument, recording an error if it's invalid.
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Option<&'a str> {
+    if ptr.is_null() {
+        set_error(format!("{name} is null"));
+        return None;
+    }
+    // SAFETY: the caller guarantees a valid nul-terminated string.
+    check(unsafe { CStr::from_ptr(ptr) }.to_str())
+}
+
+/// Returns the last error that occurred on this thread, or null if there was none.
+///
+/// The string is valid until the next call into this library on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn wt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |msg| msg.as_ptr())
+    })
+}
+
+/// Frees a string returned by this library.
+///
+/// # Safety
+/// `s` must be null or a string returned by this library that wasn't freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: the string was created by CString::into_raw.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Binds a new endpoint that can connect to and accept HTTP/3 sessions.
+#[unsafe(no_mangle)]
+pub extern "C" fn wt_endpoint_bind() -> *mut WtEndpoint {
+    let endpoint = runtime().block_on(
+        Endpoint::builder()
+            .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+            .bind(),
+    );
+    into_ptr(check(endpoint).map(|endpoint| WtEndpoint {
+        client: Client::new(endpoint.clone()),
+        endpoint,
+    }))
+}
+
+/// Returns the endpoint id, to be freed with [`wt_string_free`].
+///
+/// # Safety
+/// `endpoint` must be a valid endpoint.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_endpoint_id(endpoint: *const WtEndpoint) -> *mut c_char {
+    // SAFETY: the caller guarantees a valid endpoint.
+    let endpoint = unsafe { &*endpoint };
+    let id = CString::new(endpoint.endpoint.id().to_string()).expect("ids have no nul bytes");
+    id.into_raw()
+}
+
+/// Connects to the endpoint with the given id and requests the given path, e.g. `/`.
+///
+/// # Safety
+/// `endpoint` must be a valid endpoint; `endpoint_id` and `path` must be valid strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_connect(
+    endpoint: *const WtEndpoint,
+    endpoint_id: *const c_char,
+    path: *const c_char,
+) -> *mut WtSession {
+    // SAFETY: the caller guarantees a valid endpoint.
+    let endpoint = unsafe { &*endpoint };
+    // SAFETY: the caller guarantees valid strings.
+    let (Some(id), Some(path)) = (unsafe { str_arg(endpoint_id, "endpoint_id") }, unsafe {
+        str_arg(path, "path")
+    }) else {
+        return ptr::null_mut();
+    };
+    let Some(id) = check(id.parse::<EndpointId>()) else {
+        return ptr::null_mut();
+    };
+    let Some(url) = check(format!("https://{id}{path}").parse::<Url>()) else {
+        return ptr::null_mut();
+    };
+
+    let session = runtime().block_on(endpoint.client.connect_h3(id, url));
+    into_ptr(check(session).map(WtSession))
+}
+
+/// Waits for the next incoming session and accepts it with 200 OK.
+///
+/// Returns null once the endpoint is closed.
+///
+/// # Safety
+/// `endpoint` must be a valid endpoint.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_accept(endpoint: *const WtEndpoint) -> *mut WtSession {
+    // SAFETY: the caller guarantees a valid endpoint.
+    let endpoint = unsafe { &*endpoint };
+    let session = runtime().block_on(async {
+        let incoming = endpoint.endpoint.accept().await.ok_or("endpoint closed")?;
+        let conn = incoming
+            .accept()
+            .map_err(|err| err.to_string())?
+            .await
+            .map_err(|err| err.to_string())?;
+        let request = H3Request::accept(conn)
+            .await
+            .map_err(|err| err.to_string())?;
+        request.ok().await.map_err(|err| err.to_string())
+    });
+    into_ptr(check(session).map(WtSession))
+}
+
+/// Closes the endpoint and frees it.
+///
+/// # Safety
+/// `endpoint` must be null or a valid endpoint, which must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_endpoint_free(endpoint: *mut WtEndpoint) {
+    if endpoint.is_null() {
+        return;
+    }
+    // SAFETY: the endpoint was created by Box::into_raw.
+    let endpoint = unsafe { Box::from_raw(endpoint) };
+    runtime().block_on(endpoint.endpoint.close());
+}
+
+/// Opens a unidirectional stream.
+///
+/// # Safety
+/// `session` must be a valid session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_open_uni(session: *const WtSession) -> *mut WtSendStream {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    into_ptr(check(runtime().block_on(session.open_uni())).map(WtSendStream))
+}
+
+/// Opens a bidirectional stream, storing both halves in the out parameters.
+///
+/// # Safety
+/// `session` must be a valid session; `send` and `recv` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_open_bi(
+    session: *const WtSession,
+    send: *mut *mut WtSendStream,
+    recv: *mut *mut WtRecvStream,
+) -> c_int {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    let streams = check(runtime().block_on(session.open_bi()));
+    // SAFETY: the caller guarantees valid out parameters.
+    unsafe { write_bi(streams, send, recv) }
+}
+
+/// Waits for the next unidirectional stream opened by the peer.
+///
+/// # Safety
+/// `session` must be a valid session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_accept_uni(session: *const WtSession) -> *mut WtRecvStream {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    into_ptr(check(runtime().block_on(session.accept_uni())).map(WtRecvStream))
+}
+
+/// Waits for the next bidirectional stream opened by the peer, storing both halves in the out parameters.
+///
+/// # Safety
+/// `session` must be a valid session; `send` and `recv` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_accept_bi(
+    session: *const WtSession,
+    send: *mut *mut WtSendStream,
+    recv: *mut *mut WtRecvStream,
+) -> c_int {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    let streams = check(runtime().block_on(session.accept_bi()));
+    // SAFETY: the caller guarantees valid out parameters.
+    unsafe { write_bi(streams, send, recv) }
+}
+
+unsafe fn write_bi(
+    streams: Option<(SendStream, RecvStream)>,
+    send: *mut *mut WtSendStream,
+    recv: *mut *mut WtRecvStream,
+) -> c_int {
+    let Some((s, r)) = streams else {
+        return -1;
+    };
+    // SAFETY: the caller guarantees valid out parameters.
+    unsafe {
+        *send = into_ptr(Some(WtSendStream(s)));
+        *recv = into_ptr(Some(WtRecvStream(r)));
+    }
+    0
+}
+
+/// Sends a datagram, returning 0 on success.
+///
+/// # Safety
+/// `session` must be a valid session; `data` must be valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_send_datagram(
+    session: *const WtSession,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    // SAFETY: the caller guarantees a valid session and buffer.
+    let (session, data) = unsafe { (&(*session).0, bytes_arg(data, len)) };
+    into_status(check(session.send_datagram(Bytes::copy_from_slice(data))))
+}
+
+/// Waits for the next datagram and copies it into the buffer.
+///
+/// Returns the size of the datagram, which is larger than `len` if it was truncated.
+///
+/// # Safety
+/// `session` must be a valid session; `buf` must be valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_recv_datagram(
+    session: *const WtSession,
+    buf: *mut u8,
+    len: usize,
+) -> isize {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    let Some(datagram) = check(runtime().block_on(session.read_datagram())) else {
+        return -1;
+    };
+    let n = datagram.len().min(len);
+    // SAFETY: the caller guarantees a valid buffer.
+    unsafe { ptr::copy_nonoverlapping(datagram.as_ptr(), buf, n) };
+    datagram.len() as isize
+}
+
+/// Returns the maximum size of a datagram that can currently be sent.
+///
+/// # Safety
+/// `session` must be a valid session.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_max_datagram_size(session: *const WtSession) -> usize {
+    // SAFETY: the caller guarantees a valid session.
+    unsafe { &(*session).0 }.max_datagram_size()
+}
+
+/// Closes the session with the given code and reason. The reason may be null.
+///
+/// # Safety
+/// `session` must be a valid session; `reason` must be null or a valid string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_close(
+    session: *const WtSession,
+    code: u32,
+    reason: *const c_char,
+) {
+    // SAFETY: the caller guarantees a valid session.
+    let session = unsafe { &(*session).0 };
+    let reason = if reason.is_null() {
+        &[][..]
+    } else {
+        // SAFETY: the caller guarantees a valid string.
+        unsafe { CStr::from_ptr(reason) }.to_bytes()
+    };
+    session.close(code, reason);
+}
+
+/// Frees the session. This doesn't close it, unless it was the last reference.
+///
+/// # Safety
+/// `session` must be null or a valid session, which must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_session_free(session: *mut WtSession) {
+    if !session.is_null() {
+        // SAFETY: the session was created by Box::into_raw.
+        drop(unsafe { Box::from_raw(session) });
+    }
+}
+
+/// Writes the whole buffer to the stream, returning 0 on success.
+///
+/// # Safety
+/// `send` must be a valid stream; `data` must be valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_send_write(
+    send: *mut WtSendStream,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    // SAFETY: the caller guarantees a valid stream and buffer.
+    let (send, data) = unsafe { (&mut (*send).0, bytes_arg(data, len)) };
+    into_status(check(runtime().block_on(send.write_all(data))))
+}
+
+/// Finishes the stream, returning 0 on success.
+///
+/// # Safety
+/// `send` must be a valid stream.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_send_finish(send: *mut WtSendStream) -> c_int {
+    // SAFETY: the caller guarantees a valid stream.
+    let send = unsafe { &mut (*send).0 };
+    into_status(check(send.finish()))
+}
+
+/// Frees the stream. An unfinished stream is finished gracefully.
+///
+/// # Safety
+/// `send` must be null or a valid stream, which must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_send_free(send: *mut WtSendStream) {
+    if !send.is_null() {
+        // SAFETY: the stream was created by Box::into_raw.
+        drop(unsafe { Box::from_raw(send) });
+    }
+}
+
+/// Reads from the stream into the buffer, returning the number of bytes read or 0 at the end.
+///
+/// # Safety
+/// `recv` must be a valid stream; `buf` must be valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_recv_read(recv: *mut WtRecvStream, buf: *mut u8, len: usize) -> isize {
+    if len == 0 {
+        return 0;
+    }
+    // SAFETY: the caller guarantees a valid stream and buffer.
+    let (recv, buf) = unsafe { (&mut (*recv).0, std::slice::from_raw_parts_mut(buf, len)) };
+    match check(runtime().block_on(recv.read(buf))) {
+        Some(Some(n)) => n as isize,
+        Some(None) => 0,
+        None => -1,
+    }
+}
+
+/// Frees the stream.
+///
+/// # Safety
+/// `recv` must be null or a valid stream, which must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wt_recv_free(recv: *mut WtRecvStream) {
+    if !recv.is_null() {
+        // SAFETY: the stream was created by Box::into_raw.
+        drop(unsafe { Box::from_raw(recv) });
+    }
+}
+
+// Borrows a buffer argument, allowing null for an empty buffer.
+unsafe fn bytes_arg<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    match len {
+        0 => &[],
+        // SAFETY: the caller guarantees a valid buffer.
+        _ => unsafe { std::slice::from_raw_parts(data, len) },
+    }
+}
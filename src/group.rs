@@ -0,0 +1,57 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicI32, Ordering},
+};
+
+use crate::{RecvStream, SendStream, Session, SessionError};
+
+/// Opens streams that share a priority, e.g. the response streams of an accepted request.
+///
+/// This keeps related streams coherent under congestion, so a high priority request isn't
+/// answered on low priority streams. Created by [`Session::stream_group`] or
+/// [`Session::stream_group_for`]. Clones share the same priority.
+#[derive(Debug, Clone)]
+pub struct StreamGroup {
+    session: Session,
+    priority: Arc<AtomicI32>,
+}
+
+impl StreamGroup {
+    pub(crate) fn new(session: Session, priority: i32) -> Self {
+        Self {
+            session,
+            priority: Arc::new(AtomicI32::new(priority)),
+        }
+    }
+
+    /// Returns the priority of streams opened in this group.
+    pub fn priority(&self) -> i32 {
+        self.priority.load(Ordering::Relaxed)
+    }
+
+    /// Sets the priority of streams opened in this group from now on.
+    ///
+    /// Streams that are already open keep their priority, see [`SendStream::set_priority`].
+    pub fn set_priority(&self, priority: i32) {
+        self.priority.store(priority, Ordering::Relaxed);
+    }
+
+    /// Opens a unidirectional stream with the priority of the group.
+    pub async fn open_uni(&self) -> Result<SendStream, SessionError> {
+        let send = self.session.open_uni().await?;
+        send.set_priority(self.priority()).ok();
+        Ok(send)
+    }
+
+    /// Opens a bidirectional stream with the priority of the group.
+    pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
+        let (send, recv) = self.session.open_bi().await?;
+        send.set_priority(self.priority()).ok();
+        Ok((send, recv))
+    }
+
+    /// Returns the session the streams are opened on.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{SettingsProfile, Strictness};
+
+/// Options for the HTTP/3 handshake, i.e. the SETTINGS and CONNECT exchange.
+///
+/// Passed to [`Settings::connect_with_options`](crate::Settings::connect_with_options) and the
+/// other `*_with_options` functions, which only use the options that apply to them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeOptions {
+    /// The `SETTINGS_WT_MAX_SESSIONS` advertised to the peer.
+    ///
+    /// A server can advertise `0` to make clients fail during the SETTINGS exchange with
+    /// [`SettingsError::MaxSessionsExceeded`](crate::SettingsError::MaxSessionsExceeded),
+    /// instead of after sending a CONNECT request.
+    pub max_sessions: u32,
+    /// How strictly the protocol is enforced, for the lifetime of the connection.
+    pub strictness: Strictness,
+    /// The `SETTINGS_MAX_FIELD_SECTION_SIZE` advertised to the peer, if any.
+    ///
+    /// It is enforced when reading the CONNECT request or response, computed as in the
+    /// setting, including pseudo-headers. Larger requests are rejected with a 431 status, and
+    /// both sides fail with [`ConnectError::FieldSectionTooLarge`](crate::ConnectError::FieldSectionTooLarge).
+    pub max_field_section_size: Option<u64>,
+    /// Which optional SETTINGS are sent and which omissions by the peer are tolerated.
+    pub profile: SettingsProfile,
+}
+
+impl HandshakeOptions {
+    /// Returns the default options, advertising a single session.
+    pub fn new() -> Self {
+        Self {
+            max_sessions: 1,
+            strictness: Strictness::Default,
+            max_field_section_size: None,
+            profile: SettingsProfile::default(),
+        }
+    }
+
+    /// Sets the `SETTINGS_WT_MAX_SESSIONS` advertised to the peer.
+    pub fn with_max_sessions(mut self, max_sessions: u32) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Sets how strictly the protocol is enforced. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Limits the size of the CONNECT request or response headers, see [`Self::max_field_section_size`].
+    pub fn with_max_field_section_size(mut self, size: Option<u64>) -> Self {
+        self.max_field_section_size = size;
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent and which omissions by the peer are tolerated.
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+}
+
+impl Default for HandshakeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
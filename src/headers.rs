@@ -0,0 +1,274 @@
+//! Response headers, which web-transport-proto doesn't support.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use sfv::{ItemSerializer, List, ListEntry, ListSerializer, Parser, StringRef};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use url::Url;
+use web_transport_proto::{ConnectRequest, ConnectResponse, Frame, VarInt};
+
+use crate::{
+    ConnectError,
+    qpack::{FieldLine, decode_field_section, encode_literal},
+};
+
+// The header carrying the offered subprotocols, exposed as `ConnectRequest::protocols` instead.
+pub(crate) const AVAILABLE_PROTOCOLS: &str = "wt-available-protocols";
+
+// The header carrying the selected subprotocol, exposed as `ConnectResponse::protocol` instead.
+pub(crate) const SELECTED_PROTOCOL: &str = "wt-protocol";
+
+// The header web-transport-proto adds to every response for older clients.
+const DRAFT_VERSION: &str = "sec-webtransport-http3-draft";
+
+// Same limit as web-transport-proto uses for HEADERS frames.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+// The overhead of each field line when computing the size of a field section.
+// See: https://www.rfc-editor.org/rfc/rfc9114.html#section-4.2.2
+const FIELD_OVERHEAD: u64 = 32;
+
+/// Encode the response as a HEADERS frame, appending the given headers.
+pub(crate) fn encode_response<B: BufMut>(
+    response: &ConnectResponse,
+    headers: &HeaderMap,
+    buf: &mut B,
+) -> Result<(), ConnectError> {
+    let mut frame = BytesMut::new();
+    response.encode(&mut frame)?;
+    if headers.is_empty() {
+        buf.put(frame);
+        return Ok(());
+    }
+
+    append_literals(frame, headers, buf)
+}
+
+/// Create a request from its URL and headers, which may include the offered subprotocols.
+///
+/// The request is encoded and decoded like one sent over the network, so it is validated the
+/// same way.
+pub(crate) fn decode_request(
+    url: Url,
+    headers: &HeaderMap,
+) -> Result<ConnectRequest, ConnectError> {
+    let mut headers = headers.clone();
+    let protocols = match headers.remove(AVAILABLE_PROTOCOLS) {
+        Some(value) => decode_protocols(&value)?,
+        None => Vec::new(),
+    };
+    let mut frame = BytesMut::new();
+    ConnectRequest::new(url)
+        .with_protocols(protocols)
+        .with_headers(headers)
+        .encode(&mut frame)?;
+    Ok(ConnectRequest::decode(&mut frame.freeze())?)
+}
+
+/// Encode a list of subprotocols as a structured field list of strings.
+pub(crate) fn encode_protocols(protocols: &[String]) -> Result<HeaderValue, ConnectError> {
+    let mut list = ListSerializer::new();
+    for protocol in protocols {
+        let _ = list.bare_item(StringRef::from_str(protocol).map_err(|_| invalid_protocol())?);
+    }
+    let list = list.finish().ok_or_else(invalid_protocol)?;
+    HeaderValue::try_from(list).map_err(|_| invalid_protocol())
+}
+
+/// Encode the selected subprotocol as a structured field string.
+pub(crate) fn encode_protocol(protocol: &str) -> Result<HeaderValue, ConnectError> {
+    let protocol = StringRef::from_str(protocol).map_err(|_| invalid_protocol())?;
+    let item = ItemSerializer::new().bare_item(protocol).finish();
+    HeaderValue::try_from(item).map_err(|_| invalid_protocol())
+}
+
+// Decode a structured field list of strings, see `encode_protocols`.
+fn decode_protocols(value: &HeaderValue) -> Result<Vec<String>, ConnectError> {
+    let list = Parser::new(value.as_bytes())
+        .parse::<List>()
+        .map_err(|_| invalid_protocol())?;
+    list.iter()
+        .map(|entry| match entry {
+            ListEntry::Item(item) => item
+                .bare_item
+                .as_string()
+                .map(|protocol| protocol.as_str().to_string())
+                .ok_or_else(invalid_protocol),
+            ListEntry::InnerList(_) => Err(invalid_protocol()),
+        })
+        .collect()
+}
+
+fn invalid_protocol() -> ConnectError {
+    web_transport_proto::ConnectError::InvalidProtocol.into()
+}
+
+// Append headers to an encoded HEADERS frame as literals, which requires no QPACK table state.
+// Header names are always lowercase, as HTTP/3 requires.
+fn append_literals<B: BufMut>(
+    frame: BytesMut,
+    headers: &HeaderMap,
+    buf: &mut B,
+) -> Result<(), ConnectError> {
+    let mut frame = frame.freeze();
+    let (_, mut fields) = Frame::read(&mut frame).map_err(|_| ConnectError::UnexpectedEnd)?;
+    let mut fields = BytesMut::from(fields.copy_to_bytes(fields.remaining()));
+    for (name, value) in headers {
+        let value = value
+            .to_str()
+            .map_err(|_| web_transport_proto::ConnectError::InvalidHttpHeaderValue)?;
+        encode_literal(&mut fields, name.as_str(), value);
+    }
+
+    Frame::HEADERS.encode(buf);
+    VarInt::from_u32(fields.len() as u32).encode(buf);
+    buf.put(fields);
+    Ok(())
+}
+
+/// Read a request, rejecting field sections larger than `max_size` if given.
+pub(crate) async fn read_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_size: Option<u64>,
+) -> Result<ConnectRequest, ConnectError> {
+    let fields = read_headers_frame(stream, max_size).await?;
+    if let Some(max) = max_size {
+        check_field_section_size(&decode_field_section(&fields)?, max)?;
+    }
+
+    let mut frame = BytesMut::new();
+    Frame::HEADERS.encode(&mut frame);
+    VarInt::from_u32(fields.len() as u32).encode(&mut frame);
+    frame.put_slice(&fields);
+    Ok(ConnectRequest::decode(&mut frame.freeze())?)
+}
+
+/// Read a response, consuming only the exact bytes of the frame, along with its headers.
+///
+/// Field sections larger than `max_size`, if given, are rejected.
+pub(crate) async fn read_response<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_size: Option<u64>,
+) -> Result<(ConnectResponse, HeaderMap), ConnectError> {
+    let fields = read_headers_frame(stream, max_size).await?;
+    let lines = decode_field_section(&fields)?;
+    if let Some(max) = max_size {
+        check_field_section_size(&lines, max)?;
+    }
+
+    let mut frame = BytesMut::new();
+    Frame::HEADERS.encode(&mut frame);
+    VarInt::from_u32(fields.len() as u32).encode(&mut frame);
+    frame.put_slice(&fields);
+    // The response is only decoded for successful statuses, but we want to read rejections too.
+    let response = match ConnectResponse::decode(&mut frame.freeze()) {
+        Ok(response) => response,
+        Err(web_transport_proto::ConnectError::WrongStatus(Some(status)))
+            if !status.is_success() =>
+        {
+            ConnectResponse::new(status)
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((response, response_headers(lines)?))
+}
+
+// Computes the decoded size of a field section and fails if it exceeds the limit.
+fn check_field_section_size(lines: &[FieldLine], max: u64) -> Result<(), ConnectError> {
+    let size = lines
+        .iter()
+        .map(|(name, value)| (name.len() + value.len()) as u64 + FIELD_OVERHEAD)
+        .sum();
+    if size > max {
+        return Err(ConnectError::FieldSectionTooLarge { size, max });
+    }
+    Ok(())
+}
+
+// Collect the regular fields of a response, skipping the ones exposed by `ConnectResponse`.
+fn response_headers(lines: Vec<FieldLine>) -> Result<HeaderMap, ConnectError> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in lines {
+        if name.starts_with(b":")
+            || name == SELECTED_PROTOCOL.as_bytes()
+            || name == DRAFT_VERSION.as_bytes()
+        {
+            continue;
+        }
+        let name = HeaderName::from_bytes(&name)
+            .map_err(|_| web_transport_proto::ConnectError::InvalidHttpHeaderName)?;
+        let value = HeaderValue::from_bytes(&value)
+            .map_err(|_| web_transport_proto::ConnectError::InvalidHttpHeaderValue)?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Read the body of a response until the stream is finished, skipping any non-DATA frames.
+pub(crate) async fn read_body<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Bytes, ConnectError> {
+    let mut body = BytesMut::new();
+    loop {
+        let typ = match VarInt::read_optional(stream).await {
+            Ok(Some(typ)) => Frame(typ),
+            Ok(None) => return Ok(body.freeze()),
+            Err(_) => return Err(ConnectError::UnexpectedEnd),
+        };
+        let size = read_varint(stream).await?.into_inner();
+        if body.len() as u64 + size > MAX_FRAME_SIZE {
+            return Err(web_transport_proto::ConnectError::FrameTooLarge.into());
+        }
+
+        let mut payload = vec![0; size as usize];
+        stream
+            .read_exact(&mut payload)
+            .await
+            .map_err(|_| ConnectError::UnexpectedEnd)?;
+        if typ == Frame::DATA {
+            body.extend_from_slice(&payload);
+        }
+    }
+}
+
+// Read the payload of the next HEADERS frame, skipping any GREASE frames.
+//
+// The encoded payload is rarely larger than the decoded field section, so a payload
+// larger than `max_size` is rejected before it is buffered.
+async fn read_headers_frame<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_size: Option<u64>,
+) -> Result<Bytes, ConnectError> {
+    loop {
+        let typ = Frame(read_varint(stream).await?);
+        let size = read_varint(stream).await?.into_inner();
+        if let Some(max) = max_size
+            && typ == Frame::HEADERS
+            && size > max
+        {
+            return Err(ConnectError::FieldSectionTooLarge { size, max });
+        }
+        if size > MAX_FRAME_SIZE {
+            return Err(web_transport_proto::ConnectError::FrameTooLarge.into());
+        }
+
+        let mut payload = vec![0; size as usize];
+        stream
+            .read_exact(&mut payload)
+            .await
+            .map_err(|_| ConnectError::UnexpectedEnd)?;
+
+        if typ.is_grease() {
+            continue;
+        }
+        if typ != Frame::HEADERS {
+            return Err(web_transport_proto::ConnectError::UnexpectedFrame(typ).into());
+        }
+        return Ok(payload.into());
+    }
+}
+
+async fn read_varint<S: AsyncRead + Unpin>(stream: &mut S) -> Result<VarInt, ConnectError> {
+    VarInt::read(stream)
+        .await
+        .map_err(|_| ConnectError::UnexpectedEnd)
+}
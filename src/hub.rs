@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use iroh::EndpointId;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::Session;
+
+/// A session joining or leaving a [`SessionHub`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubEvent<K> {
+    /// A session was added under the key.
+    Joined(K),
+    /// The session under the key was closed, replaced or removed.
+    Left(K),
+}
+
+/// A registry of live sessions, keyed e.g. by the [`EndpointId`] of the peer or by URL.
+///
+/// Sessions are removed once they are closed, and [`Self::subscribe`] reports every join and
+/// leave. There is at most one session per key: joining with a key that is taken replaces the
+/// previous session, which is returned so the caller can decide what to do with it.
+///
+/// The hub holds a clone of every session, so sessions stay open until they are closed,
+/// e.g. with [`Self::close_all`], or removed. Clones share the same registry.
+pub struct SessionHub<K = EndpointId> {
+    inner: Arc<Inner<K>>,
+}
+
+struct Inner<K> {
+    sessions: Mutex<HashMap<K, Entry>>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<HubEvent<K>>,
+}
+
+struct Entry {
+    // So a replaced session doesn't remove its successor once it's closed.
+    id: u64,
+    session: Session,
+    // Cancelled once the session leaves, so the task watching it stops.
+    left: CancellationToken,
+}
+
+impl Entry {
+    fn leave(self) -> Session {
+        self.left.cancel();
+        self.session
+    }
+}
+
+impl<K> SessionHub<K>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    /// Creates an empty hub, buffering up to `capacity` events for slow subscribers.
+    ///
+    /// Subscribers that fall further behind miss events.
+    pub fn new(capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(capacity);
+        Self {
+            inner: Arc::new(Inner {
+                sessions: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+                events,
+            }),
+        }
+    }
+
+    /// Adds the session under the key until it is closed, returning the session it replaces, if any.
+    pub fn join(&self, key: K, session: Session) -> Option<Session> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let left = CancellationToken::new();
+        let entry = Entry {
+            id,
+            session: session.clone(),
+            left: left.clone(),
+        };
+        let previous = self
+            .inner
+            .sessions
+            .lock()
+            .unwrap()
+            .insert(key.clone(), entry);
+        if previous.is_some() {
+            self.inner.events.send(HubEvent::Left(key.clone())).ok();
+        }
+        self.inner.events.send(HubEvent::Joined(key.clone())).ok();
+
+        let inner = Arc::downgrade(&self.inner);
+        let spawner = session.spawner().clone();
+        let session = session.downgrade();
+        spawner.spawn(async move {
+            tokio::select! {
+                _ = session.closed() => {}
+                _ = session.dropped() => {}
+                _ = left.cancelled() => return,
+            }
+            drop(session);
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            let mut sessions = inner.sessions.lock().unwrap();
+            if sessions.get(&key).is_some_and(|entry| entry.id == id) {
+                sessions.remove(&key);
+                drop(sessions);
+                inner.events.send(HubEvent::Left(key)).ok();
+            }
+        });
+        previous.map(Entry::leave)
+    }
+
+    /// Removes the session under the key without closing it.
+    pub fn remove(&self, key: &K) -> Option<Session> {
+        let entry = self.inner.sessions.lock().unwrap().remove(key)?;
+        self.inner.events.send(HubEvent::Left(key.clone())).ok();
+        Some(entry.leave())
+    }
+
+    /// Returns the session under the key.
+    pub fn get(&self, key: &K) -> Option<Session> {
+        let sessions = self.inner.sessions.lock().unwrap();
+        sessions.get(key).map(|entry| entry.session.clone())
+    }
+
+    /// Returns true if there is a session under the key.
+    pub fn contains(&self, key: &K) -> bool {
+        self.inner.sessions.lock().unwrap().contains_key(key)
+    }
+
+    /// Returns the keys of all sessions, in no particular order.
+    pub fn keys(&self) -> Vec<K> {
+        self.inner
+            .sessions
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns all sessions with their keys, in no particular order.
+    pub fn sessions(&self) -> Vec<(K, Session)> {
+        let sessions = self.inner.sessions.lock().unwrap();
+        sessions
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.session.clone()))
+            .collect()
+    }
+
+    /// Returns the number of sessions.
+    pub fn len(&self) -> usize {
+        self.inner.sessions.lock().unwrap().len()
+    }
+
+    /// Returns true if there are no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Closes all sessions with the error code and reason, see [`Session::close`].
+    ///
+    /// They leave the hub once they noticed they are closed.
+    pub fn close_all(&self, code: u32, reason: &[u8]) {
+        for (_, session) in self.sessions() {
+            session.close(code, reason);
+        }
+    }
+
+    /// Returns a receiver for the sessions joining and leaving from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<HubEvent<K>> {
+        self.inner.events.subscribe()
+    }
+}
+
+impl SessionHub<EndpointId> {
+    /// Adds the session under the id of its peer, see [`Self::join`].
+    pub fn join_peer(&self, session: Session) -> Option<Session> {
+        self.join(session.conn().remote_id(), session)
+    }
+}
+
+impl<K> Clone for SessionHub<K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K> Default for SessionHub<K>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl<K> fmt::Debug for SessionHub<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionHub")
+            .field("len", &self.inner.sessions.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    array, fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+// The number of one-second buckets kept for computing rates, plus the current one.
+const HISTORY: usize = 61;
+
+/// Counters collected by [`Instrumented`].
+///
+/// Besides the totals, events are counted per second for the last minute, so rates over a
+/// sliding window can be read with [`Self::rates`].
+///
+/// Only datagram payload bytes are counted. Streams are handed out unwrapped, so the bytes
+/// written to and read from them are not.
+#[derive(Debug, Default)]
+pub struct SessionMetrics {
+    uni_opened: AtomicU64,
+    uni_accepted: AtomicU64,
+    bi_opened: AtomicU64,
+    bi_accepted: AtomicU64,
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    datagram_bytes_sent: AtomicU64,
+    datagram_bytes_received: AtomicU64,
+    history: History,
+}
+
+/// Event rates per second over a sliding window, see [`SessionMetrics::rates`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rates {
+    /// The window the rates were computed over.
+    ///
+    /// This is shorter than requested while the session is younger than the window.
+    pub window: Duration,
+    /// Streams opened by us per second, both unidirectional and bidirectional.
+    pub streams_opened: f64,
+    /// Streams accepted from the peer per second, both unidirectional and bidirectional.
+    pub streams_accepted: f64,
+    /// Datagrams sent per second.
+    pub datagrams_sent: f64,
+    /// Datagrams received per second.
+    pub datagrams_received: f64,
+    /// Datagram payload bytes sent per second.
+    pub datagram_bytes_sent: f64,
+    /// Datagram payload bytes received per second.
+    pub datagram_bytes_received: f64,
+}
+
+// The events counted per second, indexing the counts of a bucket.
+#[derive(Debug, Clone, Copy)]
+enum Count {
+    StreamsOpened,
+    StreamsAccepted,
+    DatagramsSent,
+    DatagramsReceived,
+    DatagramBytesSent,
+    DatagramBytesReceived,
+}
+
+const COUNTS: usize = Count::DatagramBytesReceived as usize + 1;
+
+// The counts for a single second, labelled with the second since the metrics were created.
+#[derive(Debug, Default)]
+struct Bucket {
+    second: AtomicU64,
+    counts: [AtomicU64; COUNTS],
+}
+
+// Counts per second since the metrics were created, in a ring buffer.
+//
+// Buckets are reused without a lock, so events recorded while a bucket is being reset for a
+// new second may be lost. That only skews the rates slightly.
+struct History {
+    start: Instant,
+    buckets: [Bucket; HISTORY],
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            buckets: array::from_fn(|_| Bucket::default()),
+        }
+    }
+}
+
+impl fmt::Debug for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("History")
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+impl History {
+    fn record(&self, count: Count, n: u64) {
+        let second = self.start.elapsed().as_secs();
+        let bucket = &self.buckets[second as usize % HISTORY];
+        let label = bucket.second.load(Ordering::Acquire);
+        if label != second
+            && bucket
+                .second
+                .compare_exchange(label, second, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            for counter in &bucket.counts {
+                counter.store(0, Ordering::Relaxed);
+            }
+        }
+        bucket.counts[count as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    // Averages the completed seconds within the window, so the rates don't drop at the
+    // start of every second.
+    fn rates(&self, window: Duration) -> Rates {
+        let now = self.start.elapsed().as_secs();
+        let seconds = window.as_secs().clamp(1, HISTORY as u64 - 1).min(now);
+        if seconds == 0 {
+            return Rates::default();
+        }
+        let mut sum = [0u64; COUNTS];
+        for second in now - seconds..now {
+            let bucket = &self.buckets[second as usize % HISTORY];
+            if bucket.second.load(Ordering::Acquire) != second {
+                continue;
+            }
+            for (sum, counter) in sum.iter_mut().zip(&bucket.counts) {
+                *sum += counter.load(Ordering::Relaxed);
+            }
+        }
+        let rate = |count: Count| sum[count as usize] as f64 / seconds as f64;
+        Rates {
+            window: Duration::from_secs(seconds),
+            streams_opened: rate(Count::StreamsOpened),
+            streams_accepted: rate(Count::StreamsAccepted),
+            datagrams_sent: rate(Count::DatagramsSent),
+            datagrams_received: rate(Count::DatagramsReceived),
+            datagram_bytes_sent: rate(Count::DatagramBytesSent),
+            datagram_bytes_received: rate(Count::DatagramBytesReceived),
+        }
+    }
+}
+
+impl SessionMetrics {
+    /// Returns the number of unidirectional streams opened by us.
+    pub fn uni_opened(&self) -> u64 {
+        self.uni_opened.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of unidirectional streams accepted from the peer.
+    pub fn uni_accepted(&self) -> u64 {
+        self.uni_accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bidirectional streams opened by us.
+    pub fn bi_opened(&self) -> u64 {
+        self.bi_opened.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bidirectional streams accepted from the peer.
+    pub fn bi_accepted(&self) -> u64 {
+        self.bi_accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of datagrams sent.
+    pub fn datagrams_sent(&self) -> u64 {
+        self.datagrams_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of datagrams received.
+    pub fn datagrams_received(&self) -> u64 {
+        self.datagrams_received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of datagram payload bytes sent.
+    pub fn datagram_bytes_sent(&self) -> u64 {
+        self.datagram_bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of datagram payload bytes received.
+    pub fn datagram_bytes_received(&self) -> u64 {
+        self.datagram_bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the rates per second over the last `window`, e.g. 1s, 10s or a minute.
+    ///
+    /// Only completed seconds are counted, and windows are capped at a minute. While the
+    /// session is younger than a second, all rates are zero.
+    pub fn rates(&self, window: Duration) -> Rates {
+        self.history.rates(window)
+    }
+
+    fn inc(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn record(&self, count: Count, n: u64) {
+        self.history.record(count, n)
+    }
+}
+
+/// Wraps any [`web_transport_trait::Session`] to record metrics and emit tracing events.
+///
+/// All operations run inside the configured [`tracing::Span`], and successful operations are
+/// counted in the shared [`SessionMetrics`]. Clones share the same metrics.
+#[derive(Clone)]
+pub struct Instrumented<S> {
+    inner: S,
+    span: tracing::Span,
+    metrics: Arc<SessionMetrics>,
+}
+
+impl<S: web_transport_trait::Session> Instrumented<S> {
+    /// Wraps a session, recording events in a new `session` span.
+    pub fn new(inner: S) -> Self {
+        Self::with_span(inner, tracing::debug_span!("session"))
+    }
+
+    /// Wraps a session, recording events in the given span.
+    pub fn with_span(inner: S, span: tracing::Span) -> Self {
+        Self {
+            inner,
+            span,
+            metrics: Default::default(),
+        }
+    }
+
+    /// Returns the metrics collected so far.
+    pub fn metrics(&self) -> &Arc<SessionMetrics> {
+        &self.metrics
+    }
+
+    /// Returns the span events are recorded in.
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
+
+    /// Returns the wrapped session.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the wrapped session, discarding the instrumentation.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> fmt::Debug for Instrumented<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Instrumented")
+            .field("metrics", &self.metrics)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: web_transport_trait::Session> web_transport_trait::Session for Instrumented<S> {
+    type SendStream = S::SendStream;
+    type RecvStream = S::RecvStream;
+    type Error = S::Error;
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::Error> {
+        let recv = self
+            .inner
+            .accept_uni()
+            .instrument(self.span.clone())
+            .await?;
+        SessionMetrics::inc(&self.metrics.uni_accepted, 1);
+        self.metrics.record(Count::StreamsAccepted, 1);
+        self.span
+            .in_scope(|| tracing::trace!("accepted uni stream"));
+        Ok(recv)
+    }
+
+    async fn accept_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Self::Error> {
+        let streams = self.inner.accept_bi().instrument(self.span.clone()).await?;
+        SessionMetrics::inc(&self.metrics.bi_accepted, 1);
+        self.metrics.record(Count::StreamsAccepted, 1);
+        self.span.in_scope(|| tracing::trace!("accepted bi stream"));
+        Ok(streams)
+    }
+
+    async fn open_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Self::Error> {
+        let streams = self.inner.open_bi().instrument(self.span.clone()).await?;
+        SessionMetrics::inc(&self.metrics.bi_opened, 1);
+        self.metrics.record(Count::StreamsOpened, 1);
+        self.span.in_scope(|| tracing::trace!("opened bi stream"));
+        Ok(streams)
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendStream, Self::Error> {
+        let send = self.inner.open_uni().instrument(self.span.clone()).await?;
+        SessionMetrics::inc(&self.metrics.uni_opened, 1);
+        self.metrics.record(Count::StreamsOpened, 1);
+        self.span.in_scope(|| tracing::trace!("opened uni stream"));
+        Ok(send)
+    }
+
+    fn send_datagram(&self, payload: Bytes) -> Result<(), Self::Error> {
+        let size = payload.len() as u64;
+        let _guard = self.span.enter();
+        match self.inner.send_datagram(payload) {
+            Ok(()) => {
+                SessionMetrics::inc(&self.metrics.datagrams_sent, 1);
+                SessionMetrics::inc(&self.metrics.datagram_bytes_sent, size);
+                self.metrics.record(Count::DatagramsSent, 1);
+                self.metrics.record(Count::DatagramBytesSent, size);
+                Ok(())
+            }
+            Err(err) => {
+                tracing::debug!(size, "failed to send datagram: {err}");
+                Err(err)
+            }
+        }
+    }
+
+    async fn recv_datagram(&self) -> Result<Bytes, Self::Error> {
+        let datagram = self
+            .inner
+            .recv_datagram()
+            .instrument(self.span.clone())
+            .await?;
+        SessionMetrics::inc(&self.metrics.datagrams_received, 1);
+        SessionMetrics::inc(&self.metrics.datagram_bytes_received, datagram.len() as u64);
+        self.metrics.record(Count::DatagramsReceived, 1);
+        self.metrics
+            .record(Count::DatagramBytesReceived, datagram.len() as u64);
+        Ok(datagram)
+    }
+
+    fn max_datagram_size(&self) -> usize {
+        self.inner.max_datagram_size()
+    }
+
+    fn protocol(&self) -> Option<&str> {
+        self.inner.protocol()
+    }
+
+    fn close(&self, code: u32, reason: &str) {
+        self.span
+            .in_scope(|| tracing::debug!(code, reason, "closing session"));
+        self.inner.close(code, reason)
+    }
+
+    async fn closed(&self) -> Self::Error {
+        let err = self.inner.closed().instrument(self.span.clone()).await;
+        self.span
+            .in_scope(|| tracing::debug!(metrics = ?self.metrics, "session closed: {err}"));
+        err
+    }
+
+    fn stats(&self) -> impl web_transport_trait::Stats {
+        self.inner.stats()
+    }
+}
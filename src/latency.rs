@@ -0,0 +1,283 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, RandomState},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::{Buf, Bytes};
+
+use crate::Spawner;
+
+/// A handle to the delay added by [`Delayed`], which can be changed at runtime.
+///
+/// Clones share the same settings, so one handle can control many sessions.
+/// Starts out disabled, adding no delay.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyPolicy {
+    inner: Arc<LatencySettings>,
+}
+
+#[derive(Debug, Default)]
+struct LatencySettings {
+    // Both in microseconds.
+    delay: AtomicU64,
+    jitter: AtomicU64,
+}
+
+impl LatencyPolicy {
+    /// Returns a disabled policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays each send by `delay`, plus a random duration of up to `jitter`.
+    pub fn set(&self, delay: Duration, jitter: Duration) {
+        self.inner
+            .delay
+            .store(delay.as_micros() as u64, Ordering::Relaxed);
+        self.inner
+            .jitter
+            .store(jitter.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Stops delaying sends.
+    pub fn disable(&self) {
+        self.set(Duration::ZERO, Duration::ZERO);
+    }
+
+    /// Returns true if sends are delayed.
+    pub fn is_enabled(&self) -> bool {
+        !self.delay().is_zero() || !self.jitter().is_zero()
+    }
+
+    /// Returns the fixed delay of each send.
+    pub fn delay(&self) -> Duration {
+        Duration::from_micros(self.inner.delay.load(Ordering::Relaxed))
+    }
+
+    /// Returns the maximum random delay added on top of [`Self::delay`].
+    pub fn jitter(&self) -> Duration {
+        Duration::from_micros(self.inner.jitter.load(Ordering::Relaxed))
+    }
+
+    /// Returns the delay for the next send, with jitter applied.
+    fn sample(&self) -> Duration {
+        self.delay() + random_up_to(self.jitter())
+    }
+
+    async fn wait(&self) {
+        let delay = self.sample();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Wraps any [`web_transport_trait::Session`] to delay what it sends, for staging and chaos testing.
+///
+/// Stream writes and datagrams are held back according to the [`LatencyPolicy`], which can be
+/// toggled at runtime. Received data is not affected. While the policy is disabled, the only
+/// overhead is reading its settings.
+#[derive(Clone)]
+pub struct Delayed<S> {
+    inner: S,
+    policy: LatencyPolicy,
+    // Sends delayed datagrams from a task.
+    spawner: Spawner,
+}
+
+impl<S: web_transport_trait::Session> Delayed<S> {
+    /// Wraps a session, delaying sends according to the policy.
+    pub fn new(inner: S, policy: LatencyPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            spawner: Spawner::default(),
+        }
+    }
+
+    /// Sends delayed datagrams from tasks started with the [`Spawner`].
+    ///
+    /// Defaults to the current tokio runtime.
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Returns the policy controlling the delay.
+    pub fn policy(&self) -> &LatencyPolicy {
+        &self.policy
+    }
+
+    /// Returns the wrapped session.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the wrapped session, discarding the delay.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> fmt::Debug for Delayed<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delayed")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: web_transport_trait::Session> web_transport_trait::Session for Delayed<S> {
+    type SendStream = DelayedSendStream<S::SendStream>;
+    type RecvStream = S::RecvStream;
+    type Error = S::Error;
+
+    async fn accept_uni(&self) -> Result<Self::RecvStream, Self::Error> {
+        self.inner.accept_uni().await
+    }
+
+    async fn accept_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Self::Error> {
+        let (send, recv) = self.inner.accept_bi().await?;
+        Ok((DelayedSendStream::new(send, self.policy.clone()), recv))
+    }
+
+    async fn open_bi(&self) -> Result<(Self::SendStream, Self::RecvStream), Self::Error> {
+        let (send, recv) = self.inner.open_bi().await?;
+        Ok((DelayedSendStream::new(send, self.policy.clone()), recv))
+    }
+
+    async fn open_uni(&self) -> Result<Self::SendStream, Self::Error> {
+        let send = self.inner.open_uni().await?;
+        Ok(DelayedSendStream::new(send, self.policy.clone()))
+    }
+
+    /// Sends the datagram after the delay, from a background task.
+    ///
+    /// Errors are only reported if the datagram is sent right away, since datagrams are unreliable anyway.
+    fn send_datagram(&self, payload: Bytes) -> Result<(), Self::Error> {
+        let delay = self.policy.sample();
+        if delay.is_zero() {
+            return self.inner.send_datagram(payload);
+        }
+
+        // The clone is only held for the delay, so it doesn't keep the session open for long.
+        let inner = self.inner.clone();
+        self.spawner.spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(err) = inner.send_datagram(payload) {
+                tracing::debug!("failed to send delayed datagram: {err}");
+            }
+        });
+        Ok(())
+    }
+
+    async fn recv_datagram(&self) -> Result<Bytes, Self::Error> {
+        self.inner.recv_datagram().await
+    }
+
+    fn max_datagram_size(&self) -> usize {
+        self.inner.max_datagram_size()
+    }
+
+    fn protocol(&self) -> Option<&str> {
+        self.inner.protocol()
+    }
+
+    fn close(&self, code: u32, reason: &str) {
+        self.inner.close(code, reason)
+    }
+
+    async fn closed(&self) -> Self::Error {
+        self.inner.closed().await
+    }
+
+    fn stats(&self) -> impl web_transport_trait::Stats {
+        self.inner.stats()
+    }
+}
+
+/// A send stream of a [`Delayed`] session, waiting before each write.
+pub struct DelayedSendStream<S> {
+    inner: S,
+    policy: LatencyPolicy,
+}
+
+impl<S: web_transport_trait::SendStream> DelayedSendStream<S> {
+    fn new(inner: S, policy: LatencyPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Returns the wrapped stream, discarding the delay.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> fmt::Debug for DelayedSendStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelayedSendStream")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+// Each write call is delayed once, even the ones writing everything.
+impl<S: web_transport_trait::SendStream> web_transport_trait::SendStream for DelayedSendStream<S> {
+    type Error = S::Error;
+
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.policy.wait().await;
+        self.inner.write(buf).await
+    }
+
+    async fn write_buf<B: Buf + Send>(&mut self, buf: &mut B) -> Result<usize, Self::Error> {
+        self.policy.wait().await;
+        self.inner.write_buf(buf).await
+    }
+
+    async fn write_chunk(&mut self, chunk: Bytes) -> Result<(), Self::Error> {
+        self.policy.wait().await;
+        self.inner.write_chunk(chunk).await
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.policy.wait().await;
+        self.inner.write_all(buf).await
+    }
+
+    async fn write_all_buf<B: Buf + Send>(&mut self, buf: &mut B) -> Result<(), Self::Error> {
+        self.policy.wait().await;
+        self.inner.write_all_buf(buf).await
+    }
+
+    fn set_priority(&mut self, order: u8) {
+        self.inner.set_priority(order)
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.inner.finish()
+    }
+
+    fn reset(&mut self, code: u32) {
+        self.inner.reset(code)
+    }
+
+    async fn closed(&mut self) -> Result<(), Self::Error> {
+        self.inner.closed().await
+    }
+}
+
+/// Returns a random duration of up to `max`, with microsecond precision.
+pub(crate) fn random_up_to(max: Duration) -> Duration {
+    let max = max.as_micros() as u64;
+    if max == 0 {
+        return Duration::ZERO;
+    }
+    // Randomly seeded, so we don't need a dependency on rand.
+    Duration::from_micros(RandomState::new().hash_one(()) % (max + 1))
+}
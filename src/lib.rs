@@ -18,29 +18,111 @@
 //! If you want to support multiple WebTransport sessions over the same QUIC connection...
 //! you should just dial a new QUIC connection instead.
 //!
+//! Sessions can't be transferred to another process, e.g. for zero-downtime restarts.
+//! Use [`Session::handoff`] to drain them instead, so peers reconnect to the new process.
+//!
+//! Sessions start their background tasks, such as the [`SessionDriver`], with a [`Spawner`].
+//! It defaults to the current tokio runtime. Set one with [`Client::with_spawner`] or
+//! [`Server::with_spawner`] to hand the tasks to another executor, or to poll them yourself.
+//!
 //! [web-transport-trait]: https://docs.rs/web-transport-trait/latest/web_transport_trait/
 //! [iroh documentation]: https://docs.rs/iroh/latest/iroh/
 //! [connections]: https://docs.rs/iroh/latest/iroh/endpoint/struct.Connection.html
 
+mod acl;
+mod admission;
+mod advisor;
+#[cfg(feature = "apps")]
+pub mod apps;
+mod auth;
+mod batch;
+mod bulk;
+mod capabilities;
 mod client;
+mod congestion;
 mod connect;
+mod control;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod group;
+mod handshake;
+mod headers;
+mod hub;
+mod instrument;
+mod latency;
+mod message;
+mod metrics;
+mod middleware;
+mod origin;
+mod panic_policy;
+mod path;
+mod peer;
+mod pool;
+mod profile;
+#[cfg(feature = "python")]
+mod python;
+mod qpack;
+mod quota;
 mod recv;
+mod request;
+mod resolve;
+mod retry;
+mod router;
 mod send;
 mod server;
 mod session;
 mod settings;
+mod shutdown;
+mod spawn;
+mod stream_count;
+mod stream_type;
+mod strictness;
 #[cfg(test)]
 mod tests;
+mod transport;
+mod vhost;
 
+pub use acl::*;
+pub use admission::*;
+pub use auth::{ACCESS_TOKEN_PARAM, AuthRequest};
+pub use batch::*;
+pub use bulk::*;
+pub use capabilities::*;
 pub use client::*;
 pub use connect::*;
 pub use error::*;
+pub use group::*;
+pub use handshake::*;
+pub use hub::*;
+pub use instrument::*;
+pub use latency::*;
+pub use message::*;
+pub use metrics::*;
+pub use middleware::*;
+pub use origin::*;
+pub use panic_policy::*;
+pub use path::*;
+pub use peer::*;
+pub use pool::PoolConfig;
+pub use profile::*;
+pub use quota::PEER_QUOTA_EXCEEDED;
 pub use recv::*;
+pub use request::*;
+pub use resolve::*;
+pub use retry::*;
+pub use router::*;
 pub use send::*;
 pub use server::*;
 pub use session::*;
 pub use settings::*;
+pub use shutdown::*;
+pub use spawn::*;
+pub use stream_count::*;
+pub use stream_type::*;
+pub use strictness::*;
+pub use transport::*;
+pub use vhost::*;
 
 /// The HTTP/3 ALPN is required when negotiating a QUIC connection.
 pub const ALPN_H3: &str = "h3";
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{MessageError, ReadToEndError, RecvStream, SendStream, WriteError};
+
+/// The error code used to stop a stream whose message exceeds the size limit.
+pub const MESSAGE_TOO_LONG: u32 = 0x01;
+
+/// The error code used to stop a stream whose message wasn't received before its deadline.
+pub const MESSAGE_TIMED_OUT: u32 = 0x02;
+
+/// What to do with a message that isn't fully received before its deadline.
+///
+/// See [`Session::accept_uni_message_with_timeout`](crate::Session::accept_uni_message_with_timeout).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialPolicy {
+    /// Drop what was received and fail with [`MessageError::TimedOut`].
+    ///
+    /// The stream is stopped without an error code, as if it was dropped.
+    Discard,
+    /// Return what was received so far, with [`Message::complete`] set to false.
+    ///
+    /// The stream is stopped with [`MESSAGE_TIMED_OUT`].
+    Deliver,
+    /// Fail with [`MessageError::TimedOut`], telling the peer about the timeout.
+    ///
+    /// The stream is stopped with [`MESSAGE_TIMED_OUT`], and for requests the response
+    /// stream is reset with the same code.
+    Reset,
+}
+
+/// A message read with a deadline, which may only be partially received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// The received data.
+    pub data: Bytes,
+    /// Whether the whole message was received before the deadline.
+    pub complete: bool,
+}
+
+/// The sending half of a bidirectional stream returned by [`crate::Session::accept_bi_request`].
+///
+/// Dropping the responder without replying finishes the stream gracefully.
+#[derive(Debug)]
+pub struct Responder {
+    send: SendStream,
+}
+
+impl Responder {
+    pub(crate) fn new(send: SendStream) -> Self {
+        Self { send }
+    }
+
+    /// Write the response and finish the stream.
+    pub async fn respond(mut self, response: Bytes) -> Result<(), WriteError> {
+        self.send.write_chunk(response).await?;
+        self.send.finish().map_err(|_| WriteError::ClosedStream)
+    }
+
+    /// Reject the request by resetting the stream with the given error code.
+    pub fn reject(mut self, code: u32) {
+        // The stream can only be closed already if the connection is gone.
+        self.send.reset(code).ok();
+    }
+
+    /// Return the underlying stream, to stream a response instead of sending it at once.
+    pub fn into_inner(self) -> SendStream {
+        self.send
+    }
+}
+
+/// Read a whole message within the timeout, applying the policy if it takes longer.
+pub(crate) async fn read_message_with_timeout(
+    recv: &mut RecvStream,
+    size_limit: usize,
+    timeout: Duration,
+    policy: PartialPolicy,
+) -> Result<Message, MessageError> {
+    let mut buf = BytesMut::new();
+    let read = async {
+        while let Some(chunk) = recv.read_chunk(usize::MAX).await? {
+            if buf.len() + chunk.bytes.len() > size_limit {
+                return Err(MessageError::TooLong);
+            }
+            buf.extend_from_slice(&chunk.bytes);
+        }
+        Ok(())
+    };
+    match tokio::time::timeout(timeout, read).await {
+        Ok(Ok(())) => Ok(Message {
+            data: buf.freeze(),
+            complete: true,
+        }),
+        Ok(Err(MessageError::TooLong)) => {
+            recv.stop(MESSAGE_TOO_LONG).ok();
+            Err(MessageError::TooLong)
+        }
+        Ok(Err(err)) => Err(err),
+        Err(_) => match policy {
+            PartialPolicy::Discard => {
+                recv.stop(0).ok();
+                Err(MessageError::TimedOut)
+            }
+            PartialPolicy::Deliver => {
+                recv.stop(MESSAGE_TIMED_OUT).ok();
+                Ok(Message {
+                    data: buf.freeze(),
+                    complete: false,
+                })
+            }
+            PartialPolicy::Reset => {
+                recv.stop(MESSAGE_TIMED_OUT).ok();
+                Err(MessageError::TimedOut)
+            }
+        },
+    }
+}
+
+/// Read a whole message, stopping the stream if it exceeds the limit.
+pub(crate) async fn read_message(
+    recv: &mut RecvStream,
+    size_limit: usize,
+) -> Result<Bytes, MessageError> {
+    match recv.read_to_end(size_limit).await {
+        Ok(buf) => Ok(buf.into()),
+        Err(ReadToEndError::TooLong) => {
+            // Tell the peer to stop sending, rather than buffering the rest of the message.
+            recv.stop(MESSAGE_TOO_LONG).ok();
+            Err(MessageError::TooLong)
+        }
+        Err(ReadToEndError::ReadError(err)) => Err(err.into()),
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::ServerError;
+
+/// Counters of the handshakes run by a [`Server`](crate::Server), see [`Server::metrics`](crate::Server::metrics).
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    started: AtomicU64,
+    completed: AtomicU64,
+    timed_out: AtomicU64,
+    rejected: AtomicU64,
+    failed: AtomicU64,
+    refused: AtomicU64,
+}
+
+impl ServerMetrics {
+    /// Returns the number of handshakes started for incoming connections.
+    pub fn handshakes_started(&self) -> u64 {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of handshakes that completed, yielding a session request.
+    pub fn handshakes_completed(&self) -> u64 {
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of handshakes that didn't complete within the handshake timeout.
+    pub fn handshakes_timed_out(&self) -> u64 {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of handshakes rejected by the ACL, the connection filter, the origin
+    /// policy, admission control or authentication.
+    pub fn handshakes_rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of handshakes that failed for any other reason.
+    pub fn handshakes_failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections refused before their handshake, because too many
+    /// handshakes were pending or by [`AdmissionControl::admit_connection`](crate::AdmissionControl::admit_connection).
+    pub fn connections_refused(&self) -> u64 {
+        self.refused.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn start(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn complete(&self) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn refuse(&self) {
+        self.refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Counts a failed handshake by why it failed, or None if the task panicked.
+    pub(crate) fn fail(&self, err: Option<&ServerError>) {
+        let counter = match err {
+            Some(ServerError::HandshakeTimeout) => &self.timed_out,
+            Some(
+                ServerError::PeerDenied
+                | ServerError::Filtered
+                | ServerError::NotAdmitted { .. }
+                | ServerError::OriginRejected { .. }
+                | ServerError::Unauthenticated { .. },
+            ) => &self.rejected,
+            _ => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
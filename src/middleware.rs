@@ -0,0 +1,63 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use crate::{H3Request, Router, router::reject};
+
+/// The future returned by [`Middleware::call`] and [`Next::run`].
+pub type MiddlewareFuture<'a> = Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>;
+
+/// What happened to a session request dispatched by a [`Router`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The request was passed to a handler, which decided whether to accept it.
+    Handled,
+    /// The request was rejected with the status, by a middleware or for lack of a route.
+    Rejected(http::StatusCode),
+}
+
+impl Outcome {
+    /// Rejects the request with the status, see [`H3Request::reject`].
+    pub async fn reject(request: H3Request, status: http::StatusCode) -> Self {
+        reject(request, status).await;
+        Self::Rejected(status)
+    }
+}
+
+/// Wraps the dispatch of session requests by a [`Router`], see [`Router::with_middleware`].
+///
+/// A middleware can inspect and modify the request, e.g. to authenticate it and store the
+/// identity in [`H3Request::extensions_mut`], pass it on with [`Next::run`] and look at the
+/// [`Outcome`], or reject it with [`Outcome::reject`] without calling the rest of the pipeline.
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles the request, usually by passing it on to `next`.
+    fn call<'a>(&'a self, request: H3Request, next: Next<'a>) -> MiddlewareFuture<'a>;
+}
+
+/// The rest of the pipeline after a [`Middleware`], ending with the routes of the [`Router`].
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    router: &'a Router,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>], router: &'a Router) -> Self {
+        Self { middleware, router }
+    }
+
+    /// Passes the request to the next middleware, or to the matching route of the router.
+    pub fn run(self, request: H3Request) -> MiddlewareFuture<'a> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.call(request, Self::new(rest, self.router)),
+            None => Box::pin(self.router.dispatch_routes(request)),
+        }
+    }
+}
+
+impl fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("middleware", &self.middleware.len())
+            .field("router", &self.router)
+            .finish()
+    }
+}
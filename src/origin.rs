@@ -0,0 +1,116 @@
+use std::{fmt, sync::Arc};
+
+/// The status used to reject a session whose origin isn't allowed by an [`OriginPolicy`].
+pub const ORIGIN_REJECTED: http::StatusCode = http::StatusCode::FORBIDDEN;
+
+/// Which `origin` headers are accepted by [`Server::with_origin_policy`](crate::Server::with_origin_policy)
+/// and [`H3Request::check_origin`](crate::H3Request::check_origin).
+///
+/// Browsers send the origin of the page opening the session, so this prevents other websites
+/// from connecting on behalf of a user. Clients that aren't browsers usually send no origin,
+/// and can send any origin they like, so this is no replacement for authentication.
+#[derive(Clone)]
+pub struct OriginPolicy {
+    allowed: Allowed,
+    allow_missing: bool,
+}
+
+type OriginCallback = Arc<dyn Fn(Option<&str>) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+enum Allowed {
+    Any,
+    List(Vec<String>),
+    Callback(OriginCallback),
+}
+
+impl OriginPolicy {
+    /// Accept any origin, which is the default.
+    pub fn any() -> Self {
+        Self {
+            allowed: Allowed::Any,
+            allow_missing: true,
+        }
+    }
+
+    /// Accept origins matching one of the patterns, e.g. `https://example.com`.
+    ///
+    /// A pattern like `https://*.example.com` matches any subdomain, but not `example.com` itself.
+    /// Origins are compared case-insensitively. Requests without an origin are accepted,
+    /// see [`Self::with_allow_missing`].
+    pub fn allow(patterns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| pattern.into().to_ascii_lowercase())
+            .collect();
+        Self {
+            allowed: Allowed::List(patterns),
+            allow_missing: true,
+        }
+    }
+
+    /// Decide with a callback, which receives the origin if any was sent.
+    pub fn from_fn(f: impl Fn(Option<&str>) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            allowed: Allowed::Callback(Arc::new(f)),
+            allow_missing: true,
+        }
+    }
+
+    /// Sets whether requests without an origin are accepted, which they are by default.
+    ///
+    /// Has no effect for [`Self::from_fn`], which decides on missing origins itself.
+    pub fn with_allow_missing(mut self, allow_missing: bool) -> Self {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    /// Returns true if a request with the given origin is accepted.
+    pub fn is_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed, origin) {
+            (Allowed::Callback(f), origin) => f(origin),
+            (_, None) => self.allow_missing,
+            (Allowed::Any, Some(_)) => true,
+            (Allowed::List(patterns), Some(origin)) => {
+                let origin = origin.to_ascii_lowercase();
+                patterns.iter().any(|pattern| matches(pattern, &origin))
+            }
+        }
+    }
+}
+
+impl Default for OriginPolicy {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+impl fmt::Debug for OriginPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allowed: &dyn fmt::Debug = match &self.allowed {
+            Allowed::Any => &"any",
+            Allowed::List(patterns) => patterns,
+            Allowed::Callback(_) => &"callback",
+        };
+        f.debug_struct("OriginPolicy")
+            .field("allowed", allowed)
+            .field("allow_missing", &self.allow_missing)
+            .finish()
+    }
+}
+
+// Match an origin against a pattern, with a leading `*.` in the host matching any subdomain.
+fn matches(pattern: &str, origin: &str) -> bool {
+    let Some((prefix, suffix)) = pattern.split_once("://*.") else {
+        return pattern == origin;
+    };
+    let Some(host) = origin
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .and_then(|rest| rest.strip_suffix('.'))
+    else {
+        return false;
+    };
+    !host.is_empty() && !host.contains([':', '/'])
+}
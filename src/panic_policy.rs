@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DEBUG_ASSERT: AtomicBool = AtomicBool::new(false);
+
+/// What happens when the crate runs into a state it doesn't expect, see [`set_panic_policy`].
+///
+/// These states are usually caused by a misbehaving peer, so the library never panics on
+/// them in release builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Log a warning and return an error, or fall back to a safe value.
+    #[default]
+    Error,
+    /// Panic in debug builds, to catch problems during development, otherwise like [`Self::Error`].
+    DebugAssert,
+}
+
+/// Sets the [`PanicPolicy`] for the whole process.
+pub fn set_panic_policy(policy: PanicPolicy) {
+    DEBUG_ASSERT.store(policy == PanicPolicy::DebugAssert, Ordering::Relaxed);
+}
+
+/// Returns the [`PanicPolicy`] set with [`set_panic_policy`].
+pub fn panic_policy() -> PanicPolicy {
+    if DEBUG_ASSERT.load(Ordering::Relaxed) {
+        PanicPolicy::DebugAssert
+    } else {
+        PanicPolicy::Error
+    }
+}
+
+/// Reports an unexpected state according to the policy. The caller recovers afterwards.
+pub(crate) fn unexpected(msg: &str) {
+    tracing::warn!("unexpected state: {msg}");
+    if panic_policy() == PanicPolicy::DebugAssert {
+        debug_assert!(false, "unexpected state: {msg}");
+    }
+}
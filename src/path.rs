@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use iroh::{EndpointAddr, TransportAddr, Watcher, endpoint::Connection};
+
+/// Which network path a [`Client`](crate::Client) requires before returning a session.
+///
+/// iroh starts on whichever path works first, usually the relay, and upgrades to a direct
+/// path once holepunching succeeds. Failing the connect otherwise is useful for latency
+/// sensitive applications, and to test either path type on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathMode {
+    /// Return the session as soon as it's connected, on any path.
+    #[default]
+    Any,
+    /// Only connect through a relay.
+    ///
+    /// Direct addresses of the peer aren't used, and the connect fails right away if we
+    /// aren't connected to a relay. iroh may still upgrade to a direct path later on.
+    Relay,
+    /// Wait until a direct path is selected, failing if none is found within the timeout.
+    Direct {
+        /// How long to wait for holepunching, after the connection was established.
+        timeout: Duration,
+    },
+}
+
+// Returns true if the connection currently goes through a relay.
+pub(crate) fn is_relay(conn: &Connection) -> bool {
+    conn.to_info()
+        .selected_path()
+        .is_some_and(|path| path.is_relay())
+}
+
+// Returns the round-trip time of the selected path, if known yet.
+pub(crate) fn rtt(conn: &Connection) -> Option<Duration> {
+    conn.to_info().selected_path().map(|path| path.rtt())
+}
+
+// Returns the remote addresses of all open paths, the selected one first.
+pub(crate) fn remote_addrs(conn: &Connection) -> Vec<TransportAddr> {
+    let mut paths: Vec<_> = conn.paths().get().into_iter().collect();
+    paths.sort_by_key(|path| !path.is_selected());
+    paths
+        .into_iter()
+        .map(|path| path.remote_addr().clone())
+        .collect()
+}
+
+impl PathMode {
+    /// Returns the address to dial, without direct addresses for relay-only connects.
+    pub(crate) fn filter(&self, addr: EndpointAddr) -> EndpointAddr {
+        match self {
+            Self::Relay => EndpointAddr::new(addr.id).with_addrs(
+                addr.relay_urls()
+                    .cloned()
+                    .map(TransportAddr::Relay)
+                    .collect::<Vec<_>>(),
+            ),
+            _ => addr,
+        }
+    }
+
+    /// Waits until the connection uses the required path, returning false if it doesn't.
+    pub(crate) async fn wait(&self, conn: &Connection) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Relay => conn
+                .to_info()
+                .selected_path()
+                .is_some_and(|path| path.is_relay()),
+            Self::Direct { timeout } => {
+                let mut paths = conn.paths();
+                let direct = async {
+                    loop {
+                        if paths.get().iter().any(|p| p.is_selected() && p.is_ip()) {
+                            return true;
+                        }
+                        if paths.updated().await.is_err() {
+                            return false;
+                        }
+                    }
+                };
+                tokio::time::timeout(*timeout, direct)
+                    .await
+                    .unwrap_or(false)
+            }
+        }
+    }
+}
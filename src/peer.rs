@@ -0,0 +1,152 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use iroh::{Endpoint, EndpointAddr, EndpointId};
+use web_transport_proto::ConnectRequest;
+
+use crate::{
+    Client, ClientError, H3Request, OriginPolicy, Server, Session, SettingsProfile, Spawner,
+    Strictness, TransportTuning,
+};
+
+// Type alias just so clippy doesn't complain about the complexity.
+type AuthorizeCallback = Arc<dyn Fn(EndpointId) -> bool + Send + Sync>;
+
+/// A [`Client`] and a [`Server`] sharing one iroh endpoint, for symmetric peer-to-peer applications.
+///
+/// The endpoint should accept the [`ALPN_H3`](crate::ALPN_H3) ALPN. Settings that apply to
+/// both directions, like the [`Strictness`] and the [`SettingsProfile`], are configured once.
+/// Use [`Self::client`] and [`Self::server_mut`] for everything else.
+pub struct Peer {
+    client: Client,
+    server: Server,
+}
+
+impl Peer {
+    /// Creates a peer from an endpoint with the default [`TransportTuning`].
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self::with_tuning(endpoint, TransportTuning::default())
+    }
+
+    /// Creates a peer from an endpoint with the given handshake timings.
+    pub fn with_tuning(endpoint: Endpoint, tuning: TransportTuning) -> Self {
+        Self {
+            client: Client::with_tuning(endpoint.clone(), tuning),
+            server: Server::new(endpoint).with_handshake_timeout(tuning.handshake_timeout),
+        }
+    }
+
+    /// Sets how strictly the protocol is enforced in both directions. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.client = self.client.with_strictness(strictness);
+        self.server = self.server.with_strictness(strictness);
+        self
+    }
+
+    /// Limits the size of the request and response headers, advertised in SETTINGS.
+    pub fn with_max_field_section_size(mut self, size: u64) -> Self {
+        self.client = self.client.with_max_field_section_size(size);
+        self.server = self.server.with_max_field_section_size(size);
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent and which omissions are tolerated in both directions.
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.client = self.client.with_settings_profile(profile.clone());
+        self.server = self.server.with_settings_profile(profile);
+        self
+    }
+
+    /// Starts the background tasks of sessions in both directions with the [`Spawner`].
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.client = self.client.with_spawner(spawner.clone());
+        self.server = self.server.with_spawner(spawner);
+        self
+    }
+
+    /// Sets how long to wait for handshakes in both directions, or None to only rely on the idle timeout.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.client = self.client.with_handshake_timeout(timeout);
+        self.server = self.server.with_handshake_timeout(timeout);
+        self
+    }
+
+    /// Only talks to the peers for which the callback returns true.
+    ///
+    /// Connecting to another peer fails with [`ClientError::Vetoed`] before anything is sent.
+    /// Connections from other peers are closed as soon as the QUIC handshake authenticated them,
+    /// before the HTTP/3 handshake, using the [`Server::with_connection_filter`] of the server.
+    pub fn with_authorization(
+        mut self,
+        f: impl Fn(EndpointId) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let authorize: AuthorizeCallback = Arc::new(f);
+        let hook = authorize.clone();
+        self.client = self
+            .client
+            .with_request_hook(move |addr, _| match hook(addr.id) {
+                true => Ok(()),
+                false => Err(format!("peer {} not authorized", addr.id.fmt_short())),
+            });
+        self.server = self
+            .server
+            .with_connection_filter(move |conn| std::future::ready(authorize(conn.remote_id())));
+        self
+    }
+
+    /// Only talks to the given peers, see [`Self::with_authorization`].
+    pub fn with_allowed_peers(self, peers: impl IntoIterator<Item = EndpointId>) -> Self {
+        let peers: Vec<EndpointId> = peers.into_iter().collect();
+        self.with_authorization(move |id| peers.contains(&id))
+    }
+
+    /// Rejects sessions whose origin isn't allowed by the policy, see [`Server::with_origin_policy`].
+    pub fn with_origin_policy(mut self, policy: OriginPolicy) -> Self {
+        self.server = self.server.with_origin_policy(policy);
+        self
+    }
+
+    /// Returns the underlying endpoint.
+    pub fn endpoint(&self) -> &Endpoint {
+        self.server.endpoint()
+    }
+
+    /// Returns the client used for outgoing sessions.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns the server used for incoming sessions, e.g. to set priorities or admission.
+    pub fn server_mut(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    /// Connects to another peer with a full HTTP/3 handshake, see [`Client::connect_h3`].
+    pub async fn connect(
+        &self,
+        addr: impl Into<EndpointAddr>,
+        request: impl Into<ConnectRequest>,
+    ) -> Result<Session, ClientError> {
+        self.client.connect_h3(addr, request).await
+    }
+
+    /// Accepts the next session request from an authorized peer, see [`Server::accept`].
+    ///
+    /// Returns None once the endpoint is closed.
+    pub async fn accept(&mut self) -> Option<H3Request> {
+        self.server.accept().await
+    }
+
+    /// Close the endpoint, ending both incoming and outgoing sessions.
+    pub async fn close(&self) {
+        self.client.close().await;
+    }
+}
+
+impl fmt::Debug for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Peer")
+            .field("client", &self.client)
+            .field("server", &self.server)
+            .finish()
+    }
+}
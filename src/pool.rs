@@ -0,0 +1,145 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use iroh::EndpointId;
+use tokio::time::Instant;
+use web_transport_proto::ConnectRequest;
+
+use crate::Session;
+
+/// Limits for the sessions a [`Client`](crate::Client) keeps around for reuse.
+///
+/// Sessions are handed back with [`Client::release`](crate::Client::release) and returned
+/// again by the next connect to the same peer with the same ALPN or CONNECT request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The maximum number of idle sessions, across all peers.
+    ///
+    /// When exceeded, the session that was idle the longest is closed.
+    pub max_idle: usize,
+    /// How long a session may stay idle before it is no longer reused.
+    ///
+    /// There is no background task: expired sessions are closed the next time the pool is
+    /// used, i.e. by a connect or a release, or by [`Client::close`](crate::Client::close).
+    pub idle_timeout: Duration,
+}
+
+impl PoolConfig {
+    /// Returns the default limits.
+    pub fn new() -> Self {
+        Self {
+            max_idle: 16,
+            idle_timeout: Duration::from_secs(90),
+        }
+    }
+
+    /// Sets the maximum number of idle sessions, across all peers.
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Sets how long a session may stay idle before it is no longer reused, see [`Self::idle_timeout`].
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The idle sessions of a client, oldest first.
+#[derive(Debug)]
+pub(crate) struct Pool {
+    config: PoolConfig,
+    idle: Mutex<VecDeque<Idle>>,
+}
+
+#[derive(Debug)]
+struct Idle {
+    session: Session,
+    since: Instant,
+}
+
+impl Pool {
+    pub(crate) fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Takes an idle raw QUIC session to the peer using the ALPN.
+    pub(crate) fn take_quic(&self, remote: EndpointId, alpn: &[u8]) -> Option<Session> {
+        self.take(|session| {
+            session.conn().remote_id() == remote
+                && session.conn().alpn() == alpn
+                && session.request().is_none()
+        })
+    }
+
+    /// Takes an idle HTTP/3 session to the peer that was established with the same request.
+    pub(crate) fn take_h3(&self, remote: EndpointId, request: &ConnectRequest) -> Option<Session> {
+        self.take(|session| {
+            session.conn().remote_id() == remote
+                && session.request().is_some_and(|r| {
+                    r.url == request.url
+                        && r.protocols == request.protocols
+                        && r.headers == request.headers
+                })
+        })
+    }
+
+    fn take(&self, matches: impl Fn(&Session) -> bool) -> Option<Session> {
+        let mut idle = self.idle.lock().unwrap();
+        self.evict(&mut idle);
+        let index = idle.iter().position(|entry| matches(&entry.session))?;
+        idle.remove(index).map(|entry| entry.session)
+    }
+
+    /// Keeps the session for reuse, closing the oldest idle session if there are too many.
+    pub(crate) fn release(&self, session: Session) {
+        let mut idle = self.idle.lock().unwrap();
+        idle.push_back(Idle {
+            session,
+            since: Instant::now(),
+        });
+        self.evict(&mut idle);
+        while idle.len() > self.config.max_idle {
+            if let Some(entry) = idle.pop_front() {
+                entry.session.close(0, b"idle");
+            }
+        }
+    }
+
+    /// Drops sessions whose connection was closed, and closes those that were closed by the
+    /// peer, asked to go away or drain, or idle for too long.
+    fn evict(&self, idle: &mut VecDeque<Idle>) {
+        idle.retain(|entry| {
+            let session = &entry.session;
+            if session.conn().close_reason().is_some() {
+                return false;
+            }
+            if session.close_reason().is_some()
+                || session.is_going_away()
+                || session.is_draining()
+                || entry.since.elapsed() >= self.config.idle_timeout
+            {
+                session.close(0, b"idle");
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Closes all idle sessions.
+    pub(crate) fn clear(&self) {
+        let mut idle = self.idle.lock().unwrap();
+        for entry in idle.drain(..) {
+            entry.session.close(0, b"idle");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::hash::{BuildHasher, RandomState};
+
+use web_transport_proto::{Setting, VarInt};
+
+use crate::{Capabilities, Features};
+
+/// Selects which optional SETTINGS are sent and which omissions by the peer are tolerated.
+///
+/// Some embedded HTTP/3 stacks only interoperate with a specific set of SETTINGS, e.g. an
+/// explicit `SETTINGS_QPACK_MAX_TABLE_CAPACITY` of zero. The default profile sends both the
+/// current and the deprecated (pre-draft-07) WebTransport settings and requires the peer
+/// to enable HTTP datagrams, and advertises the crate version and [`Features::builtin`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsProfile {
+    deprecated: bool,
+    require_datagram: bool,
+    grease: bool,
+    // The features we advertise, where None omits the version and features settings.
+    features: Option<Features>,
+    // Overrides of the settings we send, where None omits the setting.
+    settings: Vec<(Setting, Option<VarInt>)>,
+}
+
+impl SettingsProfile {
+    /// Returns the default profile.
+    pub fn new() -> Self {
+        Self {
+            deprecated: true,
+            require_datagram: true,
+            grease: false,
+            features: Some(Features::builtin()),
+            settings: Vec::new(),
+        }
+    }
+
+    /// Returns a profile that only sends the settings of the current drafts.
+    ///
+    /// This doesn't advertise any [`Capabilities`] either.
+    pub fn minimal() -> Self {
+        Self::new().with_deprecated(false).with_features(None)
+    }
+
+    /// Sets whether the deprecated WebTransport and datagram settings are sent.
+    ///
+    /// Older peers, including some browsers, only understand the deprecated settings.
+    pub fn with_deprecated(mut self, enabled: bool) -> Self {
+        self.deprecated = enabled;
+        self
+    }
+
+    /// Sets whether a peer that omits `SETTINGS_H3_DATAGRAM` is rejected.
+    ///
+    /// If not required, such a peer is assumed to support datagrams.
+    /// A peer that explicitly disables datagrams is still rejected.
+    pub fn with_datagram_required(mut self, required: bool) -> Self {
+        self.require_datagram = required;
+        self
+    }
+
+    /// Sets whether reserved settings and frame types are sent, see RFC 9114 section 7.2.4.1 and 7.2.8.
+    ///
+    /// Peers must ignore them, so this exercises their extension points when debugging interop.
+    /// The QUIC transport parameters are always greased by iroh, which doesn't support
+    /// turning that off.
+    pub fn with_grease(mut self, enabled: bool) -> Self {
+        self.grease = enabled;
+        self
+    }
+
+    /// Sets the features advertised to the peer along with the crate version, see [`Capabilities`].
+    ///
+    /// Pass `None` to advertise nothing, so [`Session::peer_capabilities`](crate::Session::peer_capabilities)
+    /// returns None on the peer.
+    pub fn with_features(mut self, features: Option<Features>) -> Self {
+        self.features = features;
+        self
+    }
+
+    pub(crate) fn grease(&self) -> bool {
+        self.grease
+    }
+
+    /// Sends an additional setting, replacing the value we would send otherwise.
+    pub fn with_setting(mut self, setting: Setting, value: VarInt) -> Self {
+        self.settings.retain(|(s, _)| *s != setting);
+        self.settings.push((setting, Some(value)));
+        self
+    }
+
+    /// Omits a setting we would send otherwise.
+    ///
+    /// Omitting the WebTransport settings makes peers reject the connection,
+    /// unless they are [`Strictness::Lenient`](crate::Strictness::Lenient).
+    pub fn without_setting(mut self, setting: Setting) -> Self {
+        self.settings.retain(|(s, _)| *s != setting);
+        self.settings.push((setting, None));
+        self
+    }
+
+    /// Applies the profile to the SETTINGS we send.
+    pub(crate) fn apply(&self, settings: &mut web_transport_proto::Settings) {
+        if !self.deprecated {
+            settings.remove(&Setting::ENABLE_DATAGRAM_DEPRECATED);
+            settings.remove(&Setting::WEBTRANSPORT_ENABLE_DEPRECATED);
+            settings.remove(&Setting::WEBTRANSPORT_MAX_SESSIONS_DEPRECATED);
+        }
+        if let Some(features) = self.features {
+            Capabilities::apply(features, settings);
+        }
+        if self.grease {
+            settings.insert(Setting(reserved_id()), reserved_id());
+        }
+        for (setting, value) in &self.settings {
+            match value {
+                Some(value) => settings.insert(*setting, *value),
+                None => settings.remove(setting),
+            };
+        }
+    }
+
+    /// Fills in the settings the peer may omit under this profile.
+    pub(crate) fn tolerate(&self, settings: &mut web_transport_proto::Settings) {
+        if !self.require_datagram
+            && !settings.contains_key(&Setting::ENABLE_DATAGRAM)
+            && !settings.contains_key(&Setting::ENABLE_DATAGRAM_DEPRECATED)
+        {
+            tracing::debug!("peer doesn't advertise datagrams, assuming they are supported");
+            settings.insert(Setting::ENABLE_DATAGRAM, VarInt::from_u32(1));
+        }
+    }
+}
+
+impl Default for SettingsProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns a random reserved identifier of the form `0x1f * N + 0x21`, used for GREASE.
+pub(crate) fn reserved_id() -> VarInt {
+    // Randomly seeded, so we don't need a dependency on rand.
+    let n = RandomState::new().hash_one(()) % (1 << 32);
+    VarInt::try_from(0x1f * n + 0x21).expect("reserved id fits in a varint")
+}
@@ -0,0 +1,477 @@
+//! Python bindings with asyncio integration, enabled with the `python` feature.
+//!
+//! Build the extension module with [maturin](https://www.maturin.rs), e.g. `maturin develop`.
+//! All coroutines run on a shared tokio runtime and can be awaited from any asyncio event loop.
+//! Cancelling an awaitable aborts the underlying operation.
+
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex as StdMutex, OnceLock},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use iroh::{Endpoint, EndpointId};
+use pyo3::{IntoPyObjectExt, exceptions::PyRuntimeError, prelude::*};
+use tokio::{
+    runtime::{Handle, Runtime},
+    sync::Mutex,
+    task::AbortHandle,
+};
+use url::Url;
+
+use crate::{ALPN_H3, ConnectRequestBuilder, H3Request};
+
+static RUNTIME: OnceLock<StdMutex<Option<Runtime>>> = OnceLock::new();
+
+fn runtime() -> PyResult<Handle> {
+    let runtime = RUNTIME.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the tokio runtime");
+        StdMutex::new(Some(runtime))
+    });
+    match runtime.lock().expect("poisoned").as_ref() {
+        Some(runtime) => Ok(runtime.handle().clone()),
+        None => Err(PyRuntimeError::new_err("the runtime was shut down")),
+    }
+}
+
+/// Shuts down the runtime when the interpreter exits.
+///
+/// Runtime threads must not touch the interpreter once it is finalizing, so they are stopped
+/// while the GIL is released, letting any in-flight completions finish first.
+#[pyfunction]
+fn shutdown(py: Python<'_>) {
+    let runtime = RUNTIME
+        .get()
+        .and_then(|runtime| runtime.lock().expect("poisoned").take());
+    if let Some(runtime) = runtime {
+        py.allow_threads(|| runtime.shutdown_timeout(Duration::from_secs(1)));
+    }
+}
+
+fn py_err(err: impl Display) -> PyErr {
+    PyRuntimeError::new_err(format!("{err:#}"))
+}
+
+/// Runs the future on the tokio runtime, returning an asyncio future for its result.
+///
+/// The result is handed to the event loop via `call_soon_threadsafe`, as asyncio futures
+/// must only be completed from the thread running the loop.
+fn spawn<'py, F, T>(py: Python<'py>, fut: F) -> PyResult<Bound<'py, PyAny>>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: for<'a> IntoPyObject<'a> + Send + 'static,
+{
+    let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+    let future = event_loop.call_method0("create_future")?;
+
+    let (event_loop_ref, future_ref) = (event_loop.unbind(), future.clone().unbind());
+    let task = runtime()?.spawn(async move {
+        let result = fut.await;
+        // Everything is moved into the closure so no Python reference outlives the GIL.
+        Python::with_gil(move |py| {
+            let result = result.and_then(|value| value.into_py_any(py));
+            let (ok, value) = match result {
+                Ok(value) => (true, value),
+                Err(err) => (false, err.into_value(py).into_any()),
+            };
+            let complete = wrap_pyfunction!(complete_future, py)?;
+            event_loop_ref.call_method1(
+                py,
+                "call_soon_threadsafe",
+                (complete, future_ref, ok, value),
+            )?;
+            PyResult::Ok(())
+        })
+        .ok();
+    });
+
+    // Abort the task if the awaitable is cancelled, e.g. by a timeout.
+    let abort = AbortOnCancel(task.abort_handle());
+    future.call_method1("add_done_callback", (abort,))?;
+    Ok(future)
+}
+
+/// Completes the future on the event loop, unless it was cancelled in the meantime.
+#[pyfunction]
+fn complete_future(future: &Bound<'_, PyAny>, ok: bool, value: Bound<'_, PyAny>) -> PyResult<()> {
+    if future.call_method0("done")?.is_truthy()? {
+        return Ok(());
+    }
+    match ok {
+        true => future.call_method1("set_result", (value,))?,
+        false => future.call_method1("set_exception", (value,))?,
+    };
+    Ok(())
+}
+
+#[pyclass]
+struct AbortOnCancel(AbortHandle);
+
+#[pymethods]
+impl AbortOnCancel {
+    fn __call__(&self, future: &Bound<'_, PyAny>) -> PyResult<()> {
+        if future.call_method0("cancelled")?.is_truthy()? {
+            self.0.abort();
+        }
+        Ok(())
+    }
+}
+
+/// The address of an endpoint, including any known relay and direct addresses.
+#[pyclass(module = "web_transport_iroh", frozen)]
+#[derive(Clone)]
+struct EndpointAddr {
+    inner: iroh::EndpointAddr,
+}
+
+#[pymethods]
+impl EndpointAddr {
+    /// The endpoint id.
+    #[getter]
+    fn id(&self) -> String {
+        self.inner.id.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+/// An endpoint id string, or an address that also allows connecting without discovery.
+#[derive(FromPyObject)]
+enum Target {
+    Id(String),
+    Addr(EndpointAddr),
+}
+
+impl Target {
+    fn into_request(self, path: &str) -> PyResult<(iroh::EndpointAddr, Url)> {
+        let addr = match self {
+            Self::Id(id) => id.parse::<EndpointId>().map_err(py_err)?.into(),
+            Self::Addr(addr) => addr.inner,
+        };
+        let url = format!("https://{}{path}", addr.id)
+            .parse()
+            .map_err(py_err)?;
+        Ok((addr, url))
+    }
+}
+
+/// Connects to HTTP/3 WebTransport servers.
+#[pyclass(module = "web_transport_iroh")]
+struct Client {
+    inner: Arc<crate::Client>,
+}
+
+#[pymethods]
+impl Client {
+    /// Binds a new endpoint for outgoing sessions.
+    #[staticmethod]
+    fn bind(py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+        spawn(py, async move {
+            let endpoint = Endpoint::bind().await.map_err(py_err)?;
+            Ok(Client {
+                inner: Arc::new(crate::Client::new(endpoint)),
+            })
+        })
+    }
+
+    /// Connects to an endpoint id or [`EndpointAddr`], requesting the given path.
+    #[pyo3(signature = (target, path = "/", protocols = Vec::new()))]
+    fn connect<'py>(
+        &self,
+        py: Python<'py>,
+        target: Target,
+        path: &str,
+        protocols: Vec<String>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (addr, url) = target.into_request(path)?;
+        let request = ConnectRequestBuilder::new(url).with_protocols(protocols);
+        let client = self.inner.clone();
+        spawn(py, async move {
+            let session = client.connect_h3(addr, request).await.map_err(py_err)?;
+            Ok(Session { inner: session })
+        })
+    }
+
+    /// Closes the endpoint.
+    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+        spawn(py, async move {
+            client.close().await;
+            Ok(())
+        })
+    }
+}
+
+/// Accepts HTTP/3 WebTransport sessions.
+#[pyclass(module = "web_transport_iroh")]
+struct Server {
+    endpoint: Endpoint,
+}
+
+#[pymethods]
+impl Server {
+    /// Binds a new endpoint for incoming sessions.
+    #[staticmethod]
+    fn bind(py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+        spawn(py, async move {
+            let endpoint = Endpoint::builder()
+                .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+                .bind()
+                .await
+                .map_err(py_err)?;
+            Ok(Server { endpoint })
+        })
+    }
+
+    /// The endpoint id clients connect to.
+    #[getter]
+    fn endpoint_id(&self) -> String {
+        self.endpoint.id().to_string()
+    }
+
+    /// The current address of the endpoint, to connect without discovery.
+    fn addr(&self) -> EndpointAddr {
+        EndpointAddr {
+            inner: self.endpoint.addr(),
+        }
+    }
+
+    /// Waits for the next session and accepts it, selecting the first offered protocol if any.
+    ///
+    /// Returns None once the endpoint is closed.
+    fn accept<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let endpoint = self.endpoint.clone();
+        spawn(py, async move {
+            let Some(incoming) = endpoint.accept().await else {
+                return Ok(None);
+            };
+            let conn = incoming.accept().map_err(py_err)?.await.map_err(py_err)?;
+            let request = H3Request::accept(conn).await.map_err(py_err)?;
+            let mut response = web_transport_proto::ConnectResponse::OK;
+            if let Some(protocol) = request.protocols.first() {
+                response = response.with_protocol(protocol.clone());
+            }
+            let session = request.respond(response).await.map_err(py_err)?;
+            Ok(Some(Session { inner: session }))
+        })
+    }
+
+    /// Closes the endpoint.
+    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let endpoint = self.endpoint.clone();
+        spawn(py, async move {
+            endpoint.close().await;
+            Ok(())
+        })
+    }
+}
+
+/// An established WebTransport session.
+#[pyclass(module = "web_transport_iroh")]
+struct Session {
+    inner: crate::Session,
+}
+
+#[pymethods]
+impl Session {
+    /// The subprotocol selected by the server, if any.
+    #[getter]
+    fn protocol(&self) -> Option<String> {
+        self.inner.protocol().map(str::to_string)
+    }
+
+    /// The endpoint id of the peer.
+    #[getter]
+    fn remote_id(&self) -> String {
+        self.inner.remote_id().to_string()
+    }
+
+    /// Opens a unidirectional stream.
+    fn open_uni<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move {
+            let send = session.open_uni().await.map_err(py_err)?;
+            Ok(SendStream::new(send))
+        })
+    }
+
+    /// Opens a bidirectional stream, returning `(send, recv)`.
+    fn open_bi<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move {
+            let (send, recv) = session.open_bi().await.map_err(py_err)?;
+            Ok((SendStream::new(send), RecvStream::new(recv)))
+        })
+    }
+
+    /// Waits for the next unidirectional stream opened by the peer.
+    fn accept_uni<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move {
+            let recv = session.accept_uni().await.map_err(py_err)?;
+            Ok(RecvStream::new(recv))
+        })
+    }
+
+    /// Waits for the next bidirectional stream opened by the peer, returning `(send, recv)`.
+    fn accept_bi<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move {
+            let (send, recv) = session.accept_bi().await.map_err(py_err)?;
+            Ok((SendStream::new(send), RecvStream::new(recv)))
+        })
+    }
+
+    /// Sends a datagram.
+    fn send_datagram(&self, data: &[u8]) -> PyResult<()> {
+        self.inner
+            .send_datagram(Bytes::copy_from_slice(data))
+            .map_err(py_err)
+    }
+
+    /// Waits for the next datagram.
+    fn recv_datagram<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move {
+            let datagram = session.read_datagram().await.map_err(py_err)?;
+            Ok(datagram.to_vec())
+        })
+    }
+
+    /// The maximum size of a datagram that can currently be sent.
+    #[getter]
+    fn max_datagram_size(&self) -> usize {
+        self.inner.max_datagram_size()
+    }
+
+    /// Closes the session with the given code and reason.
+    #[pyo3(signature = (code = 0, reason = ""))]
+    fn close(&self, code: u32, reason: &str) {
+        self.inner.close(code, reason.as_bytes());
+    }
+
+    /// Waits until the session is closed, returning the reason.
+    fn closed<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let session = self.inner.clone();
+        spawn(py, async move { Ok(session.closed().await.to_string()) })
+    }
+}
+
+/// The sending half of a stream.
+#[pyclass(module = "web_transport_iroh")]
+struct SendStream {
+    inner: Arc<Mutex<crate::SendStream>>,
+}
+
+impl SendStream {
+    fn new(inner: crate::SendStream) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+#[pymethods]
+impl SendStream {
+    /// Writes all of the data to the stream.
+    fn write<'py>(&self, py: Python<'py>, data: Vec<u8>) -> PyResult<Bound<'py, PyAny>> {
+        let send = self.inner.clone();
+        spawn(py, async move {
+            send.lock().await.write_all(&data).await.map_err(py_err)
+        })
+    }
+
+    /// Finishes the stream once all data was written.
+    fn finish<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let send = self.inner.clone();
+        spawn(
+            py,
+            async move { send.lock().await.finish().map_err(py_err) },
+        )
+    }
+
+    /// Abruptly resets the stream with the given error code.
+    fn reset<'py>(&self, py: Python<'py>, code: u32) -> PyResult<Bound<'py, PyAny>> {
+        let send = self.inner.clone();
+        spawn(
+            py,
+            async move { send.lock().await.reset(code).map_err(py_err) },
+        )
+    }
+}
+
+/// The receiving half of a stream.
+#[pyclass(module = "web_transport_iroh")]
+struct RecvStream {
+    inner: Arc<Mutex<crate::RecvStream>>,
+}
+
+impl RecvStream {
+    fn new(inner: crate::RecvStream) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+#[pymethods]
+impl RecvStream {
+    /// Reads up to `max_length` bytes, returning None at the end of the stream.
+    #[pyo3(signature = (max_length = 64 * 1024))]
+    fn read<'py>(&self, py: Python<'py>, max_length: usize) -> PyResult<Bound<'py, PyAny>> {
+        let recv = self.inner.clone();
+        spawn(py, async move {
+            let chunk = recv
+                .lock()
+                .await
+                .read_chunk(max_length)
+                .await
+                .map_err(py_err)?;
+            Ok(chunk.map(|chunk| chunk.bytes.to_vec()))
+        })
+    }
+
+    /// Reads until the end of the stream, failing if it exceeds `size_limit` bytes.
+    fn read_to_end<'py>(&self, py: Python<'py>, size_limit: usize) -> PyResult<Bound<'py, PyAny>> {
+        let recv = self.inner.clone();
+        spawn(py, async move {
+            let data = recv
+                .lock()
+                .await
+                .read_to_end(size_limit)
+                .await
+                .map_err(py_err)?;
+            Ok(data.to_vec())
+        })
+    }
+
+    /// Tells the peer to stop sending with the given error code.
+    fn stop<'py>(&self, py: Python<'py>, code: u32) -> PyResult<Bound<'py, PyAny>> {
+        let recv = self.inner.clone();
+        spawn(
+            py,
+            async move { recv.lock().await.stop(code).map_err(py_err) },
+        )
+    }
+}
+
+/// WebTransport over iroh.
+#[pymodule]
+fn web_transport_iroh(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let atexit = m.py().import("atexit")?;
+    atexit.call_method1("register", (wrap_pyfunction!(shutdown, m)?,))?;
+    m.add_class::<EndpointAddr>()?;
+    m.add_class::<Client>()?;
+    m.add_class::<Server>()?;
+    m.add_class::<Session>()?;
+    m.add_class::<SendStream>()?;
+    m.add_class::<RecvStream>()?;
+    Ok(())
+}
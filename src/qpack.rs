@@ -0,0 +1,238 @@
+//! The parts of QPACK needed for CONNECT headers, which web-transport-proto keeps private.
+//!
+//! Only the static table is supported. We never allow the peer a dynamic table, so field
+//! sections can't reference one.
+
+use bytes::{Buf, BufMut};
+use httlib_huffman::DecoderSpeed;
+
+use crate::ConnectError;
+
+/// A field line of a decoded field section, with its name and value.
+pub(crate) type FieldLine = (Vec<u8>, Vec<u8>);
+
+/// Decode all field lines of a field section, including pseudo-headers.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc9204.html#section-4.5
+pub(crate) fn decode_field_section(mut buf: &[u8]) -> Result<Vec<FieldLine>, ConnectError> {
+    // Without a dynamic table, the Required Insert Count is 0 and the Base is unused.
+    if decode_prefix(&mut buf, 8)? != 0 {
+        return Err(ConnectError::InvalidFieldSection);
+    }
+    decode_prefix(&mut buf, 7)?;
+
+    let mut lines = Vec::new();
+    while let Some(&first) = buf.first() {
+        let line = if first & 0b1000_0000 != 0 {
+            // Indexed field line, which must reference the static table.
+            if first & 0b0100_0000 == 0 {
+                return Err(ConnectError::InvalidFieldSection);
+            }
+            let (name, value) = static_entry(decode_prefix(&mut buf, 6)?)?;
+            (name.as_bytes().to_vec(), value.as_bytes().to_vec())
+        } else if first & 0b0100_0000 != 0 {
+            // Literal field line with a name reference, which must be to the static table.
+            if first & 0b0001_0000 == 0 {
+                return Err(ConnectError::InvalidFieldSection);
+            }
+            let (name, _) = static_entry(decode_prefix(&mut buf, 4)?)?;
+            (name.as_bytes().to_vec(), decode_string(&mut buf, 7)?)
+        } else if first & 0b0010_0000 != 0 {
+            // Literal field line with a literal name.
+            let name = decode_string(&mut buf, 3)?;
+            (name, decode_string(&mut buf, 7)?)
+        } else {
+            // Post-base references only exist with a dynamic table.
+            return Err(ConnectError::InvalidFieldSection);
+        };
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Encode a literal field line with a literal name, without Huffman coding.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc9204.html#section-4.5.6
+pub(crate) fn encode_literal<B: BufMut>(buf: &mut B, name: &str, value: &str) {
+    encode_prefix(buf, 3, 0b0010_0000, name.len());
+    buf.put_slice(name.as_bytes());
+    encode_prefix(buf, 7, 0, value.len());
+    buf.put_slice(value.as_bytes());
+}
+
+// An integer with an N-bit prefix, sharing the first byte with the given flags.
+// See: https://www.rfc-editor.org/rfc/rfc7541#section-5.1
+fn encode_prefix<B: BufMut>(buf: &mut B, bits: u8, flags: u8, value: usize) {
+    let max = (1 << bits) - 1;
+    if value < max {
+        buf.put_u8(flags | value as u8);
+        return;
+    }
+
+    buf.put_u8(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+// Decode an integer with an N-bit prefix, ignoring the flags in the first byte.
+fn decode_prefix(buf: &mut &[u8], bits: u8) -> Result<usize, ConnectError> {
+    let max = (1u64 << bits) - 1;
+    let first = buf.try_get_u8().map_err(|_| ConnectError::UnexpectedEnd)?;
+    let mut value = u64::from(first) & max;
+    if value < max {
+        return Ok(value as usize);
+    }
+
+    for shift in (0..=56).step_by(7) {
+        let byte = buf.try_get_u8().map_err(|_| ConnectError::UnexpectedEnd)?;
+        value = value
+            .checked_add(u64::from(byte & 0x7f) << shift)
+            .ok_or(ConnectError::InvalidFieldSection)?;
+        if byte & 0x80 == 0 {
+            return usize::try_from(value).map_err(|_| ConnectError::InvalidFieldSection);
+        }
+    }
+    Err(ConnectError::InvalidFieldSection)
+}
+
+// Decode a string literal with an N-bit length prefix, preceded by its Huffman flag.
+fn decode_string(buf: &mut &[u8], bits: u8) -> Result<Vec<u8>, ConnectError> {
+    let huffman = buf.first().is_some_and(|first| first & (1 << bits) != 0);
+    let len = decode_prefix(buf, bits)?;
+    if buf.len() < len {
+        return Err(ConnectError::UnexpectedEnd);
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    if !huffman {
+        return Ok(value.to_vec());
+    }
+
+    let mut decoded = Vec::with_capacity(len * 2);
+    httlib_huffman::decode(value, &mut decoded, DecoderSpeed::FourBits)
+        .map_err(|_| ConnectError::InvalidFieldSection)?;
+    Ok(decoded)
+}
+
+fn static_entry(index: usize) -> Result<(&'static str, &'static str), ConnectError> {
+    STATIC_TABLE
+        .get(index)
+        .copied()
+        .ok_or(ConnectError::InvalidFieldSection)
+}
+
+// See: https://www.rfc-editor.org/rfc/rfc9204.html#appendix-A
+const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains",
+    ),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains; preload",
+    ),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    (
+        "content-security-policy",
+        "script-src 'none'; object-src 'none'; base-uri 'none'",
+    ),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
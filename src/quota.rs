@@ -0,0 +1,57 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use iroh::{EndpointId, endpoint::Connection};
+
+use crate::Spawner;
+
+/// The status used to reject a session from a peer that holds too many, see
+/// [`Server::with_peer_quota`](crate::Server::with_peer_quota).
+pub const PEER_QUOTA_EXCEEDED: http::StatusCode = http::StatusCode::TOO_MANY_REQUESTS;
+
+// Counts the open connections of each peer.
+#[derive(Debug, Clone)]
+pub(crate) struct PeerQuota {
+    max: usize,
+    counts: Arc<Mutex<HashMap<EndpointId, usize>>>,
+}
+
+impl PeerQuota {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Default::default(),
+        }
+    }
+
+    // Counts the connection against the quota of its peer until it is closed, or returns
+    // false if the peer has no quota left.
+    pub(crate) fn acquire(&self, conn: &Connection, spawner: &Spawner) -> bool {
+        let peer = conn.remote_id();
+        {
+            let mut counts = self.counts.lock().unwrap();
+            let count = counts.entry(peer).or_default();
+            if *count >= self.max {
+                return false;
+            }
+            *count += 1;
+        }
+
+        // Doesn't keep the connection alive, unlike `Connection::closed`.
+        let info = conn.to_info();
+        let counts = self.counts.clone();
+        spawner.spawn(async move {
+            info.closed().await;
+            let mut counts = counts.lock().unwrap();
+            if let Some(count) = counts.get_mut(&peer) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&peer);
+                }
+            }
+        });
+        true
+    }
+}
@@ -1,23 +1,37 @@
 use std::{
     io,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
 use bytes::Bytes;
 use iroh::endpoint;
 
-use crate::{ReadError, ReadExactError, ReadToEndError, SessionError};
+use crate::{
+    ReadError, ReadExactError, ReadToEndError, SessionError, WebTransportError,
+    panic_policy::unexpected, stream_count::CountedStream,
+};
 
 /// A stream that can be used to receive bytes. See [`iroh::endpoint::RecvStream`].
 #[derive(Debug)]
 pub struct RecvStream {
     inner: endpoint::RecvStream,
+    // Counts the stream as open until dropped, see `Session::stream_counts`.
+    counted: Option<Arc<CountedStream>>,
 }
 
 impl RecvStream {
     pub(crate) fn new(stream: endpoint::RecvStream) -> Self {
-        Self { inner: stream }
+        Self {
+            inner: stream,
+            counted: None,
+        }
+    }
+
+    pub(crate) fn with_count(mut self, counted: Arc<CountedStream>) -> Self {
+        self.counted = Some(counted);
+        self
     }
 
     /// Tell the other end to stop sending data with the given error code. See [`iroh::endpoint::RecvStream::stop`].
@@ -60,15 +74,21 @@ impl RecvStream {
 
     /// Block until the stream has been reset and return the error code. See [`iroh::endpoint::RecvStream::received_reset`].
     ///
-    /// Unlike Quinn, this returns a SessionError, not a ResetError, because 0-RTT is not supported.
+    /// Unlike Quinn, this returns a SessionError, not a ResetError.
+    /// A code outside the WebTransport range fails with [`WebTransportError::InvalidReset`].
+    /// A stream sent in rejected 0-RTT data fails with [`SessionError::ZeroRttRejected`].
     pub async fn received_reset(&mut self) -> Result<Option<u32>, SessionError> {
         match self.inner.received_reset().await {
             Ok(None) => Ok(None),
-            Ok(Some(code)) => Ok(Some(
-                web_transport_proto::error_from_http3(code.into_inner()).unwrap(),
-            )),
+            Ok(Some(code)) => match web_transport_proto::error_from_http3(code.into_inner()) {
+                Some(code) => Ok(Some(code)),
+                None => {
+                    unexpected(&format!("stream reset with invalid code {code}"));
+                    Err(WebTransportError::InvalidReset(code).into())
+                }
+            },
             Err(endpoint::ResetError::ConnectionLost(e)) => Err(e.into()),
-            Err(endpoint::ResetError::ZeroRttRejected) => unreachable!("0-RTT not supported"),
+            Err(endpoint::ResetError::ZeroRttRejected) => Err(SessionError::ZeroRttRejected),
         }
     }
 
@@ -85,6 +105,19 @@ impl tokio::io::AsyncRead for RecvStream {
     }
 }
 
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for RecvStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        std::task::ready!(tokio::io::AsyncRead::poll_read(self, cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
 impl web_transport_trait::RecvStream for RecvStream {
     type Error = ReadError;
 
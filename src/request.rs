@@ -0,0 +1,148 @@
+use http::{HeaderMap, HeaderName, HeaderValue};
+use url::Url;
+use web_transport_proto::ConnectRequest;
+
+use crate::{
+    ConnectError,
+    headers::{AVAILABLE_PROTOCOLS, decode_request, encode_protocols},
+};
+
+/// The header carrying the affinity key of a session, see [`ConnectRequestBuilder::with_affinity_key`].
+///
+/// Load balancers and server pools can use it to route a reconnecting client to the same backend.
+pub const AFFINITY_KEY: HeaderName = HeaderName::from_static("wt-affinity-key");
+
+/// Builds the CONNECT request that opens a WebTransport session over HTTP/3.
+///
+/// Pass it to [`Client::connect_h3`](crate::Client::connect_h3) or
+/// [`Connected::open`](crate::Connected::open), or convert it into a [`ConnectRequest`].
+#[derive(Debug, Clone)]
+pub struct ConnectRequestBuilder {
+    request: ConnectRequest,
+}
+
+impl ConnectRequestBuilder {
+    /// Start a request for the given URL, which needs to have a `https:` scheme.
+    pub fn new(url: Url) -> Self {
+        Self {
+            request: ConnectRequest::new(url),
+        }
+    }
+
+    /// Offer a subprotocol, in order of preference. See [`Session::protocol`](crate::Session::protocol).
+    pub fn with_protocol(mut self, protocol: impl Into<String>) -> Self {
+        self.request = self.request.with_protocol(protocol);
+        self
+    }
+
+    /// Offer several subprotocols, in order of preference.
+    pub fn with_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.request = self
+            .request
+            .with_protocols(protocols.into_iter().map(Into::into));
+        self
+    }
+
+    /// Send an additional header, such as `authorization`.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.request = self.request.with_header(name, value);
+        self
+    }
+
+    /// Send additional headers.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.request = self.request.with_headers(headers);
+        self
+    }
+
+    /// Send an affinity key in the [`AFFINITY_KEY`] header, e.g. a user or room id.
+    ///
+    /// The server can read it with [`H3Request::affinity_key`](crate::H3Request::affinity_key)
+    /// to route the session, so reconnects with the same key end up on the same backend.
+    pub fn with_affinity_key(mut self, key: HeaderValue) -> Self {
+        self.request.headers.insert(AFFINITY_KEY, key);
+        self
+    }
+
+    /// Send the `origin` header, like browsers do, using the origin of the given URL.
+    pub fn with_origin(mut self, origin: &Url) -> Self {
+        let origin = HeaderValue::try_from(origin.origin().ascii_serialization())
+            .expect("serialized origin is a valid header value");
+        self.request.headers.insert(http::header::ORIGIN, origin);
+        self
+    }
+
+    /// Returns the [`ConnectRequest`].
+    pub fn build(self) -> ConnectRequest {
+        self.request
+    }
+}
+
+impl From<Url> for ConnectRequestBuilder {
+    fn from(url: Url) -> Self {
+        Self::new(url)
+    }
+}
+
+impl From<ConnectRequestBuilder> for ConnectRequest {
+    fn from(builder: ConnectRequestBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Create a request from an [`http::Request`], e.g. one prepared by HTTP middleware.
+///
+/// The method must be `CONNECT` and the URI absolute. Subprotocols are read from the
+/// `wt-available-protocols` header, all other headers are sent as is.
+impl TryFrom<http::Request<()>> for ConnectRequestBuilder {
+    type Error = ConnectError;
+
+    fn try_from(request: http::Request<()>) -> Result<Self, Self::Error> {
+        let (parts, ()) = request.into_parts();
+        if parts.method != http::Method::CONNECT {
+            return Err(web_transport_proto::ConnectError::WrongMethod(Some(parts.method)).into());
+        }
+        let url =
+            Url::parse(&parts.uri.to_string()).map_err(web_transport_proto::ConnectError::from)?;
+        let request = decode_request(url, &parts.headers)?;
+        Ok(Self { request })
+    }
+}
+
+/// Convert into an [`http::Request`], with subprotocols in the `wt-available-protocols` header.
+impl TryFrom<ConnectRequestBuilder> for http::Request<()> {
+    type Error = ConnectError;
+
+    fn try_from(builder: ConnectRequestBuilder) -> Result<Self, Self::Error> {
+        to_http_request(&builder.request)
+    }
+}
+
+pub(crate) fn to_http_request(request: &ConnectRequest) -> Result<http::Request<()>, ConnectError> {
+    let mut http = http::Request::new(());
+    *http.method_mut() = http::Method::CONNECT;
+    *http.uri_mut() = request_uri(&request.url)?;
+    *http.headers_mut() = request.headers.clone();
+    if !request.protocols.is_empty() {
+        http.headers_mut()
+            .insert(AVAILABLE_PROTOCOLS, encode_protocols(&request.protocols)?);
+    }
+    Ok(http)
+}
+
+// The request target as sent in the `:scheme`, `:authority` and `:path` pseudo-headers.
+pub(crate) fn request_uri(url: &Url) -> Result<http::Uri, ConnectError> {
+    let path_and_query = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    http::Uri::builder()
+        .scheme(url.scheme())
+        .authority(url.authority())
+        .path_and_query(path_and_query)
+        .build()
+        .map_err(|_| ConnectError::InvalidUri(url.to_string()))
+}
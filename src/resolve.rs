@@ -0,0 +1,56 @@
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+use iroh::{EndpointAddr, dns::DnsResolver};
+use n0_error::AnyError;
+
+/// The future returned by [`Resolve::resolve`].
+pub type ResolveFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<EndpointAddr>, AnyError>> + Send + 'a>>;
+
+/// Resolves the host of an `https:` URL to an iroh endpoint, see [`Client::with_resolver`](crate::Client::with_resolver).
+pub trait Resolve: Send + Sync + 'static {
+    /// Looks up the endpoint for a host name.
+    ///
+    /// Returns None if this resolver doesn't know the host, so the next one is tried.
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a>;
+}
+
+/// Looks up the `_iroh` TXT record of the host, as published by iroh's DNS address lookup.
+impl Resolve for DnsResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let info = self
+                .lookup_endpoint_by_domain_name(host)
+                .await
+                .map_err(AnyError::from_stack)?;
+            Ok(Some(info.into_endpoint_addr()))
+        })
+    }
+}
+
+/// Resolves a fixed set of host names, e.g. from a config file.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, EndpointAddr>,
+}
+
+impl StaticResolver {
+    /// Returns a resolver without any hosts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves the host name to the endpoint. Host names are compared case-insensitively.
+    pub fn with_host(mut self, host: impl Into<String>, addr: impl Into<EndpointAddr>) -> Self {
+        self.hosts
+            .insert(host.into().to_ascii_lowercase(), addr.into());
+        self
+    }
+}
+
+impl Resolve for StaticResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a> {
+        let addr = self.hosts.get(&host.to_ascii_lowercase()).cloned();
+        Box::pin(async move { Ok(addr) })
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::{ErrorKind, latency::random_up_to};
+
+/// How [`Client`](crate::Client) retries failed connects, with exponential backoff.
+///
+/// Only errors of the [`ErrorKind`]s in [`Self::retry_on`] are retried, as classified by
+/// [`Client::diagnose`](crate::Client::diagnose). By default these are transient failures,
+/// like a relay that is briefly unreachable or holepunching that timed out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for each further retry.
+    pub initial_backoff: Duration,
+    /// The maximum delay between attempts, before jitter.
+    pub max_backoff: Duration,
+    /// The maximum random delay added to each backoff, so clients don't retry in lockstep.
+    pub jitter: Duration,
+    /// The kinds of errors that are retried.
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl RetryPolicy {
+    /// Returns the default policy, making up to 3 attempts.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            jitter: Duration::from_millis(100),
+            retry_on: vec![
+                ErrorKind::TimedOut,
+                ErrorKind::NoRoute,
+                ErrorKind::RelayUnreachable,
+            ],
+        }
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry and the maximum delay between attempts.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the maximum random delay added to each backoff.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the kinds of errors that are retried.
+    pub fn with_retry_on(mut self, kinds: impl IntoIterator<Item = ErrorKind>) -> Self {
+        self.retry_on = kinds.into_iter().collect();
+        self
+    }
+
+    /// Returns the delay before the given retry, starting at 1.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        backoff + random_up_to(self.jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
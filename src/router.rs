@@ -0,0 +1,214 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use crate::{H3Request, Middleware, Next, Outcome, Server};
+
+/// The status used to reject a session whose path doesn't match any route of a [`Router`].
+pub const ROUTE_NOT_FOUND: http::StatusCode = http::StatusCode::NOT_FOUND;
+
+type Handler =
+    Arc<dyn Fn(H3Request, Params) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Dispatches session requests to handlers by the path of their URL.
+///
+/// Patterns are matched segment by segment. A `{name}` segment matches any single segment and
+/// captures it as a parameter, a trailing `*` matches the rest of the path, including nothing.
+/// E.g. `/chat/{room}` matches `/chat/lobby` and `/files/*` matches `/files/a/b.txt`.
+/// Routes are tried in the order they were added, and requests that match none are rejected
+/// with [`ROUTE_NOT_FOUND`], unless a fallback is set with [`Self::fallback`].
+///
+/// Cross-cutting concerns like authentication or logging can be added around the routes with
+/// [`Self::with_middleware`].
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<(Pattern, Handler)>,
+    fallback: Option<Handler>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+    /// Returns a router without any routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the handler for requests whose path matches the pattern.
+    ///
+    /// The handler decides whether to accept or reject the request. Panics if the pattern
+    /// doesn't start with `/` or has a `*` segment that isn't the last one.
+    pub fn route<F, Fut>(mut self, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(H3Request, Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.routes.push((Pattern::parse(pattern), boxed(handler)));
+        self
+    }
+
+    /// Runs the handler for requests that don't match any route, instead of rejecting them.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(H3Request, Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.fallback = Some(boxed(handler));
+        self
+    }
+
+    /// Runs the middleware before every request is routed, see [`Middleware`].
+    ///
+    /// Middleware runs in the order it was added, so the first one added sees the request first.
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Runs the middleware and the handler of the first matching route until it returns.
+    pub async fn dispatch(&self, request: H3Request) -> Outcome {
+        Next::new(&self.middleware, self).run(request).await
+    }
+
+    /// Runs the handler of the first matching route, after the middleware.
+    pub(crate) async fn dispatch_routes(&self, request: H3Request) -> Outcome {
+        let path = request.url.path().to_string();
+        for (pattern, handler) in &self.routes {
+            if let Some(params) = pattern.matches(&path) {
+                handler(request, params).await;
+                return Outcome::Handled;
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            fallback(request, Params::default()).await;
+            return Outcome::Handled;
+        }
+        tracing::debug!(%path, "rejecting session without a matching route");
+        Outcome::reject(request, ROUTE_NOT_FOUND).await
+    }
+
+    /// Accepts sessions from the server and dispatches each in a task of its own.
+    ///
+    /// Returns once the server's endpoint is closed and all handlers have returned.
+    pub async fn serve(self, server: Server) {
+        let router = Arc::new(self);
+        server
+            .serve(move |request| {
+                let router = router.clone();
+                async move {
+                    router.dispatch(request).await;
+                }
+            })
+            .await
+    }
+}
+
+/// Rejects the request, logging failures, see [`H3Request::reject`].
+pub(crate) async fn reject(request: H3Request, status: http::StatusCode) {
+    if let Err(err) = request.reject(status).await {
+        tracing::debug!("failed to reject session: {err:#}");
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field(
+                "routes",
+                &self.routes.iter().map(|(p, _)| p).collect::<Vec<_>>(),
+            )
+            .field("fallback", &self.fallback.is_some())
+            .field("middleware", &self.middleware.len())
+            .finish()
+    }
+}
+
+fn boxed<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(H3Request, Params) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |request, params| Box::pin(handler(request, params)))
+}
+
+/// The parameters captured by a route pattern, see [`Router`].
+///
+/// Values are taken from the URL as is, so they stay percent-encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    params: Vec<(String, String)>,
+    rest: Option<String>,
+}
+
+impl Params {
+    /// Returns the segment captured by `{name}`, if the pattern has one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the part of the path matched by a trailing `*`, without a leading `/`.
+    pub fn rest(&self) -> Option<&str> {
+        self.rest.as_deref()
+    }
+
+    /// Iterates over the captured segments in the order of the pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        let path = pattern
+            .strip_prefix('/')
+            .expect("route pattern must start with /");
+        let segments: Vec<Segment> = path
+            .split('/')
+            .map(|segment| match segment {
+                "*" => Segment::Rest,
+                _ => match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) => Segment::Param(name.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                },
+            })
+            .collect();
+        let rest = segments.iter().position(|s| matches!(s, Segment::Rest));
+        assert!(
+            rest.is_none_or(|i| i == segments.len() - 1),
+            "* must be the last segment of a route pattern"
+        );
+        Self(segments)
+    }
+
+    fn matches(&self, path: &str) -> Option<Params> {
+        let segments: Vec<&str> = path.strip_prefix('/')?.split('/').collect();
+        let mut params = Params::default();
+        for (i, pattern) in self.0.iter().enumerate() {
+            let segment = match pattern {
+                Segment::Rest => {
+                    params.rest = Some(segments.get(i..).unwrap_or_default().join("/"));
+                    return Some(params);
+                }
+                _ => segments.get(i)?,
+            };
+            match pattern {
+                Segment::Literal(literal) if literal == segment => {}
+                Segment::Param(name) if !segment.is_empty() => {
+                    params.params.push((name.clone(), segment.to_string()))
+                }
+                _ => return None,
+            }
+        }
+        (segments.len() == self.0.len()).then_some(params)
+    }
+}
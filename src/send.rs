@@ -1,13 +1,23 @@
 use std::{
     io,
     pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, Ordering},
+    },
     task::{Context, Poll},
 };
 
 use bytes::{Buf, Bytes};
 use iroh::endpoint;
 
-use crate::{ClosedStream, SessionError, WriteError};
+use crate::{
+    BatchedSendStream, Batching, ClosedStream, SessionError, WriteError, shutdown::StreamGuard,
+    stream_count::CountedStream,
+};
+
+// Outside the range of i32, so it can't be a priority.
+const NOT_DEFERRED: i64 = i64::MIN;
 
 /// A stream that can be used to send bytes. See [`iroh::endpoint::SendStream`].
 ///
@@ -16,11 +26,47 @@ use crate::{ClosedStream, SessionError, WriteError};
 #[derive(Debug)]
 pub struct SendStream {
     stream: endpoint::SendStream,
+    // Released once the stream is finished or reset, see `Session::shutdown`.
+    guard: Option<StreamGuard>,
+    // Counts the stream as open until dropped, see `Session::stream_counts`.
+    counted: Option<Arc<CountedStream>>,
+    // The priority applied on the first write, while the stream header is sent at max priority.
+    // Atomic so the priority can be set through a shared reference, NOT_DEFERRED once applied.
+    deferred_priority: AtomicI64,
 }
 
 impl SendStream {
     pub(crate) fn new(stream: endpoint::SendStream) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            guard: None,
+            counted: None,
+            deferred_priority: AtomicI64::new(NOT_DEFERRED),
+        }
+    }
+
+    // Keeps the stream at max priority until the first write, so the header isn't queued behind
+    // other streams if the application lowers the priority right away.
+    pub(crate) fn with_header_priority(self) -> Self {
+        self.deferred_priority.store(0, Ordering::Relaxed);
+        self
+    }
+
+    fn apply_priority(&mut self) {
+        let order = self.deferred_priority.swap(NOT_DEFERRED, Ordering::Relaxed);
+        if order != NOT_DEFERRED {
+            self.stream.set_priority(order as i32).ok();
+        }
+    }
+
+    pub(crate) fn with_count(mut self, counted: Arc<CountedStream>) -> Self {
+        self.counted = Some(counted);
+        self
+    }
+
+    pub(crate) fn with_guard(mut self, guard: StreamGuard) -> Self {
+        self.guard = Some(guard);
+        self
     }
 
     /// Abruptly reset the stream with the provided error code. See [`iroh::endpoint::SendStream::reset`].
@@ -28,21 +74,21 @@ impl SendStream {
     pub fn reset(&mut self, code: u32) -> Result<(), ClosedStream> {
         let code = web_transport_proto::error_to_http3(code);
         let code = endpoint::VarInt::try_from(code).unwrap();
+        self.guard = None;
         self.stream.reset(code).map_err(Into::into)
     }
 
     /// Wait until the stream has been stopped and return the error code. See [`iroh::endpoint::SendStream::stopped`].
     ///
     /// Unlike Quinn, this returns None if the code is not a valid WebTransport error code.
-    /// Also unlike Quinn, this returns a SessionError, not a StoppedError, because 0-RTT is not supported.
+    /// Also unlike Quinn, this returns a SessionError, not a StoppedError.
+    /// A stream sent in rejected 0-RTT data fails with [`SessionError::ZeroRttRejected`].
     pub async fn stopped(&mut self) -> Result<Option<u32>, SessionError> {
         match self.stream.stopped().await {
             Ok(Some(code)) => Ok(web_transport_proto::error_from_http3(code.into_inner())),
             Ok(None) => Ok(None),
             Err(endpoint::StoppedError::ConnectionLost(e)) => Err(e.into()),
-            Err(endpoint::StoppedError::ZeroRttRejected) => {
-                unreachable!("0-RTT not supported")
-            }
+            Err(endpoint::StoppedError::ZeroRttRejected) => Err(SessionError::ZeroRttRejected),
         }
     }
 
@@ -50,45 +96,79 @@ impl SendStream {
 
     /// Write some data to the stream, returning the size written. See [`iroh::endpoint::SendStream::write`].
     pub async fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
+        self.apply_priority();
         self.stream.write(buf).await.map_err(Into::into)
     }
 
     /// Write all of the data to the stream. See [`iroh::endpoint::SendStream::write_all`].
     pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), WriteError> {
+        self.apply_priority();
         self.stream.write_all(buf).await.map_err(Into::into)
     }
 
     /// Write chunks of data to the stream. See [`iroh::endpoint::SendStream::write_chunks`].
+    ///
+    /// The chunks are sent as they are, so a message assembled from several buffers, e.g. a
+    /// header and a payload, doesn't have to be concatenated first. Written chunks are emptied.
     pub async fn write_chunks(
         &mut self,
         bufs: &mut [Bytes],
     ) -> Result<endpoint::Written, WriteError> {
+        self.apply_priority();
         self.stream.write_chunks(bufs).await.map_err(Into::into)
     }
 
     /// Write a chunk of data to the stream. See [`iroh::endpoint::SendStream::write_chunk`].
+    ///
+    /// The chunk is sent without copying, so the same payload can be fanned out to many
+    /// streams by cloning the [`Bytes`], which only bumps a reference count.
     pub async fn write_chunk(&mut self, buf: Bytes) -> Result<(), WriteError> {
+        self.apply_priority();
         self.stream.write_chunk(buf).await.map_err(Into::into)
     }
 
     /// Write all of the chunks of data to the stream. See [`iroh::endpoint::SendStream::write_all_chunks`].
     pub async fn write_all_chunks(&mut self, bufs: &mut [Bytes]) -> Result<(), WriteError> {
+        self.apply_priority();
         self.stream.write_all_chunks(bufs).await.map_err(Into::into)
     }
 
     /// Mark the stream as finished, such that no more data can be written. See [`iroh::endpoint::SendStream::finish`].
     pub fn finish(&mut self) -> Result<(), ClosedStream> {
+        self.guard = None;
         self.stream.finish().map_err(Into::into)
     }
 
     /// Set the stream's priority. See [`iroh::endpoint::SendStream::set_priority`].
+    ///
+    /// Streams with a higher priority are sent first, and the default is 0, so e.g. a negative
+    /// priority keeps bulk data from delaying control streams. The WebTransport stream header is
+    /// always sent at the highest priority: until the first write, the priority is only recorded.
     pub fn set_priority(&self, order: i32) -> Result<(), ClosedStream> {
-        self.stream.set_priority(order).map_err(Into::into)
+        // Writes take `&mut self`, so the priority can't be applied concurrently.
+        if self.deferred_priority.load(Ordering::Relaxed) == NOT_DEFERRED {
+            return self.stream.set_priority(order).map_err(Into::into);
+        }
+        self.stream.priority()?;
+        self.deferred_priority
+            .store(order.into(), Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Returns the stream's current priority. See [`iroh::endpoint::SendStream::priority`].
+    /// Returns the stream's priority, as set with [`Self::set_priority`].
     pub fn priority(&self) -> Result<i32, ClosedStream> {
-        self.stream.priority().map_err(Into::into)
+        let priority = self.stream.priority()?;
+        match self.deferred_priority.load(Ordering::Relaxed) {
+            NOT_DEFERRED => Ok(priority),
+            deferred => Ok(deferred as i32),
+        }
+    }
+
+    /// Aggregate small writes into larger ones, to reduce the overhead of many tiny frames.
+    ///
+    /// See [`BatchedSendStream`].
+    pub fn batched(self, batching: Batching) -> BatchedSendStream {
+        BatchedSendStream::new(self, batching)
     }
 }
 
@@ -98,16 +178,37 @@ impl tokio::io::AsyncWrite for SendStream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        // We have to use this syntax because iroh::endpoint added its own poll_write method.
-        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.stream), cx, buf)
+        self.apply_priority();
+        // The inherent method, so STOP_SENDING codes are decoded before converting to io::Error.
+        endpoint::SendStream::poll_write(Pin::new(&mut self.stream), cx, buf)
+            .map_err(|err| WriteError::from(err).into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.finish().map_err(|_| WriteError::ClosedStream.into()))
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for SendStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(self, cx, buf)
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_flush(cx)
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(self, cx)
     }
 
-    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.stream).poll_shutdown(cx)
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(self, cx)
     }
 }
 
@@ -115,7 +216,7 @@ impl web_transport_trait::SendStream for SendStream {
     type Error = WriteError;
 
     fn set_priority(&mut self, order: u8) {
-        self.stream.set_priority(order.into()).ok();
+        Self::set_priority(self, order.into()).ok();
     }
 
     fn reset(&mut self, code: u32) {
@@ -1,11 +1,1012 @@
-use iroh::endpoint::Connection;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use iroh::{
+    Endpoint, RelayMode, SecretKey, TransportAddr,
+    endpoint::{self, Connection, Incoming, QuicTransportConfig, QuicTransportConfigBuilder},
+};
+use n0_future::Stream;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+use tracing::Instrument;
 use web_transport_proto::{ConnectRequest, ConnectResponse};
 
-use crate::{Connecting, ServerError, Session, Settings};
+use crate::{
+    AFFINITY_KEY, AdmissionControl, AuthRequest, Connected, Connecting, HandshakeError,
+    HandshakeOptions, ORIGIN_REJECTED, OriginPolicy, PEER_QUOTA_EXCEEDED, PeerAcl, ServerError,
+    ServerMetrics, Session, Settings, SettingsProfile, Spawner, Strictness, TransportTuning,
+    auth::{self, AuthCallback},
+    path,
+    quota::PeerQuota,
+    router::reject,
+    shutdown::SessionTracker,
+};
+
+// The HTTP/3 error code for a request that was not fully received.
+const H3_REQUEST_INCOMPLETE: u32 = 0x10d;
+// The HTTP/3 error code for a request that was rejected without any processing.
+const H3_REQUEST_REJECTED: u32 = 0x10b;
+
+// Type alias just so clippy doesn't complain about the complexity.
+type PriorityCallback = Arc<dyn Fn(&H3Request) -> u8 + Send + Sync>;
+type FilterCallback =
+    Arc<dyn Fn(Connection) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// The default limit on concurrent handshakes, see [`Server::with_max_pending`].
+pub const DEFAULT_MAX_PENDING: usize = 256;
+
+/// The default limit on completed handshakes waiting to be accepted, see [`Server::with_max_ready`].
+pub const DEFAULT_MAX_READY: usize = 256;
+
+/// What a [`Server`] does with incoming connections while too many handshakes are pending.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PendingOverflow {
+    /// Leave them in the endpoint's backlog until a handshake completes.
+    #[default]
+    Wait,
+    /// Refuse them right away, so the client fails fast and can try elsewhere.
+    Refuse,
+}
+
+/// A server accepting H3 WebTransport sessions on an iroh endpoint.
+///
+/// The endpoint should accept the [`ALPN_H3`](crate::ALPN_H3) ALPN, and the raw QUIC ALPNs set
+/// with [`Self::with_raw_alpns`], if any.
+/// Handshakes run concurrently in background tasks, so a slow client doesn't hold up the others.
+/// Pending handshakes are aborted when the server is dropped.
+///
+/// By default every incoming connection is admitted to the handshake. Use [`Self::with_admission`]
+/// to require a permit for each one instead.
+pub struct Server {
+    endpoint: Endpoint,
+    // The max sessions, strictness, header size limit and SETTINGS of HTTP/3 handshakes.
+    options: HandshakeOptions,
+    handshake_timeout: Option<Duration>,
+    priority: Option<PriorityCallback>,
+    admission: Option<Arc<Semaphore>>,
+    control: Option<Arc<dyn AdmissionControl>>,
+    acl: Option<PeerAcl>,
+    quota: Option<PeerQuota>,
+    filter: Option<FilterCallback>,
+    origin: Option<OriginPolicy>,
+    auth: Option<AuthCallback>,
+    // A permit acquired for the next incoming connection.
+    permit: Option<OwnedSemaphorePermit>,
+    max_pending: usize,
+    overflow: PendingOverflow,
+    // Connections with these ALPNs skip the HTTP/3 handshake.
+    raw_alpns: Arc<Vec<Vec<u8>>>,
+    pending: JoinSet<Result<Request, HandshakeError>>,
+    // Completed handshakes that weren't returned yet, by priority class.
+    ready: BTreeMap<u8, VecDeque<Request>>,
+    max_ready: usize,
+    metrics: Arc<ServerMetrics>,
+    // The sessions accepted through this server, drained by `shutdown`.
+    tracker: SessionTracker,
+    // Starts the drivers and other background tasks of accepted sessions.
+    spawner: Spawner,
+    // Set by `shutdown`, after which no more sessions are accepted.
+    shut_down: bool,
+}
+
+impl Server {
+    /// Returns a builder to configure a server and optionally bind its endpoint, see [`ServerBuilder`].
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Creates a server from an endpoint, with the handshake timeout of the default [`TransportTuning`].
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            options: HandshakeOptions::default(),
+            handshake_timeout: TransportTuning::default().handshake_timeout,
+            priority: None,
+            admission: None,
+            control: None,
+            acl: None,
+            quota: None,
+            filter: None,
+            origin: None,
+            auth: None,
+            permit: None,
+            max_pending: DEFAULT_MAX_PENDING,
+            overflow: PendingOverflow::Wait,
+            raw_alpns: Default::default(),
+            pending: JoinSet::new(),
+            ready: BTreeMap::new(),
+            max_ready: DEFAULT_MAX_READY,
+            metrics: Default::default(),
+            tracker: SessionTracker::default(),
+            spawner: Spawner::default(),
+            shut_down: false,
+        }
+    }
+
+    /// Sets the `SETTINGS_WT_MAX_SESSIONS` advertised to clients.
+    pub fn with_max_sessions(mut self, max_sessions: u32) -> Self {
+        self.options.max_sessions = max_sessions;
+        self
+    }
+
+    /// Sets how strictly the protocol is enforced. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.options.strictness = strictness;
+        self
+    }
+
+    /// Limits the size of the request headers, advertised in SETTINGS.
+    ///
+    /// See [`HandshakeOptions::max_field_section_size`].
+    pub fn with_max_field_section_size(mut self, size: u64) -> Self {
+        self.options.max_field_section_size = Some(size);
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent and which omissions by clients are tolerated.
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    /// Sets all options of the HTTP/3 handshakes at once, see [`HandshakeOptions`].
+    pub fn with_handshake_options(mut self, options: HandshakeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Starts the background tasks of accepted sessions with the [`Spawner`], including
+    /// their [`SessionDriver`](crate::SessionDriver) and the peer quota bookkeeping.
+    ///
+    /// Defaults to the current tokio runtime. Pending handshakes and the handlers of
+    /// [`Self::serve`] are still tokio tasks.
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Sets how long to wait for the QUIC handshake and the SETTINGS and CONNECT exchange,
+    /// or None to only rely on the idle timeout.
+    ///
+    /// Connections that don't complete the handshake in time are closed with
+    /// `H3_REQUEST_INCOMPLETE`, and counted in [`ServerMetrics::handshakes_timed_out`].
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Classifies completed handshakes into priority classes, where higher classes are returned first.
+    ///
+    /// Under load, more handshakes may complete than [`Self::accept`] is called for. Then e.g.
+    /// requests by path prefix or from allowlisted peers can be preferred, so they still get
+    /// through during a flood. Requests within a class are returned in order of completion.
+    pub fn with_priority(mut self, f: impl Fn(&H3Request) -> u8 + Send + Sync + 'static) -> Self {
+        self.priority = Some(Arc::new(f));
+        self
+    }
+
+    /// Requires a permit from the semaphore before admitting a connection to the handshake.
+    ///
+    /// Connections wait in the endpoint's backlog until a permit is available. The permit is
+    /// held by the [`H3Request`] until it is responded to or dropped, unless the application
+    /// keeps it for longer with [`H3Request::take_permit`]. Sharing the semaphore with other
+    /// parts of the application bounds them together. Once the semaphore is closed, no more
+    /// connections are admitted and [`Self::accept`] returns None.
+    pub fn with_admission(mut self, semaphore: Arc<Semaphore>) -> Self {
+        self.admission = Some(semaphore);
+        self
+    }
+
+    /// Decides whether to pass each session request on to the application, e.g. with a [`RateLimit`](crate::RateLimit).
+    ///
+    /// Connections refused by [`AdmissionControl::admit_connection`] are refused before their
+    /// handshake starts and counted in [`ServerMetrics::connections_refused`]. Requests that
+    /// aren't admitted are rejected with the returned status after [`Self::with_auth`], and
+    /// reported as [`ServerError::NotAdmitted`] by [`Self::accept_with_errors`].
+    pub fn with_admission_control(mut self, control: impl AdmissionControl) -> Self {
+        self.control = Some(Arc::new(control));
+        self
+    }
+
+    /// Only accepts connections from the peers allowed by the ACL.
+    ///
+    /// It is checked as soon as the peer is authenticated by the QUIC handshake, and
+    /// connections from other peers are closed before the HTTP/3 handshake starts. They are
+    /// reported as [`ServerError::PeerDenied`] by [`Self::accept_with_errors`]. Keep a clone of
+    /// the ACL to update it while the server is running.
+    pub fn with_acl(mut self, acl: PeerAcl) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Limits how many sessions each peer may hold at the same time.
+    ///
+    /// A session counts against the quota from the moment its CONNECT request is received,
+    /// after [`Self::with_auth`] and [`Self::with_admission_control`], until its connection is
+    /// closed. Excess requests are rejected with [`PEER_QUOTA_EXCEEDED`] and reported as
+    /// [`ServerError::NotAdmitted`]. The rejected connection is dropped once the client has the
+    /// response, or after [`REJECT_LINGER`](crate::REJECT_LINGER), and holds a pending handshake
+    /// slot until then.
+    /// Raw QUIC connections count too, and excess ones are closed with the same status as code.
+    pub fn with_peer_quota(mut self, max_per_peer: usize) -> Self {
+        self.quota = Some(PeerQuota::new(max_per_peer));
+        self
+    }
+
+    /// Drops connections for which the filter returns false, before any HTTP/3 work happens.
+    ///
+    /// The filter is called with the connection as soon as the QUIC handshake completes, after
+    /// [`Self::with_acl`], so it can look at the [`Connection::remote_id`], the
+    /// [`Connection::alpn`] or the network paths. It runs in the handshake task, under the
+    /// handshake timeout, and a synchronous check can return [`std::future::ready`]. Dropped
+    /// connections are closed and reported as [`ServerError::Filtered`] by [`Self::accept_with_errors`].
+    pub fn with_connection_filter<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.filter = Some(Arc::new(move |conn| Box::pin(f(conn))));
+        self
+    }
+
+    /// Rejects session requests whose origin isn't allowed by the policy, see [`H3Request::check_origin`].
+    ///
+    /// The origin is checked in the handshake task once the CONNECT request was received,
+    /// before [`Self::with_auth`]. Rejected requests are reported as
+    /// [`ServerError::OriginRejected`] by [`Self::accept_with_errors`].
+    pub fn with_origin_policy(mut self, policy: OriginPolicy) -> Self {
+        self.origin = Some(policy);
+        self
+    }
+
+    /// Authenticates each session request with the hook, e.g. by its [`AuthRequest::bearer_token`].
+    ///
+    /// The hook runs in the handshake task once the CONNECT request was received, before
+    /// [`Self::with_admission_control`] and [`Self::with_peer_quota`], so unauthenticated
+    /// requests don't use up their capacity. It's bounded by the handshake timeout. If it returns
+    /// an identity, the identity is stored in the [`H3Request::extensions`], and from there in
+    /// the [`Session::extensions`]. Otherwise the request is rejected with the returned status,
+    /// e.g. 401 Unauthorized, and reported as [`ServerError::Unauthenticated`] by
+    /// [`Self::accept_with_errors`].
+    pub fn with_auth<F, Fut, I>(mut self, f: F) -> Self
+    where
+        F: Fn(AuthRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<I, http::StatusCode>> + Send + 'static,
+        I: Clone + Send + Sync + 'static,
+    {
+        self.auth = Some(auth::callback(f));
+        self
+    }
+
+    /// Limits how many handshakes run concurrently, [`DEFAULT_MAX_PENDING`] by default.
+    ///
+    /// Each pending handshake holds a connection and a task, so this bounds the memory a flood
+    /// of connections can take. Once the limit is reached, incoming connections are handled
+    /// according to the [`PendingOverflow`]. Completed handshakes that weren't returned by
+    /// [`Self::accept`] yet don't count against the limit, see [`Self::with_max_ready`] for that.
+    pub fn with_max_pending(mut self, max: usize, overflow: PendingOverflow) -> Self {
+        self.max_pending = max.max(1);
+        self.overflow = overflow;
+        self
+    }
+
+    /// Limits how many completed handshakes wait to be returned by [`Self::accept`],
+    /// [`DEFAULT_MAX_READY`] by default.
+    ///
+    /// Once the limit is reached, the oldest request of the lowest [`Self::with_priority`]
+    /// class is dropped and its connection closed, so a flood of low priority requests
+    /// can't hold connections while the application is busy.
+    pub fn with_max_ready(mut self, max: usize) -> Self {
+        self.max_ready = max.max(1);
+        self
+    }
+
+    /// Also accepts raw QUIC sessions on connections that negotiated one of the ALPNs.
+    ///
+    /// They skip the HTTP/3 handshake and are returned as [`Request::Quic`] by [`Self::accept_any`].
+    /// [`Self::accept`] only returns HTTP/3 sessions, and closes raw ones. Raw sessions aren't
+    /// subject to [`Self::with_priority`] and [`Self::with_admission_control`], which need the
+    /// CONNECT request, and aren't drained by [`Self::shutdown`], only closed.
+    pub fn with_raw_alpns(mut self, alpns: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Self {
+        self.raw_alpns = Arc::new(alpns.into_iter().map(|a| a.as_ref().to_vec()).collect());
+        self
+    }
+
+    /// Returns the underlying endpoint.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Returns the accepted session requests as a [`Stream`], see [`Self::accept`].
+    pub fn into_stream(self) -> impl Stream<Item = H3Request> + Send + 'static {
+        n0_future::stream::unfold(self, |mut server| async move {
+            let request = server.accept().await?;
+            Some((request, server))
+        })
+    }
+
+    /// Accepts the next session request, skipping connections that fail the handshake.
+    ///
+    /// Of the completed handshakes, the one with the highest priority is returned, see [`Self::with_priority`].
+    /// Returns None once the endpoint is closed. Use [`Self::accept_with_errors`] to see failed handshakes.
+    pub async fn accept(&mut self) -> Option<H3Request> {
+        loop {
+            match self.accept_with_errors().await? {
+                Ok(request) => return Some(request),
+                Err(err) => tracing::debug!("failed to accept session: {err:#}"),
+            }
+        }
+    }
+
+    /// Accepts the next session request, or returns the next handshake that failed.
+    ///
+    /// Failed handshakes are returned as soon as they fail, along with the id of the peer if it
+    /// got far enough to be authenticated. Otherwise this behaves like [`Self::accept`].
+    pub async fn accept_with_errors(&mut self) -> Option<Result<H3Request, HandshakeError>> {
+        loop {
+            match self.accept_any_with_errors().await? {
+                Ok(Request::H3(request)) => return Some(Ok(*request)),
+                Ok(Request::Quic(request)) => {
+                    tracing::debug!("closing raw QUIC session, use accept_any to accept them");
+                    request.close(http::StatusCode::NOT_IMPLEMENTED);
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    /// Accepts the next HTTP/3 or raw QUIC session request, see [`Self::with_raw_alpns`].
+    ///
+    /// Returns None once the endpoint is closed. Otherwise this behaves like [`Self::accept`].
+    pub async fn accept_any(&mut self) -> Option<Request> {
+        loop {
+            match self.accept_any_with_errors().await? {
+                Ok(request) => return Some(request),
+                Err(err) => tracing::debug!("failed to accept session: {err:#}"),
+            }
+        }
+    }
+
+    /// Accepts the next HTTP/3 or raw QUIC session request, or returns the next handshake that failed.
+    ///
+    /// See [`Self::accept_with_errors`] and [`Self::accept_any`].
+    pub async fn accept_any_with_errors(&mut self) -> Option<Result<Request, HandshakeError>> {
+        if self.shut_down {
+            return None;
+        }
+        loop {
+            while let Some(result) = self.pending.try_join_next() {
+                if let Err(err) = self.completed(result) {
+                    return Some(Err(err));
+                }
+            }
+            if let Some(mut entry) = self.ready.last_entry() {
+                let request = entry.get_mut().pop_front();
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+                return request.map(Ok);
+            }
+
+            let admitted = self.admission.is_none() || self.permit.is_some();
+            let full = self.pending.len() >= self.max_pending;
+            let wait = full && self.overflow == PendingOverflow::Wait;
+            let admission = self.admission.clone();
+            tokio::select! {
+                incoming = self.endpoint.accept(), if admitted && !wait => {
+                    let incoming = incoming?;
+                    if self
+                        .control
+                        .as_ref()
+                        .is_some_and(|c| !c.admit_connection(&incoming, self.tracker.active.count()))
+                    {
+                        tracing::debug!("refusing connection, not admitted");
+                        incoming.refuse();
+                        self.metrics.refuse();
+                        continue;
+                    }
+                    if full {
+                        tracing::debug!("refusing connection, too many pending handshakes");
+                        incoming.refuse();
+                        self.metrics.refuse();
+                        continue;
+                    }
+                    let permit = self.permit.take();
+                    let handshake = self.handshake(incoming, permit);
+                    self.pending.spawn(handshake);
+                    self.metrics.start();
+                }
+                permit = async move { admission?.acquire_owned().await.ok() }, if !admitted => {
+                    self.permit = Some(permit?);
+                }
+                Some(result) = self.pending.join_next() => {
+                    if let Err(err) = self.completed(result) {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+
+    fn completed(
+        &mut self,
+        result: Result<Result<Request, HandshakeError>, tokio::task::JoinError>,
+    ) -> Result<(), HandshakeError> {
+        match result {
+            Ok(Ok(request)) => {
+                let priority = match (&self.priority, &request) {
+                    (Some(f), Request::H3(request)) => f(request),
+                    _ => 0,
+                };
+                self.ready.entry(priority).or_default().push_back(request);
+                self.metrics.complete();
+                self.evict();
+            }
+            Ok(Err(err)) => {
+                self.metrics.fail(Some(&err.source));
+                return Err(err);
+            }
+            Err(err) => {
+                self.metrics.fail(None);
+                tracing::warn!("handshake task failed: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    // Closes the oldest request of the lowest priority class while there are too many.
+    fn evict(&mut self) {
+        while self.ready.values().map(VecDeque::len).sum::<usize>() > self.max_ready {
+            let Some(mut entry) = self.ready.first_entry() else {
+                return;
+            };
+            let request = entry.get_mut().pop_front();
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            tracing::debug!("dropping completed handshake, too many waiting to be accepted");
+            match request {
+                Some(Request::H3(request)) => request
+                    .conn()
+                    .close(H3_REQUEST_REJECTED.into(), b"server busy"),
+                Some(Request::Quic(request)) => {
+                    request.close(http::StatusCode::SERVICE_UNAVAILABLE)
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Returns the counters of the handshakes run so far.
+    pub fn metrics(&self) -> &Arc<ServerMetrics> {
+        &self.metrics
+    }
+
+    /// Close the server endpoint.
+    pub async fn close(&self) {
+        self.endpoint.close().await;
+    }
+
+    /// Gracefully shuts down the server, returning false if the deadline passed first.
+    ///
+    /// The steps are, in order:
+    ///   1. Stop accepting sessions, so [`Self::accept`] returns None, and drop pending handshakes.
+    ///   2. Send GOAWAY and DRAIN_WEBTRANSPORT_SESSION to every session accepted by this server.
+    ///   3. Wait until all of them are closed, refusing new connections in the meantime.
+    ///
+    /// Finally, or once the deadline passes, the remaining sessions and the endpoint are closed.
+    /// Raw QUIC sessions have no drain signal, so they aren't waited for and are only closed.
+    /// This is meant for restarts, where clients reconnect to the new process, see [`Session::handoff`].
+    pub async fn shutdown(&mut self, deadline: Instant) -> bool {
+        self.shut_down = true;
+        self.pending.abort_all();
+        self.ready.clear();
+        self.tracker.drain.cancel();
+
+        let endpoint = self.endpoint.clone();
+        let active = self.tracker.active.clone();
+        let graceful = tokio::time::timeout_at(deadline.into(), async move {
+            let refuse = async {
+                while let Some(incoming) = endpoint.accept().await {
+                    incoming.refuse();
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                _ = active.finished() => {}
+                _ = refuse => {}
+            }
+        })
+        .await
+        .is_ok();
+
+        self.tracker.close.cancel();
+        self.endpoint.close().await;
+        graceful
+    }
+
+    /// Accepts sessions and runs the handler for each in a task of its own.
+    ///
+    /// Each handler runs in a `session` span with the remote and path of its request. A handler that
+    /// panics is logged and its request or session dropped, without affecting the others.
+    /// Returns once the endpoint is closed and all handlers have returned.
+    pub async fn serve<F, Fut>(self, handler: F)
+    where
+        F: Fn(H3Request) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.serve_with_shutdown(handler, std::future::pending(), Duration::ZERO)
+            .await;
+    }
+
+    /// Like [`Self::serve`], but shuts down gracefully once the signal completes, e.g. on Ctrl-C.
+    ///
+    /// The server is shut down with [`Self::shutdown`], giving sessions and then their handlers up to
+    /// `grace` to finish before the remaining handlers are aborted. Returns false if any had to be
+    /// closed or aborted, and true if the endpoint was closed before the signal.
+    pub async fn serve_with_shutdown<F, Fut>(
+        mut self,
+        handler: F,
+        signal: impl Future<Output = ()>,
+        grace: Duration,
+    ) -> bool
+    where
+        F: Fn(H3Request) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut handlers = JoinSet::new();
+        let mut signal = std::pin::pin!(signal);
+        let shutdown = loop {
+            tokio::select! {
+                request = self.accept() => {
+                    let Some(request) = request else {
+                        break false;
+                    };
+                    let span = tracing::info_span!(
+                        "session",
+                        remote = %request.conn().remote_id().fmt_short(),
+                        path = %request.url.path(),
+                    );
+                    handlers.spawn(handler(request).instrument(span));
+                }
+                Some(result) = handlers.join_next() => handler_finished(result),
+                _ = &mut signal => break true,
+            }
+        };
+
+        let mut graceful = true;
+        let deadline = Instant::now() + grace;
+        if shutdown {
+            tracing::debug!(handlers = handlers.len(), "shutting down server");
+            graceful = self.shutdown(deadline).await;
+        }
+        let finished = async {
+            while let Some(result) = handlers.join_next().await {
+                handler_finished(result);
+            }
+        };
+        if !shutdown {
+            finished.await;
+        } else if tokio::time::timeout_at(deadline.into(), finished)
+            .await
+            .is_err()
+        {
+            tracing::debug!(handlers = handlers.len(), "aborting session handlers");
+            handlers.shutdown().await;
+            graceful = false;
+        }
+        graceful
+    }
+
+    fn handshake(
+        &self,
+        incoming: Incoming,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> impl Future<Output = Result<Request, HandshakeError>> + Send + 'static {
+        let options = self.options.clone();
+        let tracker = self.tracker.clone();
+        let spawner = self.spawner.clone();
+        let control = self.control.clone();
+        let raw_alpns = self.raw_alpns.clone();
+        let acl = self.acl.clone();
+        let filter = self.filter.clone();
+        let quota = self.quota.clone();
+        let origin = self.origin.clone();
+        let auth = self.auth.clone();
+        let deadline = self
+            .handshake_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+
+        async move {
+            let started = tokio::time::Instant::now();
+            let failed = |remote, source| HandshakeError { remote, source };
+            let connecting = async {
+                incoming
+                    .await
+                    .map_err(|err| ServerError::Connecting(Arc::new(err)))
+            };
+            let conn = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, connecting)
+                    .await
+                    .unwrap_or(Err(ServerError::HandshakeTimeout)),
+                None => connecting.await,
+            }
+            .map_err(|err| failed(None, err))?;
+            let remote = Some(conn.remote_id());
+            if acl.is_some_and(|acl| !acl.is_allowed(&conn.remote_id())) {
+                conn.close(H3_REQUEST_REJECTED.into(), b"peer denied");
+                return Err(failed(remote, ServerError::PeerDenied));
+            }
+            if let Some(filter) = filter {
+                let allowed = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, filter(conn.clone()))
+                        .await
+                        .map_err(|_| ServerError::HandshakeTimeout),
+                    None => Ok(filter(conn.clone()).await),
+                };
+                match allowed {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        conn.close(H3_REQUEST_REJECTED.into(), b"connection filtered");
+                        return Err(failed(remote, ServerError::Filtered));
+                    }
+                    Err(err) => {
+                        conn.close(H3_REQUEST_INCOMPLETE.into(), b"handshake timeout");
+                        return Err(failed(remote, err));
+                    }
+                }
+            }
+            if raw_alpns.iter().any(|alpn| conn.alpn() == alpn.as_slice()) {
+                let request = QuicRequest::accept(conn)
+                    .with_handshake_duration(started.elapsed())
+                    .with_spawner(spawner.clone());
+                if quota.is_some_and(|quota| !quota.acquire(request.conn(), &spawner)) {
+                    request.close(PEER_QUOTA_EXCEEDED);
+                    let status = PEER_QUOTA_EXCEEDED;
+                    return Err(failed(remote, ServerError::NotAdmitted { status }));
+                }
+                return Ok(Request::Quic(request.with_permit(permit)));
+            }
+
+            let accept = H3Request::accept_with_options(conn.clone(), &options);
+            let mut request = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, accept).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        conn.close(H3_REQUEST_INCOMPLETE.into(), b"handshake timeout");
+                        Err(ServerError::HandshakeTimeout)
+                    }
+                },
+                None => accept.await,
+            }
+            .map_err(|err| failed(remote, err))?
+            .with_handshake_duration(started.elapsed());
+
+            if let Some(policy) = origin {
+                request = request
+                    .check_origin(&policy)
+                    .await
+                    .map_err(|err| failed(remote, err))?;
+            }
+            if let Some(auth) = auth {
+                let auth_request = AuthRequest {
+                    remote: conn.remote_id(),
+                    url: request.url.clone(),
+                    headers: request.headers().clone(),
+                };
+                let identity = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, auth(auth_request))
+                        .await
+                        .map_err(|_| ServerError::HandshakeTimeout),
+                    None => Ok(auth(auth_request).await),
+                };
+                match identity {
+                    Ok(Ok(identity)) => request.extensions_mut().extend(identity),
+                    Ok(Err(status)) => {
+                        reject(request, status).await;
+                        return Err(failed(remote, ServerError::Unauthenticated { status }));
+                    }
+                    Err(err) => {
+                        conn.close(H3_REQUEST_INCOMPLETE.into(), b"handshake timeout");
+                        return Err(failed(remote, err));
+                    }
+                }
+            }
+            if let Some(control) = control
+                && let Err(status) = control.admit(&request, tracker.active.count())
+            {
+                // The rejection lingers for at most REJECT_LINGER, in this pending slot.
+                reject(request, status).await;
+                return Err(failed(remote, ServerError::NotAdmitted { status }));
+            }
+            if quota.is_some_and(|quota| !quota.acquire(&conn, &spawner)) {
+                let status = PEER_QUOTA_EXCEEDED;
+                tracing::debug!(remote = %conn.remote_id().fmt_short(), "peer quota exceeded");
+                reject(request, status).await;
+                return Err(failed(remote, ServerError::NotAdmitted { status }));
+            }
+            Ok(Request::H3(Box::new(
+                request
+                    .with_permit(permit)
+                    .with_tracker(tracker)
+                    .with_spawner(spawner),
+            )))
+        }
+    }
+}
+
+// Logs a session handler that panicked or was aborted.
+fn handler_finished(result: Result<(), tokio::task::JoinError>) {
+    match result {
+        Ok(()) => {}
+        Err(err) if err.is_panic() => tracing::error!("session handler panicked: {err}"),
+        Err(err) => tracing::warn!("session handler failed: {err}"),
+    }
+}
+
+/// Builds a [`Server`] with a custom configuration, see [`Server::builder`].
+///
+/// Use [`Self::bind`] to also bind the endpoint with the ALPNs and transport config, or
+/// [`Self::build`] for an existing endpoint. Priorities and admission are set on the server.
+#[derive(Debug)]
+pub struct ServerBuilder {
+    tuning: TransportTuning,
+    transport: Option<QuicTransportConfigBuilder>,
+    handshake_timeout: Option<Duration>,
+    options: HandshakeOptions,
+    raw_alpns: Vec<Vec<u8>>,
+    max_pending: usize,
+    overflow: PendingOverflow,
+    spawner: Spawner,
+    endpoint: Option<endpoint::Builder>,
+    relay_mode: Option<RelayMode>,
+    secret_key: Option<SecretKey>,
+}
+
+impl ServerBuilder {
+    /// Returns a builder with the default [`TransportTuning`].
+    pub fn new() -> Self {
+        let tuning = TransportTuning::default();
+        Self {
+            tuning,
+            transport: None,
+            handshake_timeout: tuning.handshake_timeout,
+            options: HandshakeOptions::default(),
+            raw_alpns: Vec::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            overflow: PendingOverflow::Wait,
+            spawner: Spawner::default(),
+            endpoint: None,
+            relay_mode: None,
+            secret_key: None,
+        }
+    }
+
+    /// Sets the transport timings, including the handshake timeout.
+    ///
+    /// They are applied to the transport config of a bound endpoint, unless one is set with
+    /// [`Self::with_transport_config`].
+    pub fn with_tuning(mut self, tuning: TransportTuning) -> Self {
+        self.tuning = tuning;
+        self.handshake_timeout = tuning.handshake_timeout;
+        self
+    }
+
+    /// Sets the transport config of a bound endpoint, replacing the defaults of the [`TransportTuning`].
+    pub fn with_transport_config(mut self, config: QuicTransportConfigBuilder) -> Self {
+        self.transport = Some(config);
+        self
+    }
+
+    /// Sets how long to wait for handshakes, see [`Server::with_handshake_timeout`].
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Sets the `SETTINGS_WT_MAX_SESSIONS` advertised to clients, see [`Server::with_max_sessions`].
+    pub fn with_max_sessions(mut self, max_sessions: u32) -> Self {
+        self.options.max_sessions = max_sessions;
+        self
+    }
+
+    /// Sets how strictly the protocol is enforced. See [`Strictness`].
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.options.strictness = strictness;
+        self
+    }
+
+    /// Limits the size of the request headers, see [`Server::with_max_field_section_size`].
+    pub fn with_max_field_section_size(mut self, size: u64) -> Self {
+        self.options.max_field_section_size = Some(size);
+        self
+    }
+
+    /// Selects which optional SETTINGS are sent, see [`Server::with_settings_profile`].
+    pub fn with_settings_profile(mut self, profile: SettingsProfile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    /// Sets all options of the HTTP/3 handshakes at once, see [`HandshakeOptions`].
+    pub fn with_handshake_options(mut self, options: HandshakeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Starts the background tasks of accepted sessions with the spawner, see [`Server::with_spawner`].
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    /// Also accepts raw QUIC sessions with the ALPNs, see [`Server::with_raw_alpns`].
+    ///
+    /// A bound endpoint accepts them after [`ALPN_H3`](crate::ALPN_H3).
+    pub fn with_raw_alpns(mut self, alpns: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Self {
+        self.raw_alpns = alpns.into_iter().map(|a| a.as_ref().to_vec()).collect();
+        self
+    }
+
+    /// Limits how many handshakes run concurrently, see [`Server::with_max_pending`].
+    pub fn with_max_pending(mut self, max: usize, overflow: PendingOverflow) -> Self {
+        self.max_pending = max;
+        self.overflow = overflow;
+        self
+    }
+
+    /// Binds the endpoint from the builder instead of [`Endpoint::builder`], e.g. to configure address lookup.
+    ///
+    /// The ALPNs and the transport config are still set by [`Self::bind`], as are the relay
+    /// mode and the secret key, if given.
+    pub fn with_endpoint_builder(mut self, builder: endpoint::Builder) -> Self {
+        self.endpoint = Some(builder);
+        self
+    }
+
+    /// Sets the relay mode of a bound endpoint, e.g. [`RelayMode::Disabled`] for local networks.
+    pub fn with_relay_mode(mut self, mode: RelayMode) -> Self {
+        self.relay_mode = Some(mode);
+        self
+    }
+
+    /// Sets the secret key of a bound endpoint, so it keeps its [`EndpointId`](iroh::EndpointId)
+    /// across restarts. Otherwise a new one is generated.
+    pub fn with_secret_key(mut self, key: SecretKey) -> Self {
+        self.secret_key = Some(key);
+        self
+    }
+
+    /// Binds an endpoint for the server, accepting [`ALPN_H3`](crate::ALPN_H3) and the raw ALPNs.
+    pub async fn bind(mut self) -> Result<Server, ServerError> {
+        let mut alpns = vec![crate::ALPN_H3.as_bytes().to_vec()];
+        alpns.extend(self.raw_alpns.iter().cloned());
+        let transport = self
+            .transport
+            .take()
+            .unwrap_or_else(|| self.tuning.apply(QuicTransportConfig::builder()));
+
+        let mut builder = self
+            .endpoint
+            .take()
+            .unwrap_or_else(Endpoint::builder)
+            .alpns(alpns)
+            .transport_config(transport.build());
+        if let Some(mode) = self.relay_mode.take() {
+            builder = builder.relay_mode(mode);
+        }
+        if let Some(key) = self.secret_key.take() {
+            builder = builder.secret_key(key);
+        }
+        let endpoint = builder
+            .bind()
+            .await
+            .map_err(|err| ServerError::Bind(Arc::new(err)))?;
+        Ok(self.build(endpoint))
+    }
+
+    /// Creates the server from an existing endpoint.
+    ///
+    /// The endpoint options of this builder, like the transport config, aren't applied.
+    pub fn build(self, endpoint: Endpoint) -> Server {
+        Server::new(endpoint)
+            .with_handshake_timeout(self.handshake_timeout)
+            .with_handshake_options(self.options)
+            .with_raw_alpns(self.raw_alpns)
+            .with_max_pending(self.max_pending, self.overflow)
+            .with_spawner(self.spawner)
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("endpoint", &self.endpoint)
+            .field("options", &self.options)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("admission", &self.admission)
+            .field("admission_control", &self.control.is_some())
+            .field("raw_alpns", &self.raw_alpns)
+            .field("acl", &self.acl)
+            .field("connection_filter", &self.filter.is_some())
+            .field("peer_quota", &self.quota)
+            .field("origin_policy", &self.origin)
+            .field("auth", &self.auth.is_some())
+            .field("max_pending", &self.max_pending)
+            .field("max_ready", &self.max_ready)
+            .field("overflow", &self.overflow)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A session request accepted by [`Server::accept_any`], with or without HTTP/3.
+#[derive(Debug)]
+pub enum Request {
+    /// A full WebTransport handshake on the [`ALPN_H3`](crate::ALPN_H3) ALPN.
+    H3(Box<H3Request>),
+    /// A raw QUIC session on one of the ALPNs set with [`Server::with_raw_alpns`].
+    Quic(QuicRequest),
+}
+
+impl Request {
+    /// Returns the underlying QUIC connection.
+    pub fn conn(&self) -> &Connection {
+        match self {
+            Self::H3(request) => request.conn(),
+            Self::Quic(request) => request.conn(),
+        }
+    }
+
+    /// Returns the extensions mutably, which are carried into the [`Session`].
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        match self {
+            Self::H3(request) => request.extensions_mut(),
+            Self::Quic(request) => request.extensions_mut(),
+        }
+    }
+
+    /// Accepts the session, with a default 200 OK response for HTTP/3.
+    pub async fn ok(self) -> Result<Session, ServerError> {
+        match self {
+            Self::H3(request) => request.ok().await,
+            Self::Quic(request) => Ok(request.ok()),
+        }
+    }
+
+    /// Rejects the session with the status, which raw QUIC sessions send as the close code.
+    pub async fn reject(self, status: http::StatusCode) -> Result<(), ServerError> {
+        match self {
+            Self::H3(request) => request.reject(status).await,
+            Self::Quic(request) => {
+                request.close(status);
+                Ok(())
+            }
+        }
+    }
+}
 
 /// A QUIC-only WebTransport handshake, awaiting server decision.
+#[derive(Debug)]
 pub struct QuicRequest {
     conn: Connection,
+    extensions: http::Extensions,
+    permit: Option<OwnedSemaphorePermit>,
+    handshake_duration: Option<Duration>,
+    spawner: Spawner,
 }
 
 /// An H3 WebTransport handshake, SETTINGS exchanged and CONNECT accepted,
@@ -15,12 +1016,34 @@ pub struct H3Request {
     conn: Connection,
     settings: Settings,
     connect: Connecting,
+    extensions: http::Extensions,
+    permit: Option<OwnedSemaphorePermit>,
+    handshake_duration: Option<Duration>,
+    // Set if accepted by a `Server`, so its sessions are drained on shutdown.
+    tracker: Option<SessionTracker>,
+    spawner: Spawner,
 }
 
 impl QuicRequest {
     /// Accept a new QUIC-only WebTransport session from a client.
     pub fn accept(conn: Connection) -> Self {
-        Self { conn }
+        Self {
+            conn,
+            extensions: Default::default(),
+            permit: None,
+            handshake_duration: None,
+            spawner: Spawner::default(),
+        }
+    }
+
+    fn with_permit(mut self, permit: Option<OwnedSemaphorePermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    /// Takes the admission permit, see [`H3Request::take_permit`].
+    pub fn take_permit(&mut self) -> Option<OwnedSemaphorePermit> {
+        self.permit.take()
     }
 
     /// Returns the underlying QUIC connection.
@@ -28,9 +1051,62 @@ impl QuicRequest {
         &self.conn
     }
 
+    /// Returns the ALPN negotiated in the QUIC handshake.
+    pub fn alpn(&self) -> &[u8] {
+        self.conn.alpn()
+    }
+
+    /// Returns true if the connection currently goes through a relay.
+    pub fn is_relay(&self) -> bool {
+        path::is_relay(&self.conn)
+    }
+
+    /// Returns the round-trip time of the selected path, if known yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        path::rtt(&self.conn)
+    }
+
+    /// Returns the remote addresses of all open paths, the selected one first.
+    ///
+    /// These are the relay URLs and socket addresses the peer is reachable on.
+    pub fn remote_addrs(&self) -> Vec<TransportAddr> {
+        path::remote_addrs(&self.conn)
+    }
+
+    /// Returns how long the handshake took, from the incoming connection until the request
+    /// was received, if accepted by a [`Server`].
+    pub fn handshake_duration(&self) -> Option<Duration> {
+        self.handshake_duration
+    }
+
+    fn with_handshake_duration(mut self, duration: Duration) -> Self {
+        self.handshake_duration = Some(duration);
+        self
+    }
+
+    /// Returns the extensions, which are carried into the [`Session`].
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+
+    /// Returns the extensions mutably, e.g. to store an authenticated identity.
+    ///
+    /// They are carried into the [`Session`], see [`Session::extensions`].
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.extensions
+    }
+
     /// Accept the session.
     pub fn ok(self) -> Session {
         Session::raw(self.conn)
+            .with_spawner(self.spawner)
+            .with_extensions(self.extensions)
+    }
+
+    /// Sets the [`Spawner`] of the session, see [`Server::with_spawner`].
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
     }
 
     /// Reject the session.
@@ -43,24 +1119,129 @@ impl QuicRequest {
 impl H3Request {
     /// Accept a new H3 WebTransport session from a client.
     pub async fn accept(conn: Connection) -> Result<Self, ServerError> {
+        Self::accept_with_options(conn, &HandshakeOptions::default()).await
+    }
+
+    /// Accept a new H3 WebTransport session, with the max sessions, strictness, header size
+    /// limit and SETTINGS of the [`HandshakeOptions`].
+    ///
+    /// Requests larger than [`HandshakeOptions::max_field_section_size`] are rejected.
+    pub async fn accept_with_options(
+        conn: Connection,
+        options: &HandshakeOptions,
+    ) -> Result<Self, ServerError> {
         // Perform the H3 handshake by sending/receiving SETTINGS frames.
-        let settings = Settings::connect(&conn).await?;
+        // Our SETTINGS are sent right away, without waiting for the client's.
+        let settings = async {
+            Settings::connect_with_options(&conn, options)
+                .await
+                .map_err(ServerError::from)
+        };
 
         // Accept the CONNECT request but don't send a response yet.
-        let connect = Connecting::accept(&conn).await?;
+        // The client may send it before our SETTINGS arrive, so read it concurrently.
+        let connect = async {
+            Connecting::accept_with_options(&conn, options)
+                .await
+                .map_err(ServerError::from)
+        };
+
+        let (settings, connect) = tokio::try_join!(settings, connect)?;
 
         Ok(Self {
             conn,
             settings,
             connect,
+            extensions: Default::default(),
+            permit: None,
+            handshake_duration: None,
+            tracker: None,
+            spawner: Spawner::default(),
         })
     }
 
+    fn with_permit(mut self, permit: Option<OwnedSemaphorePermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+
+    fn with_tracker(mut self, tracker: SessionTracker) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    /// Starts the driver and other background tasks of the session with the [`Spawner`].
+    ///
+    /// Defaults to the current tokio runtime, see [`Server::with_spawner`].
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    // Creates the session once the response was sent.
+    fn session(
+        conn: Connection,
+        settings: Settings,
+        connect: Connected,
+        extensions: http::Extensions,
+        tracker: Option<SessionTracker>,
+        spawner: Spawner,
+    ) -> Session {
+        Session::new_h3_spawned(conn, settings, connect, tracker, spawner)
+            .with_extensions(extensions)
+    }
+
+    /// Takes the admission permit, see [`Server::with_admission`].
+    ///
+    /// Keep it alongside the session to count sessions against the semaphore, instead of
+    /// only pending requests. Returns None if the server doesn't require permits.
+    pub fn take_permit(&mut self) -> Option<OwnedSemaphorePermit> {
+        self.permit.take()
+    }
+
     /// Returns the underlying QUIC connection.
     pub fn conn(&self) -> &Connection {
         &self.conn
     }
 
+    /// Returns the ALPN negotiated in the QUIC handshake.
+    pub fn alpn(&self) -> &[u8] {
+        self.conn.alpn()
+    }
+
+    /// Returns true if the connection currently goes through a relay.
+    pub fn is_relay(&self) -> bool {
+        path::is_relay(&self.conn)
+    }
+
+    /// Returns the round-trip time of the selected path, if known yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        path::rtt(&self.conn)
+    }
+
+    /// Returns the remote addresses of all open paths, the selected one first.
+    ///
+    /// These are the relay URLs and socket addresses the peer is reachable on.
+    pub fn remote_addrs(&self) -> Vec<TransportAddr> {
+        path::remote_addrs(&self.conn)
+    }
+
+    /// Returns how long the handshake took, from the incoming connection until the request
+    /// was received, if accepted by a [`Server`].
+    pub fn handshake_duration(&self) -> Option<Duration> {
+        self.handshake_duration
+    }
+
+    fn with_handshake_duration(mut self, duration: Duration) -> Self {
+        self.handshake_duration = Some(duration);
+        self
+    }
+
+    /// Returns the HTTP/3 [`Settings`] exchanged with the client.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
     /// Accept the session with a default 200 OK response.
     pub async fn ok(self) -> Result<Session, ServerError> {
         self.respond(ConnectResponse::OK).await
@@ -75,19 +1256,144 @@ impl H3Request {
     ) -> Result<Session, ServerError> {
         let response = response.into();
         let connect = self.connect.respond(response).await?;
-        Ok(Session::new_h3(self.conn, self.settings, connect))
+        Ok(Self::session(
+            self.conn,
+            self.settings,
+            connect,
+            self.extensions,
+            self.tracker,
+            self.spawner,
+        ))
+    }
+
+    /// Reply to the session with the given response and additional headers.
+    ///
+    /// The client can read them with [`Session::response_headers`].
+    pub async fn respond_with_headers(
+        self,
+        response: impl Into<ConnectResponse>,
+        headers: http::HeaderMap,
+    ) -> Result<Session, ServerError> {
+        let connect = self.connect.respond_with_headers(response, headers).await?;
+        Ok(Self::session(
+            self.conn,
+            self.settings,
+            connect,
+            self.extensions,
+            self.tracker,
+            self.spawner,
+        ))
     }
 
     /// Reject the session with the given status code.
+    ///
+    /// Waits until the client has the response, up to [`REJECT_LINGER`](crate::REJECT_LINGER),
+    /// so the connection can be dropped afterwards, see [`Connecting::reject_with`].
     pub async fn reject(self, status: http::StatusCode) -> Result<(), ServerError> {
         self.connect.reject(status).await?;
         Ok(())
     }
 
+    /// Reject the session with a full response, see [`Connecting::reject_with`].
+    pub async fn reject_with(self, response: http::Response<Bytes>) -> Result<(), ServerError> {
+        self.connect.reject_with(response).await?;
+        Ok(())
+    }
+
+    /// Reject the session with 404 Not Found, e.g. for an unknown path.
+    pub async fn reject_not_found(self) -> Result<(), ServerError> {
+        self.reject(http::StatusCode::NOT_FOUND).await
+    }
+
+    /// Reject the session with 401 Unauthorized and the `WWW-Authenticate` challenge, e.g. `Bearer`.
+    pub async fn reject_unauthorized(
+        self,
+        www_authenticate: http::HeaderValue,
+    ) -> Result<(), ServerError> {
+        let mut response = http::Response::new(Bytes::new());
+        *response.status_mut() = http::StatusCode::UNAUTHORIZED;
+        response
+            .headers_mut()
+            .insert(http::header::WWW_AUTHENTICATE, www_authenticate);
+        self.reject_with(response).await
+    }
+
+    /// Reject the session with 429 Too Many Requests, telling the client when to retry if known.
+    ///
+    /// The delay is sent in the `Retry-After` header in seconds, rounded up.
+    pub async fn reject_too_many_requests(
+        self,
+        retry_after: Option<Duration>,
+    ) -> Result<(), ServerError> {
+        let mut response = http::Response::new(Bytes::new());
+        *response.status_mut() = http::StatusCode::TOO_MANY_REQUESTS;
+        if let Some(delay) = retry_after {
+            let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(http::header::RETRY_AFTER, secs.into());
+        }
+        self.reject_with(response).await
+    }
+
+    /// Returns the request headers sent by the client, excluding pseudo-headers.
+    ///
+    /// Use these to authenticate or route the session before calling [`Self::ok`] or [`Self::reject`].
+    pub fn headers(&self) -> &http::HeaderMap {
+        self.connect.headers()
+    }
+
+    /// Returns the request headers mutably, e.g. to rewrite them in a [`Middleware`](crate::Middleware).
+    ///
+    /// The rewritten headers are carried into the [`Session`], see [`Session::request`].
+    pub fn headers_mut(&mut self) -> &mut http::HeaderMap {
+        self.connect.headers_mut()
+    }
+
+    /// Returns the request target, see [`Connecting::uri`].
+    pub fn uri(&self) -> http::Uri {
+        self.connect.uri()
+    }
+
     /// Returns the [`ConnectRequest`] sent by the client.
     pub fn request(&self) -> &ConnectRequest {
         &self.connect
     }
+
+    /// Returns the extensions, which are carried into the [`Session`].
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+
+    /// Returns the extensions mutably, e.g. to store an authenticated identity or tenant.
+    ///
+    /// They are carried into the [`Session`], see [`Session::extensions`].
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns the `origin` header sent by the client, usually only by browsers.
+    pub fn origin(&self) -> Option<&str> {
+        self.headers().get(http::header::ORIGIN)?.to_str().ok()
+    }
+
+    /// Returns the [`AFFINITY_KEY`] header sent by the client, if any.
+    pub fn affinity_key(&self) -> Option<&str> {
+        self.headers().get(AFFINITY_KEY)?.to_str().ok()
+    }
+
+    /// Reject the session with [`ORIGIN_REJECTED`] unless its origin is allowed by the policy.
+    ///
+    /// Returns [`ServerError::OriginRejected`] after rejecting, otherwise the request to respond to.
+    pub async fn check_origin(self, policy: &OriginPolicy) -> Result<Self, ServerError> {
+        let origin = self.origin().map(str::to_string);
+        if policy.is_allowed(origin.as_deref()) {
+            return Ok(self);
+        }
+        tracing::debug!(?origin, "rejecting session with disallowed origin");
+        self.reject(ORIGIN_REJECTED).await?;
+        Err(ServerError::OriginRejected { origin })
+    }
 }
 
 impl core::ops::Deref for H3Request {
@@ -97,3 +1403,10 @@ impl core::ops::Deref for H3Request {
         &self.connect
     }
 }
+
+/// Convert the received request into an [`http::Request`], see [`Connecting`].
+impl From<&H3Request> for http::Request<()> {
+    fn from(request: &H3Request) -> Self {
+        (&request.connect).into()
+    }
+}
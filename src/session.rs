@@ -4,20 +4,37 @@ use std::{
     io::Cursor,
     ops::Deref,
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
     task::{Context, Poll, ready},
+    time::{Duration, Instant},
 };
 
 use bytes::{Bytes, BytesMut};
-use iroh::endpoint::{self, Connection};
+use iroh::endpoint::{self, Connection, Side};
 use n0_future::{
     FuturesUnordered,
     stream::{Stream, StreamExt},
 };
-use web_transport_proto::{ConnectRequest, ConnectResponse, Frame, StreamUni, VarInt};
+use tokio::{sync::oneshot, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use web_transport_proto::{Capsule, ConnectRequest, ConnectResponse, Frame, StreamUni, VarInt};
 
 use crate::{
-    ClientError, Connected, RecvStream, SendStream, SessionError, Settings, WebTransportError,
+    BulkConfig, BulkSendStream, Capabilities, ClientError, Connected, HandshakeOptions,
+    MESSAGE_TIMED_OUT, Message, MessageError, PartialPolicy, RecvStream, Responder, SendStream,
+    SessionError, Settings, ShutdownPolicy, Spawner, StreamCounts, StreamGroup, Strictness,
+    UniStreams, UnknownUniStreams, WebTransportError,
+    advisor::DatagramAdvisor,
+    congestion::Congestion,
+    control::{Control, DRAIN_WEBTRANSPORT_SESSION},
+    message::{read_message, read_message_with_timeout},
+    panic_policy::unexpected,
+    shutdown::{OpenStreams, SessionTracker},
+    stream_count::{StreamCounter, StreamKind},
+    stream_type::StreamTypes,
 };
 
 /// An established WebTransport session, acting like a full QUIC connection. See [`iroh::endpoint::Connection`].
@@ -29,56 +46,272 @@ use crate::{
 ///
 /// Deref is used to expose non-overloaded methods on [`iroh::endpoint::Connection`].
 /// These should be safe to use with WebTransport, but file a PR if you find one that isn't.
+///
+/// Dropping the last clone of a session closes it without an error code, which logs a warning
+/// in debug builds. Use [`Self::close`], [`Self::close_session`] or [`Self::shutdown`] instead.
 #[derive(Clone)]
+#[must_use = "dropping a session closes it, use Session::close to close it explicitly"]
 pub struct Session {
     conn: Connection,
     h3: Option<H3SessionState>,
+    // Routes unidirectional streams by type, once any type is registered.
+    stream_types: Arc<StreamTypes>,
+    // Samples the congestion controller, once congestion is queried.
+    congestion: Arc<Congestion>,
+    // Tracks which datagram sizes survive the path.
+    datagram_advisor: Arc<DatagramAdvisor>,
+    // Whether empty datagrams are dropped instead of returned, see `EmptyPayload`.
+    suppress_empty: Arc<AtomicBool>,
+    // How many items the accept and datagram loops process before yielding, see `set_poll_budget`.
+    poll_budget: Arc<AtomicUsize>,
+    // Counts the send streams that are still open, for a graceful shutdown.
+    open_streams: Arc<OpenStreams>,
+    // Counts the open streams by direction and initiator.
+    stream_counter: Arc<StreamCounter>,
+    // Populated by the request that was accepted, see `H3Request::extensions_mut`.
+    extensions: Arc<http::Extensions>,
+    // Dropped along with the last clone of the session, None for a `WeakSession`.
+    last_clone: Option<Arc<LastClone>>,
+    // Stops the driver of an HTTP/3 session once the last clone is dropped.
+    _driver_guard: Option<Arc<oneshot::Sender<()>>>,
+    // Whether the handshake was sent in 0-RTT data that the server accepted.
+    zero_rtt: bool,
+    // Starts background tasks, such as congestion sampling and stream type routing.
+    spawner: Spawner,
 }
 
 impl Session {
-    /// Create a new session from a raw QUIC connection and a URL.
+    /// Create a new session from a raw QUIC connection.
     ///
+    /// There is no handshake, so no URL or request is involved and [`Self::request`] returns None.
     /// This is used to pretend like a QUIC connection is a WebTransport session.
     /// It's a hack, but it makes it much easier to support WebTransport and raw QUIC simultaneously.
     pub fn raw(conn: Connection) -> Self {
-        Self { conn, h3: None }
+        let last_clone = Arc::new(LastClone {
+            conn: conn.clone(),
+            control: None,
+            dropped: CancellationToken::new(),
+        });
+        Self {
+            conn,
+            h3: None,
+            stream_types: Default::default(),
+            congestion: Default::default(),
+            datagram_advisor: Default::default(),
+            suppress_empty: Default::default(),
+            poll_budget: Arc::new(AtomicUsize::new(DEFAULT_POLL_BUDGET)),
+            open_streams: Default::default(),
+            stream_counter: Default::default(),
+            extensions: Default::default(),
+            last_clone: Some(last_clone),
+            _driver_guard: None,
+            zero_rtt: false,
+            spawner: Default::default(),
+        }
     }
 
     /// Connect using an established QUIC connection if you want to create the connection yourself.
     /// This will only work with a brand new QUIC connection using the HTTP/3 ALPN.
+    ///
+    /// Pass a [`ConnectRequest`], or a [`ConnectRequestBuilder`](crate::ConnectRequestBuilder),
+    /// instead of a URL to offer subprotocols or send additional headers such as the `origin`.
+    /// The request is available as [`Self::request`] afterwards.
     pub async fn connect_h3(
         conn: Connection,
         request: impl Into<ConnectRequest>,
     ) -> Result<Session, ClientError> {
-        let request = request.into();
+        Self::connect_h3_with_options(conn, request, &HandshakeOptions::default()).await
+    }
+
+    /// Connect using an established QUIC connection, with the strictness, header size limit and
+    /// SETTINGS of the [`HandshakeOptions`].
+    pub async fn connect_h3_with_options(
+        conn: Connection,
+        request: impl Into<ConnectRequest>,
+        options: &HandshakeOptions,
+    ) -> Result<Session, ClientError> {
+        Self::connect_h3_spawned(conn, request.into(), options, &Spawner::default()).await
+    }
 
+    // Like `connect_h3_with_options`, starting the driver with the spawner of the client.
+    pub(crate) async fn connect_h3_spawned(
+        conn: Connection,
+        request: ConnectRequest,
+        options: &HandshakeOptions,
+        spawner: &Spawner,
+    ) -> Result<Session, ClientError> {
         // Perform the H3 handshake by sending/receiving SETTINGS frames.
-        let settings = Settings::connect(&conn).await?;
+        let settings = Settings::connect_with_options(&conn, options).await?;
 
         // Send the HTTP/3 CONNECT request.
-        let connect = Connected::open(&conn, request).await?;
+        let connect = Connected::open_with_options(&conn, request, options).await?;
 
         // Return the resulting session with a reference to the control/connect streams.
         // If either stream is closed, then the session will be closed, so we need to keep them around.
-        let session = Session::new_h3(conn, settings, connect);
+        let session = Session::new_h3_spawned(conn, settings, connect, None, spawner.clone());
 
         Ok(session)
     }
 
     /// Creates a session from pre-established HTTP/3 handshake components.
-    pub fn new_h3(conn: Connection, settings: Settings, mut connect: Connected) -> Self {
-        let h3 = H3SessionState::connect(conn.clone(), settings, &connect);
-        let this = Session { conn, h3: Some(h3) };
-        // Run a background task to check if the connect stream is closed.
-        let this2 = this.clone();
-        tokio::spawn(async move {
-            let (code, reason) = connect.run_closed().await;
-            if this2.conn().close_reason().is_none() {
-                // TODO We shouldn't be closing the QUIC connection with the same error.
-                this2.close(code, reason.as_bytes());
+    ///
+    /// The [`SessionDriver`] is spawned on the current tokio runtime.
+    /// Use [`Self::new_h3_with_driver`] to run it yourself.
+    pub fn new_h3(conn: Connection, settings: Settings, connect: Connected) -> Self {
+        Self::new_h3_spawned(conn, settings, connect, None, Spawner::default())
+    }
+
+    /// Creates a session from pre-established HTTP/3 handshake components, without spawning tasks.
+    ///
+    /// The returned [`SessionDriver`] reads from the CONNECT and control streams, so the session
+    /// notices when it is closed or drained. It must be polled until it completes, e.g. by
+    /// spawning it on a task of your choice. Tasks the session starts later, e.g. to sample
+    /// congestion, use its [`Spawner`], see [`Self::with_spawner`].
+    pub fn new_h3_with_driver(
+        conn: Connection,
+        settings: Settings,
+        connect: Connected,
+    ) -> (Self, SessionDriver) {
+        Self::new_h3_with_tracker(conn, settings, connect, None)
+    }
+
+    // Creates a session and starts its driver with the spawner, which also drains and closes
+    // the session when the tracker says so, if any.
+    pub(crate) fn new_h3_spawned(
+        conn: Connection,
+        settings: Settings,
+        connect: Connected,
+        tracker: Option<SessionTracker>,
+        spawner: Spawner,
+    ) -> Self {
+        let (session, driver) = Self::new_h3_with_tracker(conn, settings, connect, tracker);
+        spawner.spawn(driver);
+        session.with_spawner(spawner)
+    }
+
+    fn new_h3_with_tracker(
+        conn: Connection,
+        settings: Settings,
+        connect: Connected,
+        tracker: Option<SessionTracker>,
+    ) -> (Self, SessionDriver) {
+        let (h3, mut recv) = H3SessionState::connect(conn.clone(), settings, connect);
+
+        // Read capsules until the connect stream is closed.
+        // The session is closed when that happens, but the QUIC connection is left intact.
+        let control = h3.control.clone();
+        let conn2 = conn.clone();
+        let capsules = async move {
+            let (code, reason) = control.run(&mut recv).await;
+            // If the connection is gone, that is the more accurate close reason.
+            if conn2.close_reason().is_none() {
+                control.set_closed(code, reason);
+            }
+        };
+        // Read GOAWAY frames from the HTTP/3 control stream.
+        let settings = h3.settings.clone();
+        let conn2 = conn.clone();
+        let goaway = async move { settings.run(&conn2).await };
+
+        // Drain the session once its server shuts down, and close it if that takes too long.
+        let active = tracker.as_ref().map(|tracker| tracker.active.guard());
+        let settings = h3.settings.clone();
+        let control = h3.control.clone();
+        let goaway_id = goaway_id(conn.side(), h3.session_id);
+        let conn2 = conn.clone();
+        let shutdown = async move {
+            let Some(tracker) = tracker else {
+                return std::future::pending::<()>().await;
+            };
+            tracker.drain.cancelled().await;
+            // These fail if already sent or the session is closed, which is fine either way.
+            if let Some(id) = goaway_id {
+                settings.goaway(id).await.ok();
             }
+            control.write(&drain_capsule()).await.ok();
+            tracker.close.cancelled().await;
+            conn2.close(h3_close_code(0), b"server shutdown");
+            // Keep driving the streams until they notice the connection is closed.
+            std::future::pending::<()>().await
+        };
+
+        // Stop early once the session is dropped, instead of waiting for the streams to close.
+        let (guard, dropped) = oneshot::channel::<()>();
+        let driver = SessionDriver {
+            inner: Box::pin(async move {
+                tokio::select! {
+                    _ = async { tokio::join!(capsules, goaway) } => {}
+                    _ = shutdown => {}
+                    _ = dropped => {}
+                }
+                drop(active);
+            }),
+        };
+
+        let last_clone = Arc::new(LastClone {
+            conn: conn.clone(),
+            control: Some(h3.control.clone()),
+            dropped: CancellationToken::new(),
         });
-        this
+        let session = Session {
+            conn,
+            h3: Some(h3),
+            stream_types: Default::default(),
+            congestion: Default::default(),
+            datagram_advisor: Default::default(),
+            suppress_empty: Default::default(),
+            poll_budget: Arc::new(AtomicUsize::new(DEFAULT_POLL_BUDGET)),
+            open_streams: Default::default(),
+            stream_counter: Default::default(),
+            extensions: Default::default(),
+            last_clone: Some(last_clone),
+            _driver_guard: Some(Arc::new(guard)),
+            zero_rtt: false,
+            spawner: Default::default(),
+        };
+        (session, driver)
+    }
+
+    // Returns a handle for internal tasks, which doesn't keep the session open.
+    pub(crate) fn downgrade(&self) -> WeakSession {
+        let dropped = match &self.last_clone {
+            Some(last_clone) => last_clone.dropped.clone(),
+            None => {
+                unexpected("downgrading a weak session");
+                CancellationToken::new()
+            }
+        };
+        let session = Session {
+            last_clone: None,
+            _driver_guard: None,
+            ..self.clone()
+        };
+        WeakSession { session, dropped }
+    }
+
+    /// Sets the [`Spawner`] for the background tasks the session starts from now on.
+    ///
+    /// These sample congestion and route unidirectional streams by type, once used. Sessions
+    /// created by a [`Client`](crate::Client) or [`Server`](crate::Server) use theirs.
+    pub fn with_spawner(mut self, spawner: Spawner) -> Self {
+        self.spawner = spawner;
+        self
+    }
+
+    pub(crate) fn spawner(&self) -> &Spawner {
+        &self.spawner
+    }
+
+    pub(crate) fn with_extensions(mut self, extensions: http::Extensions) -> Self {
+        self.extensions = Arc::new(extensions);
+        self
+    }
+
+    /// Returns the extensions of the accepted request, see [`crate::H3Request::extensions_mut`].
+    ///
+    /// These are empty for sessions created by the client.
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
     }
 
     /// Returns the underlying QUIC connection.
@@ -91,57 +324,505 @@ impl Session {
         self.h3.as_ref().map(|s| &s.request)
     }
 
+    /// Returns the [`AFFINITY_KEY`](crate::AFFINITY_KEY) header of the [`ConnectRequest`], if any.
+    pub fn affinity_key(&self) -> Option<&str> {
+        self.request()?
+            .headers
+            .get(crate::AFFINITY_KEY)?
+            .to_str()
+            .ok()
+    }
+
     /// Returns the [`ConnectResponse`] if this session was established over HTTP/3.
     pub fn response(&self) -> Option<&ConnectResponse> {
         self.h3.as_ref().map(|s| &s.response)
     }
 
+    /// Returns the additional headers of the [`ConnectResponse`] if this session was established over HTTP/3.
+    pub fn response_headers(&self) -> Option<&http::HeaderMap> {
+        self.h3.as_ref().map(|s| &s.response_headers)
+    }
+
+    /// Returns the subprotocol selected by the server, if any.
+    ///
+    /// For HTTP/3 sessions this is the protocol from the [`ConnectResponse`], chosen from those
+    /// offered in the [`ConnectRequest`]. For raw QUIC sessions this is the negotiated ALPN.
+    pub fn protocol(&self) -> Option<&str> {
+        match self.h3.as_ref() {
+            None => std::str::from_utf8(self.conn.alpn()).ok(),
+            Some(h3) => h3.response.protocol.as_deref(),
+        }
+    }
+
+    /// Returns true if the session was established with 0-RTT data accepted by the server.
+    ///
+    /// See [`Client::with_0rtt`](crate::Client::with_0rtt).
+    pub fn is_0rtt(&self) -> bool {
+        self.zero_rtt
+    }
+
+    pub(crate) fn with_0rtt(mut self) -> Self {
+        self.zero_rtt = true;
+        self
+    }
+
+    /// Returns the HTTP/3 [`Settings`] if this session was established over HTTP/3.
+    pub fn settings(&self) -> Option<&Settings> {
+        self.h3.as_ref().map(|s| s.settings.as_ref())
+    }
+
+    /// Returns the crate version and features the peer advertised in its SETTINGS, if any.
+    ///
+    /// This is None for raw QUIC sessions and for peers that don't advertise them,
+    /// e.g. other HTTP/3 stacks, older versions of this crate or a [`SettingsProfile::minimal`](crate::SettingsProfile::minimal).
+    pub fn peer_capabilities(&self) -> Option<Capabilities> {
+        self.settings()?.peer_capabilities()
+    }
+
+    /// Ask the peer to gracefully wind down the session by sending a DRAIN_WEBTRANSPORT_SESSION capsule.
+    ///
+    /// The session stays usable; the peer is expected to stop opening new streams and close
+    /// the session once in-flight work completes. This has no effect on raw QUIC sessions.
+    pub async fn drain(&self) -> Result<(), SessionError> {
+        let Some(h3) = &self.h3 else {
+            return Ok(());
+        };
+        h3.control.write(&drain_capsule()).await
+    }
+
+    /// Wait until the peer asks to drain the session, see [`Self::drain`].
+    ///
+    /// This never resolves for raw QUIC sessions.
+    pub async fn draining(&self) {
+        match &self.h3 {
+            Some(h3) => h3.control.draining().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Returns true if the peer asked to drain the session, see [`Self::drain`].
+    pub fn is_draining(&self) -> bool {
+        self.h3.as_ref().is_some_and(|h3| h3.control.is_draining())
+    }
+
+    /// Send a capsule with the given type and payload on the CONNECT stream.
+    ///
+    /// This can be used to implement WebTransport extensions built on the capsule protocol.
+    /// The session-level capsules are written by [`Self::drain`] and [`Self::close_session`];
+    /// avoid sending them manually.
+    pub async fn send_capsule(&self, typ: VarInt, payload: Bytes) -> Result<(), SessionError> {
+        let h3 = self
+            .h3
+            .as_ref()
+            .ok_or(WebTransportError::CapsulesUnsupported)?;
+        h3.control
+            .write(&Capsule::Unknown { typ, payload })
+            .await
+            .map_err(|err| self.map_error(err))
+    }
+
+    /// Receive the next capsule from the CONNECT stream, returning its type and payload.
+    ///
+    /// Capsules handled by the session itself (close, drain and GREASE) are not returned,
+    /// nor are those with a handler registered via [`Self::set_capsule_handler`].
+    /// Up to 32 capsules are buffered; further capsules are dropped until this is called.
+    pub async fn recv_capsule(&self) -> Result<(VarInt, Bytes), SessionError> {
+        let h3 = self
+            .h3
+            .as_ref()
+            .ok_or(WebTransportError::CapsulesUnsupported)?;
+        match h3.control.recv().await {
+            Some(capsule) => Ok(capsule),
+            None => Err(self.closed().await),
+        }
+    }
+
+    /// Register a handler invoked for every received capsule of the given type.
+    ///
+    /// The handler runs on the task reading the CONNECT stream, so it should not block.
+    /// Capsules with a handler are not returned by [`Self::recv_capsule`].
+    /// Capsules handled by the session itself (close, drain and GREASE) never reach a handler.
+    ///
+    /// Returns true if a previously registered handler for this type was replaced.
+    pub fn set_capsule_handler<F>(&self, typ: VarInt, handler: F) -> Result<bool, SessionError>
+    where
+        F: Fn(Bytes) + Send + Sync + 'static,
+    {
+        let h3 = self
+            .h3
+            .as_ref()
+            .ok_or(WebTransportError::CapsulesUnsupported)?;
+        Ok(h3.control.set_handler(typ, Arc::new(handler)))
+    }
+
+    /// Remove the handler for the given capsule type, returning true if there was one.
+    ///
+    /// Capsules of this type are returned by [`Self::recv_capsule`] again.
+    pub fn remove_capsule_handler(&self, typ: VarInt) -> Result<bool, SessionError> {
+        let h3 = self
+            .h3
+            .as_ref()
+            .ok_or(WebTransportError::CapsulesUnsupported)?;
+        Ok(h3.control.remove_handler(typ))
+    }
+
     /// Accept a new unidirectional stream. See [`iroh::endpoint::Connection::accept_uni`].
     pub async fn accept_uni(&self) -> Result<RecvStream, SessionError> {
-        if let Some(h3) = &self.h3 {
-            poll_fn(|cx| h3.accept.lock().unwrap().poll_accept_uni(cx)).await
+        let recv = if let Some(h3) = &self.h3 {
+            self.until_closed(
+                h3,
+                poll_fn(|cx| {
+                    let budget = self.poll_budget();
+                    h3.accept.lock().unwrap().poll_accept_uni(cx, budget)
+                }),
+            )
+            .await?
         } else {
             self.conn
                 .accept_uni()
                 .await
                 .map(RecvStream::new)
-                .map_err(Into::into)
-        }
+                .map_err(|err| self.map_error(err))?
+        };
+        Ok(recv.with_count(self.stream_counter.open(StreamKind::UniRemote)))
     }
 
     /// Accept a new bidirectional stream. See [`iroh::endpoint::Connection::accept_bi`].
     pub async fn accept_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
-        if let Some(h3) = &self.h3 {
-            poll_fn(|cx| h3.accept.lock().unwrap().poll_accept_bi(cx)).await
+        let (send, recv) = if let Some(h3) = &self.h3 {
+            self.until_closed(
+                h3,
+                poll_fn(|cx| {
+                    let budget = self.poll_budget();
+                    h3.accept.lock().unwrap().poll_accept_bi(cx, budget)
+                }),
+            )
+            .await?
         } else {
             self.conn
                 .accept_bi()
                 .await
                 .map(|(send, recv)| (SendStream::new(send), RecvStream::new(recv)))
-                .map_err(Into::into)
+                .map_err(|err| self.map_error(err))?
+        };
+        let counted = self.stream_counter.open(StreamKind::BiRemote);
+        Ok((
+            send.with_guard(self.open_streams.guard())
+                .with_count(counted.clone()),
+            recv.with_count(counted),
+        ))
+    }
+
+    /// Wait for the next incoming stream or datagram, whichever arrives first.
+    ///
+    /// This replaces separate loops around [`Self::accept_uni`], [`Self::accept_bi`] and
+    /// [`Self::read_datagram`]. All three sources are polled in random order, so none is starved.
+    /// It is safe to call other accept methods concurrently; each item is only returned once.
+    pub async fn accept(&self) -> Result<SessionEventKind, SessionError> {
+        tokio::select! {
+            res = self.accept_uni() => res.map(SessionEventKind::Uni),
+            res = self.accept_bi() => res.map(|(send, recv)| SessionEventKind::Bi(send, recv)),
+            res = self.read_datagram() => res.map(SessionEventKind::Datagram),
+        }
+    }
+
+    /// Accept a unidirectional stream and read it to the end as a single message.
+    ///
+    /// If the message exceeds `size_limit` bytes, the stream is stopped with [`crate::MESSAGE_TOO_LONG`]
+    /// and [`MessageError::TooLong`] is returned.
+    pub async fn accept_uni_message(&self, size_limit: usize) -> Result<Bytes, MessageError> {
+        let mut recv = self.accept_uni().await?;
+        read_message(&mut recv, size_limit).await
+    }
+
+    /// Accept a bidirectional stream and read it to the end as a single request.
+    ///
+    /// Returns the request along with a [`Responder`] to reply on the same stream.
+    /// If the request exceeds `size_limit` bytes, the stream is stopped with [`crate::MESSAGE_TOO_LONG`]
+    /// and [`MessageError::TooLong`] is returned.
+    pub async fn accept_bi_request(
+        &self,
+        size_limit: usize,
+    ) -> Result<(Bytes, Responder), MessageError> {
+        let (send, mut recv) = self.accept_bi().await?;
+        let request = read_message(&mut recv, size_limit).await?;
+        Ok((request, Responder::new(send)))
+    }
+
+    /// Accept a unidirectional stream and read it as a single message, within the timeout.
+    ///
+    /// The timeout starts once the stream is accepted. If the message isn't received in time,
+    /// the [`PartialPolicy`] decides whether it is discarded or delivered partially.
+    /// Otherwise this behaves like [`Self::accept_uni_message`].
+    pub async fn accept_uni_message_with_timeout(
+        &self,
+        size_limit: usize,
+        timeout: Duration,
+        policy: PartialPolicy,
+    ) -> Result<Message, MessageError> {
+        let mut recv = self.accept_uni().await?;
+        read_message_with_timeout(&mut recv, size_limit, timeout, policy).await
+    }
+
+    /// Accept a bidirectional stream and read it as a single request, within the timeout.
+    ///
+    /// See [`Self::accept_uni_message_with_timeout`] and [`Self::accept_bi_request`].
+    pub async fn accept_bi_request_with_timeout(
+        &self,
+        size_limit: usize,
+        timeout: Duration,
+        policy: PartialPolicy,
+    ) -> Result<(Message, Responder), MessageError> {
+        let (mut send, mut recv) = self.accept_bi().await?;
+        match read_message_with_timeout(&mut recv, size_limit, timeout, policy).await {
+            Ok(request) => Ok((request, Responder::new(send))),
+            Err(err) => {
+                if matches!(err, MessageError::TimedOut) && policy == PartialPolicy::Reset {
+                    send.reset(MESSAGE_TIMED_OUT).ok();
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Accept unidirectional streams, running `handler` on each with at most `limit` at a time.
+    ///
+    /// See [`Self::for_each_bi`].
+    pub async fn for_each_uni<F, Fut, E>(&self, limit: usize, handler: F) -> SessionError
+    where
+        F: FnMut(RecvStream) -> Fut,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display + Send + 'static,
+    {
+        self.for_each(limit, || self.accept_uni(), handler).await
+    }
+
+    /// Accept bidirectional streams, running `handler` on each with at most `limit` at a time.
+    ///
+    /// Each handler is spawned as a task. While `limit` handlers are running, no further streams
+    /// are accepted, so flow control pushes back on the peer. Handler errors and panics are logged.
+    /// Returns the error once the session is closed, aborting any handlers still running.
+    pub async fn for_each_bi<F, Fut, E>(&self, limit: usize, mut handler: F) -> SessionError
+    where
+        F: FnMut(SendStream, RecvStream) -> Fut,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display + Send + 'static,
+    {
+        self.for_each(
+            limit,
+            || self.accept_bi(),
+            |(send, recv)| handler(send, recv),
+        )
+        .await
+    }
+
+    /// Route incoming unidirectional streams by their first byte, returning those of type `tag`.
+    ///
+    /// The first call starts a task accepting all unidirectional streams, so [`Self::accept_uni`]
+    /// must not be used afterwards. The type byte is consumed before a stream is returned.
+    /// Streams of an unregistered type are stopped with [`crate::UNKNOWN_STREAM_TYPE`], unless
+    /// [`Self::unknown_uni_streams`] is used. Registering a type again replaces the previous receiver.
+    pub fn uni_streams_of_type(&self, tag: u8) -> UniStreams {
+        self.stream_types.register(self, tag)
+    }
+
+    /// Returns unidirectional streams of an unregistered type, instead of stopping them.
+    ///
+    /// See [`Self::uni_streams_of_type`]. Calling this again replaces the previous receiver.
+    pub fn unknown_uni_streams(&self) -> UnknownUniStreams {
+        self.stream_types.register_unknown(self)
+    }
+
+    /// Returns the number of open streams, by direction and initiator.
+    ///
+    /// Streams accepted or opened through this session are counted until all of their halves are dropped.
+    pub fn stream_counts(&self) -> StreamCounts {
+        self.stream_counter.get()
+    }
+
+    /// Wait until [`Self::stream_counts`] changes, returning the new counts.
+    pub async fn stream_counts_changed(&self) -> Result<StreamCounts, SessionError> {
+        let mut counts = self.stream_counter.subscribe();
+        tokio::select! {
+            res = counts.changed() => {
+                res.expect("sender is owned by the session");
+                Ok(*counts.borrow_and_update())
+            }
+            err = self.closed() => Err(err),
+        }
+    }
+
+    /// Sets how many unidirectional streams the peer may have open at once, e.g. to grant more
+    /// to a client once it is authenticated.
+    ///
+    /// For HTTP/3 sessions, the peer's control and QPACK streams don't count against the limit.
+    /// Lowering it doesn't affect streams that are already open.
+    pub fn set_max_concurrent_uni_streams(&self, count: u32) {
+        let reserved = if self.h3.is_some() {
+            H3_PEER_UNI_STREAMS
+        } else {
+            0
+        };
+        self.conn
+            .set_max_concurrent_uni_streams(count.saturating_add(reserved).into());
+    }
+
+    /// Sets how many bidirectional streams the peer may have open at once.
+    ///
+    /// For HTTP/3 sessions accepted by a server, the CONNECT stream doesn't count against the limit.
+    /// Lowering it doesn't affect streams that are already open.
+    pub fn set_max_concurrent_bi_streams(&self, count: u32) {
+        let reserved = match (&self.h3, self.conn.side()) {
+            (Some(_), Side::Server) => 1,
+            _ => 0,
+        };
+        self.conn
+            .set_max_concurrent_bi_streams(count.saturating_add(reserved).into());
+    }
+
+    /// Sets how many bytes the peer may send across all streams before they are read.
+    ///
+    /// Values beyond the largest QUIC varint are clamped.
+    pub fn set_receive_window(&self, bytes: u64) {
+        let bytes = endpoint::VarInt::from_u64(bytes).unwrap_or(endpoint::VarInt::MAX);
+        self.conn.set_receive_window(bytes);
+    }
+
+    /// Returns true if the connection hit congestion within the last round trip.
+    ///
+    /// Writes are likely to queue up while congested, so applications with real-time data
+    /// may want to skip producing it instead, e.g. drop video frames at the source.
+    /// The first call starts sampling the congestion controller, so it returns false.
+    ///
+    /// Only the congestion controller is reported. Writes blocked on the peer's flow control
+    /// limits are not, since quinn doesn't expose when that happens.
+    pub fn is_congested(&self) -> bool {
+        self.congestion.is_congested(&self.conn, &self.spawner)
+    }
+
+    /// Wait until [`Self::is_congested`] changes, returning the new value.
+    pub async fn congestion_changed(&self) -> Result<bool, SessionError> {
+        let mut state = self.congestion.subscribe(&self.conn, &self.spawner);
+        tokio::select! {
+            res = state.changed() => {
+                res.expect("sender is owned by the session");
+                Ok(*state.borrow_and_update())
+            }
+            err = self.closed() => Err(err),
+        }
+    }
+
+    async fn for_each<T, A, AFut, F, Fut, E>(
+        &self,
+        limit: usize,
+        mut accept: A,
+        mut handler: F,
+    ) -> SessionError
+    where
+        A: FnMut() -> AFut,
+        AFut: Future<Output = Result<T, SessionError>>,
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display + Send + 'static,
+    {
+        let limit = limit.max(1);
+        let mut tasks = JoinSet::new();
+        loop {
+            tokio::select! {
+                // Accepting is cancel-safe, so no stream is lost when a handler finishes first.
+                res = accept(), if tasks.len() < limit => match res {
+                    Ok(stream) => {
+                        tasks.spawn(handler(stream));
+                    }
+                    Err(err) => return err,
+                },
+                Some(res) = tasks.join_next() => match res {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => tracing::warn!("stream handler failed: {err:#}"),
+                    Err(err) => tracing::warn!("stream handler panicked: {err}"),
+                },
+            }
         }
     }
 
     /// Open a new unidirectional stream. See [`iroh::endpoint::Connection::open_uni`].
     pub async fn open_uni(&self) -> Result<SendStream, SessionError> {
-        let mut send = self.conn.open_uni().await?;
+        self.check_open()?;
+        let mut send = self
+            .conn
+            .open_uni()
+            .await
+            .map_err(|err| self.map_error(err))?;
 
         if let Some(h3) = self.h3.as_ref() {
-            write_full_with_max_prio(&mut send, &h3.header_uni).await?;
+            write_full_with_max_prio(&mut send, &h3.header_uni)
+                .await
+                .map_err(|err| self.map_error(err))?;
         }
 
-        Ok(SendStream::new(send))
+        Ok(self
+            .send_stream(send)
+            .with_count(self.stream_counter.open(StreamKind::UniLocal)))
+    }
+
+    /// Opens a unidirectional stream for bulk transfers, at the lowest priority.
+    ///
+    /// See [`Self::open_uni_bulk_with_config`] to change the priority or pace the stream.
+    pub async fn open_uni_bulk(&self) -> Result<BulkSendStream, SessionError> {
+        self.open_uni_bulk_with_config(BulkConfig::default()).await
+    }
+
+    /// Opens a unidirectional stream for bulk transfers, tuned by the [`BulkConfig`].
+    pub async fn open_uni_bulk_with_config(
+        &self,
+        config: BulkConfig,
+    ) -> Result<BulkSendStream, SessionError> {
+        let send = self.open_uni().await?;
+        Ok(BulkSendStream::new(send, config))
+    }
+
+    /// Returns a [`StreamGroup`] opening streams with the given priority.
+    pub fn stream_group(&self, priority: i32) -> StreamGroup {
+        StreamGroup::new(self.clone(), priority)
+    }
+
+    /// Returns a [`StreamGroup`] opening streams with the priority of the given stream.
+    ///
+    /// Use this on the send half of an accepted bidirectional stream, so the uni streams
+    /// opened to respond to it inherit its priority.
+    pub fn stream_group_for(&self, send: &SendStream) -> StreamGroup {
+        self.stream_group(send.priority().unwrap_or_default())
     }
 
     /// Open a new bidirectional stream. See [`iroh::endpoint::Connection::open_bi`].
     pub async fn open_bi(&self) -> Result<(SendStream, RecvStream), SessionError> {
-        let (mut send, recv) = self.conn.open_bi().await?;
+        self.check_open()?;
+        let (mut send, recv) = self
+            .conn
+            .open_bi()
+            .await
+            .map_err(|err| self.map_error(err))?;
 
         if let Some(h3) = self.h3.as_ref() {
-            write_full_with_max_prio(&mut send, &h3.header_bi).await?;
+            write_full_with_max_prio(&mut send, &h3.header_bi)
+                .await
+                .map_err(|err| self.map_error(err))?;
         }
 
-        Ok((SendStream::new(send), RecvStream::new(recv)))
+        let counted = self.stream_counter.open(StreamKind::BiLocal);
+        Ok((
+            self.send_stream(send).with_count(counted.clone()),
+            RecvStream::new(recv).with_count(counted),
+        ))
+    }
+
+    // Wraps a stream opened by this session, which is at max priority if it has a header.
+    fn send_stream(&self, send: endpoint::SendStream) -> SendStream {
+        let send = SendStream::new(send).with_guard(self.open_streams.guard());
+        match self.h3 {
+            Some(_) => send.with_header_priority(),
+            None => send,
+        }
     }
 
     /// Asynchronously receives an application datagram from the remote peer.
@@ -149,25 +830,78 @@ impl Session {
     /// This method is used to receive an application datagram sent by the remote
     /// peer over the connection.
     /// It waits for a datagram to become available and returns the received bytes.
+    ///
+    /// Datagrams without a payload are returned as empty bytes, unless suppressed with
+    /// [`Self::set_empty_datagrams`].
     pub async fn read_datagram(&self) -> Result<Bytes, SessionError> {
-        let mut datagram = self
-            .conn
-            .read_datagram()
-            .await
-            .map_err(SessionError::from)?;
+        let mut dropped = 0usize;
+        loop {
+            let datagram = self.read_datagram_once().await?;
+            if !datagram.is_empty() || !self.suppress_empty.load(Ordering::Relaxed) {
+                return Ok(datagram);
+            }
+            tracing::trace!("dropping empty datagram");
+            dropped += 1;
+            if dropped.is_multiple_of(self.poll_budget()) {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    /// Sets how many items the accept and datagram loops process before yielding to other tasks.
+    ///
+    /// Accepting a stream reads its header, and streams that turn out to be ignored or broken
+    /// are skipped without returning, as are suppressed datagrams. Under load, a smaller budget
+    /// improves the latency of other tasks on the same runtime, especially a single-threaded one,
+    /// at the cost of more wakeups. This applies to all clones of the session. Defaults to 32,
+    /// and a budget of zero is treated as one.
+    pub fn set_poll_budget(&self, budget: usize) {
+        self.poll_budget.store(budget.max(1), Ordering::Relaxed);
+    }
+
+    /// Returns the budget set with [`Self::set_poll_budget`].
+    pub fn poll_budget(&self) -> usize {
+        self.poll_budget.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether datagrams without a payload are returned by [`Self::read_datagram`].
+    ///
+    /// Some peers send empty datagrams as keep-alives, while others never send them. This
+    /// applies to all clones of the session. Streams without a payload are always returned,
+    /// since whether a stream is empty is only known once it is finished.
+    pub fn set_empty_datagrams(&self, policy: EmptyPayload) {
+        self.suppress_empty
+            .store(policy == EmptyPayload::Suppress, Ordering::Relaxed);
+    }
+
+    async fn read_datagram_once(&self) -> Result<Bytes, SessionError> {
+        let mut datagram = match &self.h3 {
+            Some(h3) => {
+                self.until_closed(h3, async {
+                    self.conn.read_datagram().await.map_err(SessionError::from)
+                })
+                .await?
+            }
+            None => self
+                .conn
+                .read_datagram()
+                .await
+                .map_err(|err| self.map_error(err))?,
+        };
 
         let datagram = if let Some(h3) = self.h3.as_ref() {
             let mut cursor = Cursor::new(&datagram);
 
-            // We have to check and strip the session ID from the datagram.
+            // We have to check and strip the quarter stream ID from the datagram (RFC 9297).
             let actual_id =
                 VarInt::decode(&mut cursor).map_err(|_| WebTransportError::UnknownSession)?;
-            if actual_id != h3.session_id {
+            // Older versions of this crate sent the full session ID, so accept it when lenient.
+            let legacy = h3.settings.strictness().is_lenient() && actual_id == h3.session_id;
+            if actual_id != quarter_stream_id(h3.session_id) && !legacy {
                 return Err(WebTransportError::UnknownSession.into());
             }
 
-            // Return the datagram without the session ID.
-
+            // Return the datagram without the quarter stream ID.
             datagram.split_off(cursor.position() as usize)
         } else {
             datagram
@@ -181,7 +915,62 @@ impl Session {
     /// Datagrams are unreliable and may be dropped or delivered out of order.
     /// The data must be smaller than [`max_datagram_size`](Self::max_datagram_size).
     pub fn send_datagram(&self, data: Bytes) -> Result<(), SessionError> {
-        let datagram = if let Some(h3) = self.h3.as_ref() {
+        self.conn
+            .send_datagram(self.encode_datagram(data))
+            .map_err(|err| self.map_error(err))?;
+
+        Ok(())
+    }
+
+    /// Sends an application datagram and estimates whether it displaced older datagrams.
+    ///
+    /// When the outgoing datagram buffer is full, the oldest queued datagrams are dropped
+    /// before they are transmitted to make room for the new one.
+    /// The returned [`DatagramSend`] estimates whether that happened from the buffer space right
+    /// before sending, so applications can keep approximate local loss statistics in addition
+    /// to what the peer reports.
+    pub fn send_datagram_tracked(&self, data: Bytes) -> Result<DatagramSend, SessionError> {
+        let datagram = self.encode_datagram(data);
+        let size = datagram.len();
+        let buffer_space = self.conn.datagram_send_buffer_space();
+        self.conn
+            .send_datagram(datagram)
+            .map_err(|err| self.map_error(err))?;
+
+        Ok(DatagramSend {
+            size,
+            may_have_displaced_older: buffer_space < size,
+        })
+    }
+
+    /// Sends an application datagram, waiting for buffer space instead of dropping older datagrams.
+    ///
+    /// See [`iroh::endpoint::Connection::send_datagram_wait`].
+    pub async fn send_datagram_wait(&self, data: Bytes) -> Result<(), SessionError> {
+        self.conn
+            .send_datagram_wait(self.encode_datagram(data))
+            .await
+            .map_err(|err| self.map_error(err))?;
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes that can be queued without dropping older datagrams.
+    ///
+    /// See [`iroh::endpoint::Connection::datagram_send_buffer_space`].
+    pub fn datagram_send_buffer_space(&self) -> usize {
+        let space = self.conn.datagram_send_buffer_space();
+        if let Some(h3) = self.h3.as_ref() {
+            space.saturating_sub(h3.header_datagram.len())
+        } else {
+            space
+        }
+    }
+
+    fn encode_datagram(&self, data: Bytes) -> Bytes {
+        let header = self.h3.as_ref().map_or(0, |h3| h3.header_datagram.len());
+        self.datagram_advisor.sent(header + data.len());
+        if let Some(h3) = self.h3.as_ref() {
             // Unfortunately, we need to allocate/copy each datagram because of the Quinn API.
             // https://github.com/quinn-rs/quinn/issues/1724
             let mut buf = BytesMut::with_capacity(h3.header_datagram.len() + data.len());
@@ -191,20 +980,18 @@ impl Session {
             buf.into()
         } else {
             data
-        };
-
-        self.conn.send_datagram(datagram)?;
-
-        Ok(())
+        }
     }
 
     /// Computes the maximum size of datagrams that may be passed to
     /// [`send_datagram`](Self::send_datagram).
+    ///
+    /// Returns zero if the peer doesn't support datagrams, see [`PanicPolicy`](crate::PanicPolicy).
     pub fn max_datagram_size(&self) -> usize {
-        let mtu = self
-            .conn
-            .max_datagram_size()
-            .expect("datagram support is required");
+        let Some(mtu) = self.conn.max_datagram_size() else {
+            unexpected("peer doesn't support datagrams");
+            return 0;
+        };
         if let Some(h3) = self.h3.as_ref() {
             mtu.saturating_sub(h3.header_datagram.len())
         } else {
@@ -212,12 +999,24 @@ impl Session {
         }
     }
 
+    /// Returns a datagram size that is likely to survive the current path, at most [`Self::max_datagram_size`].
+    ///
+    /// Starts at the maximum for the path MTU, shrinks when large datagrams are sent while
+    /// packets get lost or when a black hole is detected, and grows back while there is no loss.
+    /// Each call updates the recommendation, so call it whenever fragmenting a message.
+    pub fn recommended_datagram_size(&self) -> usize {
+        let header = self.h3.as_ref().map_or(0, |h3| h3.header_datagram.len());
+        let max = self.max_datagram_size() + header;
+        let stats = self.conn.to_info().selected_path().map(|path| path.stats());
+        self.datagram_advisor
+            .recommend(max, stats)
+            .saturating_sub(header)
+    }
+
     /// Immediately close the connection with an error code and reason. See [`iroh::endpoint::Connection::close`].
     pub fn close(&self, code: u32, reason: &[u8]) {
         let code = if self.h3.is_some() {
-            web_transport_proto::error_to_http3(code)
-                .try_into()
-                .unwrap()
+            h3_close_code(code)
         } else {
             code.into()
         };
@@ -225,14 +1024,237 @@ impl Session {
         self.conn.close(code, reason)
     }
 
+    /// Close the session with a CLOSE_WEBTRANSPORT_SESSION capsule, leaving the QUIC connection intact.
+    ///
+    /// The capsule is written to the CONNECT stream, which is then finished.
+    /// Unlike [`Self::close`], other users of the underlying connection are unaffected.
+    /// Raw QUIC sessions have no CONNECT stream, so this closes the connection instead.
+    pub async fn close_session(&self, code: u32, reason: &str) -> Result<(), SessionError> {
+        match &self.h3 {
+            Some(h3) => h3.control.close(code, reason).await,
+            None => {
+                self.close(code, reason.as_bytes());
+                Ok(())
+            }
+        }
+    }
+
+    /// Announce that the connection is shutting down by sending an HTTP/3 GOAWAY frame.
+    ///
+    /// GOAWAY only stops new sessions from being requested on this connection. This session
+    /// keeps working, including opening new streams, so it can finish its work. Call
+    /// [`Self::close`] once it is done.
+    pub async fn goaway(&self) -> Result<(), SessionError> {
+        let h3 = self
+            .h3
+            .as_ref()
+            .ok_or(WebTransportError::GoAwayUnsupported)?;
+        let id = goaway_id(self.conn.side(), h3.session_id)
+            .ok_or(WebTransportError::GoAwayUnsupported)?;
+        match h3.settings.goaway(id).await {
+            Ok(()) => Ok(()),
+            Err(endpoint::WriteError::ConnectionLost(err)) => Err(self.map_error(err)),
+            Err(err) => Err(WebTransportError::WriteError(err).into()),
+        }
+    }
+
+    /// Hand the session off to a restarted process by draining it.
+    ///
+    /// Live sessions can't be serialized and resumed in another process, as the QUIC connection
+    /// state, including its keys, is owned by this endpoint. Instead, this sends a GOAWAY frame
+    /// followed by a DRAIN_WEBTRANSPORT_SESSION capsule, asking the peer to finish in-flight work
+    /// and reconnect, which then reaches the new process. Wait for [`Self::closed`], with a
+    /// deadline of your choice, before exiting. This has no effect on raw QUIC sessions.
+    pub async fn handoff(&self) -> Result<(), SessionError> {
+        if self.h3.is_none() {
+            return Ok(());
+        }
+        self.goaway().await?;
+        self.drain().await
+    }
+
+    /// Gracefully shut down the session, see [`Self::shutdown_with_policy`].
+    ///
+    /// Open send streams are given until the deadline to finish, see [`ShutdownPolicy::Finish`].
+    pub async fn shutdown(&self, code: u32, reason: &str, deadline: Instant) -> bool {
+        self.shutdown_with_policy(code, reason, deadline, ShutdownPolicy::default())
+            .await
+    }
+
+    /// Gracefully shut down the session, returning false if the deadline passed first.
+    ///
+    /// The steps are, in order:
+    ///   1. Stop the peer from opening new streams, and for HTTP/3 send GOAWAY and DRAIN_WEBTRANSPORT_SESSION.
+    ///   2. Wait until all send streams are finished, reset or dropped, unless the policy is [`ShutdownPolicy::Reset`].
+    ///   3. For HTTP/3, send a CLOSE_WEBTRANSPORT_SESSION capsule, see [`Self::close_session`].
+    ///   4. For HTTP/3, wait for the peer to close the connection, which it should do once it read
+    ///      everything, unless the policy is [`ShutdownPolicy::Reset`].
+    ///
+    /// Finally, or once the deadline passes, the connection is closed with the code and reason.
+    /// Closing it earlier could discard stream data that was not yet acknowledged. Raw QUIC
+    /// sessions have no way to tell the peer, so they are closed as soon as their streams are
+    /// finished: wait for [`SendStream::stopped`] first if the data has to arrive.
+    pub async fn shutdown_with_policy(
+        &self,
+        code: u32,
+        reason: &str,
+        deadline: Instant,
+        policy: ShutdownPolicy,
+    ) -> bool {
+        let graceful = tokio::time::timeout_at(deadline.into(), async {
+            self.conn.set_max_concurrent_uni_streams(0u32.into());
+            self.conn.set_max_concurrent_bi_streams(0u32.into());
+            if self.h3.is_some() {
+                // These fail if already sent or the session is closed, which is fine either way.
+                self.goaway().await.ok();
+                self.drain().await.ok();
+            }
+            if policy == ShutdownPolicy::Finish {
+                self.open_streams.finished().await;
+            }
+            if self.h3.is_some() {
+                self.close_session(code, reason).await.ok();
+                if policy == ShutdownPolicy::Finish {
+                    self.conn.closed().await;
+                }
+            }
+        })
+        .await
+        .is_ok();
+
+        self.close(code, reason.as_bytes());
+        graceful
+    }
+
+    /// Returns true if either side sent a GOAWAY frame.
+    pub fn is_going_away(&self) -> bool {
+        self.h3.as_ref().is_some_and(|h3| {
+            h3.settings.sent_goaway().is_some() || h3.settings.peer_goaway().is_some()
+        })
+    }
+
+    /// Wait until the peer sends a GOAWAY frame.
+    ///
+    /// This never resolves for raw QUIC sessions.
+    pub async fn going_away(&self) {
+        match &self.h3 {
+            Some(h3) => {
+                h3.settings.peer_goaway_received().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
     /// Wait until the session is closed, returning the error. See [`iroh::endpoint::Connection::closed`].
+    ///
+    /// For HTTP/3 sessions, this also resolves when either side closed the session with [`Self::close_session`].
     pub async fn closed(&self) -> SessionError {
-        self.conn.closed().await.into()
+        let Some(h3) = &self.h3 else {
+            return self.map_error(self.conn.closed().await);
+        };
+        tokio::select! {
+            err = self.conn.closed() => self.map_error(err),
+            (code, reason) = h3.control.closed() => {
+                // Prefer the connection error if both happened.
+                self.close_reason()
+                    .unwrap_or(WebTransportError::Closed { code, reason }.into())
+            }
+        }
     }
 
     /// Return why the session was closed, or None if it's not closed. See [`iroh::endpoint::Connection::close_reason`].
     pub fn close_reason(&self) -> Option<SessionError> {
-        self.conn.close_reason().map(Into::into)
+        if let Some(err) = self.conn.close_reason() {
+            return Some(self.map_error(err));
+        }
+        let (code, reason) = self.h3.as_ref()?.control.close_reason()?;
+        Some(WebTransportError::Closed { code, reason }.into())
+    }
+
+    // Returns an error if the session was closed.
+    fn check_open(&self) -> Result<(), SessionError> {
+        match self.close_reason() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // Runs the future until the session is closed.
+    async fn until_closed<T>(
+        &self,
+        h3: &H3SessionState,
+        fut: impl Future<Output = Result<T, SessionError>>,
+    ) -> Result<T, SessionError> {
+        tokio::select! {
+            res = fut => res.map_err(|err| self.map_error(err)),
+            _ = h3.control.closed() => Err(self.closed().await),
+        }
+    }
+
+    // Decodes the application close code, translating it from HTTP/3 for HTTP/3 sessions.
+    fn map_error(&self, err: impl Into<SessionError>) -> SessionError {
+        let err = err.into();
+        let SessionError::ConnectionError(endpoint::ConnectionError::ApplicationClosed(frame)) =
+            &err
+        else {
+            return err;
+        };
+        let code = frame.error_code.into_inner();
+        let code = if self.h3.is_some() {
+            web_transport_proto::error_from_http3(code)
+        } else {
+            u32::try_from(code).ok()
+        };
+        match code {
+            Some(code) => SessionError::ApplicationClosed {
+                code,
+                reason: String::from_utf8_lossy(&frame.reason).into_owned(),
+            },
+            None => err,
+        }
+    }
+}
+
+/// An incoming stream or datagram, returned by [`Session::accept`].
+#[derive(Debug)]
+pub enum SessionEventKind {
+    /// A unidirectional stream opened by the peer.
+    Uni(RecvStream),
+    /// A bidirectional stream opened by the peer.
+    Bi(SendStream, RecvStream),
+    /// A datagram sent by the peer.
+    Datagram(Bytes),
+}
+
+/// What to do with a datagram without a payload, see [`Session::set_empty_datagrams`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyPayload {
+    /// Return it as empty bytes.
+    #[default]
+    Deliver,
+    /// Drop it, as if it was never received.
+    Suppress,
+}
+
+/// The outcome of [`Session::send_datagram_tracked`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatagramSend {
+    size: usize,
+    may_have_displaced_older: bool,
+}
+
+impl DatagramSend {
+    /// Returns the size of the datagram on the wire, including the session header.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns true if queuing this datagram likely dropped older, not yet transmitted datagrams.
+    ///
+    /// This is an estimate: the buffer space is sampled before sending, and other clones of
+    /// the session or the connection may send or transmit datagrams in between.
+    pub fn may_have_displaced_older(&self) -> bool {
+        self.may_have_displaced_older
     }
 }
 
@@ -243,15 +1265,13 @@ async fn write_full_with_max_prio(
     // Set the stream priority to max and then write the stream header.
     // Otherwise the application could write data with lower priority than the header, resulting in queuing.
     // Also the header is very important for determining the session ID without reliable reset.
+    // The SendStream applies the application's priority on its first write.
     send.set_priority(i32::MAX).ok();
-    let res = match send.write_all(buf).await {
+    match send.write_all(buf).await {
         Ok(_) => Ok(()),
         Err(endpoint::WriteError::ConnectionLost(err)) => Err(err.into()),
         Err(err) => Err(WebTransportError::WriteError(err).into()),
-    };
-    // Reset the stream priority back to the default of 0.
-    send.set_priority(0).ok();
-    res
+    }
 }
 
 impl Deref for Session {
@@ -262,6 +1282,57 @@ impl Deref for Session {
     }
 }
 
+// Dropped along with the last clone of a session, which stops the tasks holding a `WeakSession`.
+// Warns if the session is still open, in debug builds.
+struct LastClone {
+    conn: Connection,
+    control: Option<Arc<Control>>,
+    dropped: CancellationToken,
+}
+
+impl Drop for LastClone {
+    fn drop(&mut self) {
+        self.dropped.cancel();
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let closed = self.conn.close_reason().is_some()
+            || self
+                .control
+                .as_ref()
+                .is_some_and(|control| control.close_reason().is_some());
+        if !closed {
+            tracing::warn!(
+                "session dropped while open, close it explicitly with Session::close or Session::close_session"
+            );
+        }
+    }
+}
+
+// A session held by an internal task, which has to exit once the application dropped it.
+//
+// Unlike a `Session` clone, this doesn't count as a clone for the drop warning or the driver,
+// so the connection is closed once the task exits.
+pub(crate) struct WeakSession {
+    session: Session,
+    dropped: CancellationToken,
+}
+
+impl WeakSession {
+    // Resolves once the last clone of the session was dropped.
+    pub(crate) async fn dropped(&self) {
+        self.dropped.cancelled().await
+    }
+}
+
+impl Deref for WeakSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+
 impl fmt::Debug for Session {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.conn.fmt(f)
@@ -286,16 +1357,19 @@ struct H3SessionState {
     header_datagram: Vec<u8>,
 
     // Keep a reference to the settings and connect stream to avoid closing them until dropped.
-    #[allow(unused)]
     settings: Arc<Settings>,
     // The accept logic is stateful, so use an Arc<Mutex> to share it.
     accept: Arc<Mutex<H3SessionAccept>>,
 
+    // The send half of the CONNECT stream, used to write capsules.
+    control: Arc<Control>,
+
     // The request sent by the client.
     request: ConnectRequest,
 
     // The response sent by the server.
     response: ConnectResponse,
+    response_headers: http::HeaderMap,
 }
 
 impl fmt::Debug for H3SessionState {
@@ -307,7 +1381,12 @@ impl fmt::Debug for H3SessionState {
 }
 
 impl H3SessionState {
-    fn connect(conn: Connection, settings: Settings, connect: &Connected) -> Self {
+    // Returns the state along with the recv half of the CONNECT stream, used to read capsules.
+    fn connect(
+        conn: Connection,
+        settings: Settings,
+        connect: Connected,
+    ) -> (Self, endpoint::RecvStream) {
         // The session ID is the stream ID of the CONNECT request.
         let session_id = connect.session_id();
 
@@ -320,21 +1399,55 @@ impl H3SessionState {
         Frame::WEBTRANSPORT.encode(&mut header_bi);
         session_id.encode(&mut header_bi);
 
+        // HTTP datagrams are prefixed with the quarter stream ID of the CONNECT stream (RFC 9297).
         let mut header_datagram = Vec::new();
-        session_id.encode(&mut header_datagram);
+        quarter_stream_id(session_id).encode(&mut header_datagram);
 
         // Accept logic is stateful, so use an Arc<Mutex> to share it.
-        let accept = H3SessionAccept::new(conn, session_id);
-        Self {
+        let accept = H3SessionAccept::new(conn, session_id, settings.strictness());
+        let Connected {
+            request,
+            response,
+            response_headers,
+            send,
+            recv,
+        } = connect;
+        let state = Self {
             session_id,
             header_uni,
             header_bi,
             header_datagram,
             settings: Arc::new(settings),
             accept: Arc::new(Mutex::new(accept)),
-            request: connect.request.clone(),
-            response: connect.response.clone(),
-        }
+            control: Arc::new(Control::new(send)),
+            request,
+            response,
+            response_headers,
+        };
+        (state, recv)
+    }
+}
+
+/// Drives the background work of an HTTP/3 session, see [`Session::new_h3_with_driver`].
+///
+/// Completes once the CONNECT and control streams are closed, or once all clones of the session
+/// are dropped.
+#[must_use = "the session doesn't notice being closed unless the driver is polled"]
+pub struct SessionDriver {
+    inner: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl Future for SessionDriver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+impl fmt::Debug for SessionDriver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionDriver").finish_non_exhaustive()
     }
 }
 
@@ -349,7 +1462,9 @@ type PendingBi = dyn Future<Output = Result<Option<(endpoint::SendStream, endpoi
 
 // Logic just for accepting streams, which is annoying because of the stream header.
 struct H3SessionAccept {
+    conn: Connection,
     session_id: VarInt,
+    strictness: Strictness,
 
     // We also need to keep a reference to the qpack streams if the endpoint (incorrectly) creates them.
     // Again, this is just so they don't get closed until we drop the session.
@@ -365,18 +1480,20 @@ struct H3SessionAccept {
 }
 
 impl H3SessionAccept {
-    pub(crate) fn new(conn: Connection, session_id: VarInt) -> Self {
+    pub(crate) fn new(conn: Connection, session_id: VarInt, strictness: Strictness) -> Self {
         // Create a stream that just outputs new streams, so it's easy to call from poll.
         let accept_uni = Box::pin(n0_future::stream::unfold(conn.clone(), |conn| async {
             Some((conn.accept_uni().await, conn))
         }));
 
-        let accept_bi = Box::pin(n0_future::stream::unfold(conn, |conn| async {
+        let accept_bi = Box::pin(n0_future::stream::unfold(conn.clone(), |conn| async {
             Some((conn.accept_bi().await, conn))
         }));
 
         Self {
+            conn,
             session_id,
+            strictness,
 
             qpack_decoder: None,
             qpack_encoder: None,
@@ -392,16 +1509,27 @@ impl H3SessionAccept {
     // This is poll-based because we accept and decode streams in parallel.
     // In async land I would use tokio::JoinSet, but that requires a runtime.
     // It's better to use FuturesUnordered instead because it's agnostic.
+    //
+    // Yields after processing `budget` streams without returning one, so a flood of streams
+    // doesn't hog the task.
     pub fn poll_accept_uni(
         &mut self,
         cx: &mut Context<'_>,
+        budget: usize,
     ) -> Poll<Result<RecvStream, SessionError>> {
+        let mut processed = 0;
         loop {
+            if processed >= budget {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            processed += 1;
+
             // Accept any new streams.
             if let Poll::Ready(Some(res)) = self.accept_uni.poll_next(cx) {
                 // Start decoding the header and add the future to the list of pending streams.
                 let recv = res?;
-                let pending = Self::decode_uni(recv, self.session_id);
+                let pending = Self::decode_uni(recv, self.session_id(), self.strictness);
                 self.pending_uni.push(Box::pin(pending));
 
                 continue;
@@ -441,7 +1569,8 @@ impl H3SessionAccept {
     // Reads the stream header, returning the stream type.
     async fn decode_uni(
         mut recv: endpoint::RecvStream,
-        expected_session: VarInt,
+        expected_session: SessionId,
+        strictness: Strictness,
     ) -> Result<(StreamUni, endpoint::RecvStream), SessionError> {
         // Read the VarInt at the start of the stream.
         let typ = VarInt::read(&mut recv)
@@ -454,9 +1583,7 @@ impl H3SessionAccept {
             let session_id = VarInt::read(&mut recv)
                 .await
                 .map_err(|_| WebTransportError::UnknownSession)?;
-            if session_id != expected_session {
-                return Err(WebTransportError::UnknownSession.into());
-            }
+            expected_session.check(session_id, strictness)?;
         }
 
         // We need to keep a reference to the qpack streams if the endpoint (incorrectly) creates them, so return everything.
@@ -466,13 +1593,21 @@ impl H3SessionAccept {
     pub fn poll_accept_bi(
         &mut self,
         cx: &mut Context<'_>,
+        budget: usize,
     ) -> Poll<Result<(SendStream, RecvStream), SessionError>> {
+        let mut processed = 0;
         loop {
+            if processed >= budget {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            processed += 1;
+
             // Accept any new streams.
             if let Poll::Ready(Some(res)) = self.accept_bi.poll_next(cx) {
                 // Start decoding the header and add the future to the list of pending streams.
                 let (send, recv) = res?;
-                let pending = Self::decode_bi(send, recv, self.session_id);
+                let pending = Self::decode_bi(send, recv, self.session_id(), self.strictness);
                 self.pending_bi.push(Box::pin(pending));
 
                 continue;
@@ -504,7 +1639,8 @@ impl H3SessionAccept {
     async fn decode_bi(
         send: endpoint::SendStream,
         mut recv: endpoint::RecvStream,
-        expected_session: VarInt,
+        expected_session: SessionId,
+        strictness: Strictness,
     ) -> Result<Option<(endpoint::SendStream, endpoint::RecvStream)>, SessionError> {
         let typ = VarInt::read(&mut recv)
             .await
@@ -518,12 +1654,82 @@ impl H3SessionAccept {
         let session_id = VarInt::read(&mut recv)
             .await
             .map_err(|_| WebTransportError::UnknownSession)?;
-        if session_id != expected_session {
-            return Err(WebTransportError::UnknownSession.into());
-        }
+        expected_session.check(session_id, strictness)?;
 
         Ok(Some((send, recv)))
     }
+
+    fn session_id(&self) -> SessionId {
+        SessionId {
+            id: self.session_id,
+            conn: self.conn.clone(),
+        }
+    }
+}
+
+// Returns the quarter stream ID used to associate HTTP datagrams with a request stream.
+fn quarter_stream_id(session_id: VarInt) -> VarInt {
+    VarInt::try_from(session_id.into_inner() / 4).expect("smaller than the session ID")
+}
+
+// Maps a WebTransport error code to the HTTP/3 code it's sent as when closing the connection.
+// The mapped codes always fit in a varint, even for `u32::MAX`.
+fn h3_close_code(code: u32) -> endpoint::VarInt {
+    let code = web_transport_proto::error_to_http3(code);
+    endpoint::VarInt::from_u64(code).unwrap_or(endpoint::VarInt::MAX)
+}
+
+// The ID sent in a GOAWAY frame for the session, see `Session::goaway`.
+fn goaway_id(side: Side, session_id: VarInt) -> Option<VarInt> {
+    // A server refuses requests after the CONNECT stream; a client never accepts pushes.
+    match side {
+        Side::Server => VarInt::try_from(session_id.into_inner() + 4).ok(),
+        Side::Client => Some(VarInt::from_u32(0)),
+    }
+}
+
+// The capsule asking the peer to wind down the session, see `Session::drain`.
+fn drain_capsule() -> Capsule {
+    Capsule::Unknown {
+        typ: DRAIN_WEBTRANSPORT_SESSION,
+        payload: Bytes::new(),
+    }
+}
+
+// The HTTP/3 error code for an invalid stream or session ID.
+const H3_ID_ERROR: u32 = 0x108;
+
+// How many items the accept and datagram loops process before yielding, unless configured.
+const DEFAULT_POLL_BUDGET: usize = 32;
+
+// The unidirectional streams an HTTP/3 peer keeps open: control, QPACK encoder and decoder.
+const H3_PEER_UNI_STREAMS: u32 = 3;
+
+// The expected session ID of incoming streams, along with the connection to close on mismatch.
+struct SessionId {
+    id: VarInt,
+    conn: Connection,
+}
+
+impl SessionId {
+    // Validates the session ID of an incoming stream according to the strictness.
+    fn check(&self, session_id: VarInt, strictness: Strictness) -> Result<(), SessionError> {
+        if session_id == self.id {
+            return Ok(());
+        }
+
+        match strictness {
+            Strictness::Lenient => {
+                tracing::debug!(%session_id, "accepting stream for unknown session");
+                Ok(())
+            }
+            Strictness::Default => Err(WebTransportError::UnknownSession.into()),
+            Strictness::Strict => {
+                self.conn.close(H3_ID_ERROR.into(), b"unknown session");
+                Err(WebTransportError::UnknownSession.into())
+            }
+        }
+    }
 }
 
 impl web_transport_trait::Session for Session {
@@ -568,9 +1774,6 @@ impl web_transport_trait::Session for Session {
     }
 
     fn protocol(&self) -> Option<&str> {
-        match self.h3.as_ref() {
-            None => std::str::from_utf8(self.conn.alpn()).ok(),
-            Some(h3) => h3.response.protocol.as_deref(),
-        }
+        Self::protocol(self)
     }
 }
@@ -1,6 +1,21 @@
 use iroh::endpoint;
 use n0_error::stack_error;
-use tokio::try_join;
+use tokio::{
+    sync::{Mutex, watch},
+    try_join,
+};
+use web_transport_proto::{Frame, Setting, VarInt};
+
+use crate::{Capabilities, HandshakeOptions, SettingsProfile, Strictness, profile::reserved_id};
+
+// The HTTP/3 GOAWAY frame type.
+const GOAWAY: Frame = Frame(VarInt::from_u32(0x07));
+
+// The maximum size of a frame on the control stream after SETTINGS, which we only parse for GOAWAY.
+const MAX_CONTROL_FRAME_SIZE: u64 = 64 * 1024;
+
+// The HTTP/3 error code for a frame that is not permitted in the current state or stream.
+const H3_FRAME_UNEXPECTED: u32 = 0x105;
 
 /// An error during the HTTP/3 SETTINGS frame exchange.
 #[stack_error(derive, from_sources)]
@@ -15,6 +30,9 @@ pub enum SettingsError {
     #[error("WebTransport is not supported")]
     WebTransportUnsupported,
 
+    #[error("peer does not accept any WebTransport sessions")]
+    MaxSessionsExceeded,
+
     #[error("connection error")]
     ConnectionError(#[error(source, from, std_err)] endpoint::ConnectionError),
 
@@ -28,47 +46,370 @@ pub enum SettingsError {
 /// Maintains the HTTP/3 control stream by holding references to the send/recv streams.
 #[derive(Debug)]
 pub struct Settings {
-    // A reference to the send/recv stream, so we don't close it until dropped.
-    #[allow(dead_code)]
-    send: endpoint::SendStream,
+    // The control streams, also used for GOAWAY after the SETTINGS exchange.
+    // Holding them also makes sure we don't close them until dropped.
+    send: Mutex<endpoint::SendStream>,
+    recv: Mutex<endpoint::RecvStream>,
 
-    #[allow(dead_code)]
-    recv: endpoint::RecvStream,
+    // The number of sessions we advertised to the peer.
+    max_sessions: u32,
+
+    // The number of sessions the peer advertised to us.
+    peer_max_sessions: u64,
+
+    // The `SETTINGS_MAX_FIELD_SECTION_SIZE` we advertised to the peer, if any.
+    max_field_section_size: Option<u64>,
+
+    // The `SETTINGS_MAX_FIELD_SECTION_SIZE` the peer advertised to us, if any.
+    peer_max_field_section_size: Option<u64>,
+
+    // Which identifiers the peer used in its SETTINGS.
+    peer_dialect: SettingsDialect,
+
+    // The version and features the peer advertised, if any.
+    peer_capabilities: Option<Capabilities>,
+
+    // How strictly the protocol is enforced for this connection.
+    strictness: Strictness,
+
+    // The ID of the GOAWAY frame we sent, if any.
+    goaway: watch::Sender<Option<VarInt>>,
+
+    // The ID of the last GOAWAY frame received from the peer, if any.
+    peer_goaway: watch::Sender<Option<VarInt>>,
 }
 
 impl Settings {
     /// Establishes an HTTP/3 connection by exchanging SETTINGS frames.
+    ///
+    /// Advertises support for a single WebTransport session.
     pub async fn connect(conn: &endpoint::Connection) -> Result<Self, SettingsError> {
-        let recv = Self::accept(conn);
-        let send = Self::open(conn);
+        Self::connect_with_options(conn, &HandshakeOptions::default()).await
+    }
+
+    /// Establishes an HTTP/3 connection, sending the SETTINGS selected by the [`HandshakeOptions`].
+    ///
+    /// The strictness is kept for the lifetime of the connection, see [`Self::strictness`].
+    /// The field section size is only advertised here, it is enforced when reading the CONNECT
+    /// request or response.
+    pub async fn connect_with_options<T: endpoint::ConnectionState>(
+        conn: &endpoint::Connection<T>,
+        options: &HandshakeOptions,
+    ) -> Result<Self, SettingsError> {
+        let HandshakeOptions {
+            max_sessions,
+            strictness,
+            max_field_section_size,
+            ref profile,
+        } = *options;
+        let recv = Self::accept(conn, strictness, profile);
+        let send = Self::open(conn, max_sessions, max_field_section_size, profile);
 
         // Run both tasks concurrently until one errors or they both complete.
-        let (send, recv) = try_join!(send, recv)?;
-        Ok(Self { send, recv })
+        let (send, peer) = try_join!(send, recv)?;
+        Ok(Self {
+            send: Mutex::new(send),
+            recv: Mutex::new(peer.recv),
+            max_sessions,
+            peer_max_sessions: peer.max_sessions,
+            max_field_section_size,
+            peer_max_field_section_size: peer.max_field_section_size,
+            peer_dialect: peer.dialect,
+            peer_capabilities: peer.capabilities,
+            strictness,
+            goaway: watch::Sender::new(None),
+            peer_goaway: watch::Sender::new(None),
+        })
+    }
+
+    /// Returns the maximum number of sessions we advertised to the peer.
+    pub fn max_sessions(&self) -> u32 {
+        self.max_sessions
+    }
+
+    /// Returns the maximum number of sessions the peer advertised.
+    pub fn peer_max_sessions(&self) -> u64 {
+        self.peer_max_sessions
     }
 
-    async fn accept(conn: &endpoint::Connection) -> Result<endpoint::RecvStream, SettingsError> {
+    /// Returns the maximum size of a field section we advertised to the peer, if any.
+    pub fn max_field_section_size(&self) -> Option<u64> {
+        self.max_field_section_size
+    }
+
+    /// Returns the maximum size of a field section the peer advertised, if any.
+    pub fn peer_max_field_section_size(&self) -> Option<u64> {
+        self.peer_max_field_section_size
+    }
+
+    /// Returns which variants of the WebTransport and datagram settings the peer sent.
+    ///
+    /// Use this to find peers still relying on legacy identifiers, before dropping support for them.
+    pub fn peer_dialect(&self) -> SettingsDialect {
+        self.peer_dialect
+    }
+
+    /// Returns the version and features the peer advertised, if any.
+    ///
+    /// This is None for peers that don't advertise them, e.g. other HTTP/3 stacks.
+    pub fn peer_capabilities(&self) -> Option<Capabilities> {
+        self.peer_capabilities
+    }
+
+    /// Returns how strictly the protocol is enforced for this connection.
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// Sends a GOAWAY frame on the control stream, announcing that the connection is shutting down.
+    ///
+    /// A server sends the first request stream ID it won't process, a client the first push ID.
+    pub async fn goaway(&self, id: VarInt) -> Result<(), endpoint::WriteError> {
+        let mut buf = Vec::new();
+        GOAWAY.encode(&mut buf);
+        VarInt::try_from(id.size() as u64)
+            .expect("varint size fits in a varint")
+            .encode(&mut buf);
+        id.encode(&mut buf);
+
+        tracing::debug!(%id, "sending GOAWAY frame");
+
+        self.send.lock().await.write_all(&buf).await?;
+        self.goaway.send_replace(Some(id));
+        Ok(())
+    }
+
+    /// Returns the ID of the GOAWAY frame we sent, if any.
+    pub fn sent_goaway(&self) -> Option<VarInt> {
+        *self.goaway.borrow()
+    }
+
+    /// Returns the ID of the last GOAWAY frame received from the peer, if any.
+    pub fn peer_goaway(&self) -> Option<VarInt> {
+        *self.peer_goaway.borrow()
+    }
+
+    /// Waits until the peer sends a GOAWAY frame, returning its ID.
+    pub async fn peer_goaway_received(&self) -> VarInt {
+        let mut goaway = self.peer_goaway.subscribe();
+        // The sender lives as long as self, so this can't fail.
+        let goaway = match goaway.wait_for(|goaway| goaway.is_some()).await {
+            Ok(goaway) => *goaway,
+            Err(_) => None,
+        };
+        goaway.expect("goaway is set before the sender is dropped")
+    }
+
+    /// Keeps reading frames from the peer's control stream, recording any GOAWAY.
+    ///
+    /// Returns once the control stream is closed or a frame can't be read.
+    pub(crate) async fn run(&self, conn: &endpoint::Connection) {
+        let mut recv = self.recv.lock().await;
+        loop {
+            let (typ, payload) = match read_frame(&mut recv).await {
+                Ok(frame) => frame,
+                Err(err) => {
+                    tracing::debug!("control stream closed: {err:#}");
+                    return;
+                }
+            };
+
+            // These frames are only valid on request streams or once at the start.
+            let unexpected = [Frame::DATA, Frame::HEADERS, Frame::SETTINGS].contains(&typ);
+            if unexpected && self.strictness.is_strict() {
+                tracing::warn!(typ = %typ.0, "unexpected frame on control stream");
+                conn.close(H3_FRAME_UNEXPECTED.into(), b"unexpected frame");
+                return;
+            }
+
+            if typ != GOAWAY {
+                tracing::trace!(typ = %typ.0, "ignoring control frame");
+                continue;
+            }
+
+            match VarInt::decode(&mut payload.as_slice()) {
+                Ok(id) => {
+                    tracing::debug!(%id, "received GOAWAY frame");
+                    self.peer_goaway.send_replace(Some(id));
+                }
+                Err(_) if self.strictness.is_lenient() => {
+                    tracing::debug!("ignoring invalid GOAWAY frame");
+                }
+                Err(_) => {
+                    tracing::warn!("received invalid GOAWAY frame");
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn accept<T: endpoint::ConnectionState>(
+        conn: &endpoint::Connection<T>,
+        strictness: Strictness,
+        profile: &SettingsProfile,
+    ) -> Result<PeerSettings, SettingsError> {
         let mut recv = conn.accept_uni().await?;
-        let settings = web_transport_proto::Settings::read(&mut recv).await?;
+        let mut settings = web_transport_proto::Settings::read(&mut recv).await?;
 
         tracing::debug!("received SETTINGS frame: {settings:?}");
+        let dialect = SettingsDialect::new(&settings);
+        if dialect.uses_legacy() {
+            tracing::debug!(?dialect, "peer uses legacy SETTINGS identifiers");
+        }
+        profile.tolerate(&mut settings);
+        let capabilities = Capabilities::read(&settings);
 
-        if settings.supports_webtransport() == 0 {
+        let max_field_section_size = settings
+            .get(&Setting::MAX_FIELD_SECTION_SIZE)
+            .map(|size| size.into_inner());
+
+        let explicit = settings.get(&Setting::WEBTRANSPORT_MAX_SESSIONS);
+        let max_sessions = settings.supports_webtransport();
+        if max_sessions == 0 {
+            // An explicit zero means the peer supports WebTransport but doesn't take any sessions.
+            if explicit == Some(&VarInt::from_u32(0)) {
+                return Err(SettingsError::MaxSessionsExceeded);
+            }
+            if strictness.is_lenient() {
+                tracing::debug!("peer doesn't advertise WebTransport, assuming a single session");
+                return Ok(PeerSettings {
+                    recv,
+                    max_sessions: 1,
+                    max_field_section_size,
+                    dialect,
+                    capabilities,
+                });
+            }
             return Err(SettingsError::WebTransportUnsupported);
         }
 
-        Ok(recv)
+        // Only the deprecated (pre-draft-07) settings were sent.
+        if explicit.is_none() && strictness.is_strict() {
+            return Err(SettingsError::WebTransportUnsupported);
+        }
+
+        Ok(PeerSettings {
+            recv,
+            max_sessions,
+            max_field_section_size,
+            dialect,
+            capabilities,
+        })
     }
 
-    async fn open(conn: &endpoint::Connection) -> Result<endpoint::SendStream, SettingsError> {
+    async fn open<T: endpoint::ConnectionState>(
+        conn: &endpoint::Connection<T>,
+        max_sessions: u32,
+        max_field_section_size: Option<u64>,
+        profile: &SettingsProfile,
+    ) -> Result<endpoint::SendStream, SettingsError> {
         let mut settings = web_transport_proto::Settings::default();
-        settings.enable_webtransport(1);
+        settings.enable_webtransport(max_sessions);
+        if let Some(size) = max_field_section_size {
+            let size = VarInt::try_from(size).unwrap_or(VarInt::MAX);
+            settings.insert(Setting::MAX_FIELD_SECTION_SIZE, size);
+        }
+        profile.apply(&mut settings);
 
         tracing::debug!("sending SETTINGS frame: {settings:?}");
 
         let mut send = conn.open_uni().await?;
         settings.write(&mut send).await?;
 
+        // A reserved frame, which the peer must ignore.
+        if profile.grease() {
+            let mut frame = Vec::new();
+            reserved_id().encode(&mut frame);
+            VarInt::from_u32(0).encode(&mut frame);
+            send.write_all(&frame).await?;
+        }
+
         Ok(send)
     }
 }
+
+// The result of reading the peer's SETTINGS.
+struct PeerSettings {
+    recv: endpoint::RecvStream,
+    max_sessions: u64,
+    max_field_section_size: Option<u64>,
+    dialect: SettingsDialect,
+    capabilities: Option<Capabilities>,
+}
+
+/// Which variants of a setting the peer sent, see [`SettingsDialect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettingVariant {
+    /// Neither variant was sent.
+    Missing,
+    /// Only the legacy identifier from an earlier draft was sent.
+    Legacy,
+    /// Only the current identifier was sent.
+    Current,
+    /// Both the legacy and the current identifier were sent, for compatibility.
+    Both,
+}
+
+impl SettingVariant {
+    fn new(legacy: bool, current: bool) -> Self {
+        match (legacy, current) {
+            (false, false) => Self::Missing,
+            (true, false) => Self::Legacy,
+            (false, true) => Self::Current,
+            (true, true) => Self::Both,
+        }
+    }
+}
+
+/// Which identifiers the peer used in its SETTINGS, see [`Settings::peer_dialect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SettingsDialect {
+    /// The WebTransport settings, where the legacy ones predate draft-07.
+    pub webtransport: SettingVariant,
+    /// The datagram setting, where the legacy one predates RFC 9297.
+    pub datagram: SettingVariant,
+}
+
+impl SettingsDialect {
+    fn new(settings: &web_transport_proto::Settings) -> Self {
+        let webtransport = SettingVariant::new(
+            settings.contains_key(&Setting::WEBTRANSPORT_ENABLE_DEPRECATED)
+                || settings.contains_key(&Setting::WEBTRANSPORT_MAX_SESSIONS_DEPRECATED),
+            settings.contains_key(&Setting::WEBTRANSPORT_MAX_SESSIONS),
+        );
+        let datagram = SettingVariant::new(
+            settings.contains_key(&Setting::ENABLE_DATAGRAM_DEPRECATED),
+            settings.contains_key(&Setting::ENABLE_DATAGRAM),
+        );
+        Self {
+            webtransport,
+            datagram,
+        }
+    }
+
+    /// Returns true if the peer relies on a legacy identifier, without sending the current one.
+    pub fn uses_legacy(&self) -> bool {
+        self.webtransport == SettingVariant::Legacy || self.datagram == SettingVariant::Legacy
+    }
+}
+
+/// Reads a single HTTP/3 frame from the stream, returning its type and payload.
+async fn read_frame(recv: &mut endpoint::RecvStream) -> Result<(Frame, Vec<u8>), SettingsError> {
+    let typ = Frame(read_varint(recv).await?);
+    let size = read_varint(recv).await?.into_inner();
+    if size > MAX_CONTROL_FRAME_SIZE {
+        return Err(web_transport_proto::SettingsError::InvalidSize.into());
+    }
+
+    let mut payload = vec![0u8; size as usize];
+    recv.read_exact(&mut payload)
+        .await
+        .map_err(|_| SettingsError::UnexpectedEnd)?;
+    Ok((typ, payload))
+}
+
+async fn read_varint(recv: &mut endpoint::RecvStream) -> Result<VarInt, SettingsError> {
+    VarInt::read(recv)
+        .await
+        .map_err(|_| SettingsError::UnexpectedEnd)
+}
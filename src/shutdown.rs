@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// What [`Session::shutdown`](crate::Session::shutdown) does with send streams that are still open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShutdownPolicy {
+    /// Wait until every send stream is finished, reset or dropped, up to the deadline.
+    #[default]
+    Finish,
+    /// Close right away, which resets all streams, without waiting for the peer.
+    Reset,
+}
+
+// Counts the send streams of a session that are neither finished, reset nor dropped.
+#[derive(Debug)]
+pub(crate) struct OpenStreams {
+    count: watch::Sender<usize>,
+}
+
+impl Default for OpenStreams {
+    fn default() -> Self {
+        Self {
+            count: watch::Sender::new(0),
+        }
+    }
+}
+
+impl OpenStreams {
+    pub(crate) fn guard(self: &Arc<Self>) -> StreamGuard {
+        self.count.send_modify(|count| *count += 1);
+        StreamGuard(self.clone())
+    }
+
+    // The number of guards that are held.
+    pub(crate) fn count(&self) -> usize {
+        *self.count.borrow()
+    }
+
+    // Wait until all guards are released.
+    pub(crate) async fn finished(&self) {
+        let mut count = self.count.subscribe();
+        // The sender lives as long as we do, so this can't fail.
+        count.wait_for(|count| *count == 0).await.ok();
+    }
+}
+
+// Held by a send stream until it's finished, reset or dropped.
+#[derive(Debug)]
+pub(crate) struct StreamGuard(Arc<OpenStreams>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.count.send_modify(|count| *count -= 1);
+    }
+}
+
+// Tracks the sessions accepted by a server, so they can be drained on shutdown.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SessionTracker {
+    // Cancelled to send GOAWAY and DRAIN_WEBTRANSPORT_SESSION to every session.
+    pub(crate) drain: CancellationToken,
+    // Cancelled to close the sessions that are still open.
+    pub(crate) close: CancellationToken,
+    // Counts the sessions whose driver is still running, reusing the stream counter.
+    pub(crate) active: Arc<OpenStreams>,
+}
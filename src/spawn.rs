@@ -0,0 +1,42 @@
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+/// A background task started by a [`Spawner`].
+pub type SpawnedTask = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Starts the background tasks of sessions, such as the [`SessionDriver`](crate::SessionDriver).
+///
+/// The default spawns them on the current tokio runtime, and panics outside of one.
+/// Use [`Self::new`] to hand them to another executor, or to poll them yourself.
+/// Each task must be polled until it completes, or the session stops making progress.
+#[derive(Clone)]
+pub struct Spawner(Arc<dyn Fn(SpawnedTask) + Send + Sync>);
+
+impl Spawner {
+    /// Creates a spawner that hands each task to the function.
+    pub fn new(spawn: impl Fn(SpawnedTask) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(spawn))
+    }
+
+    /// Returns a spawner for the current tokio runtime, the default.
+    pub fn tokio() -> Self {
+        Self::new(|task| {
+            tokio::spawn(task);
+        })
+    }
+
+    pub(crate) fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        (self.0)(Box::pin(task))
+    }
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self::tokio()
+    }
+}
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spawner").finish_non_exhaustive()
+    }
+}
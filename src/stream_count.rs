@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// The number of open streams of a session, by direction and initiator.
+///
+/// A stream is open until all of its halves are dropped. See [`Session::stream_counts`](crate::Session::stream_counts).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamCounts {
+    /// Unidirectional streams opened by us.
+    pub uni_local: usize,
+    /// Unidirectional streams opened by the peer.
+    pub uni_remote: usize,
+    /// Bidirectional streams opened by us.
+    pub bi_local: usize,
+    /// Bidirectional streams opened by the peer.
+    pub bi_remote: usize,
+}
+
+impl StreamCounts {
+    fn get_mut(&mut self, kind: StreamKind) -> &mut usize {
+        match kind {
+            StreamKind::UniLocal => &mut self.uni_local,
+            StreamKind::UniRemote => &mut self.uni_remote,
+            StreamKind::BiLocal => &mut self.bi_local,
+            StreamKind::BiRemote => &mut self.bi_remote,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum StreamKind {
+    UniLocal,
+    UniRemote,
+    BiLocal,
+    BiRemote,
+}
+
+// Maintains the stream counts of a session.
+#[derive(Debug)]
+pub(crate) struct StreamCounter {
+    counts: watch::Sender<StreamCounts>,
+}
+
+impl Default for StreamCounter {
+    fn default() -> Self {
+        Self {
+            counts: watch::Sender::new(StreamCounts::default()),
+        }
+    }
+}
+
+impl StreamCounter {
+    pub(crate) fn open(self: &Arc<Self>, kind: StreamKind) -> Arc<CountedStream> {
+        self.counts.send_modify(|counts| *counts.get_mut(kind) += 1);
+        Arc::new(CountedStream {
+            counter: self.clone(),
+            kind,
+        })
+    }
+
+    pub(crate) fn get(&self) -> StreamCounts {
+        *self.counts.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<StreamCounts> {
+        self.counts.subscribe()
+    }
+}
+
+// Shared by the halves of a stream, which is counted until this is dropped.
+#[derive(Debug)]
+pub(crate) struct CountedStream {
+    counter: Arc<StreamCounter>,
+    kind: StreamKind,
+}
+
+impl Drop for CountedStream {
+    fn drop(&mut self) {
+        self.counter
+            .counts
+            .send_modify(|counts| *counts.get_mut(self.kind) -= 1);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use n0_future::{FuturesUnordered, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::{RecvStream, Session, session::WeakSession};
+
+/// The error code used to stop a unidirectional stream with an unregistered type.
+///
+/// See [`Session::uni_streams_of_type`].
+pub const UNKNOWN_STREAM_TYPE: u32 = 0x02;
+
+/// How many streams of a type are queued until [`UniStreams::accept`] is called.
+///
+/// Once a queue is full, no more streams are accepted until it has room, so the peer is
+/// limited by the stream limit of the connection.
+pub const UNI_STREAM_QUEUE: usize = 16;
+
+/// Incoming unidirectional streams of a single type, see [`Session::uni_streams_of_type`].
+#[derive(Debug)]
+pub struct UniStreams {
+    recv: mpsc::Receiver<RecvStream>,
+}
+
+impl UniStreams {
+    /// Accept the next stream of this type, with the type byte already consumed.
+    ///
+    /// Returns None once the session is closed or dropped, or the type was registered again.
+    pub async fn accept(&mut self) -> Option<RecvStream> {
+        self.recv.recv().await
+    }
+}
+
+/// Incoming unidirectional streams with an unregistered type, see [`Session::unknown_uni_streams`].
+#[derive(Debug)]
+pub struct UnknownUniStreams {
+    recv: mpsc::Receiver<(u8, RecvStream)>,
+}
+
+impl UnknownUniStreams {
+    /// Accept the next stream with an unregistered type, returning the type byte and the stream.
+    ///
+    /// Returns None once the session is closed or dropped, or this was called again.
+    pub async fn accept(&mut self) -> Option<(u8, RecvStream)> {
+        self.recv.recv().await
+    }
+}
+
+// Routes incoming unidirectional streams by their first byte.
+#[derive(Debug, Default)]
+pub(crate) struct StreamTypes {
+    routes: Mutex<Routes>,
+}
+
+#[derive(Debug, Default)]
+struct Routes {
+    // Whether the routing task was started.
+    started: bool,
+    // Whether the routing task exited, because the session was closed or dropped.
+    closed: bool,
+    types: HashMap<u8, mpsc::Sender<RecvStream>>,
+    unknown: Option<mpsc::Sender<(u8, RecvStream)>>,
+}
+
+impl StreamTypes {
+    pub(crate) fn register(self: &Arc<Self>, session: &Session, tag: u8) -> UniStreams {
+        let (send, recv) = mpsc::channel(UNI_STREAM_QUEUE);
+        let mut routes = self.routes.lock().unwrap();
+        if !routes.closed {
+            routes.types.insert(tag, send);
+            self.start(&mut routes, session);
+        }
+        UniStreams { recv }
+    }
+
+    pub(crate) fn register_unknown(self: &Arc<Self>, session: &Session) -> UnknownUniStreams {
+        let (send, recv) = mpsc::channel(UNI_STREAM_QUEUE);
+        let mut routes = self.routes.lock().unwrap();
+        if !routes.closed {
+            routes.unknown = Some(send);
+            self.start(&mut routes, session);
+        }
+        UnknownUniStreams { recv }
+    }
+
+    fn start(self: &Arc<Self>, routes: &mut Routes, session: &Session) {
+        if std::mem::replace(&mut routes.started, true) {
+            return;
+        }
+        let this = self.clone();
+        let spawner = session.spawner().clone();
+        let session = session.downgrade();
+        spawner.spawn(async move { this.run(session).await });
+    }
+
+    async fn run(&self, session: WeakSession) {
+        // Read the type of each stream concurrently, so a slow stream doesn't block the others.
+        let mut pending = FuturesUnordered::new();
+        let mut processed = 0usize;
+        loop {
+            processed += 1;
+            if processed.is_multiple_of(session.poll_budget()) {
+                tokio::task::yield_now().await;
+            }
+            tokio::select! {
+                res = session.accept_uni() => match res {
+                    Ok(recv) => pending.push(read_type(recv)),
+                    Err(_) => break,
+                },
+                Some(res) = pending.next() => {
+                    if let Some((tag, recv)) = res {
+                        // Waits for room in the queue, so a slow receiver holds back new streams.
+                        tokio::select! {
+                            _ = self.dispatch(tag, recv) => {}
+                            _ = session.dropped() => break,
+                        }
+                    }
+                }
+                _ = session.dropped() => break,
+            }
+        }
+
+        // Drop all senders, so the receivers return None.
+        let mut routes = self.routes.lock().unwrap();
+        routes.closed = true;
+        routes.types.clear();
+        routes.unknown = None;
+    }
+
+    async fn dispatch(&self, tag: u8, recv: RecvStream) {
+        let (send, unknown) = {
+            let routes = self.routes.lock().unwrap();
+            (routes.types.get(&tag).cloned(), routes.unknown.clone())
+        };
+        let recv = match send {
+            Some(send) => match send.send(recv).await {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(recv)) => recv,
+            },
+            None => recv,
+        };
+        let mut recv = match unknown {
+            Some(send) => match send.send((tag, recv)).await {
+                Ok(()) => return,
+                Err(mpsc::error::SendError((_, recv))) => recv,
+            },
+            None => recv,
+        };
+        tracing::debug!(tag, "stopping stream with unknown type");
+        recv.stop(UNKNOWN_STREAM_TYPE).ok();
+    }
+}
+
+async fn read_type(mut recv: RecvStream) -> Option<(u8, RecvStream)> {
+    let mut tag = [0u8];
+    recv.read_exact(&mut tag).await.ok()?;
+    Some((tag[0], recv))
+}
@@ -0,0 +1,37 @@
+/// How strictly the HTTP/3 and WebTransport protocols are enforced.
+///
+/// Applies to SETTINGS validation, the protocol selected by a CONNECT response, unexpected frames
+/// on the HTTP/3 control stream and the session ID of incoming streams. Other CONNECT headers are
+/// always validated the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Strictness {
+    /// Enforce the specification, closing the connection on protocol violations.
+    ///
+    /// Peers relying on the deprecated pre-draft-07 SETTINGS are rejected,
+    /// unexpected control frames close the connection with `H3_FRAME_UNEXPECTED`
+    /// and streams for another session close it with `H3_ID_ERROR`.
+    Strict,
+
+    /// Enforce the protocol, but ignore violations that don't affect this session.
+    ///
+    /// Unexpected control frames and streams for another session are ignored.
+    #[default]
+    Default,
+
+    /// Accept anything that can be reasonably interpreted, for maximal interoperability.
+    ///
+    /// Peers without WebTransport SETTINGS are assumed to accept a single session,
+    /// a CONNECT response may select a protocol that wasn't offered,
+    /// and streams are accepted regardless of their session ID.
+    Lenient,
+}
+
+impl Strictness {
+    pub(crate) fn is_strict(self) -> bool {
+        self == Self::Strict
+    }
+
+    pub(crate) fn is_lenient(self) -> bool {
+        self == Self::Lenient
+    }
+}
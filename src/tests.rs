@@ -1,9 +1,24 @@
-use iroh::{Endpoint, endpoint::ConnectionError};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use iroh::Endpoint;
 use n0_tracing_test::traced_test;
 use tracing::Instrument;
 use url::Url;
+use web_transport_proto::VarInt;
 
-use crate::{ALPN_H3, Client, H3Request, QuicRequest, SessionError};
+use crate::{
+    ALPN_H3, Batching, Client, ClientError, ConnectError, ConnectRequestBuilder, Connected,
+    Connecting, ErrorKind, H3Request, HandshakeOptions, MessageError, ORIGIN_REJECTED,
+    OriginPolicy, QuicRequest, Server, ServerError, Session, SessionError, SessionEventKind,
+    Settings, SettingsError, StreamCounts, Strictness, TransportTuning, WebTransportError,
+};
 
 #[tokio::test]
 #[traced_test]
@@ -32,13 +47,14 @@ async fn h3_smoke() -> n0_error::Result<()> {
             let session = client.connect_h3(server_addr, url.clone()).await.inspect_err(|err| println!("{err:#?}")).unwrap();
             assert_eq!(session.remote_id(), server_id);
             assert_eq!(session.request().map(|r| &r.url), Some(&url));
+            assert_eq!(session.settings().map(|s| s.peer_max_sessions()), Some(1));
 
             let mut stream = session.open_uni().await.unwrap();
             stream.write_all(b"hi").await.unwrap();
             stream.finish().unwrap();
             let reason = session.closed().await;
             assert!(
-                matches!(reason, SessionError::ConnectionError(ConnectionError::ApplicationClosed(frame)) if web_transport_proto::error_from_http3(frame.error_code.into_inner()) == Some(23))
+                matches!(reason, SessionError::ApplicationClosed { code: 23, reason } if reason == "bye")
             );
 
             drop(session);
@@ -102,7 +118,7 @@ async fn quic_smoke() -> n0_error::Result<()> {
             assert!(session.request().is_none());
             let reason = session.closed().await;
             assert!(
-                matches!(reason, SessionError::ConnectionError(ConnectionError::ApplicationClosed(frame)) if frame.error_code.into_inner() == 23)
+                matches!(reason, SessionError::ApplicationClosed { code: 23, reason } if reason == "bye")
             )
         }.instrument(tracing::error_span!("client"))
     });
@@ -126,3 +142,5338 @@ async fn quic_smoke() -> n0_error::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+#[traced_test]
+async fn h3_max_sessions_exceeded() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        // The client gives up after the SETTINGS exchange, so no CONNECT arrives.
+        assert!(
+            H3Request::accept_with_options(conn, &HandshakeOptions::new().with_max_sessions(0))
+                .await
+                .is_err()
+        );
+        server.close().await;
+    });
+
+    let err = client.connect_h3(server_addr, url).await.unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::SettingsError(SettingsError::MaxSessionsExceeded)
+    ));
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_drain() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        assert!(!session.is_draining());
+        session.draining().await;
+        assert!(session.is_draining());
+        session.close(0, b"drained");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.drain().await.unwrap();
+    session.closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_close_session() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let reason = session.closed().await;
+        assert!(matches!(
+            reason,
+            SessionError::WebTransportError(WebTransportError::Closed { code: 7, reason }) if reason == "done"
+        ));
+        // The QUIC connection outlives the session.
+        assert!(session.conn().close_reason().is_none());
+        assert!(session.accept_uni().await.is_err());
+        session.conn().close(0u32.into(), b"bye");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close_session(7, "done").await.unwrap();
+    assert!(session.open_uni().await.is_err());
+    session.conn().closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_capsules() -> n0_error::Result<()> {
+    const CAPSULE_TYPE: VarInt = VarInt::from_u32(0x1234);
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let (typ, payload) = session.recv_capsule().await.unwrap();
+        assert_eq!(typ, CAPSULE_TYPE);
+        session.send_capsule(typ, payload).await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session
+        .send_capsule(CAPSULE_TYPE, Bytes::from_static(b"ping"))
+        .await
+        .unwrap();
+    let (typ, payload) = session.recv_capsule().await.unwrap();
+    assert_eq!(typ, CAPSULE_TYPE);
+    assert_eq!(payload, b"ping".as_slice());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_capsule_handler() -> n0_error::Result<()> {
+    const HANDLED: VarInt = VarInt::from_u32(0x1234);
+    const QUEUED: VarInt = VarInt::from_u32(0x1235);
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        assert!(
+            !session
+                .set_capsule_handler(HANDLED, move |payload| tx.send(payload).unwrap())
+                .unwrap()
+        );
+        session.send_capsule(QUEUED, Bytes::new()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), b"ping".as_slice());
+        let (typ, _) = session.recv_capsule().await.unwrap();
+        assert_eq!(typ, QUEUED);
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    // Wait until the server registered its handler.
+    let (typ, _) = session.recv_capsule().await.unwrap();
+    assert_eq!(typ, QUEUED);
+    session
+        .send_capsule(HANDLED, Bytes::from_static(b"ping"))
+        .await
+        .unwrap();
+    session.send_capsule(QUEUED, Bytes::new()).await.unwrap();
+    session.closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_messages() -> n0_error::Result<()> {
+    const ALPN: &str = "messages";
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let message = session.accept_uni_message(16).await.unwrap();
+        assert_eq!(message, b"hello".as_slice());
+        let (request, responder) = session.accept_bi_request(16).await.unwrap();
+        responder.respond(request).await.unwrap();
+        let err = session.accept_uni_message(16).await.unwrap_err();
+        assert!(matches!(err, MessageError::TooLong));
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish().unwrap();
+
+    let (mut send, mut recv) = session.open_bi().await.unwrap();
+    send.write_all(b"ping").await.unwrap();
+    send.finish().unwrap();
+    assert_eq!(recv.read_to_end(16).await.unwrap(), b"ping");
+
+    let mut send = session.open_uni().await.unwrap();
+    // The server stops the stream once the limit is exceeded.
+    send.write_all(&[0u8; 1024]).await.ok();
+    assert_eq!(send.stopped().await.unwrap(), Some(crate::MESSAGE_TOO_LONG));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_goaway() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let (mut send, mut recv) = session.accept_bi().await.unwrap();
+        session.goaway().await.unwrap();
+        assert!(session.is_going_away());
+        // Existing streams keep working, and the established session can open new ones.
+        let request = recv.read_to_end(16).await.unwrap();
+        send.write_all(&request).await.unwrap();
+        send.finish().unwrap();
+        let mut send = session.open_uni().await.unwrap();
+        send.write_all(b"late").await.unwrap();
+        send.finish().unwrap();
+        let mut recv = session.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(16).await.unwrap(), b"late");
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let (mut send, mut recv) = session.open_bi().await.unwrap();
+    send.write_all(b"ping").await.unwrap();
+    session.going_away().await;
+    send.finish().unwrap();
+    assert_eq!(recv.read_to_end(16).await.unwrap(), b"ping");
+    let mut recv = session.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(16).await.unwrap(), b"late");
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"late").await.unwrap();
+    send.finish().unwrap();
+    send.stopped().await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_lenient_protocol() -> n0_error::Result<()> {
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let options = HandshakeOptions::new().with_strictness(Strictness::Lenient);
+            let request = H3Request::accept_with_options(conn, &options)
+                .await
+                .unwrap();
+            // The client didn't offer any protocol.
+            let response = web_transport_proto::ConnectResponse::OK.with_protocol("foo");
+            let session = request.respond(response).await.unwrap();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let err = client.connect_h3(server_addr.clone(), url.clone()).await;
+    assert!(matches!(
+        err,
+        Err(ClientError::HttpError(crate::ConnectError::ProtocolMismatch(protocol))) if protocol == "foo"
+    ));
+    client.close().await;
+
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_strictness(Strictness::Lenient);
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert_eq!(session.protocol(), Some("foo"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_accept_events() -> n0_error::Result<()> {
+    const ALPN: &str = "events";
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let (mut uni, mut bi, mut datagram) = (false, false, false);
+        while !(uni && bi && datagram) {
+            match session.accept().await.unwrap() {
+                SessionEventKind::Uni(_) => uni = true,
+                SessionEventKind::Bi(_, _) => bi = true,
+                SessionEventKind::Datagram(data) => {
+                    assert_eq!(data, b"ping".as_slice());
+                    datagram = true;
+                }
+            }
+        }
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"uni").await.unwrap();
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"bi").await.unwrap();
+    // Raw QUIC datagrams have no session header, and the buffer is empty.
+    let sent = session
+        .send_datagram_tracked(Bytes::from_static(b"ping"))
+        .unwrap();
+    assert_eq!(sent.size(), 4);
+    assert!(!sent.may_have_displaced_older());
+    session.closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_datagram_quarter_stream_id() -> n0_error::Result<()> {
+    let client = Endpoint::bind().await.unwrap();
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        // Skip the stream the client opened first, so the CONNECT request is on stream 4.
+        let (_send, mut recv) = conn.accept_bi().await.unwrap();
+        assert_eq!(recv.read_to_end(16).await.unwrap(), b"skip");
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        // Read the raw datagram to check the prefix a spec-compliant peer would see.
+        let datagram = session.conn().read_datagram().await.unwrap();
+        let mut prefix = Vec::new();
+        VarInt::from_u32(1).encode(&mut prefix);
+        assert_eq!(datagram, [prefix.as_slice(), b"ping"].concat());
+        // Echo it back as written by a spec-compliant peer.
+        session.conn().send_datagram(datagram).unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let conn = client
+        .connect(server_addr, ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+    let (mut send, _recv) = conn.open_bi().await.unwrap();
+    send.write_all(b"skip").await.unwrap();
+    send.finish().unwrap();
+    let session = Session::connect_h3(conn, url).await.unwrap();
+    session.send_datagram(Bytes::from_static(b"ping")).unwrap();
+    assert_eq!(session.read_datagram().await.unwrap(), b"ping".as_slice());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_protocol() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.protocols, ["v1", "v2"]);
+        let response = web_transport_proto::ConnectResponse::OK.with_protocol("v2");
+        let session = request.respond(response).await.unwrap();
+        assert_eq!(session.protocol(), Some("v2"));
+        session.closed().await;
+        server.close().await;
+    });
+
+    let request = web_transport_proto::ConnectRequest::new(url)
+        .with_protocols(["v1".to_string(), "v2".to_string()]);
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    assert_eq!(session.protocol(), Some("v2"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_connect_headers() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo?room=1", server.id())
+        .parse()
+        .unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.uri().path_and_query().unwrap(), "/foo?room=1");
+        assert_eq!(request.headers()["x-room"], "lobby");
+        assert_eq!(
+            request.headers()[http::header::AUTHORIZATION],
+            "Bearer secret"
+        );
+        assert_eq!(
+            request.headers()[http::header::ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(request.protocols, ["chat", "chat-v0"]);
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server
+    });
+
+    let origin = Url::parse("https://example.com/app").unwrap();
+    let request = ConnectRequestBuilder::new(url)
+        .with_origin(&origin)
+        .with_protocols(["chat", "chat-v0"])
+        .with_header(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_static("Bearer secret"),
+        )
+        .with_header(
+            http::HeaderName::from_static("x-room"),
+            http::HeaderValue::from_static("lobby"),
+        );
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_handoff() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        session.handoff().await.unwrap();
+        assert!(session.is_going_away());
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.going_away().await;
+    session.draining().await;
+    session.close(0, b"reconnecting");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_for_each_bi() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"for-each";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let err = session
+            .for_each_bi(2, |mut send, mut recv| {
+                let running = running.clone();
+                let max_running = max_running.clone();
+                async move {
+                    let msg = recv.read_to_end(1024).await?;
+                    if msg == b"fail" {
+                        anyhow::bail!("bad request");
+                    }
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    send.write_all(&msg).await?;
+                    send.finish()?;
+                    anyhow::Ok(())
+                }
+            })
+            .await;
+        assert!(matches!(
+            err,
+            SessionError::ApplicationClosed { code: 0, .. }
+        ));
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"fail").await.unwrap();
+    send.finish().unwrap();
+
+    let requests = (0..5u8).map(|i| {
+        let session = session.clone();
+        async move {
+            let (mut send, mut recv) = session.open_bi().await.unwrap();
+            send.write_all(&[i]).await.unwrap();
+            send.finish().unwrap();
+            assert_eq!(recv.read_to_end(1024).await.unwrap(), [i]);
+        }
+    });
+    n0_future::join_all(requests).await;
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_uni_stream_types() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let (registered_tx, registered_rx) = tokio::sync::oneshot::channel();
+    let (dropped_tx, dropped_rx) = tokio::sync::oneshot::channel();
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let mut chat = session.uni_streams_of_type(1);
+        let mut unknown = session.unknown_uni_streams();
+        registered_tx.send(()).unwrap();
+
+        let mut recv = chat.accept().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello");
+        let (tag, mut recv) = unknown.accept().await.unwrap();
+        assert_eq!(tag, 7);
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"other");
+
+        // Without a receiver for unknown types, they are stopped.
+        drop(unknown);
+        dropped_tx.send(()).unwrap();
+        session.closed().await;
+        assert!(chat.accept().await.is_none());
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    registered_rx.await.unwrap();
+
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"\x01hello").await.unwrap();
+    send.finish().unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"\x07other").await.unwrap();
+    send.finish().unwrap();
+
+    dropped_rx.await.unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"\x09dropped").await.unwrap();
+    assert_eq!(
+        send.stopped().await.unwrap(),
+        Some(crate::UNKNOWN_STREAM_TYPE)
+    );
+
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_uni_stream_types_dropped() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let mut chat = session.uni_streams_of_type(1);
+        // The routing task doesn't keep the session alive.
+        drop(session);
+        assert!(chat.accept().await.is_none());
+        server
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), session.conn().closed())
+        .await
+        .expect("connection is closed once the server dropped the session");
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_response_headers() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+    // Long enough to need a multi-byte QPACK length prefix.
+    let token = "t".repeat(300);
+
+    let server_task = tokio::task::spawn({
+        let token = token.clone();
+        async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let request = H3Request::accept(conn).await.unwrap();
+            let mut headers = http::HeaderMap::new();
+            headers.insert("server", http::HeaderValue::from_static("wt-iroh"));
+            headers.insert("x-token", token.parse().unwrap());
+            let response = web_transport_proto::ConnectResponse::OK.with_protocol("v1");
+            let session = request
+                .respond_with_headers(response, headers)
+                .await
+                .unwrap();
+            assert_eq!(session.response_headers().unwrap()["server"], "wt-iroh");
+            session.closed().await;
+            server.close().await;
+        }
+    });
+
+    let request = web_transport_proto::ConnectRequest::new(url).with_protocol("v1");
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    let headers = session.response_headers().unwrap();
+    assert_eq!(headers["server"], "wt-iroh");
+    assert_eq!(headers["x-token"], token.as_str());
+    // The selected protocol is exposed separately, and the draft version is of no use.
+    assert!(!headers.contains_key("wt-protocol"));
+    assert!(!headers.contains_key("sec-webtransport-http3-draft"));
+    assert_eq!(session.protocol(), Some("v1"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_response_headers_huffman() -> n0_error::Result<()> {
+    use bytes::BufMut;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    // Other servers may use the static table and Huffman coding, which we never send.
+    let huffman = |value: &str| {
+        let mut encoded = Vec::new();
+        httlib_huffman::encode(value.as_bytes(), &mut encoded).unwrap();
+        encoded
+    };
+    let mut fields = vec![0, 0];
+    // Indexed field line for `:status: 200`.
+    fields.put_u8(0b1100_0000 | 25);
+    // Literal field line with a reference to the `server` name, and a Huffman-coded value.
+    let value = huffman("wt-iroh");
+    fields.put_slice(&[0b0101_1111, 92 - 15]);
+    fields.put_u8(0b1000_0000 | value.len() as u8);
+    fields.put_slice(&value);
+    // Literal field line with a Huffman-coded name and value.
+    let (name, value) = (huffman("x-room"), huffman("lobby"));
+    fields.put_u8(0b0010_1000 | name.len() as u8);
+    fields.put_slice(&name);
+    fields.put_u8(0b1000_0000 | value.len() as u8);
+    fields.put_slice(&value);
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let (_settings, (mut send, mut recv)) = tokio::try_join!(Settings::connect(&conn), async {
+            Ok(conn.accept_bi().await.unwrap())
+        })
+        .unwrap();
+        web_transport_proto::ConnectRequest::read(&mut recv)
+            .await
+            .unwrap();
+        let mut frame = Vec::new();
+        web_transport_proto::Frame::HEADERS.encode(&mut frame);
+        VarInt::from_u32(fields.len() as u32).encode(&mut frame);
+        frame.put_slice(&fields);
+        send.write_all(&frame).await.unwrap();
+        conn.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let headers = session.response_headers().unwrap();
+    assert_eq!(headers["server"], "wt-iroh");
+    assert_eq!(headers["x-room"], "lobby");
+    assert_eq!(headers.len(), 2);
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_reject_with() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn.clone()).await.unwrap();
+        let response = http::Response::builder()
+            .status(http::StatusCode::TOO_MANY_REQUESTS)
+            .header(http::header::RETRY_AFTER, "10")
+            .body(Bytes::from_static(br#"{"error":"busy"}"#))
+            .unwrap();
+        request.reject_with(response).await.unwrap();
+        conn.closed().await;
+        server.close().await;
+    });
+
+    let err = client.connect_h3(server_addr, url).await.unwrap_err();
+    let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[http::header::RETRY_AFTER], "10");
+    assert_eq!(response.body(), br#"{"error":"busy"}"#.as_slice());
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_batched_send() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"batched";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let (received_tx, received_rx) = tokio::sync::oneshot::channel();
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let mut recv = session.accept_uni().await.unwrap();
+
+        // Small writes are sent by the first write after the delay, without an explicit flush.
+        let mut buf = [0u8; 6];
+        recv.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"abcdef");
+        received_tx.send(()).unwrap();
+
+        // The rest is sent when the batch is full, and when the stream is dropped.
+        let rest = recv.read_to_end(4096).await.unwrap();
+        assert_eq!(rest.len(), 2001);
+        assert!(rest[..2000].iter().all(|&b| b == 1));
+        assert_eq!(rest[2000], 2);
+
+        // Once sending failed, small writes that are only buffered fail too.
+        let mut recv = session.accept_uni().await.unwrap();
+        recv.stop(7).unwrap();
+
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let send = session.open_uni().await.unwrap();
+    let mut send = send.batched(Batching::new(Duration::from_millis(20), 1024));
+    send.write_all(b"abc").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    send.write_chunk(Bytes::from_static(b"def")).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), received_rx)
+        .await
+        .expect("sent without a flush")
+        .unwrap();
+    send.write_all(&[1; 2000]).await.unwrap();
+    send.write_all(&[2]).await.unwrap();
+    drop(send);
+
+    let mut send = session
+        .open_uni()
+        .await
+        .unwrap()
+        .batched(Batching::new(Duration::from_secs(10), 1024));
+    let err = loop {
+        if let Err(err) = send.write_all(&[3; 1024]).await {
+            break err;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert!(matches!(err, crate::WriteError::Stopped(7)), "{err:?}");
+    let err = send.write_all(b"x").await.unwrap_err();
+    assert!(matches!(err, crate::WriteError::Stopped(7)), "{err:?}");
+    assert!(send.flush().await.is_err());
+    session.close(0, b"done");
+
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_congestion() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"congestion";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    // An idle connection isn't congested, and waiting for a change ends with the session.
+    assert!(!session.is_congested());
+    let changed = tokio::spawn({
+        let session = session.clone();
+        async move { session.congestion_changed().await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!session.is_congested());
+    session.close(0, b"done");
+    assert!(matches!(
+        changed.await.unwrap(),
+        Err(SessionError::ConnectionError(
+            iroh::endpoint::ConnectionError::LocallyClosed
+        ))
+    ));
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_http_conversions() -> n0_error::Result<()> {
+    let client = Endpoint::bind().await.unwrap();
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let uri = format!("https://{}/chat?room=1", server.id());
+
+    let server_task = tokio::task::spawn({
+        let uri = uri.clone();
+        async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let request = H3Request::accept(conn).await.unwrap();
+            let http = http::Request::from(&request);
+            assert_eq!(http.method(), http::Method::CONNECT);
+            assert_eq!(http.uri().to_string(), uri);
+            assert_eq!(http.headers()["x-room"], "lobby");
+            assert_eq!(
+                http.headers()["wt-available-protocols"],
+                r#""chat", "chat-v0""#
+            );
+            let response = web_transport_proto::ConnectResponse::OK.with_protocol("chat-v0");
+            let session = request.respond(response).await.unwrap();
+            session.closed().await;
+            server
+        }
+    });
+
+    let request = http::Request::builder()
+        .method(http::Method::CONNECT)
+        .uri(&uri)
+        .header("x-room", "lobby")
+        .header("wt-available-protocols", r#""chat", "chat-v0""#)
+        .body(())
+        .unwrap();
+    let request = ConnectRequestBuilder::try_from(request).unwrap().build();
+    assert_eq!(request.protocols, ["chat", "chat-v0"]);
+    assert!(!request.headers.contains_key("wt-available-protocols"));
+
+    let conn = client
+        .connect(server_addr, ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+    let settings = Settings::connect(&conn).await.unwrap();
+    let connected = Connected::open(&conn, request).await.unwrap();
+    let response = http::Response::from(&connected);
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.headers()["wt-protocol"], r#""chat-v0""#);
+    let session = Session::new_h3(conn, settings, connected);
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap().close().await;
+
+    let get = http::Request::get(&uri).body(()).unwrap();
+    assert!(ConnectRequestBuilder::try_from(get).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_shutdown() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let mut send = session.open_uni().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+
+        // The shutdown waits for the open stream to be finished.
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        let shutdown = tokio::spawn({
+            let session = session.clone();
+            async move { session.shutdown(7, "bye", deadline).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!shutdown.is_finished());
+        send.write_all(b" world").await.unwrap();
+        send.finish().unwrap();
+
+        assert!(shutdown.await.unwrap());
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut recv = session.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello world");
+    session.draining().await;
+    assert!(matches!(
+        session.closed().await,
+        SessionError::WebTransportError(WebTransportError::Closed { code: 7, .. })
+    ));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_shutdown() -> n0_error::Result<()> {
+    use iroh::endpoint::ConnectionError;
+
+    use crate::ShutdownPolicy;
+
+    const ALPN: &[u8] = b"shutdown";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec(), ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        // Neither shutdown waits for the peer, which keeps the connection open.
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let mut send = session.open_uni().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+        send.stopped().await.unwrap();
+        let shutdown = session.shutdown(7, "bye", deadline);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(2), shutdown)
+                .await
+                .unwrap()
+        );
+
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        // Wait until the client has the response, and keep a stream open.
+        let _recv = session.accept_uni().await.unwrap();
+        let _send = session.open_uni().await.unwrap();
+        let shutdown = session.shutdown_with_policy(7, "bye", deadline, ShutdownPolicy::Reset);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(2), shutdown)
+                .await
+                .unwrap()
+        );
+        server.close().await;
+    });
+
+    let session = client
+        .connect_quic(server_addr.clone(), ALPN)
+        .await
+        .unwrap();
+    let mut recv = session.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(16).await.unwrap(), b"hello");
+    let ConnectionError::ApplicationClosed(close) = session.conn().closed().await else {
+        panic!("expected an application close");
+    };
+    assert_eq!(close.error_code, 7u32.into());
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"ready").await.unwrap();
+    session.conn().closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_origin_policy() -> n0_error::Result<()> {
+    let policy = OriginPolicy::allow(["https://example.com", "https://*.example.org"]);
+    assert!(policy.is_allowed(Some("https://example.com")));
+    assert!(policy.is_allowed(Some("https://app.Example.org")));
+    assert!(!policy.is_allowed(Some("https://example.org")));
+    assert!(!policy.is_allowed(Some("https://evil.com/.example.org")));
+    assert!(!policy.is_allowed(Some("http://example.com")));
+    assert!(policy.is_allowed(None));
+    assert!(!policy.clone().with_allow_missing(false).is_allowed(None));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        // The first session has an allowed origin, the second doesn't.
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.origin(), Some("https://app.example.org"));
+        let session = request
+            .check_origin(&policy)
+            .await
+            .unwrap()
+            .ok()
+            .await
+            .unwrap();
+        session.closed().await;
+
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn.clone()).await.unwrap();
+        let err = request.check_origin(&policy).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ServerError::OriginRejected { origin: Some(origin) } if origin == "https://evil.com"
+        ));
+        conn.closed().await;
+        server.close().await;
+    });
+
+    let origin = Url::parse("https://app.example.org").unwrap();
+    let request = ConnectRequestBuilder::new(url.clone()).with_origin(&origin);
+    let session = client
+        .connect_h3(server_addr.clone(), request)
+        .await
+        .unwrap();
+    session.close(0, b"done");
+
+    let origin = Url::parse("https://evil.com").unwrap();
+    let request = ConnectRequestBuilder::new(url).with_origin(&origin);
+    let err = client.connect_h3(server_addr, request).await.unwrap_err();
+    let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(response.status(), ORIGIN_REJECTED);
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_origin_policy() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint)
+        .with_max_sessions(8)
+        .with_origin_policy(OriginPolicy::allow(["https://example.com"]));
+    let metrics = server.metrics().clone();
+
+    let server_task = tokio::task::spawn(async move {
+        // The first session is rejected without the application seeing it.
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert!(matches!(
+            err.source,
+            ServerError::OriginRejected { origin: Some(origin) } if origin == "https://evil.com"
+        ));
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.closed().await;
+        server
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let origin = Url::parse("https://evil.com").unwrap();
+    let request = ConnectRequestBuilder::new(url.clone()).with_origin(&origin);
+    let err = client
+        .connect_h3(server_addr.clone(), request)
+        .await
+        .unwrap_err();
+    let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(response.status(), ORIGIN_REJECTED);
+
+    let origin = Url::parse("https://example.com").unwrap();
+    let request = ConnectRequestBuilder::new(url).with_origin(&origin);
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    session.close(0, b"done");
+    let server = server_task.await.unwrap();
+    assert_eq!(metrics.handshakes_rejected(), 1);
+
+    client.close().await;
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_stream_counts() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"stream-counts";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let uni = session.accept_uni().await.unwrap();
+        let (send, recv) = session.accept_bi().await.unwrap();
+        let counts = session.stream_counts();
+        assert_eq!((counts.uni_remote, counts.bi_remote), (1, 1));
+        assert_eq!((counts.uni_local, counts.bi_local), (0, 0));
+
+        // A bidirectional stream is open until both halves are dropped.
+        drop(uni);
+        drop(send);
+        assert_eq!(session.stream_counts().uni_remote, 0);
+        assert_eq!(session.stream_counts().bi_remote, 1);
+        let changed = tokio::spawn({
+            let session = session.clone();
+            async move { session.stream_counts_changed().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(recv);
+        assert_eq!(changed.await.unwrap().unwrap(), StreamCounts::default());
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let mut uni = session.open_uni().await.unwrap();
+    uni.write_all(b"uni").await.unwrap();
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"bi").await.unwrap();
+    let counts = session.stream_counts();
+    assert_eq!((counts.uni_local, counts.bi_local), (1, 1));
+    assert_eq!((counts.uni_remote, counts.bi_remote), (0, 0));
+    session.closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_drop_warning() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"drop-warning";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let session = QuicRequest::accept(conn).ok();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    // Closing explicitly doesn't warn, dropping an open session does.
+    let session = client
+        .connect_quic(server_addr.clone(), ALPN)
+        .await
+        .unwrap();
+    session.close(0, b"done");
+    drop(session);
+    assert!(!logs_contain("session dropped while open"));
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let clone = session.clone();
+    drop(session);
+    assert!(!logs_contain("session dropped while open"));
+    drop(clone);
+    assert_eq!(
+        logs_contain("session dropped while open"),
+        cfg!(debug_assertions)
+    );
+
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_extensions() -> n0_error::Result<()> {
+    #[derive(Debug, Clone, PartialEq)]
+    struct Tenant(&'static str);
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut request = H3Request::accept(conn).await.unwrap();
+        request.extensions_mut().insert(Tenant("acme"));
+        assert_eq!(request.extensions().get(), Some(&Tenant("acme")));
+        let session = request.ok().await.unwrap();
+        assert_eq!(session.extensions().get(), Some(&Tenant("acme")));
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert!(session.extensions().is_empty());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_handshake_timeout() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"handshake-timeout";
+    let tuning = TransportTuning::default()
+        .with_initial_rtt(Duration::from_millis(100))
+        .with_handshake_timeout(Some(Duration::from_millis(200)));
+    let client = Client::with_tuning(Endpoint::bind().await.unwrap(), tuning);
+
+    // Nobody answers once the server is gone, so the handshake never completes.
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    server.close().await;
+
+    let err = client.connect_quic(server_addr, ALPN).await.unwrap_err();
+    assert!(matches!(err, ClientError::HandshakeTimeout), "{err:?}");
+    client.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_max_field_section_size() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_max_field_section_size(512);
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        // The first response is too large for the client.
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let options = HandshakeOptions::new().with_max_field_section_size(Some(1024));
+        let request = H3Request::accept_with_options(conn.clone(), &options)
+            .await
+            .unwrap();
+        assert_eq!(request.settings().max_field_section_size(), Some(1024));
+        assert_eq!(request.settings().peer_max_field_section_size(), Some(512));
+        let mut headers = http::HeaderMap::new();
+        headers.insert("x-large", "a".repeat(600).parse().unwrap());
+        let session = request
+            .respond_with_headers(http::StatusCode::OK, headers)
+            .await
+            .unwrap();
+        session.closed().await;
+
+        // The second request is too large for the server.
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let err = H3Request::accept_with_options(conn.clone(), &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ServerError::HttpError(ConnectError::FieldSectionTooLarge { max: 1024, .. })
+            ),
+            "{err:?}"
+        );
+        conn.closed().await;
+        server.close().await;
+    });
+
+    let err = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            ClientError::HttpError(ConnectError::FieldSectionTooLarge { max: 512, .. })
+        ),
+        "{err:?}"
+    );
+
+    let request = ConnectRequestBuilder::new(url).with_header(
+        http::HeaderName::from_static("x-large"),
+        "a".repeat(2000).parse().unwrap(),
+    );
+    let err = client.connect_h3(server_addr, request).await.unwrap_err();
+    let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(
+        response.status(),
+        http::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+    );
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_handshake_timeout() -> n0_error::Result<()> {
+    let timeout = Some(Duration::from_millis(300));
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_handshake_timeout(timeout);
+
+    // A server that completes the QUIC handshake but never sends SETTINGS.
+    let silent = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let silent_addr = silent.addr();
+    let url: Url = format!("https://{}/foo", silent.id()).parse().unwrap();
+    let silent_task = tokio::task::spawn(async move {
+        let conn = silent.accept().await.unwrap().await.unwrap();
+        conn.closed().await;
+        silent.close().await;
+    });
+
+    let err = client.connect_h3(silent_addr, url).await.unwrap_err();
+    assert!(matches!(err, ClientError::HandshakeTimeout), "{err:?}");
+    silent_task.await.unwrap();
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_handshake_timeout(timeout);
+    let server_task = tokio::task::spawn(async move {
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // A client that never sends SETTINGS is disconnected.
+    let raw = Endpoint::bind().await.unwrap();
+    let conn = raw
+        .connect(server_addr.clone(), ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+    let iroh::endpoint::ConnectionError::ApplicationClosed(close) = conn.closed().await else {
+        panic!("unexpected close reason: {:?}", conn.close_reason());
+    };
+    assert_eq!(close.error_code, 0x10du32.into());
+    raw.close().await;
+
+    // Other clients are still accepted.
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn error_kinds() -> n0_error::Result<()> {
+    // Binding a port that is already taken.
+    let first = Endpoint::builder()
+        .bind_addr("127.0.0.1:0")
+        .unwrap()
+        .bind()
+        .await
+        .unwrap();
+    let taken = first.bound_sockets()[0];
+    let err = Endpoint::builder()
+        .clear_ip_transports()
+        .bind_addr(taken)
+        .unwrap()
+        .bind()
+        .await
+        .unwrap_err();
+    let err = ServerError::Bind(Arc::new(err));
+    assert_eq!(err.kind(), ErrorKind::PortInUse);
+    assert!(err.kind().hint().contains("port"));
+    first.close().await;
+
+    // Connecting to a peer without any known address.
+    let client = Client::new(
+        Endpoint::empty_builder(iroh::RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap(),
+    );
+    let peer = iroh::SecretKey::from_bytes(&[7; 32]).public();
+    let err = client.connect_quic(peer, b"unknown").await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::DiscoveryNotConfigured, "{err:?}");
+    assert_eq!(client.diagnose(&err), ErrorKind::DiscoveryNotConfigured);
+
+    assert_eq!(ClientError::HandshakeTimeout.kind(), ErrorKind::TimedOut);
+    // Without a relay, a timeout most likely means the relay is unreachable.
+    assert_eq!(
+        client.diagnose(&ClientError::HandshakeTimeout),
+        ErrorKind::RelayUnreachable
+    );
+    client.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_session_driver() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let (settings, connecting) = tokio::try_join!(Settings::connect(&conn), async {
+            Ok(Connecting::accept(&conn).await.unwrap())
+        })
+        .unwrap();
+        let connected = connecting.respond(http::StatusCode::OK).await.unwrap();
+        let (session, driver) = Session::new_h3_with_driver(conn.clone(), settings, connected);
+        // Any runtime can drive the session, this test happens to use tokio.
+        let driver = tokio::task::spawn(driver);
+
+        let reason = session.closed().await;
+        assert!(matches!(
+            reason,
+            SessionError::WebTransportError(WebTransportError::Closed { code: 7, .. })
+        ));
+        // The connection is still open, but the driver stops along with the session.
+        drop(session);
+        tokio::time::timeout(Duration::from_secs(5), driver)
+            .await
+            .expect("driver stops once the session is dropped")
+            .unwrap();
+
+        conn.close(0u32.into(), b"bye");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close_session(7, "done").await.unwrap();
+    session.conn().closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_builder() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"builder/2";
+    let client = Client::builder()
+        .with_keep_alive_interval(Duration::from_secs(1))
+        .with_alpns([ALPN.to_vec()])
+        .with_header(
+            http::HeaderName::from_static("x-client"),
+            http::HeaderValue::from_static("default"),
+        )
+        .with_header(
+            http::HeaderName::from_static("x-version"),
+            http::HeaderValue::from_static("1"),
+        )
+        .build(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec(), ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        // The client offers an ALPN the server doesn't know first.
+        let conn = server.accept().await.unwrap().await.unwrap();
+        assert_eq!(conn.alpn(), ALPN);
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+
+        // Default headers are sent unless the request sets them.
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.headers()["x-client"], "default");
+        assert_eq!(request.headers()["x-version"], "2");
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client
+        .connect_quic(server_addr.clone(), b"builder/1")
+        .await
+        .unwrap();
+    assert_eq!(session.conn().alpn(), ALPN);
+    session.close(0, b"done");
+
+    let request = ConnectRequestBuilder::new(url).with_header(
+        http::HeaderName::from_static("x-version"),
+        http::HeaderValue::from_static("2"),
+    );
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_server_priority() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base = format!("https://{}", endpoint.id());
+    let mut server = Server::new(endpoint)
+        .with_priority(|request| request.url.path().starts_with("/admin").into());
+
+    // Sends the CONNECT request after a delay, to control when the handshakes complete.
+    let connect = |path: &str, delay: u64| {
+        let server_addr = server_addr.clone();
+        let url: Url = format!("{base}{path}").parse().unwrap();
+        tokio::task::spawn(async move {
+            let endpoint = Endpoint::bind().await.unwrap();
+            let conn = endpoint
+                .connect(server_addr, ALPN_H3.as_bytes())
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            let session = Session::connect_h3(conn, url).await.unwrap();
+            session.close(0, b"done");
+            endpoint.close().await;
+        })
+    };
+    let clients = [
+        connect("/first", 200),
+        connect("/normal", 600),
+        connect("/admin", 600),
+    ];
+
+    let first = server.accept().await.unwrap();
+    assert_eq!(first.url.path(), "/first");
+    // Both remaining handshakes complete while we are busy.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let second = server.accept().await.unwrap();
+    assert_eq!(second.url.path(), "/admin");
+    let third = server.accept().await.unwrap();
+    assert_eq!(third.url.path(), "/normal");
+
+    for request in [first, second, third] {
+        request.ok().await.unwrap().closed().await;
+    }
+    for client in clients {
+        client.await.unwrap();
+    }
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_server_max_ready() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base = format!("https://{}", endpoint.id());
+    let mut server = Server::new(endpoint)
+        .with_priority(|request| request.url.path().starts_with("/admin").into())
+        .with_max_ready(1);
+
+    // Sends the CONNECT request after a delay, like in `h3_server_priority`.
+    let connect = |path: &str, delay: u64| {
+        let server_addr = server_addr.clone();
+        let url: Url = format!("{base}{path}").parse().unwrap();
+        tokio::task::spawn(async move {
+            let endpoint = Endpoint::bind().await.unwrap();
+            let conn = endpoint
+                .connect(server_addr, ALPN_H3.as_bytes())
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            let result = Session::connect_h3(conn, url).await;
+            if let Ok(session) = &result {
+                session.close(0, b"done");
+            }
+            endpoint.close().await;
+            result.is_ok()
+        })
+    };
+    let first = connect("/first", 200);
+    let normal = connect("/normal", 600);
+    let admin = connect("/admin", 600);
+
+    let request = server.accept().await.unwrap();
+    assert_eq!(request.url.path(), "/first");
+    request.ok().await.unwrap().closed().await;
+    // Both remaining handshakes complete while we are busy, but only one may wait.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let request = server.accept().await.unwrap();
+    assert_eq!(request.url.path(), "/admin");
+    request.ok().await.unwrap().closed().await;
+
+    assert!(first.await.unwrap());
+    assert!(admin.await.unwrap());
+    assert!(
+        !normal.await.unwrap(),
+        "the lower priority request is dropped"
+    );
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_0rtt() -> n0_error::Result<()> {
+    let client = Client::builder()
+        .with_0rtt(true)
+        .build(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let request = H3Request::accept(conn).await.unwrap();
+            let session = request.ok().await.unwrap();
+            let (mut send, mut recv) = session.accept_bi().await.unwrap();
+            let msg = recv.read_to_end(1024).await.unwrap();
+            send.write_all(&msg).await.unwrap();
+            send.finish().unwrap();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    // Without a session ticket the first connection uses a full handshake.
+    for zero_rtt in [false, true] {
+        let session = client
+            .connect_h3(server_addr.clone(), url.clone())
+            .await
+            .unwrap();
+        assert_eq!(session.is_0rtt(), zero_rtt);
+        let (mut send, mut recv) = session.open_bi().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"hello");
+        session.close(0, b"done");
+    }
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_settings_profile() -> n0_error::Result<()> {
+    use crate::{
+        SettingsProfile,
+        proto::{Setting, VarInt},
+    };
+
+    // Like an embedded stack that sends an explicit QPACK table size but no datagram setting.
+    let profile = SettingsProfile::minimal()
+        .with_setting(Setting::QPACK_MAX_TABLE_CAPACITY, VarInt::from_u32(0))
+        .without_setting(Setting::ENABLE_DATAGRAM);
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_settings_profile(profile);
+
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept().await.unwrap();
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // The default profile requires the server to enable datagrams.
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let err = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            ClientError::SettingsError(SettingsError::WebTransportUnsupported)
+        ),
+        "{err:?}"
+    );
+
+    let client = Client::new(Endpoint::bind().await.unwrap())
+        .with_settings_profile(SettingsProfile::minimal().with_datagram_required(false));
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert!(logs_contain("QPACK_MAX_TABLE_CAPACITY: 0"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_grease() -> n0_error::Result<()> {
+    use crate::SettingsProfile;
+
+    let profile = SettingsProfile::new().with_grease(true);
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint)
+        .with_strictness(Strictness::Strict)
+        .with_settings_profile(profile.clone());
+
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept().await.unwrap();
+        let session = request.ok().await.unwrap();
+        session.goaway().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // The reserved setting and frame are ignored, even when strict.
+    let client = Client::new(Endpoint::bind().await.unwrap())
+        .with_strictness(Strictness::Strict)
+        .with_settings_profile(profile);
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert!(logs_contain("GREASE SETTING"));
+    // A GOAWAY after the reserved frame is still read.
+    session.going_away().await;
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_connect_request() -> n0_error::Result<()> {
+    let client = Endpoint::bind().await.unwrap();
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.origin(), Some("https://example.com"));
+        assert_eq!(request.headers()["x-token"], "secret");
+        assert_eq!(request.request().protocols, ["chat", "echo"]);
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // Wire up the session on a connection we established ourselves.
+    let conn = client
+        .connect(server_addr, ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+    let request = ConnectRequestBuilder::new(url)
+        .with_protocols(["chat", "echo"])
+        .with_header(
+            http::HeaderName::from_static("x-token"),
+            http::HeaderValue::from_static("secret"),
+        )
+        .with_origin(&"https://example.com/app".parse().unwrap())
+        .build();
+    let session = Session::connect_h3(conn, request).await.unwrap();
+    assert_eq!(session.request().unwrap().protocols, ["chat", "echo"]);
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_pool() -> n0_error::Result<()> {
+    use crate::PoolConfig;
+
+    const ALPN: &[u8] = b"pool/1";
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_pool(
+        PoolConfig::new()
+            .with_max_idle(1)
+            .with_idle_timeout(Duration::from_millis(300)),
+    );
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec(), ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    // Counts the connections the server accepted.
+    let server_task = tokio::task::spawn(async move {
+        let mut sessions = Vec::new();
+        while let Some(incoming) = server.accept().await {
+            let Ok(conn) = incoming.await else { continue };
+            let session = if conn.alpn() == ALPN {
+                QuicRequest::accept(conn).ok()
+            } else {
+                H3Request::accept(conn).await.unwrap().ok().await.unwrap()
+            };
+            sessions.push(session);
+            if sessions.len() == 4 {
+                break;
+            }
+        }
+        for session in &sessions {
+            session.closed().await;
+        }
+        server.close().await;
+        sessions.len()
+    });
+
+    // A released session is reused for the same ALPN.
+    let session = client
+        .connect_quic(server_addr.clone(), ALPN)
+        .await
+        .unwrap();
+    let id = session.conn().stable_id();
+    client.release(session);
+    let session = client
+        .connect_quic(server_addr.clone(), ALPN)
+        .await
+        .unwrap();
+    assert_eq!(session.conn().stable_id(), id);
+
+    // Only one idle session is kept, so releasing another closes the first.
+    let h3 = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    let h3_id = h3.conn().stable_id();
+    client.release(session.clone());
+    client.release(h3);
+    assert!(session.conn().close_reason().is_some());
+
+    // HTTP/3 sessions are reused for the same request, until they were idle for too long.
+    let h3 = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_eq!(h3.conn().stable_id(), h3_id);
+    client.release(h3);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let h3 = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_ne!(h3.conn().stable_id(), h3_id);
+
+    // A different request gets its own session.
+    client.release(h3.clone());
+    let other: Url = format!("https://{}/bar", server_addr.id).parse().unwrap();
+    let session = client.connect_h3(server_addr, other).await.unwrap();
+    assert_ne!(session.conn().stable_id(), h3.conn().stable_id());
+
+    client.close().await;
+    assert_eq!(server_task.await.unwrap(), 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_pool_session_closed() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_pool(Default::default());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    // Closes the first session and drains the second, keeping their connections open.
+    let server_task = tokio::task::spawn(async move {
+        let mut sessions = Vec::new();
+        for i in 0..3 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+            match i {
+                0 => session.close_session(0, "bye").await.unwrap(),
+                1 => session.drain().await.unwrap(),
+                _ => {}
+            }
+            sessions.push(session);
+        }
+        for session in &sessions {
+            session.conn().closed().await;
+        }
+        server.close().await;
+    });
+
+    let session = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    client.release(session.clone());
+    session.closed().await;
+    let drained = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_ne!(drained.conn().stable_id(), session.conn().stable_id());
+    assert!(session.conn().close_reason().is_some());
+
+    client.release(drained.clone());
+    drained.draining().await;
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert_ne!(session.conn().stable_id(), drained.conn().stable_id());
+    assert!(drained.conn().close_reason().is_some());
+
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn latency_injection() -> n0_error::Result<()> {
+    use web_transport_trait::{SendStream as _, Session as _};
+
+    use crate::{Delayed, LatencyPolicy};
+
+    const ALPN: &[u8] = b"latency/1";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        // Echo each stream, without delay.
+        while let Ok((mut send, mut recv)) = session.accept_bi().await {
+            let msg = recv.read_to_end(16).await.unwrap();
+            send.write_all(&msg).await.unwrap();
+            send.finish().unwrap();
+        }
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let policy = LatencyPolicy::new();
+    let delayed = Delayed::new(session.clone(), policy.clone());
+
+    let echo = async |delayed: &Delayed<Session>| {
+        let start = tokio::time::Instant::now();
+        let (mut send, mut recv) = delayed.open_bi().await.unwrap();
+        send.write_all(b"ping").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(recv.read_to_end(16).await.unwrap(), b"ping");
+        start.elapsed()
+    };
+
+    policy.set(Duration::from_millis(300), Duration::from_millis(50));
+    assert!(policy.is_enabled());
+    assert!(echo(&delayed).await >= Duration::from_millis(300));
+
+    // The delay can be turned off at runtime.
+    policy.disable();
+    assert!(echo(&delayed).await < Duration::from_millis(300));
+
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_stream_group() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+
+        // Respond to the request on uni streams that inherit its priority.
+        let (send, mut recv) = session.accept_bi().await.unwrap();
+        send.set_priority(7).unwrap();
+        let group = session.stream_group_for(&send);
+        assert_eq!(group.priority(), 7);
+        let request = recv.read_to_end(16).await.unwrap();
+        let mut response = group.open_uni().await.unwrap();
+        assert_eq!(response.priority().unwrap(), 7);
+        response.write_all(&request).await.unwrap();
+        response.finish().unwrap();
+
+        // Later streams pick up a changed priority.
+        group.set_priority(-1);
+        let (other, _) = group.open_bi().await.unwrap();
+        assert_eq!(other.priority().unwrap(), -1);
+        assert_eq!(response.priority().unwrap(), 7);
+
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"ping").await.unwrap();
+    send.finish().unwrap();
+    let mut response = session.accept_uni().await.unwrap();
+    assert_eq!(response.read_to_end(16).await.unwrap(), b"ping");
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_retry() -> n0_error::Result<()> {
+    use crate::RetryPolicy;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    // The server is too slow for the first attempts.
+    let server_task = tokio::task::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // Without retries, the handshake timeout is final.
+    let client = Client::new(Endpoint::bind().await.unwrap())
+        .with_handshake_timeout(Some(Duration::from_millis(100)));
+    let err = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::HandshakeTimeout), "{err:?}");
+
+    let client = client.with_retry(
+        RetryPolicy::new()
+            .with_max_attempts(10)
+            .with_backoff(Duration::from_millis(50), Duration::from_millis(100)),
+    );
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert!(logs_contain("failed to connect, retrying"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_settings_dialect() -> n0_error::Result<()> {
+    use crate::{SettingVariant, SettingsDialect, SettingsProfile, proto::Setting};
+
+    let profiles = [
+        SettingsProfile::new(),
+        SettingsProfile::minimal(),
+        // Like a peer that predates draft-07 and RFC 9297.
+        SettingsProfile::new()
+            .without_setting(Setting::WEBTRANSPORT_MAX_SESSIONS)
+            .without_setting(Setting::ENABLE_DATAGRAM),
+    ];
+    let expected = [
+        (SettingVariant::Both, false),
+        (SettingVariant::Current, false),
+        (SettingVariant::Legacy, true),
+    ];
+
+    for (profile, (variant, legacy)) in profiles.into_iter().zip(expected) {
+        let endpoint = Endpoint::builder()
+            .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+            .bind()
+            .await
+            .unwrap();
+        let server_addr = endpoint.addr();
+        let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+        let mut server = Server::new(endpoint).with_settings_profile(profile);
+
+        let server_task = tokio::task::spawn(async move {
+            let request = server.accept().await.unwrap();
+            // The client uses the default profile.
+            let dialect = request.settings().peer_dialect();
+            assert_eq!(dialect.webtransport, SettingVariant::Both);
+            let session = request.ok().await.unwrap();
+            session.closed().await;
+            server.close().await;
+        });
+
+        let client = Client::new(Endpoint::bind().await.unwrap());
+        let session = client.connect_h3(server_addr, url).await.unwrap();
+        let dialect = session.settings().unwrap().peer_dialect();
+        assert_eq!(
+            dialect,
+            SettingsDialect {
+                webtransport: variant,
+                datagram: variant,
+            }
+        );
+        assert_eq!(dialect.uses_legacy(), legacy);
+        session.close(0, b"done");
+        client.close().await;
+
+        server_task.await.unwrap();
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn datagram_size_advisor() -> n0_error::Result<()> {
+    use iroh::endpoint::PathStats;
+
+    use crate::advisor::DatagramAdvisor;
+
+    const ALPN: &[u8] = b"advisor/1";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // Without loss, the recommendation is the maximum for the path.
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let max = session.max_datagram_size();
+    session.send_datagram(Bytes::from(vec![0u8; max])).unwrap();
+    assert_eq!(session.recommended_datagram_size(), max);
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    // Loss while sending large datagrams shrinks the recommendation, and it recovers without loss.
+    let advisor = DatagramAdvisor::default();
+    let mut stats = PathStats::default();
+    assert_eq!(advisor.recommend(1400, Some(stats)), 1400);
+    advisor.sent(1400);
+    stats.udp_tx.datagrams = 100;
+    stats.lost_packets = 10;
+    let shrunk = advisor.recommend(1400, Some(stats));
+    assert!(shrunk < 1400, "{shrunk}");
+    // Small datagrams aren't affected by the loss.
+    stats.udp_tx.datagrams = 200;
+    stats.lost_packets = 20;
+    assert_eq!(advisor.recommend(1400, Some(stats)), shrunk);
+    stats.udp_tx.datagrams = 300;
+    assert!(advisor.recommend(1400, Some(stats)) > shrunk);
+
+    // A black hole drops the recommendation to the size every path carries.
+    stats.black_holes_detected = 1;
+    let floor = advisor.recommend(1400, Some(stats));
+    assert!(floor < shrunk && floor >= 1100, "{floor}");
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_connect_any() -> n0_error::Result<()> {
+    // Never accepts, so the handshake stalls.
+    let stalled = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let server_id = endpoint.id();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..3 {
+            let session = server.accept().await.unwrap().ok().await.unwrap();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    // By default, the stalled candidate doesn't delay the others.
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.connect_h3_any([stalled.addr(), server_addr.clone()], url.clone()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(session.conn().remote_id(), server_id);
+    session.close(0, b"done");
+    client.close().await;
+
+    let client =
+        Client::new(Endpoint::bind().await.unwrap()).with_stagger(Duration::from_millis(50));
+    let err = client
+        .connect_h3_any(Vec::<iroh::EndpointAddr>::new(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::NoAddresses), "{err:?}");
+
+    let session = client
+        .connect_h3_any([stalled.addr(), server_addr.clone()], url.clone())
+        .await
+        .unwrap();
+    assert_eq!(session.conn().remote_id(), server_id);
+    session.close(0, b"done");
+    client.close().await;
+
+    // Dial all candidates at once, with the server listed twice, despite the long stagger.
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_stagger(Duration::from_secs(60));
+    let session = client
+        .connect_any(
+            [stalled.addr(), server_addr.clone(), server_addr.clone()],
+            url,
+        )
+        .await
+        .unwrap();
+    assert_eq!(session.conn().remote_id(), server_id);
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+    stalled.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_connect_url() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let mut server = Server::new(endpoint);
+
+    let mut url: Url = format!("https://{}/foo?x=1", server_addr.id)
+        .parse()
+        .unwrap();
+    for relay in server_addr.relay_urls() {
+        url.query_pairs_mut().append_pair("relay", relay.as_str());
+    }
+    for addr in server_addr.ip_addrs() {
+        url.query_pairs_mut().append_pair("addr", &addr.to_string());
+    }
+
+    let expected: Url = format!("https://{}/foo?x=1", server_addr.id)
+        .parse()
+        .unwrap();
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept().await.unwrap();
+        assert_eq!(request.request().url, expected);
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let err = client
+        .connect_url("file:///foo".parse().unwrap())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::InvalidUrl), "{err:?}");
+
+    // Domain names are resolved through DNS, which fails for the reserved TLD.
+    let err = client
+        .connect_url("https://endpoint.invalid/foo".parse().unwrap())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Resolve(_)), "{err:?}");
+
+    let session = client.connect_url(url).await.unwrap();
+    assert_eq!(session.conn().remote_id(), server_addr.id);
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_empty_payloads() -> n0_error::Result<()> {
+    use crate::EmptyPayload;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+
+        // Empty streams are delivered and read as empty.
+        let mut recv = session.accept_uni().await.unwrap();
+        assert!(recv.read_to_end(16).await.unwrap().is_empty());
+        let (mut send, mut recv) = session.accept_bi().await.unwrap();
+        assert!(recv.read_to_end(16).await.unwrap().is_empty());
+        send.finish().unwrap();
+
+        // Empty datagrams are delivered by default.
+        assert_eq!(session.read_datagram().await.unwrap(), Bytes::new());
+        assert_eq!(session.read_datagram().await.unwrap(), "a");
+
+        session.set_empty_datagrams(EmptyPayload::Suppress);
+        let mut send = session.open_uni().await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(session.read_datagram().await.unwrap(), "b");
+
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.finish().unwrap();
+    let (mut send, mut recv) = session.open_bi().await.unwrap();
+    send.finish().unwrap();
+    assert!(recv.read_to_end(16).await.unwrap().is_empty());
+
+    session.send_datagram(Bytes::new()).unwrap();
+    session.send_datagram(Bytes::from_static(b"a")).unwrap();
+
+    // Wait until the server suppresses empty datagrams.
+    session.accept_uni().await.unwrap();
+    session.send_datagram(Bytes::new()).unwrap();
+    session.send_datagram(Bytes::from_static(b"b")).unwrap();
+
+    session.closed().await;
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_bulk_stream() -> n0_error::Result<()> {
+    use crate::BulkConfig;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        for size in [64 * 1024, 128 * 1024] {
+            let mut recv = session.accept_uni().await.unwrap();
+            let data = recv.read_to_end(1 << 20).await.unwrap();
+            assert_eq!(data.len(), size);
+        }
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut send = session.open_uni_bulk().await.unwrap();
+    send.write_all(&[1u8; 64 * 1024]).await.unwrap();
+    send.finish().unwrap();
+    assert_eq!(send.into_inner().priority().unwrap(), i32::MIN);
+
+    // Pacing spreads the writes over time.
+    let start = tokio::time::Instant::now();
+    let mut send = session
+        .open_uni_bulk_with_config(BulkConfig::new().with_pacing(256 * 1024))
+        .await
+        .unwrap();
+    for _ in 0..4 {
+        send.write_chunk(Bytes::from(vec![2u8; 16 * 1024]))
+            .await
+            .unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(240));
+
+    // Idle time only builds up a bounded burst.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let start = tokio::time::Instant::now();
+    for _ in 0..4 {
+        send.write_all(&[3u8; 16 * 1024]).await.unwrap();
+    }
+    send.finish().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(140));
+
+    send.stopped().await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[cfg(feature = "tickets")]
+#[tokio::test]
+#[traced_test]
+async fn client_connect_ticket() -> n0_error::Result<()> {
+    use iroh_tickets::endpoint::EndpointTicket;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let ticket = EndpointTicket::new(server.addr());
+    let server_id = server.id();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        assert_eq!(request.url.path(), "/chat");
+        assert_eq!(request.protocols, ["v1"]);
+        let response = web_transport_proto::ConnectResponse::OK.with_protocol("v1");
+        let session = request.respond(response).await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let err = client.connect_ticket("endpointnope").await.unwrap_err();
+    assert!(matches!(err, ClientError::InvalidTicket(_)), "{err:?}");
+
+    let session = client
+        .connect_ticket(&format!("{ticket}/chat#v1"))
+        .await
+        .unwrap();
+    assert_eq!(session.conn().remote_id(), server_id);
+    assert_eq!(session.protocol(), Some("v1"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_message_timeout() -> n0_error::Result<()> {
+    use crate::{MESSAGE_TIMED_OUT, Message, PartialPolicy, ReadError};
+
+    const ALPN: &str = "messages";
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let message = session
+            .accept_uni_message_with_timeout(16, TIMEOUT, PartialPolicy::Discard)
+            .await
+            .unwrap();
+        assert_eq!(
+            message,
+            Message {
+                data: Bytes::from_static(b"hello"),
+                complete: true
+            }
+        );
+        let message = session
+            .accept_uni_message_with_timeout(16, TIMEOUT, PartialPolicy::Deliver)
+            .await
+            .unwrap();
+        assert_eq!(message.data, "hel");
+        assert!(!message.complete);
+        let err = session
+            .accept_bi_request_with_timeout(16, TIMEOUT, PartialPolicy::Reset)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MessageError::TimedOut));
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish().unwrap();
+
+    // The rest of the message never arrives.
+    let mut send = session.open_uni().await.unwrap();
+    send.write_all(b"hel").await.unwrap();
+    assert_eq!(send.stopped().await.unwrap(), Some(MESSAGE_TIMED_OUT));
+
+    let (mut send, mut recv) = session.open_bi().await.unwrap();
+    send.write_all(b"pi").await.unwrap();
+    assert_eq!(send.stopped().await.unwrap(), Some(MESSAGE_TIMED_OUT));
+    let err = recv.read_to_end(16).await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            crate::ReadToEndError::ReadError(ReadError::Reset(MESSAGE_TIMED_OUT))
+        ),
+        "{err:?}"
+    );
+
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_connect_cancel() -> n0_error::Result<()> {
+    use tokio_util::sync::CancellationToken;
+
+    // Never accepts, so the handshake stalls.
+    let stalled = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let url: Url = format!("https://{}/foo", stalled.id()).parse().unwrap();
+
+    let client = Client::new(Endpoint::bind().await.unwrap()).with_handshake_timeout(None);
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        }
+    });
+    let err = client
+        .connect_h3_with_cancel(stalled.addr(), url.clone(), &cancel)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Cancelled), "{err:?}");
+
+    let client = client.with_connect_timeout(Some(Duration::from_millis(100)));
+    let err = client
+        .connect_quic(stalled.addr(), ALPN_H3.as_bytes())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::HandshakeTimeout), "{err:?}");
+
+    client.close().await;
+    stalled.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_request_hook() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base: Url = format!("https://{}", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept().await.unwrap();
+        assert_eq!(request.url.path(), "/v2/foo");
+        assert_eq!(request.headers()["x-trace"], "abc");
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::builder()
+        .with_request_hook(|_, request| {
+            request
+                .headers
+                .insert("x-trace", http::HeaderValue::from_static("abc"));
+            let path = format!("/v2{}", request.url.path());
+            request.url.set_path(&path);
+            Ok(())
+        })
+        .with_request_hook(|_, request| match request.url.path() {
+            "/v2/forbidden" => Err("forbidden path".to_string()),
+            _ => Ok(()),
+        })
+        .build(Endpoint::bind().await.unwrap());
+
+    let err = client
+        .connect_h3(server_addr.clone(), base.join("/forbidden").unwrap())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Vetoed(ref reason) if reason == "forbidden path"));
+
+    let session = client
+        .connect_h3(server_addr, base.join("/foo").unwrap())
+        .await
+        .unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_idle_timeout() -> n0_error::Result<()> {
+    const ALPN: &str = "idle";
+
+    // Neither side sends keep-alives unless the client is told to.
+    let quiet = TransportTuning::new().with_keep_alive_interval(Duration::from_secs(3600));
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .transport_config(quiet.transport_config())
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            tokio::spawn(async move { conn.closed().await });
+        }
+        server
+    });
+
+    let client = Client::builder()
+        .with_tuning(quiet)
+        .with_max_idle_timeout(Some(Duration::from_millis(300)))
+        .build(Endpoint::bind().await.unwrap());
+    let session = client
+        .connect_quic(server_addr.clone(), ALPN.as_bytes())
+        .await
+        .unwrap();
+    let err = tokio::time::timeout(Duration::from_secs(5), session.closed())
+        .await
+        .unwrap();
+    assert!(
+        matches!(
+            err,
+            SessionError::ConnectionError(iroh::endpoint::ConnectionError::TimedOut)
+        ),
+        "{err:?}"
+    );
+    client.close().await;
+
+    // Keep-alives prevent the idle timeout.
+    let client = Client::builder()
+        .with_tuning(quiet)
+        .with_keep_alive_interval(Duration::from_millis(50))
+        .with_max_idle_timeout(Some(Duration::from_millis(300)))
+        .build(Endpoint::bind().await.unwrap());
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(900)).await;
+    assert!(session.close_reason().is_none());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_resolver() -> n0_error::Result<()> {
+    use crate::StaticResolver;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let mut server = Server::new(endpoint);
+
+    let url: Url = "https://chat.example/room".parse().unwrap();
+    let server_task = tokio::task::spawn({
+        let url = url.clone();
+        async move {
+            let request = server.accept().await.unwrap();
+            assert_eq!(request.url, url);
+            let session = request.ok().await.unwrap();
+            session.closed().await;
+            server.close().await;
+        }
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap())
+        .with_resolver(StaticResolver::new().with_host("Chat.Example", server_addr.clone()));
+    let session = client.connect_url(url).await.unwrap();
+    assert_eq!(session.conn().remote_id(), server_addr.id);
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_path_mode() -> n0_error::Result<()> {
+    use crate::PathMode;
+    use iroh::RelayMode;
+
+    const ALPN: &str = "path";
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        conn.closed().await;
+        server
+    });
+
+    // Without a relay, a relayed path is impossible.
+    let endpoint = Endpoint::empty_builder(RelayMode::Disabled)
+        .bind()
+        .await
+        .unwrap();
+    let client = Client::new(endpoint).with_path_mode(PathMode::Relay);
+    let err = client
+        .connect_quic(server_addr.clone(), ALPN.as_bytes())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, ClientError::PathUnavailable(PathMode::Relay)),
+        "{err:?}"
+    );
+    assert_eq!(err.kind(), ErrorKind::RelayUnreachable);
+
+    let client = client.with_path_mode(PathMode::Direct {
+        timeout: Duration::from_secs(5),
+    });
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    let path = session.conn().to_info().selected_path().unwrap();
+    assert!(path.is_ip());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_affinity_key() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/room", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    let server_task = tokio::task::spawn(async move {
+        for expected in ["user-1", "user-2"] {
+            let request = server.accept().await.unwrap();
+            assert_eq!(request.affinity_key(), Some(expected));
+            let session = request.ok().await.unwrap();
+            assert_eq!(session.affinity_key(), Some(expected));
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    let client = Client::builder()
+        .with_affinity_key(http::HeaderValue::from_static("user-1"))
+        .build(Endpoint::bind().await.unwrap());
+
+    let session = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_eq!(session.affinity_key(), Some("user-1"));
+    session.close(0, b"done");
+
+    // A key set on the request takes precedence over the client's.
+    let request =
+        ConnectRequestBuilder::new(url).with_affinity_key(http::HeaderValue::from_static("user-2"));
+    let session = client.connect_h3(server_addr, request).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_admission() -> n0_error::Result<()> {
+    use n0_future::StreamExt;
+    use tokio::sync::Semaphore;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/", endpoint.id()).parse().unwrap();
+    let permits = Arc::new(Semaphore::new(1));
+    let server = Server::new(endpoint).with_admission(permits.clone());
+
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let client = Client::new(Endpoint::bind().await.unwrap());
+        let (addr, url) = (server_addr.clone(), url.clone());
+        clients.push(tokio::spawn(async move {
+            let session = client.connect_h3(addr, url).await.unwrap();
+            session.close(0, b"done");
+            client.close().await;
+        }));
+    }
+
+    let mut requests = std::pin::pin!(server.into_stream());
+    let mut first = requests.next().await.unwrap();
+    assert_eq!(permits.available_permits(), 0);
+
+    // The second connection isn't admitted while the first request holds the permit.
+    let pending = tokio::time::timeout(Duration::from_millis(500), requests.next()).await;
+    assert!(pending.is_err());
+
+    // Keep the permit for the lifetime of the first session.
+    let permit = first.take_permit().unwrap();
+    let first = first.ok().await.unwrap();
+    assert_eq!(permits.available_permits(), 0);
+    drop(permit);
+
+    let second = requests.next().await.unwrap().ok().await.unwrap();
+    assert_eq!(permits.available_permits(), 1);
+
+    first.closed().await;
+    second.closed().await;
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "apps")]
+#[tokio::test]
+#[traced_test]
+async fn apps_echo_chat_file_drop() -> n0_error::Result<()> {
+    use crate::apps::{self, ChatRoom, FileDropError};
+
+    const ALPN: &str = "apps";
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let room = ChatRoom::default();
+
+    // The first session is echoed, the next two join the chat room, the last drops a file.
+    let server_task = tokio::task::spawn({
+        let room = room.clone();
+        async move {
+            let accept = async || {
+                let conn = server.accept().await.unwrap().await.unwrap();
+                QuicRequest::accept(conn).ok()
+            };
+            let echo = tokio::spawn(apps::echo(accept().await));
+            let alice = tokio::spawn({
+                let (room, session) = (room.clone(), accept().await);
+                async move { room.join(session).await }
+            });
+            let bob = tokio::spawn({
+                let (room, session) = (room.clone(), accept().await);
+                async move { room.join(session).await }
+            });
+
+            let session = accept().await;
+            let file = apps::accept_file(&session).await.unwrap();
+            assert_eq!(file.name, "notes.txt");
+            let mut stream = file.stream;
+            assert_eq!(stream.read_to_end(1024).await.unwrap(), b"hello file");
+            session.close(0, b"done");
+
+            for task in [echo, alice, bob] {
+                task.await.unwrap();
+            }
+            server.close().await;
+        }
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let connect = async || {
+        client
+            .connect_quic(server_addr.clone(), ALPN.as_bytes())
+            .await
+            .unwrap()
+    };
+
+    let echo = connect().await;
+    let (mut send, mut recv) = echo.open_bi().await.unwrap();
+    send.write_all(b"ping").await.unwrap();
+    send.finish().unwrap();
+    assert_eq!(recv.read_to_end(1024).await.unwrap(), b"ping");
+    echo.close(0, b"done");
+
+    let alice = connect().await;
+    let bob = connect().await;
+    while room.members() < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    apps::send_chat(&alice, Bytes::from_static(b"hi bob"))
+        .await
+        .unwrap();
+    assert_eq!(apps::recv_chat(&bob).await.unwrap(), "hi bob");
+    apps::send_chat(&bob, Bytes::from_static(b"hi alice"))
+        .await
+        .unwrap();
+    assert_eq!(apps::recv_chat(&alice).await.unwrap(), "hi alice");
+    alice.close(0, b"done");
+    bob.close(0, b"done");
+
+    let sender = connect().await;
+    let err = apps::send_file(&sender, "../escape", &b""[..])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, FileDropError::InvalidName), "{err:?}");
+    let sent = apps::send_file(&sender, "notes.txt", &b"hello file"[..])
+        .await
+        .unwrap();
+    assert_eq!(sent, 10);
+    sender.closed().await;
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_connect_auto() -> n0_error::Result<()> {
+    const ALPN: &str = "raw";
+
+    let raw = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let raw_addr = raw.addr();
+    let raw_task = tokio::task::spawn(async move {
+        let conn = raw.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        raw.close().await;
+    });
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let h3_addr = endpoint.addr();
+    let mut server = Server::new(endpoint);
+    let h3_task = tokio::task::spawn(async move {
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let url: Url = format!("https://{}/foo", raw_addr.id).parse().unwrap();
+    let session = client
+        .connect_auto(raw_addr, url, ALPN.as_bytes())
+        .await
+        .unwrap();
+    assert!(session.request().is_none());
+    assert_eq!(session.conn().alpn(), ALPN.as_bytes());
+    session.close(0, b"done");
+
+    let url: Url = format!("https://{}/foo", h3_addr.id).parse().unwrap();
+    let session = client
+        .connect_auto(h3_addr, url.clone(), ALPN.as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(session.request().map(|r| &r.url), Some(&url));
+    session.close(0, b"done");
+    client.close().await;
+
+    raw_task.await.unwrap();
+    h3_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn instrumented_rates() -> n0_error::Result<()> {
+    use web_transport_trait::Session as _;
+
+    use crate::Instrumented;
+
+    const ALPN: &str = "rates";
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    let session = Instrumented::new(session);
+    assert_eq!(
+        session.metrics().rates(Duration::from_secs(1)),
+        Default::default()
+    );
+
+    for _ in 0..10 {
+        session
+            .send_datagram(Bytes::from_static(b"0123456789"))
+            .unwrap();
+    }
+    session.open_uni().await.unwrap();
+
+    // Wait for the second the events were counted in to complete.
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let rates = session.metrics().rates(Duration::from_secs(60));
+    assert!(rates.window >= Duration::from_secs(1), "{rates:?}");
+    assert!(rates.window < Duration::from_secs(60), "{rates:?}");
+    let seconds = rates.window.as_secs_f64();
+    assert_eq!((rates.datagrams_sent * seconds).round(), 10.0);
+    assert_eq!((rates.datagram_bytes_sent * seconds).round(), 100.0);
+    assert_eq!((rates.streams_opened * seconds).round(), 1.0);
+    assert_eq!(rates.streams_accepted, 0.0);
+
+    session.close(0, "done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_poll_budget() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    let server_task = tokio::task::spawn(async move {
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        assert_eq!(session.poll_budget(), 32);
+        session.set_poll_budget(0);
+        assert_eq!(session.poll_budget(), 1);
+
+        // Every stream is still accepted when yielding after each one.
+        for _ in 0..8 {
+            let mut recv = session.accept_uni().await.unwrap();
+            assert_eq!(recv.read_to_end(16).await.unwrap(), b"data");
+        }
+        for _ in 0..8 {
+            let (mut send, mut recv) = session.accept_bi().await.unwrap();
+            assert_eq!(recv.read_to_end(16).await.unwrap(), b"data");
+            send.finish().unwrap();
+        }
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    for _ in 0..8 {
+        let mut send = session.open_uni().await.unwrap();
+        send.write_all(b"data").await.unwrap();
+        send.finish().unwrap();
+    }
+    let mut recvs = Vec::new();
+    for _ in 0..8 {
+        let (mut send, recv) = session.open_bi().await.unwrap();
+        send.write_all(b"data").await.unwrap();
+        send.finish().unwrap();
+        recvs.push(recv);
+    }
+    for mut recv in recvs {
+        recv.read_to_end(16).await.unwrap();
+    }
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_datagrams_unsupported() -> n0_error::Result<()> {
+    use iroh::endpoint::QuicTransportConfig;
+
+    use crate::PanicPolicy;
+
+    const ALPN: &str = "no-datagrams";
+    assert_eq!(crate::panic_policy(), PanicPolicy::Error);
+
+    let transport = QuicTransportConfig::builder()
+        .datagram_receive_buffer_size(None)
+        .build();
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.as_bytes().to_vec()])
+        .transport_config(transport)
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client
+        .connect_quic(server_addr, ALPN.as_bytes())
+        .await
+        .unwrap();
+    // The peer disabled datagrams, which is reported instead of panicking.
+    assert_eq!(session.max_datagram_size(), 0);
+    assert!(session.send_datagram(Bytes::from_static(b"x")).is_err());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_connect_staged() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_id = endpoint.id();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{server_id}/staged").parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    let server_task = tokio::task::spawn(async move {
+        // The aborted connection never sends a CONNECT request, so only the second one arrives.
+        let request = server.accept().await.unwrap();
+        assert_eq!(request.url.path(), "/staged");
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let dialed = client.connect_h3_staged(server_addr.clone()).await.unwrap();
+    assert_eq!(dialed.conn().remote_id(), server_id);
+    dialed.abort(b"not today");
+
+    let dialed = client.connect_h3_staged(server_addr).await.unwrap();
+    assert_eq!(dialed.conn().remote_id(), server_id);
+    let session = dialed.handshake(url).await.unwrap();
+    assert_eq!(session.request().unwrap().url.path(), "/staged");
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_peer_capabilities() -> n0_error::Result<()> {
+    use crate::{Features, SettingsProfile, Version};
+
+    const CUSTOM: Features = Features::application(3);
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/caps", endpoint.id()).parse().unwrap();
+    let profile = SettingsProfile::new().with_features(Some(Features::builtin() | CUSTOM));
+    let mut server = Server::new(endpoint).with_settings_profile(profile);
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let request = server.accept().await.unwrap();
+            let session = request.ok().await.unwrap();
+            if session.request().unwrap().url.query() == Some("minimal") {
+                assert_eq!(session.peer_capabilities(), None);
+            } else {
+                let caps = session.peer_capabilities().unwrap();
+                assert_eq!(caps.version, Some(Version::current()));
+                assert_eq!(caps.features, Features::builtin());
+                assert!(!caps.supports(CUSTOM));
+            }
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    let caps = session.peer_capabilities().unwrap();
+    assert_eq!(caps.version.unwrap().to_string(), env!("CARGO_PKG_VERSION"));
+    assert!(caps.supports(Features::DRAIN | CUSTOM));
+    assert_eq!(
+        caps.features.intersection(Features::builtin()),
+        Features::builtin()
+    );
+    session.close(0, b"done");
+    client.close().await;
+
+    let client = Client::new(Endpoint::bind().await.unwrap())
+        .with_settings_profile(SettingsProfile::minimal());
+    let session = client
+        .connect_h3(server_addr, url.join("?minimal").unwrap())
+        .await
+        .unwrap();
+    assert!(session.peer_capabilities().is_some());
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_alpn_preference() -> n0_error::Result<()> {
+    const V1: &[u8] = b"proto/1";
+    const V2: &[u8] = b"proto/2";
+    const V3: &[u8] = b"proto/3";
+
+    // The server still runs the two older protocol versions.
+    let server = Endpoint::builder()
+        .alpns(vec![V1.to_vec(), V2.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        assert_eq!(conn.alpn(), V2);
+        let session = QuicRequest::accept(conn).ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let err = client
+        .connect_quic_preferred(server_addr.clone(), Vec::<&[u8]>::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::NoAlpns), "{err:?}");
+
+    let session = client
+        .connect_quic_preferred(server_addr, [V3, V2])
+        .await
+        .unwrap();
+    assert_eq!(session.protocol(), Some("proto/2"));
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn peer_symmetric() -> n0_error::Result<()> {
+    use crate::{Peer, Strictness};
+
+    async fn bind() -> Endpoint {
+        Endpoint::builder()
+            .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+            .bind()
+            .await
+            .unwrap()
+    }
+
+    let (a, b, stranger) = (bind().await, bind().await, bind().await);
+    let (a_addr, b_addr) = (a.addr(), b.addr());
+    let (a_id, b_id) = (a.id(), b.id());
+
+    let mut alice = Peer::new(a)
+        .with_strictness(Strictness::Strict)
+        .with_allowed_peers([b_id]);
+    let mut bob = Peer::new(b).with_allowed_peers([a_id]);
+    let stranger = Peer::new(stranger);
+
+    let url: Url = format!("https://{b_id}/hello").parse().unwrap();
+    let err = alice
+        .connect(stranger.endpoint().addr(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ClientError::Vetoed(_)), "{err:?}");
+
+    let accept = tokio::task::spawn(async move {
+        // The stranger is rejected, so bob only accepts alice.
+        let request = bob.accept().await.unwrap();
+        assert_eq!(request.conn().remote_id(), a_id);
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+
+        // And the other way around, on the same endpoint.
+        let url: Url = format!("https://{a_id}/back").parse().unwrap();
+        let session = bob.connect(a_addr, url).await.unwrap();
+        session.close(0, b"done");
+        bob
+    });
+    let err = stranger
+        .connect(b_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    // Before the HTTP/3 handshake, by closing the connection.
+    assert_eq!(err.kind(), ErrorKind::Refused, "{err:?}");
+
+    let session = alice.connect(b_addr, url).await.unwrap();
+    session.close(0, b"done");
+
+    let request = alice.accept().await.unwrap();
+    assert_eq!(request.url.path(), "/back");
+    let session = request.ok().await.unwrap();
+    session.closed().await;
+
+    let bob = accept.await.unwrap();
+    alice.close().await;
+    bob.close().await;
+    stranger.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_accept_with_errors() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_handshake_timeout(Some(Duration::from_millis(300)));
+
+    // A client that never sends SETTINGS.
+    let raw = Endpoint::bind().await.unwrap();
+    let raw_id = raw.id();
+    let server_task = tokio::task::spawn(async move {
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert_eq!(err.remote, Some(raw_id));
+        assert!(
+            matches!(err.source, ServerError::HandshakeTimeout),
+            "{err:?}"
+        );
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let request = server.accept_with_errors().await.unwrap().unwrap();
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let conn = raw
+        .connect(server_addr.clone(), ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+    conn.closed().await;
+    raw.close().await;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_router() -> n0_error::Result<()> {
+    use tokio::sync::mpsc;
+
+    use crate::{Params, ROUTE_NOT_FOUND, Router};
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base: Url = format!("https://{}", endpoint.id()).parse().unwrap();
+    let server = Server::new(endpoint.clone()).with_max_sessions(8);
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<(&str, Params)>();
+    let accept = move |name: &'static str| {
+        let tx = tx.clone();
+        move |request: H3Request, params: Params| {
+            let tx = tx.clone();
+            async move {
+                tx.send((name, params)).unwrap();
+                let session = request.ok().await.unwrap();
+                session.closed().await;
+            }
+        }
+    };
+    let router = Router::new()
+        .route("/chat/{room}", accept("chat"))
+        .route("/files/*", accept("files"))
+        .route("/users/{id}/posts/{post}", accept("posts"));
+    let server_task = tokio::task::spawn(router.serve(server));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let connect = |path: &str| client.connect_h3(server_addr.clone(), base.join(path).unwrap());
+
+    let session = connect("/chat/lobby").await.unwrap();
+    let (name, params) = rx.recv().await.unwrap();
+    assert_eq!((name, params.get("room")), ("chat", Some("lobby")));
+    session.close(0, b"done");
+
+    let session = connect("/files/a/b.txt").await.unwrap();
+    let (name, params) = rx.recv().await.unwrap();
+    assert_eq!((name, params.rest()), ("files", Some("a/b.txt")));
+    session.close(0, b"done");
+
+    let session = connect("/users/7/posts/42").await.unwrap();
+    let (name, params) = rx.recv().await.unwrap();
+    assert_eq!(name, "posts");
+    assert_eq!(
+        params.iter().collect::<Vec<_>>(),
+        [("id", "7"), ("post", "42")]
+    );
+    session.close(0, b"done");
+
+    for path in ["/chat", "/chat/lobby/extra", "/nope"] {
+        let err = connect(path).await.unwrap_err();
+        assert!(
+            matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == ROUTE_NOT_FOUND),
+            "{path}: {err:?}"
+        );
+    }
+    client.close().await;
+
+    endpoint.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_virtual_hosts() -> n0_error::Result<()> {
+    use tokio::sync::mpsc;
+
+    use crate::{HOST_NOT_FOUND, Params, Router, VirtualHosts};
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let server = Server::new(endpoint.clone()).with_max_sessions(8);
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<&str>();
+    let service = |name: &'static str| {
+        let tx = tx.clone();
+        Router::new().route("/", move |request: H3Request, _: Params| {
+            let tx = tx.clone();
+            async move {
+                tx.send(name).unwrap();
+                let session = request.ok().await.unwrap();
+                session.closed().await;
+            }
+        })
+    };
+    let hosts = VirtualHosts::new()
+        .host("chat.example.com", service("chat"))
+        .host("*.files.example.com", service("files"))
+        .host("admin.example.com:8443", service("admin"));
+    let server_task = tokio::task::spawn(hosts.clone().serve(server));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let connect = |url: &str| client.connect_h3(server_addr.clone(), Url::parse(url).unwrap());
+
+    for (url, name) in [
+        ("https://CHAT.example.com/", "chat"),
+        ("https://eu.files.example.com/", "files"),
+        ("https://admin.example.com:8443/", "admin"),
+    ] {
+        let session = connect(url).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), name);
+        session.close(0, b"done");
+    }
+
+    for url in [
+        "https://files.example.com/",
+        "https://admin.example.com/",
+        "https://other.example.com/",
+    ] {
+        let err = connect(url).await.unwrap_err();
+        assert!(
+            matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == HOST_NOT_FOUND),
+            "{url}: {err:?}"
+        );
+    }
+    client.close().await;
+    endpoint.close().await;
+    server_task.await.unwrap();
+
+    // With a default host, unknown hosts are dispatched to it instead.
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let server = Server::new(endpoint.clone());
+    let hosts = hosts.default_host(service("default"));
+    let server_task = tokio::task::spawn(hosts.serve(server));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let url = Url::parse("https://other.example.com/").unwrap();
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), "default");
+    session.close(0, b"done");
+    client.close().await;
+    endpoint.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_middleware() -> n0_error::Result<()> {
+    use std::sync::Mutex;
+
+    use crate::{Middleware, MiddlewareFuture, Next, Outcome, Params, ROUTE_NOT_FOUND, Router};
+
+    // Requires a token and rewrites it into an identity for the handler.
+    struct Auth;
+
+    impl Middleware for Auth {
+        fn call<'a>(&'a self, mut request: H3Request, next: Next<'a>) -> MiddlewareFuture<'a> {
+            Box::pin(async move {
+                match request.headers_mut().remove(http::header::AUTHORIZATION) {
+                    Some(token) if token == "secret" => {
+                        request
+                            .headers_mut()
+                            .insert("x-user", http::HeaderValue::from_static("alice"));
+                        next.run(request).await
+                    }
+                    _ => Outcome::reject(request, http::StatusCode::UNAUTHORIZED).await,
+                }
+            })
+        }
+    }
+
+    // Records the outcome of every request.
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<(String, Outcome)>>>);
+
+    impl Middleware for Log {
+        fn call<'a>(&'a self, request: H3Request, next: Next<'a>) -> MiddlewareFuture<'a> {
+            Box::pin(async move {
+                let path = request.url.path().to_string();
+                let outcome = next.run(request).await;
+                self.0.lock().unwrap().push((path, outcome));
+                outcome
+            })
+        }
+    }
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base: Url = format!("https://{}", endpoint.id()).parse().unwrap();
+    let server = Server::new(endpoint.clone()).with_max_sessions(8);
+
+    let log = Log::default();
+    let router = Router::new()
+        .with_middleware(log.clone())
+        .with_middleware(Auth)
+        .route("/chat", |request: H3Request, _: Params| async move {
+            assert_eq!(request.headers()["x-user"], "alice");
+            assert!(request.headers().get(http::header::AUTHORIZATION).is_none());
+            let session = request.ok().await.unwrap();
+            assert_eq!(session.request().unwrap().headers["x-user"], "alice");
+            session.closed().await;
+        });
+    let server_task = tokio::task::spawn(router.serve(server));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let connect = |path: &str, token: &'static str| {
+        let request = ConnectRequestBuilder::new(base.join(path).unwrap()).with_header(
+            http::header::AUTHORIZATION,
+            http::HeaderValue::from_static(token),
+        );
+        client.connect_h3(server_addr.clone(), request)
+    };
+
+    let session = connect("/chat", "secret").await.unwrap();
+    session.close(0, b"done");
+    session.closed().await;
+
+    for (path, token, status) in [
+        ("/chat", "wrong", http::StatusCode::UNAUTHORIZED),
+        ("/nope", "secret", ROUTE_NOT_FOUND),
+    ] {
+        let err = connect(path, token).await.unwrap_err();
+        assert!(
+            matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == status),
+            "{path}: {err:?}"
+        );
+    }
+    client.close().await;
+    endpoint.close().await;
+    server_task.await.unwrap();
+
+    let mut log = log.0.lock().unwrap().clone();
+    log.sort_by_key(|(path, outcome)| (path.clone(), *outcome == Outcome::Handled));
+    assert_eq!(
+        log,
+        [
+            (
+                "/chat".to_string(),
+                Outcome::Rejected(http::StatusCode::UNAUTHORIZED)
+            ),
+            ("/chat".to_string(), Outcome::Handled),
+            ("/nope".to_string(), Outcome::Rejected(ROUTE_NOT_FOUND)),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_shutdown() -> n0_error::Result<()> {
+    use std::time::Instant;
+
+    for cooperative in [true, false] {
+        let endpoint = Endpoint::builder()
+            .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+            .bind()
+            .await
+            .unwrap();
+        let server_addr = endpoint.addr();
+        let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+        let mut server = Server::new(endpoint);
+
+        let server_task = tokio::task::spawn(async move {
+            let request = server.accept().await.unwrap();
+            let session = request.ok().await.unwrap();
+            let deadline = Instant::now() + Duration::from_millis(500);
+            let graceful = server.shutdown(deadline).await;
+            assert!(server.accept().await.is_none());
+            session.closed().await;
+            graceful
+        });
+
+        let client = Client::new(Endpoint::bind().await.unwrap());
+        let session = client.connect_h3(server_addr, url).await.unwrap();
+        session.going_away().await;
+        session.draining().await;
+        if cooperative {
+            session.close(0, b"drained");
+        }
+        session.closed().await;
+        client.close().await;
+
+        assert_eq!(server_task.await.unwrap(), cooperative);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_max_pending() -> n0_error::Result<()> {
+    use crate::PendingOverflow;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_max_pending(1, PendingOverflow::Refuse);
+
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept().await.unwrap();
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    // A client that never sends SETTINGS takes the only handshake slot.
+    let raw = Endpoint::bind().await.unwrap();
+    let conn = raw
+        .connect(server_addr.clone(), ALPN_H3.as_bytes())
+        .await
+        .unwrap();
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    assert!(
+        client
+            .connect_h3(server_addr.clone(), url.clone())
+            .await
+            .is_err()
+    );
+
+    conn.close(0u32.into(), b"bye");
+    raw.close().await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_admission_control() -> n0_error::Result<()> {
+    use crate::{ADMISSION_REJECTED, RateLimit};
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    // One session at a time, and two connections with a slow refill.
+    let limit = RateLimit::new(1, 2).with_max_sessions(1);
+    let mut server = Server::new(endpoint)
+        .with_max_sessions(8)
+        .with_admission_control(limit);
+
+    let server_task = tokio::task::spawn(async move {
+        let request = server.accept_with_errors().await.unwrap().unwrap();
+        let first = request.ok().await.unwrap();
+
+        // The second connection takes the last token, but the session cap is reached.
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert!(
+            matches!(err.source, ServerError::NotAdmitted { status } if status == ADMISSION_REJECTED),
+            "{err:?}"
+        );
+        assert_eq!(err.kind(), ErrorKind::Refused);
+        first.closed().await;
+
+        // The third connection finds the bucket empty and is refused without a handshake.
+        let request = server.accept_with_errors().await.unwrap().unwrap();
+        assert_eq!(server.metrics().connections_refused(), 1);
+        assert_eq!(server.metrics().handshakes_started(), 3);
+        let fourth = request.ok().await.unwrap();
+        fourth.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let first = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    let err = client
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == ADMISSION_REJECTED),
+        "{err:?}"
+    );
+    first.close(0, b"done");
+    first.closed().await;
+
+    assert!(
+        client
+            .connect_h3(server_addr.clone(), url.clone())
+            .await
+            .is_err()
+    );
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let fourth = client.connect_h3(server_addr, url).await.unwrap();
+    fourth.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_raw_alpns() -> n0_error::Result<()> {
+    use crate::Request;
+
+    const RAW: &[u8] = b"proto/1";
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec(), RAW.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint)
+        .with_max_sessions(8)
+        .with_raw_alpns([RAW]);
+
+    let server_task = tokio::task::spawn(async move {
+        let mut kinds = Vec::new();
+        for _ in 0..2 {
+            let request = server.accept_any().await.unwrap();
+            kinds.push(matches!(request, Request::H3(_)));
+            let session = request.ok().await.unwrap();
+            let mut recv = session.accept_uni().await.unwrap();
+            let msg = recv.read_to_end(1024).await.unwrap();
+            assert_eq!(msg, session.protocol().unwrap_or("h3").as_bytes());
+        }
+        assert_eq!(kinds, [true, false]);
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let h3 = client.connect_h3(server_addr.clone(), url).await.unwrap();
+    let mut send = h3.open_uni().await.unwrap();
+    send.write_all(b"h3").await.unwrap();
+    send.finish().unwrap();
+
+    let raw = client.connect_quic(server_addr, RAW).await.unwrap();
+    let mut send = raw.open_uni().await.unwrap();
+    send.write_all(RAW).await.unwrap();
+    send.finish().unwrap();
+
+    server_task.await.unwrap();
+    client.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_builder_bind() -> n0_error::Result<()> {
+    use iroh::{RelayMode, SecretKey};
+
+    const RAW: &[u8] = b"proto/1";
+
+    let key = SecretKey::from_bytes(&[7; 32]);
+    let mut server = Server::builder()
+        .with_relay_mode(RelayMode::Disabled)
+        .with_secret_key(key.clone())
+        .with_tuning(TransportTuning::new().with_max_idle_timeout(Some(Duration::from_secs(5))))
+        .with_max_sessions(4)
+        .with_raw_alpns([RAW])
+        .bind()
+        .await
+        .unwrap();
+    assert_eq!(server.endpoint().id(), key.public());
+    let server_addr = server.endpoint().addr();
+    let url: Url = format!("https://{}/foo", server_addr.id).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let session = server.accept_any().await.unwrap().ok().await.unwrap();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr.clone(), url).await.unwrap();
+    assert_eq!(session.settings().unwrap().peer_max_sessions(), 4);
+    session.close(0, b"done");
+    let session = client.connect_quic(server_addr, RAW).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_peer_acl() -> n0_error::Result<()> {
+    use crate::PeerAcl;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+
+    let (alice_endpoint, bob_endpoint) = (
+        Endpoint::bind().await.unwrap(),
+        Endpoint::bind().await.unwrap(),
+    );
+    let (alice_id, bob_id) = (alice_endpoint.id(), bob_endpoint.id());
+    let alice = Client::new(alice_endpoint);
+    let bob = Client::new(bob_endpoint);
+    let acl = PeerAcl::allowlist([alice_id]);
+    let mut server = Server::new(endpoint)
+        .with_max_sessions(8)
+        .with_acl(acl.clone());
+
+    let server_task = tokio::task::spawn(async move {
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert_eq!(err.remote, Some(bob_id));
+        assert!(matches!(err.source, ServerError::PeerDenied), "{err:?}");
+        for _ in 0..2 {
+            let session = server.accept().await.unwrap().ok().await.unwrap();
+            session.closed().await;
+        }
+        server.close().await;
+    });
+
+    let err = bob
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Refused, "{err:?}");
+
+    let session = alice
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    session.close(0, b"done");
+
+    // Updates apply to the next connection.
+    acl.allow(bob_id);
+    acl.deny(alice_id);
+    assert!(!acl.is_allowed(&alice_id));
+    let session = bob.connect_h3(server_addr, url).await.unwrap();
+    session.close(0, b"done");
+
+    server_task.await.unwrap();
+    alice.close().await;
+    bob.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_auth() -> n0_error::Result<()> {
+    use crate::{AuthRequest, ConnectRequestBuilder};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User(String);
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let base = format!("https://{}/foo", endpoint.id());
+    let mut server =
+        Server::new(endpoint)
+            .with_max_sessions(8)
+            .with_auth(|request: AuthRequest| async move {
+                match request.bearer_token().as_deref() {
+                    Some("secret") => Ok(User("alice".into())),
+                    _ => Err(http::StatusCode::UNAUTHORIZED),
+                }
+            });
+
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let session = server.accept().await.unwrap().ok().await.unwrap();
+            assert_eq!(session.extensions().get(), Some(&User("alice".into())));
+            session.closed().await;
+        }
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert!(
+            matches!(err.source, ServerError::Unauthenticated { status } if status == http::StatusCode::UNAUTHORIZED),
+            "{err:?}"
+        );
+        // Closing the endpoint right away would cut off the rejection.
+        server
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let url: Url = base.parse().unwrap();
+    let request = ConnectRequestBuilder::new(url.clone()).with_header(
+        http::header::AUTHORIZATION,
+        http::HeaderValue::from_static("Bearer secret"),
+    );
+    let session = client
+        .connect_h3(server_addr.clone(), request)
+        .await
+        .unwrap();
+    session.close(0, b"done");
+
+    // Browsers can't set headers, so the token goes in the query.
+    let with_query: Url = format!("{base}?access_token=secret").parse().unwrap();
+    let session = client
+        .connect_h3(server_addr.clone(), with_query)
+        .await
+        .unwrap();
+    session.close(0, b"done");
+
+    let err = client.connect_h3(server_addr, url).await.unwrap_err();
+    assert!(
+        matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == http::StatusCode::UNAUTHORIZED),
+        "{err:?}"
+    );
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_auth_deadline() -> n0_error::Result<()> {
+    use crate::AuthRequest;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    // The hook never answers, the handshake timeout has to cut it off.
+    let mut server = Server::new(endpoint)
+        .with_handshake_timeout(Some(Duration::from_millis(200)))
+        .with_auth(|_: AuthRequest| std::future::pending::<Result<(), http::StatusCode>>());
+
+    let server_task = tokio::task::spawn(async move {
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert!(
+            matches!(err.source, ServerError::HandshakeTimeout),
+            "{err:?}"
+        );
+        server
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    assert!(client.connect_h3(server_addr, url).await.is_err());
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_handshake_deadline() -> n0_error::Result<()> {
+    use iroh::endpoint::ConnectionError;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let mut server = Server::new(endpoint).with_handshake_timeout(Some(Duration::from_millis(200)));
+    let metrics = server.metrics().clone();
+
+    let server_task = tokio::task::spawn(async move {
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert!(
+            matches!(err.source, ServerError::HandshakeTimeout),
+            "{err:?}"
+        );
+        server
+    });
+
+    // Completes the QUIC handshake, but never sends SETTINGS or CONNECT.
+    let raw = Endpoint::bind().await.unwrap();
+    let conn = raw.connect(server_addr, ALPN_H3.as_bytes()).await.unwrap();
+    let err = conn.closed().await;
+    let ConnectionError::ApplicationClosed(close) = err else {
+        panic!("unexpected close: {err:?}");
+    };
+    assert_eq!(close.error_code, 0x10d_u32.into());
+    raw.close().await;
+
+    let server = server_task.await.unwrap();
+    assert_eq!(metrics.handshakes_started(), 1);
+    assert_eq!(metrics.handshakes_timed_out(), 1);
+    assert_eq!(metrics.handshakes_completed(), 0);
+    assert_eq!(metrics.handshakes_failed(), 0);
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_connection_filter() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+
+    let blocked = Endpoint::bind().await.unwrap();
+    let blocked_id = blocked.id();
+    let mut server = Server::new(endpoint).with_connection_filter(move |conn| {
+        std::future::ready(conn.remote_id() != blocked_id && conn.alpn() == ALPN_H3.as_bytes())
+    });
+    let metrics = server.metrics().clone();
+
+    let server_task = tokio::task::spawn(async move {
+        let err = server.accept_with_errors().await.unwrap().unwrap_err();
+        assert_eq!(err.remote, Some(blocked_id));
+        assert!(matches!(err.source, ServerError::Filtered), "{err:?}");
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let err = Client::new(blocked)
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Refused, "{err:?}");
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+    assert_eq!(metrics.handshakes_rejected(), 1);
+    assert_eq!(metrics.handshakes_completed(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn session_hub() -> n0_error::Result<()> {
+    use crate::{HubEvent, SessionHub};
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_max_sessions(8);
+
+    let hub = SessionHub::default();
+    let mut events = hub.subscribe();
+    let server_hub = hub.clone();
+    let server_task = tokio::task::spawn(async move {
+        for _ in 0..2 {
+            let session = server.accept().await.unwrap().ok().await.unwrap();
+            assert!(server_hub.join_peer(session).is_none());
+        }
+        server
+    });
+
+    let (alice_endpoint, bob_endpoint) = (
+        Endpoint::bind().await.unwrap(),
+        Endpoint::bind().await.unwrap(),
+    );
+    let (alice_id, bob_id) = (alice_endpoint.id(), bob_endpoint.id());
+    let alice = Client::new(alice_endpoint);
+    let bob = Client::new(bob_endpoint);
+    let alice_session = alice
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    assert_eq!(events.recv().await.unwrap(), HubEvent::Joined(alice_id));
+    let bob_session = bob.connect_h3(server_addr, url).await.unwrap();
+    assert_eq!(events.recv().await.unwrap(), HubEvent::Joined(bob_id));
+    let server = server_task.await.unwrap();
+
+    assert_eq!(hub.len(), 2);
+    let mut keys = hub.keys();
+    keys.sort();
+    let mut expected = vec![alice_id, bob_id];
+    expected.sort();
+    assert_eq!(keys, expected);
+    assert_eq!(hub.get(&alice_id).unwrap().conn().remote_id(), alice_id);
+
+    alice_session.close(0, b"bye");
+    assert_eq!(events.recv().await.unwrap(), HubEvent::Left(alice_id));
+    assert!(!hub.contains(&alice_id));
+
+    hub.close_all(42, b"shutting down");
+    bob_session.closed().await;
+    assert_eq!(events.recv().await.unwrap(), HubEvent::Left(bob_id));
+    assert!(hub.is_empty());
+
+    alice.close().await;
+    bob.close().await;
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn session_hub_dropped() -> n0_error::Result<()> {
+    use crate::SessionHub;
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        let hub = SessionHub::default();
+        hub.join_peer(session);
+        // The task watching the session doesn't keep it alive.
+        drop(hub);
+        server
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), session.conn().closed())
+        .await
+        .expect("connection is closed once the server dropped the hub");
+    assert!(logs_contain("session dropped while open"));
+    client.close().await;
+
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_peer_quota() -> n0_error::Result<()> {
+    use crate::PEER_QUOTA_EXCEEDED;
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint)
+        .with_max_sessions(8)
+        .with_peer_quota(1);
+
+    let server_task = tokio::task::spawn(async move {
+        let mut sessions = Vec::new();
+        while sessions.len() < 3 {
+            match server.accept_with_errors().await.unwrap() {
+                Ok(request) => sessions.push(request.ok().await.unwrap()),
+                Err(err) => assert!(
+                    matches!(err.source, ServerError::NotAdmitted { status } if status == PEER_QUOTA_EXCEEDED),
+                    "{err:?}"
+                ),
+            }
+        }
+        for session in sessions {
+            session.closed().await;
+        }
+        server
+    });
+
+    let alice = Client::new(Endpoint::bind().await.unwrap());
+    let bob = Client::new(Endpoint::bind().await.unwrap());
+    let first = alice
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+    let err = alice
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, ClientError::HttpError(ConnectError::Rejected(ref response)) if response.status() == PEER_QUOTA_EXCEEDED),
+        "{err:?}"
+    );
+
+    // Other peers have a quota of their own.
+    let other = bob
+        .connect_h3(server_addr.clone(), url.clone())
+        .await
+        .unwrap();
+
+    // Closing the first session frees the quota again.
+    first.close(0, b"done");
+    first.closed().await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let second = alice.connect_h3(server_addr, url).await.unwrap();
+
+    second.close(0, b"done");
+    other.close(0, b"done");
+    let server = server_task.await.unwrap();
+    alice.close().await;
+    bob.close().await;
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_request_metadata() -> n0_error::Result<()> {
+    use crate::Request;
+
+    const RAW: &[u8] = b"proto/1";
+
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec(), RAW.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_raw_alpns([RAW]);
+
+    let server_task = tokio::task::spawn(async move {
+        let Request::H3(request) = server.accept_any().await.unwrap() else {
+            panic!("expected an h3 request");
+        };
+        assert_eq!(request.alpn(), ALPN_H3.as_bytes());
+        assert!(request.handshake_duration().is_some());
+        assert!(!request.remote_addrs().is_empty());
+        assert!(request.rtt().is_some());
+        let session = request.ok().await.unwrap();
+        session.closed().await;
+
+        let Request::Quic(request) = server.accept_any().await.unwrap() else {
+            panic!("expected a raw request");
+        };
+        assert_eq!(request.alpn(), RAW);
+        assert!(request.handshake_duration().is_some());
+        assert!(!request.remote_addrs().is_empty());
+        let session = request.ok();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr.clone(), url).await.unwrap();
+    session.close(0, b"done");
+    let session = client.connect_quic(server_addr, RAW).await.unwrap();
+    session.close(0, b"done");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn session_stream_limits() -> n0_error::Result<()> {
+    use iroh::endpoint::QuicTransportConfig;
+
+    // The CONNECT stream takes one of the two.
+    let transport = QuicTransportConfig::builder()
+        .max_concurrent_bidi_streams(2u32.into())
+        .build();
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .transport_config(transport)
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint);
+
+    let (raise_tx, raise_rx) = tokio::sync::oneshot::channel();
+    let server_task = tokio::task::spawn(async move {
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        let _first = session.accept_bi().await.unwrap();
+        raise_rx.await.unwrap();
+        session.set_max_concurrent_bi_streams(2);
+        session.set_max_concurrent_uni_streams(16);
+        session.set_receive_window(u64::MAX);
+        let _second = session.accept_bi().await.unwrap();
+        session.close(0, b"done");
+        server
+    });
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let (mut send, _recv) = session.open_bi().await.unwrap();
+    send.write_all(b"first").await.unwrap();
+    let blocked = tokio::time::timeout(Duration::from_millis(100), session.open_bi()).await;
+    assert!(blocked.is_err(), "the server only allows one stream");
+
+    raise_tx.send(()).unwrap();
+    let (mut send, _recv) = tokio::time::timeout(Duration::from_secs(5), session.open_bi())
+        .await
+        .expect("the server raised the limit")
+        .unwrap();
+    send.write_all(b"second").await.unwrap();
+
+    session.closed().await;
+    let server = server_task.await.unwrap();
+    client.close().await;
+    server.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn server_serve() -> n0_error::Result<()> {
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let id = endpoint.id();
+    let server = Server::new(endpoint).with_max_sessions(8);
+
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server_task = tokio::task::spawn(server.serve_with_shutdown(
+        |request| async move {
+            if request.url.path() == "/panic" {
+                panic!("handler panicked");
+            }
+            let session = request.ok().await.unwrap();
+            session.closed().await;
+        },
+        async move {
+            stop_rx.await.ok();
+        },
+        Duration::from_secs(5),
+    ));
+
+    let client = Client::new(Endpoint::bind().await.unwrap());
+    let url = |path: &str| -> Url { format!("https://{id}{path}").parse().unwrap() };
+    // The panic drops the request without taking the server down.
+    let err = client.connect_h3(server_addr.clone(), url("/panic")).await;
+    assert!(err.is_err());
+    let session = client.connect_h3(server_addr, url("/ok")).await.unwrap();
+
+    stop_tx.send(()).unwrap();
+    session.draining().await;
+    session.close(0, b"drained");
+    assert!(server_task.await.unwrap());
+    client.close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_reject_helpers() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        for i in 0..3 {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let request = H3Request::accept(conn.clone()).await.unwrap();
+            match i {
+                0 => request.reject_not_found().await.unwrap(),
+                1 => request
+                    .reject_unauthorized(http::HeaderValue::from_static("Bearer realm=\"chat\""))
+                    .await
+                    .unwrap(),
+                _ => request
+                    .reject_too_many_requests(Some(Duration::from_millis(1500)))
+                    .await
+                    .unwrap(),
+            }
+            conn.closed().await;
+        }
+        server.close().await;
+    });
+
+    let mut responses = Vec::new();
+    for _ in 0..3 {
+        let err = client
+            .connect_h3(server_addr.clone(), url.clone())
+            .await
+            .unwrap_err();
+        let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        responses.push(response);
+    }
+    assert_eq!(responses[0].status(), http::StatusCode::NOT_FOUND);
+    assert_eq!(responses[1].status(), http::StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        responses[1].headers()[http::header::WWW_AUTHENTICATE],
+        "Bearer realm=\"chat\""
+    );
+    assert_eq!(responses[2].status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(responses[2].headers()[http::header::RETRY_AFTER], "2");
+    client.close().await;
+
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_send_stream_async_write() -> n0_error::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::WriteError;
+
+    const ALPN: &[u8] = b"async-write";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let mut recv = session.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(1024).await.unwrap(), b"copied");
+        let mut recv = session.accept_uni().await.unwrap();
+        recv.stop(42).unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    tokio::io::copy(&mut b"copied".as_slice(), &mut send)
+        .await
+        .unwrap();
+    AsyncWriteExt::shutdown(&mut send).await.unwrap();
+
+    let mut send = session.open_uni().await.unwrap();
+    let err = loop {
+        if let Err(err) = AsyncWriteExt::write_all(&mut send, b"stop me").await {
+            break err;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+    let err = err.downcast::<WriteError>().unwrap();
+    assert!(matches!(err, WriteError::Stopped(42)), "{err:?}");
+
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[cfg(feature = "futures-io")]
+#[tokio::test]
+#[traced_test]
+async fn quic_futures_io() -> n0_error::Result<()> {
+    use n0_future::io::{AsyncReadExt, AsyncWriteExt};
+
+    const ALPN: &[u8] = b"futures-io";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        let (mut send, mut recv) = session.accept_bi().await.unwrap();
+        let mut buf = Vec::new();
+        AsyncReadExt::read_to_end(&mut recv, &mut buf)
+            .await
+            .unwrap();
+        AsyncWriteExt::write_all(&mut send, &buf).await.unwrap();
+        AsyncWriteExt::close(&mut send).await.unwrap();
+        session.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let (mut send, mut recv) = session.open_bi().await.unwrap();
+    AsyncWriteExt::write_all(&mut send, b"echo").await.unwrap();
+    AsyncWriteExt::close(&mut send).await.unwrap();
+    let mut buf = Vec::new();
+    AsyncReadExt::read_to_end(&mut recv, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(buf, b"echo");
+
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_stream_priority() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        for expected in [b"control".as_slice(), b"bulk"] {
+            let mut recv = session.accept_uni().await.unwrap();
+            assert_eq!(recv.read_to_end(16).await.unwrap(), expected);
+        }
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut control = session.open_uni().await.unwrap();
+    assert_eq!(control.priority().unwrap(), 0);
+    control.write_all(b"control").await.unwrap();
+    control.finish().unwrap();
+
+    // The priority is recorded while the header is still sent first, and applied on write.
+    let mut bulk = session.open_uni().await.unwrap();
+    bulk.set_priority(-10).unwrap();
+    assert_eq!(bulk.priority().unwrap(), -10);
+    bulk.write_all(b"bulk").await.unwrap();
+    assert_eq!(bulk.priority().unwrap(), -10);
+    bulk.set_priority(-20).unwrap();
+    assert_eq!(bulk.priority().unwrap(), -20);
+    bulk.finish().unwrap();
+
+    session.closed().await;
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_write_all_chunks() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = H3Request::accept(conn).await.unwrap().ok().await.unwrap();
+        for _ in 0..3 {
+            let mut recv = session.accept_uni().await.unwrap();
+            assert_eq!(recv.read_to_end(64).await.unwrap(), b"\x05hello");
+        }
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    // A length prefix and a payload, sent without concatenating them.
+    let message = || [Bytes::from_static(b"\x05"), Bytes::from_static(b"hello")];
+    let session = client.connect_h3(server_addr, url).await.unwrap();
+    let mut send = session.open_uni().await.unwrap();
+    let mut chunks = message();
+    send.write_all_chunks(&mut chunks).await.unwrap();
+    assert!(chunks.iter().all(Bytes::is_empty));
+    send.finish().unwrap();
+
+    let mut send = session.open_uni_bulk().await.unwrap();
+    send.write_all_chunks(&mut message()).await.unwrap();
+    send.finish().unwrap();
+
+    // The payload is large enough to skip the batch, after the buffered length prefix.
+    let send = session.open_uni().await.unwrap();
+    let mut send = send.batched(Batching::new(Duration::from_secs(10), 4));
+    send.write_all_chunks(&mut message()).await.unwrap();
+    send.finish().await.unwrap();
+
+    session.closed().await;
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_write_chunk_fan_out() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"fan-out";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let session = QuicRequest::accept(conn).ok();
+        for _ in 0..3 {
+            let mut recv = session.accept_uni().await.unwrap();
+            let data = recv.read_to_end(8192).await.unwrap();
+            assert_eq!(data.len(), 4096);
+            assert!(data.iter().all(|&b| b == 7));
+        }
+
+        // A large chunk skips the batch, after the data that was already buffered.
+        let mut recv = session.accept_uni().await.unwrap();
+        let data = recv.read_to_end(8192).await.unwrap();
+        assert_eq!(&data[..5], b"small");
+        assert_eq!(data.len(), 5 + 4096);
+
+        session.close(0, b"done");
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let payload = Bytes::from(vec![7u8; 4096]);
+    for _ in 0..3 {
+        let mut send = session.open_uni().await.unwrap();
+        send.write_chunk(payload.clone()).await.unwrap();
+        send.finish().unwrap();
+    }
+
+    let send = session.open_uni().await.unwrap();
+    let mut send = send.batched(Batching::new(Duration::from_secs(10), 1024));
+    send.write_all(b"small").await.unwrap();
+    send.write_chunk(payload).await.unwrap();
+    send.finish().await.unwrap();
+
+    session.closed().await;
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn quic_invalid_reset_code() -> n0_error::Result<()> {
+    const ALPN: &[u8] = b"invalid-reset";
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        // H3_NO_ERROR is outside the WebTransport range.
+        let mut send = conn.open_uni().await.unwrap();
+        send.write_all(b"x").await.unwrap();
+        send.reset(iroh::endpoint::VarInt::from_u32(0x100)).unwrap();
+        conn.closed().await;
+        server.close().await;
+    });
+
+    let session = client.connect_quic(server_addr, ALPN).await.unwrap();
+    let mut recv = session.accept_uni().await.unwrap();
+    let err = recv.received_reset().await.unwrap_err();
+    assert!(
+        matches!(
+            err,
+            SessionError::WebTransportError(WebTransportError::InvalidReset(code))
+                if code.into_inner() == 0x100
+        ),
+        "{err:?}"
+    );
+    session.close(0, b"done");
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn h3_reject_then_drop() -> n0_error::Result<()> {
+    let client = Client::new(Endpoint::bind().await.unwrap());
+
+    let server = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = server.addr();
+    let url: Url = format!("https://{}/foo", server.id()).parse().unwrap();
+
+    // The connection is dropped as soon as reject returns, which must not lose the response.
+    let server_task = tokio::task::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let request = H3Request::accept(conn).await.unwrap();
+        request.reject(http::StatusCode::FORBIDDEN).await.unwrap();
+        server
+    });
+
+    let err = client.connect_h3(server_addr, url).await.unwrap_err();
+    let ClientError::HttpError(ConnectError::Rejected(response)) = err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+    client.close().await;
+    server_task.await.unwrap().close().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn client_server_spawner() -> n0_error::Result<()> {
+    use n0_future::{FuturesUnordered, StreamExt};
+
+    use crate::{SpawnedTask, Spawner};
+
+    // The server counts the tasks it starts, and still runs them on tokio.
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let spawner = Spawner::new({
+        let spawned = spawned.clone();
+        move |task| {
+            spawned.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(task);
+        }
+    });
+    let endpoint = Endpoint::builder()
+        .alpns(vec![ALPN_H3.as_bytes().to_vec()])
+        .bind()
+        .await
+        .unwrap();
+    let server_addr = endpoint.addr();
+    let url: Url = format!("https://{}/foo", endpoint.id()).parse().unwrap();
+    let mut server = Server::new(endpoint).with_spawner(spawner);
+    let server_task = tokio::task::spawn(async move {
+        let session = server.accept().await.unwrap().ok().await.unwrap();
+        session.close_session(7, "done").await.unwrap();
+        session.conn().closed().await;
+        server.close().await;
+    });
+
+    // The client hands its tasks to us, so we poll them ourselves.
+    let (send, mut recv) = tokio::sync::mpsc::unbounded_channel::<SpawnedTask>();
+    let client =
+        Client::new(Endpoint::bind().await.unwrap()).with_spawner(Spawner::new(move |task| {
+            send.send(task).ok();
+        }));
+    let connect = async {
+        let session = client.connect_h3(server_addr, url).await.unwrap();
+        assert_eq!(spawned.load(Ordering::Relaxed), 1);
+        // Only the driver reads the close from the server.
+        let reason = session.closed().await;
+        session.conn().close(0u32.into(), b"bye");
+        reason
+    };
+    tokio::pin!(connect);
+    let mut tasks = FuturesUnordered::new();
+    let mut received = 0;
+    let reason = loop {
+        tokio::select! {
+            reason = &mut connect => break reason,
+            Some(task) = recv.recv() => {
+                received += 1;
+                tasks.push(task);
+            }
+            Some(()) = tasks.next() => {}
+        }
+    };
+    assert!(matches!(
+        reason,
+        SessionError::WebTransportError(WebTransportError::Closed { code: 7, .. })
+    ));
+    assert_eq!(received, 1);
+    client.close().await;
+    server_task.await.unwrap();
+
+    Ok(())
+}
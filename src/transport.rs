@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use iroh::endpoint::{IdleTimeout, QuicTransportConfig, QuicTransportConfigBuilder};
+
+/// Handshake timings, with defaults suited to paths through a busy relay.
+///
+/// QUIC's defaults assume direct paths. Over a relay, the first round trips can take much
+/// longer, so the handshake flight is retransmitted before it could possibly be acknowledged.
+/// Until the client's address is validated, a server may only send three times the bytes it
+/// received, so spurious retransmits use up that budget and stall the handshake.
+/// A higher [`Self::initial_rtt`] avoids this. Use [`Self::apply`] to tune a server's endpoint too.
+///
+/// NAT and relay paths are dropped after a short idle period, so keep-alives are sent well
+/// within the [`Self::max_idle_timeout`]. Only one side needs to send them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportTuning {
+    /// The RTT assumed before the first sample is taken.
+    pub initial_rtt: Duration,
+    /// How long to wait for the handshake to complete, or None to only rely on the idle timeout.
+    ///
+    /// For HTTP/3 sessions, this includes the SETTINGS and CONNECT exchange.
+    pub handshake_timeout: Option<Duration>,
+    /// How often to send keep-alive packets while the connection is idle.
+    pub keep_alive_interval: Duration,
+    /// How long the connection may be idle before it is closed, or None to never close it.
+    ///
+    /// The peer's idle timeout applies if it is shorter.
+    pub max_idle_timeout: Option<Duration>,
+}
+
+impl TransportTuning {
+    /// Returns the default timings, tuned for relayed paths.
+    pub fn new() -> Self {
+        Self {
+            initial_rtt: Duration::from_millis(500),
+            handshake_timeout: Some(Duration::from_secs(20)),
+            keep_alive_interval: Duration::from_secs(5),
+            max_idle_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// Sets the RTT assumed before the first sample is taken.
+    pub fn with_initial_rtt(mut self, initial_rtt: Duration) -> Self {
+        self.initial_rtt = initial_rtt;
+        self
+    }
+
+    /// Sets how long to wait for the handshake, or None to only rely on the idle timeout.
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets how often to send keep-alive packets while the connection is idle.
+    pub fn with_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = interval;
+        self
+    }
+
+    /// Sets how long the connection may be idle before it is closed, or None to never close it.
+    pub fn with_max_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.max_idle_timeout = timeout;
+        self
+    }
+
+    /// Applies the transport settings to a builder, e.g. for the endpoint of a server.
+    ///
+    /// The handshake timeout is only used by [`crate::Client`], see [`crate::Server::with_handshake_timeout`].
+    pub fn apply(&self, builder: QuicTransportConfigBuilder) -> QuicTransportConfigBuilder {
+        builder
+            .initial_rtt(self.initial_rtt)
+            .keep_alive_interval(self.keep_alive_interval)
+            .max_idle_timeout(idle_timeout(self.max_idle_timeout))
+    }
+
+    /// Returns a transport config with these settings applied to iroh's defaults.
+    pub fn transport_config(&self) -> QuicTransportConfig {
+        self.apply(QuicTransportConfig::builder()).build()
+    }
+}
+
+/// Converts the timeout, treating timeouts too large to encode as infinite.
+pub(crate) fn idle_timeout(timeout: Option<Duration>) -> Option<IdleTimeout> {
+    timeout.and_then(|timeout| timeout.try_into().ok())
+}
+
+impl Default for TransportTuning {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use url::Url;
+
+use crate::{H3Request, Outcome, Router, Server};
+
+/// The status used to reject a session whose authority doesn't match any host of [`VirtualHosts`].
+pub const HOST_NOT_FOUND: http::StatusCode = http::StatusCode::MISDIRECTED_REQUEST;
+
+/// Hosts several logical services on one endpoint, dispatching by the authority of the CONNECT request.
+///
+/// Each host has a [`Router`] of its own. Hosts are compared case-insensitively, and a pattern
+/// like `*.example.com` matches any subdomain, but not `example.com` itself. The port is only
+/// compared if the pattern has one, e.g. `example.com:8443`. Hosts are tried in the order they
+/// were added, and requests that match none go to the default router, if any, or are rejected
+/// with [`HOST_NOT_FOUND`].
+#[derive(Debug, Clone, Default)]
+pub struct VirtualHosts {
+    hosts: Vec<(String, Router)>,
+    default: Option<Router>,
+}
+
+impl VirtualHosts {
+    /// Returns virtual hosts without any hosts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Dispatches requests for the host to the router.
+    pub fn host(mut self, pattern: impl Into<String>, router: Router) -> Self {
+        self.hosts
+            .push((pattern.into().to_ascii_lowercase(), router));
+        self
+    }
+
+    /// Dispatches requests that don't match any host to the router, instead of rejecting them.
+    pub fn default_host(mut self, router: Router) -> Self {
+        self.default = Some(router);
+        self
+    }
+
+    /// Runs the router of the first matching host, see [`Router::dispatch`].
+    pub async fn dispatch(&self, request: H3Request) -> Outcome {
+        let router = self
+            .hosts
+            .iter()
+            .find(|(pattern, _)| matches_host(pattern, &request.url))
+            .map(|(_, router)| router)
+            .or(self.default.as_ref());
+        match router {
+            Some(router) => router.dispatch(request).await,
+            None => {
+                tracing::debug!(authority = %request.url.authority(), "rejecting session for unknown host");
+                Outcome::reject(request, HOST_NOT_FOUND).await
+            }
+        }
+    }
+
+    /// Accepts sessions from the server and dispatches each in a task of its own.
+    ///
+    /// Returns once the server's endpoint is closed and all handlers have returned.
+    pub async fn serve(self, server: Server) {
+        let hosts = Arc::new(self);
+        server
+            .serve(move |request| {
+                let hosts = hosts.clone();
+                async move {
+                    hosts.dispatch(request).await;
+                }
+            })
+            .await
+    }
+}
+
+fn matches_host(pattern: &str, url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_ascii_lowercase();
+    let target = match (pattern.contains(':'), url.port_or_known_default()) {
+        (true, Some(port)) => format!("{host}:{port}"),
+        (true, None) => return false,
+        (false, _) => host,
+    };
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => target
+            .strip_suffix(suffix)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => target == pattern,
+    }
+}